@@ -0,0 +1,182 @@
+use chrono::{DateTime, Duration, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use crate::cron::CronSchedule;
+use crate::db::{Event, EventExclusion, Holiday, Kind};
+use crate::ics::next_weekly_occurrence;
+
+// a weekly occurrence on a day off of the event's holiday country, by the date of the zone it's kept in
+fn on_holiday(event: &Event, time: DateTime<Utc>, holidays: &[Holiday]) -> bool {
+    let Some(country) = event.holiday_country.as_deref() else { return false };
+    let day = match event.timezone.as_deref().and_then(|timezone| timezone.parse::<Tz>().ok()) {
+        Some(timezone) => time.with_timezone(&timezone).date_naive(),
+        None => time.date_naive(),
+    };
+    holidays.iter().any(|holiday| holiday.country == country && holiday.day == day)
+}
+
+// times the events fire at within [from, to), weekly events expanded to every occurrence but the skipped ones, ordered by time
+pub fn occurrences<'a>(events: &'a [Event], exclusions: &[EventExclusion], holidays: &[Holiday], from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<(DateTime<Utc>, &'a Event)> {
+    let mut occurrences = vec![];
+    // heads-ups belong to the main reminder and aren't listed on their own
+    for event in events.iter().filter(|event| event.lead_minutes == 0) {
+        match (&event.kind, event.time, event.day, event.hour, event.minute) {
+            (Kind::Absolute | Kind::Cron, Some(time), _, _, _) if time >= from && time < to => occurrences.push((time, event)),
+            (Kind::Recurrent, _, Some(day @ 1..=7), Some(hour), Some(minute)) => {
+                let mut time = next_weekly_occurrence(from, day, hour, minute);
+                while time < to {
+                    let skipped = exclusions.iter().any(|exclusion| exclusion.event_uid == event.uid && exclusion.occurs_on == time.date_naive());
+                    if !skipped && !on_holiday(event, time, holidays) {
+                        occurrences.push((time, event));
+                    }
+                    time += Duration::weeks(1);
+                }
+            }
+            _ => {}
+        }
+    }
+    occurrences.sort_by_key(|(time, _)| *time);
+    occurrences
+}
+
+// the next `count` times the events fire at from `from` on, following weekly events across dst switches of the
+// timezone they are kept in and cron ones past their next match, in `timezone` when they have none of their own
+pub fn upcoming<'a>(events: &'a [Event], exclusions: &[EventExclusion], holidays: &[Holiday], from: DateTime<Utc>, count: usize, timezone: Tz) -> Vec<(DateTime<Utc>, &'a Event)> {
+    let mut occurrences = vec![];
+    for event in events.iter().filter(|event| event.lead_minutes == 0) {
+        let kept = event.timezone.as_deref().and_then(|name| name.parse::<Tz>().ok());
+        match (&event.kind, event.time, event.day, event.hour, event.minute) {
+            (Kind::Absolute, Some(time), _, _, _) if time >= from => occurrences.push((time, event)),
+            (Kind::Cron, Some(time), _, _, _) if time >= from => {
+                let schedule = event.cron.as_deref().and_then(|cron| cron.parse::<CronSchedule>().ok());
+                let mut next = Some(time);
+                for _ in 0..count {
+                    let Some(time) = next else { break };
+                    occurrences.push((time, event));
+                    next = schedule.as_ref().and_then(|schedule| schedule.next_after(time, kept.unwrap_or(timezone)));
+                }
+            }
+            (Kind::Recurrent, _, Some(day @ 1..=7), Some(hour), Some(minute)) => {
+                // the slot is in utc as of now, a kept timezone moves it by the change of its offset since then
+                let offset = |at: DateTime<Utc>| kept.map_or(0, |kept| kept.offset_from_utc_datetime(&at.naive_utc()).fix().local_minus_utc() as i64);
+                let mut time = next_weekly_occurrence(from, day, hour, minute);
+                let mut found = 0;
+                // skipped occurrences are finite, so are the weeks to look through
+                for _ in 0..count + exclusions.len() + holidays.len() {
+                    let shifted = time + Duration::seconds(offset(from) - offset(time));
+                    let skipped = exclusions.iter().any(|exclusion| exclusion.event_uid == event.uid && exclusion.occurs_on == shifted.date_naive());
+                    if !skipped && shifted >= from && !on_holiday(event, shifted, holidays) {
+                        occurrences.push((shifted, event));
+                        found += 1;
+                    }
+                    if found == count {
+                        break;
+                    }
+                    time += Duration::weeks(1);
+                }
+            }
+            _ => {}
+        }
+    }
+    occurrences.sort_by_key(|(time, _)| *time);
+    occurrences.truncate(count);
+    occurrences
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, Utc};
+    use crate::db::{Event, EventExclusion, Kind, Source};
+    use crate::models::Priority;
+    use super::{occurrences, upcoming};
+
+    fn time(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn event(text: &str, kind: Kind, time: Option<DateTime<Utc>>, day: Option<u8>, lead_minutes: u32) -> Event {
+        Event {
+            uid: text.to_string(),
+            kind,
+            source: Source::Telegram,
+            text: text.to_string(),
+            time,
+            day,
+            hour: day.map(|_| 7),
+            minute: day.map(|_| 0),
+            is_deleted: false,
+            lead_minutes,
+            priority: Priority::Normal,
+            nag_minutes: 0,
+            expires_minutes: 0,
+            silent: false,
+            cron: None,
+            quote: None,
+            timezone: None,
+            holiday_country: None,
+        }
+    }
+
+    #[test]
+    fn should_expand_weekly_events_and_order_by_time() {
+        // thursday
+        let now = time("2023-01-26T14:40:00Z");
+        let events = vec![
+            event("dentist", Kind::Absolute, Some(time("2023-01-27T12:00:00Z")), None, 0),
+            event("dentist", Kind::Absolute, Some(time("2023-01-27T11:00:00Z")), None, 60),
+            event("far away", Kind::Absolute, Some(time("2023-02-27T12:00:00Z")), None, 0),
+            event("pills", Kind::Recurrent, None, Some(5), 0),
+        ];
+
+        let exclusions = vec![EventExclusion { event_id: 1, event_uid: "pills".to_string(), occurs_on: time("2023-02-03T07:00:00Z").date_naive() }];
+
+        let found = occurrences(&events, &exclusions, &[], now, now + Duration::days(21));
+
+        let found = found.iter().map(|(time, event)| (*time, event.text.as_str())).collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            (time("2023-01-27T07:00:00Z"), "pills"),
+            (time("2023-01-27T12:00:00Z"), "dentist"),
+            (time("2023-02-10T07:00:00Z"), "pills"),
+        ]);
+    }
+
+    #[test]
+    fn should_list_next_occurrences_across_dst_and_cron_matches() {
+        // thursday, berlin moves to summer time on 26 march
+        let now = time("2023-03-16T12:00:00Z");
+        let mut weekly = event("swim", Kind::Recurrent, None, Some(1), 0);
+        (weekly.hour, weekly.timezone) = (Some(6), Some("Europe/Berlin".to_string()));
+        let mut monthly = event("rent", Kind::Cron, Some(time("2023-04-01T06:00:00Z")), None, 0);
+        monthly.cron = Some("0 9 1 * *".to_string());
+        let events = vec![
+            event("dentist", Kind::Absolute, Some(time("2023-03-18T10:00:00Z")), None, 0),
+            event("gone", Kind::Absolute, Some(time("2023-03-15T10:00:00Z")), None, 0),
+            weekly,
+            monthly,
+        ];
+
+        let found = upcoming(&events, &[], &[], now, 5, chrono_tz::Israel);
+
+        let found = found.iter().map(|(time, event)| (*time, event.text.as_str())).collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            (time("2023-03-18T10:00:00Z"), "dentist"),
+            (time("2023-03-20T06:00:00Z"), "swim"),
+            (time("2023-03-27T05:00:00Z"), "swim"),
+            (time("2023-04-01T06:00:00Z"), "rent"),
+            (time("2023-04-03T05:00:00Z"), "swim"),
+        ]);
+    }
+
+    #[test]
+    fn should_follow_cron_events_without_timezone_in_the_given_one() {
+        // 9:00 in new york is 13:00 utc in daylight time
+        let now = time("2023-03-16T12:00:00Z");
+        let mut monthly = event("rent", Kind::Cron, Some(time("2023-04-01T13:00:00Z")), None, 0);
+        monthly.cron = Some("0 9 1 * *".to_string());
+        let events = vec![monthly];
+
+        let found = upcoming(&events, &[], &[], now, 2, chrono_tz::America::New_York);
+
+        let found = found.iter().map(|(time, _)| *time).collect::<Vec<_>>();
+        assert_eq!(found, vec![time("2023-04-01T13:00:00Z"), time("2023-05-01T13:00:00Z")]);
+    }
+}
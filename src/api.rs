@@ -0,0 +1,279 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use arrayvec::ArrayVec;
+use chrono::{DateTime, Utc};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use hyper::header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use tracing::{error, info};
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use tokio::task::JoinHandle;
+use crate::bot::BotDeps;
+use crate::db::Source;
+use crate::errors::BotError;
+use crate::models::{Env, StoredNotification};
+
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+// rest endpoints for managing reminders from scripts, served next to the bot when API_BIND is set
+#[derive(Clone)]
+pub struct Api {
+    deps: Arc<BotDeps>,
+    token: Arc<str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateReminder {
+    text: String,
+    time: Option<DateTime<Utc>>,
+    recurrence: Option<Recurrence>,
+}
+
+// weekly schedule in utc, days are numbered from 1 (Monday) to 7 and default to every day
+#[derive(Debug, Deserialize)]
+struct Recurrence {
+    days: Option<ArrayVec<u8, 7>>,
+    hour: u8,
+    minute: u8,
+}
+
+// only the text can be changed, a different schedule is a new reminder
+#[derive(Debug, Deserialize)]
+struct EditReminder {
+    text: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+impl CreateReminder {
+    fn into_notification(self) -> Result<(String, StoredNotification), &'static str> {
+        if self.text.trim().is_empty() {
+            return Err("text should not be empty");
+        }
+        let notification = match (self.time, self.recurrence) {
+            (Some(time), None) => StoredNotification::Absolute { time },
+            (None, Some(Recurrence { days, hour, minute })) => {
+                let days = days.unwrap_or_else(|| (1..=7).collect());
+                if hour > 23 || minute > 59 || days.is_empty() || days.iter().any(|day| !(1..=7).contains(day)) {
+                    return Err("recurrence should have days between 1 and 7, hour below 24 and minute below 60");
+                }
+                StoredNotification::Recurrent { hours: hour, minutes: minute, days: Some(days) }
+            }
+            _ => return Err("exactly one of time and recurrence should be given"),
+        };
+        Ok((self.text, notification))
+    }
+}
+
+impl Api {
+    // returns None when the api is not configured
+    pub fn new(env: &Env, deps: Arc<BotDeps>) -> Result<Option<(Api, SocketAddr)>, BotError> {
+        let bind = match &env.api_bind {
+            Some(bind) => bind,
+            None => return Ok(None),
+        };
+        let address = bind.parse().map_err(|_| BotError::Usage("API_BIND should be an address like 127.0.0.1:8080"))?;
+        let token = env.api_token.as_deref()
+            .filter(|token| !token.is_empty())
+            .ok_or(BotError::Usage("API_TOKEN is required when API_BIND is set"))?;
+        Ok(Some((Api { deps, token: token.into() }, address)))
+    }
+
+    pub fn run_task(self, address: SocketAddr) -> Result<JoinHandle<()>, BotError> {
+        let server = hyper::Server::try_bind(&address)?
+            .serve(make_service_fn(move |_| {
+                let api = self.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request| {
+                        let api = api.clone();
+                        async move { Ok::<_, Infallible>(api.handle(request).await) }
+                    }))
+                }
+            }));
+        info!("Api is listening on {}", address);
+        Ok(tokio::spawn(async move {
+            if let Err(err) = server.await {
+                error!("Api server stopped: {}", err);
+            }
+        }))
+    }
+
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let method = request.method().clone();
+        let path = request.uri().path().to_string();
+        match self.route(request).await {
+            Ok(response) => response,
+            Err(err) => {
+                error!("Error in api handler for {} {}: {}", method, path, err);
+                error_response(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+            }
+        }
+    }
+
+    async fn route(&self, request: Request<Body>) -> Result<Response<Body>, BotError> {
+        let segments = request.uri().path()
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+
+        if let (&Method::GET, ["health"]) = (request.method(), segments.as_slice()) {
+            let mode = if self.deps.is_standby() { "standby" } else { "primary" };
+            return json_response(StatusCode::OK, &serde_json::json!({ "status": "ok", "mode": mode }));
+        }
+        // google sends the user here after /connect_calendar, the state parameter stands in for the token
+        if let (&Method::GET, ["calendar", "callback"]) = (request.method(), segments.as_slice()) {
+            return self.calendar_callback(request.uri().query().unwrap_or_default()).await;
+        }
+        if !self.is_authorized(&request) {
+            return Ok(error_response(StatusCode::UNAUTHORIZED, "invalid api token"));
+        }
+        if self.deps.is_standby() && request.method() != Method::GET {
+            return Ok(error_response(StatusCode::SERVICE_UNAVAILABLE, "standby instance is read-only, use the primary"));
+        }
+
+        let repository = self.deps.event_repository();
+        match (request.method().clone(), segments.as_slice()) {
+            (Method::GET, ["stats"]) => json_response(StatusCode::OK, &repository.get_stats().await?),
+            (Method::GET, ["maintenance"]) => json_response(StatusCode::OK, &self.deps.last_maintenance()),
+            (method, ["users", user_id, "reminders", rest @ ..]) => {
+                let user_id = match user_id.parse::<u64>() {
+                    Ok(user_id) if self.deps.user_repository().is_chat_id_valid(user_id) => user_id,
+                    _ => return Ok(error_response(StatusCode::NOT_FOUND, "unknown user")),
+                };
+                match (method, rest) {
+                    (Method::GET, []) => json_response(StatusCode::OK, &repository.get_events(user_id, None, None).await?),
+                    (Method::POST, []) => self.create_reminder(user_id, request).await,
+                    (Method::PATCH, [uid]) => match repository.find_event_id(user_id, uid.to_string()).await? {
+                        Some(event_id) => self.edit_reminder(user_id, event_id, request).await,
+                        None => Ok(error_response(StatusCode::NOT_FOUND, "unknown reminder")),
+                    },
+                    (Method::DELETE, [uid]) => match repository.find_event_id(user_id, uid.to_string()).await? {
+                        Some(event_id) => {
+                            repository.delete_events(vec![event_id]).await?;
+                            Ok(Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty()).unwrap_or_default())
+                        }
+                        None => Ok(error_response(StatusCode::NOT_FOUND, "unknown reminder")),
+                    },
+                    _ => Ok(error_response(StatusCode::NOT_FOUND, "not found")),
+                }
+            }
+            _ => Ok(error_response(StatusCode::NOT_FOUND, "not found")),
+        }
+    }
+
+    async fn create_reminder(&self, user_id: u64, request: Request<Body>) -> Result<Response<Body>, BotError> {
+        let reminder: CreateReminder = match read_json(request).await? {
+            Ok(reminder) => reminder,
+            Err(response) => return Ok(response),
+        };
+        let (text, notification) = match reminder.into_notification() {
+            Ok(reminder) => reminder,
+            Err(message) => return Ok(error_response(StatusCode::BAD_REQUEST, message)),
+        };
+
+        let repository = self.deps.event_repository();
+        let ids = repository.insert_event(user_id, text, Source::Api, vec![notification]).await?;
+        let mut events = Vec::with_capacity(ids.len());
+        for id in ids {
+            events.extend(repository.get_event(user_id, id).await?);
+        }
+        json_response(StatusCode::CREATED, &events)
+    }
+
+    async fn edit_reminder(&self, user_id: u64, event_id: u64, request: Request<Body>) -> Result<Response<Body>, BotError> {
+        let reminder: EditReminder = match read_json(request).await? {
+            Ok(reminder) => reminder,
+            Err(response) => return Ok(response),
+        };
+        if reminder.text.trim().is_empty() {
+            return Ok(error_response(StatusCode::BAD_REQUEST, "text should not be empty"));
+        }
+
+        let repository = self.deps.event_repository();
+        if !repository.edit_event_text(user_id, event_id, reminder.text).await? {
+            return Ok(error_response(StatusCode::NOT_FOUND, "unknown reminder"));
+        }
+        json_response(StatusCode::OK, &repository.get_event(user_id, event_id).await?)
+    }
+
+    // a page the user sees in the browser, so the answer is plain text rather than json
+    async fn calendar_callback(&self, query: &str) -> Result<Response<Body>, BotError> {
+        let param = |name: &str| url::form_urlencoded::parse(query.as_bytes()).find(|(key, _)| key == name).map(|(_, value)| value.into_owned());
+        let connected = match (param("state"), param("code")) {
+            (Some(state), Some(code)) if !self.deps.is_standby() => self.deps.connect_calendar(&state, &code).await?,
+            _ => false,
+        };
+        let (status, text) = if connected {
+            (StatusCode::OK, "Google Calendar is connected, you can go back to Telegram")
+        } else {
+            (StatusCode::BAD_REQUEST, "The link is invalid or expired, send /connect_calendar again")
+        };
+        Ok(Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(Body::from(text))
+            .unwrap_or_default())
+    }
+
+    // compares in constant time so the token can't be guessed byte by byte
+    fn is_authorized(&self, request: &Request<Body>) -> bool {
+        let given = request.headers().get(AUTHORIZATION)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        given.len() == self.token.len()
+            && given.bytes().zip(self.token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+}
+
+pub fn json_response(status: StatusCode, body: &impl Serialize) -> Result<Response<Body>, BotError> {
+    Ok(Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(body)?))
+        .unwrap_or_default())
+}
+
+// the error side is the response to send back as is
+async fn read_json<T: DeserializeOwned>(request: Request<Body>) -> Result<Result<T, Response<Body>>, BotError> {
+    let length = request.headers().get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<u64>().ok());
+    if length.is_none_or(|length| length > MAX_BODY_SIZE) {
+        return Ok(Err(error_response(StatusCode::PAYLOAD_TOO_LARGE, "body should have a content length below 64KiB")));
+    }
+    let body = hyper::body::to_bytes(request.into_body()).await?;
+    Ok(serde_json::from_slice(&body).map_err(|err| error_response(StatusCode::BAD_REQUEST, &err.to_string())))
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, &ErrorBody { error: message }).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CreateReminder;
+    use crate::models::StoredNotification;
+
+    #[test]
+    fn should_validate_created_reminders() {
+        let parse = |body: &str| serde_json::from_str::<CreateReminder>(body).unwrap().into_notification();
+
+        let (text, notification) = parse("{\"text\": \"stand up\", \"recurrence\": {\"hour\": 9, \"minute\": 30}}").unwrap();
+        assert_eq!(text, "stand up");
+        assert!(matches!(notification, StoredNotification::Recurrent { hours: 9, minutes: 30, days: Some(days) } if days.len() == 7));
+        assert!(matches!(parse("{\"text\": \"t\", \"time\": \"2030-01-01T10:00:00Z\"}").unwrap().1, StoredNotification::Absolute { .. }));
+
+        assert!(parse("{\"text\": \"t\"}").is_err());
+        assert!(parse("{\"text\": \" \", \"time\": \"2030-01-01T10:00:00Z\"}").is_err());
+        assert!(parse("{\"text\": \"t\", \"recurrence\": {\"days\": [8], \"hour\": 9, \"minute\": 0}}").is_err());
+        assert!(parse("{\"text\": \"t\", \"time\": \"2030-01-01T10:00:00Z\", \"recurrence\": {\"hour\": 9, \"minute\": 0}}").is_err());
+    }
+}
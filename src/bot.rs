@@ -1,72 +1,2196 @@
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::Arc;
-use std::time::Duration;
-use chrono::Utc;
-use fnv::FnvHashMap;
-use crate::db::{EventRepository, UserRepository};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use chrono::{Datelike, DateTime, Months, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use crate::db::{AccessStatus, CalendarConnection, ConnectionOptions, Event, EventRepository, Kind, MaintenanceReport, MaintenanceStep, Outcome, ParseCorrection, Role, Source, Transition, UserRepository, Webhook};
 use crate::errors::BotError;
-use crate::models::{Env, InlineKeyboardButton, InlineKeyboardMarkup, Message, Notification, Update};
-use crate::parser::OpenAIParser;
-use crate::tg::Tg;
-use std::fmt::Write;
-use log::{error, info};
+use crate::agenda;
+use crate::bundle::{self, SettingsBundle};
+use crate::demo;
+use crate::commands::{self, Resolution};
+use crate::config::SettingsFiles;
+use crate::cron::CronSchedule;
+use crate::health::{Heartbeats, Subsystem, SubsystemHealth, Task};
+use crate::humanize::{self, Locale};
+use crate::i18n::{self, tr, Phrase};
+use crate::render::{self, PlainChoices};
+use crate::ics::{self, ImportedEvent};
+use crate::ids::{IdGenerator, UuidV7Generator};
+use crate::models::{BusinessConnection, ChosenInlineResult, CommaSeparatedIds, Document, Env, User, EventToFire, FormattedTime, InlineKeyboardButton, InlineKeyboardMarkup, InlineQuery, InlineQueryResultArticle, InputTextMessageContent, Location, Message, MissedPolicy, Notification, Delivery, Priority, QuietHours, StoredNotification, Update, on_wall_clock, shift_weekly, DEFAULT_TIMEZONE};
+use crate::parser::{self, LlmParser, ModelOptions, Usage};
+use crate::queue::{Admission, ParserPermit, ParserQueue};
+use crate::state::StateStore;
+use crate::templates;
+use crate::tg::{TelegramApi, Tg};
+use crate::webhooks::{self, WebhookClient, WebhookPayload};
+use crate::tzlookup;
+use crate::holidays;
+use crate::eval::{self, Disagreement};
+use crate::gcal::{self, GoogleCalendar, SyncDirections};
+use std::fmt::{Display, Formatter, Write};
+use fnv::FnvHashSet;
+use tracing::{error, field, info, info_span, warn, Instrument};
 use tokio::task::JoinHandle;
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum State {
+    #[default]
     Idle,
-    Parsed { text: String, notification: Notification },
-    ParsedWithError { text: String }
+    // snooze prompt was sent, the next message says when to remind about the text of the event again
+    AwaitingSnooze { event_id: u64, text: String },
+    // a template was picked, the next messages are the values of its placeholders one by one
+    FillingTemplate { text: String },
+    // a location or a forwarded message came without a text, the next reminder typed in the chat carries it
+    Holding { context: DraftContext },
+    // edit of the draft under the message was asked for, the next message is corrected json or what to change
+    EditingDraft { message_id: u64 },
+    // /timezone was sent without a name, the next location pin sets it
+    AwaitingTimezone,
+}
+
+// what came along with the text of a draft and is stored with the reminder once it's accepted
+#[derive(Debug, Clone, Default)]
+pub struct DraftContext {
+    location: Option<Location>,
+    quote: Option<String>,
+    // the first parse was rejected with Repeat, whatever gets accepted is kept as a correction
+    corrected: bool,
+    // toggled on the draft, the reminder is sent without sound
+    silent: bool,
+    // toggled on weekly drafts, the reminder doesn't fire on the days off of HOLIDAY_COUNTRY
+    skip_holidays: bool,
+    // message the draft was parsed from, the reminder is sent as a reply to it
+    source_message_id: Option<u64>,
+    // public id of the draft, the reminder it's accepted as keeps it as the id of its group
+    uid: Option<String>,
+}
+
+// reply waiting for Accept, kept under the message with its buttons, so several drafts of a chat
+// can be pending at once and accepted in any order
+#[derive(Debug, Clone)]
+pub enum Draft {
+    // other readings of an ambiguous text are offered as options after the first one
+    Parsed { text: String, notification: Notification, alternatives: Vec<Notification> },
+    ParsedWithError { text: String },
+    ImportPreview { events: Vec<ImportedEvent> },
+    // the parser failed twice, the date and then the hour and minutes of the text are picked with buttons
+    Picking { text: String, date: Option<NaiveDate>, hour: Option<u32> },
+    // accepted draft that lands close to existing reminders, waiting for keep both or shift
+    Conflicting { text: String, delivery: Delivery, notifications: Vec<StoredNotification> },
+}
+
+impl Draft {
+    // readings a parsed draft offers to accept
+    fn options(&self) -> usize {
+        match self {
+            Draft::Parsed { alternatives, .. } => alternatives.len() + 1,
+            _ => 1,
+        }
+    }
+
+    fn is_weekly(&self) -> bool {
+        matches!(self, Draft::Parsed { notification: Notification::Recurrent { .. }, .. })
+    }
+}
+
+// chat id and id of the message with the draft buttons
+type DraftKey = (u64, u64);
+
+// draft as it was read by a handler, writes through it fail once another update changed the draft
+#[derive(Debug, Clone, Copy)]
+struct DraftSlot {
+    key: DraftKey,
+    version: u64,
+}
+
+impl DraftSlot {
+    // the slot after this handler's own successful write
+    fn next(self) -> DraftSlot {
+        DraftSlot { key: self.key, version: self.version + 1 }
+    }
+}
+
+// older drafts of a chat are forgotten, their buttons answer that the draft is gone
+const MAX_PENDING_DRAFTS: usize = 10;
+
+// chat id and id of the user's message a draft was parsed from, editing it parses the draft again
+type DraftSourceKey = (u64, u64);
+
+pub struct BotDeps {
+    event_repository: EventRepository,
+    user_repository: UserRepository,
+    parser: LlmParser,
+    tg: Arc<dyn TelegramApi>,
+    webhooks: WebhookClient,
+    // two-way sync with google calendar, when its oauth client is configured
+    calendar: Option<GoogleCalendar>,
+    calendar_pull_interval: Duration,
+    monthly_token_budget: Option<u64>,
+    delivery_max_attempts: u32,
+    // due reminders sent per tick of the background loop, a backlog after downtime drains over several ticks
+    fire_batch_size: usize,
+    missed_policy: MissedPolicy,
+    missed_threshold_minutes: u32,
+    expiry_notice: bool,
+    // urgent reminders are sent again after this long without an acknowledgement, at most urgent_max_resends times
+    urgent_resend_window: chrono::Duration,
+    urgent_max_resends: u32,
+    cleanup_retention: chrono::Duration,
+    cleanup_interval: Duration,
+    // hour of the bot timezone the nightly database maintenance starts at
+    maintenance_hour: u32,
+    last_maintenance: RwLock<Option<MaintenanceReport>>,
+    heartbeats: Heartbeats,
+    subsystems: SubsystemHealth,
+    plain_choices: StateStore<u64, PlainChoices>,
+    admin_id: Option<u64>,
+    started_at: DateTime<Utc>,
+    parse_attempts: AtomicU64,
+    parse_failures: AtomicU64,
+    // set by the admin with /debug, the offline parser reads every text the model gets as well
+    parser_debug: AtomicBool,
+    eval_corpus: Option<PathBuf>,
+    parser_queue: Arc<ParserQueue>,
+    conflict_window: chrono::Duration,
+    // messages longer than this many characters are summarized before the parse
+    summarize_threshold: usize,
+    // tokens all demo users may spend together between wipes, none outside of the demo
+    demo_token_budget: Option<u64>,
+    demo_wipe_interval: Duration,
+    // where TG_USERS is read from again by /reload_users
+    settings_files: SettingsFiles,
+    standby: bool,
+    log_redact: bool,
+    poll_timeout: Duration,
+    // country of the days off weekly reminders can skip
+    holiday_country: String,
+}
+
+// a longer poll would leave the polling heartbeat stale for the liveness probe
+const MAX_POLL_TIMEOUT_SECS: u64 = 50;
+
+const POLL_ERROR_DELAY: Duration = Duration::from_secs(3);
+
+// how far ahead events of connected google calendars become reminders
+const CALENDAR_PULL_DAYS: i64 = 7;
+
+// allowed users from the database with their stored roles;
+// the admin from the environment comes last, so a stored role can't lock them out
+async fn allowed_users(event_repository: &EventRepository, admin_id: Option<u64>) -> Result<Vec<(u64, Role)>, BotError> {
+    Ok(event_repository.get_users().await?.into_iter()
+        .map(|user| (user.user_id, Role::User))
+        .chain(event_repository.get_user_roles().await?)
+        .chain(admin_id.map(|admin_id| (admin_id, Role::Admin)))
+        .collect())
+}
+
+impl BotDeps {
+    pub async fn new(env: &Env) -> Result<BotDeps, BotError> {
+        let event_repository = if env.standby_mode {
+            EventRepository::open_read_only(&env.connection_string, Arc::new(UuidV7Generator), ConnectionOptions::from_env(env)).await?
+        } else {
+            EventRepository::open(&env.connection_string, Arc::new(UuidV7Generator), ConnectionOptions::from_env(env)).await?
+        };
+        if !env.standby_mode {
+            event_repository.seed_users(env.user_ids.iter().copied().collect()).await?;
+            event_repository.clear_reservations().await?;
+            event_repository.load_holidays(holidays::calendar(&env.holiday_country, env.holidays_file.as_deref())?).await?;
+        }
+        let user_repository = UserRepository::new(allowed_users(&event_repository, env.admin_id).await?.into_iter());
+        let user_repository = if env.demo_mode { user_repository.with_default_role(Role::User) } else { user_repository };
+        let parser = LlmParser::new(env.openai_token.clone(), ModelOptions {
+            provider: env.llm_provider,
+            model: env.openai_model.clone(),
+            temperature: env.openai_temperature,
+            max_tokens: env.openai_max_tokens,
+            base_url: env.openai_base_url.clone(),
+            organization: env.openai_organization.clone(),
+            project: env.openai_project.clone(),
+            api_version: env.azure_api_version.clone(),
+            max_retries: env.openai_max_retries,
+            retry_base_delay: Duration::from_millis(env.openai_retry_base_ms),
+            timeout: Duration::from_secs(env.openai_timeout_secs),
+            prompt_price: env.openai_prompt_price,
+            completion_price: env.openai_completion_price,
+            cache_ttl: Duration::from_secs(env.parser_cache_ttl_secs),
+            summary_model: env.summary_model.clone(),
+            fixtures: env.parser_fixtures.clone(),
+            prompt_file: env.system_prompt_file.clone(),
+        })?;
+        let tg = Arc::new(Tg::new(env.bot_token.to_string(), env.message_prefix.clone()));
+        for (chat_id, connection_id) in event_repository.get_business_chats().await? {
+            tg.route_business_chat(chat_id, connection_id);
+        }
+        let webhooks = WebhookClient::new()?;
+        let calendar = GoogleCalendar::new(env)?;
+        Ok(BotDeps {
+            user_repository,
+            event_repository,
+            parser,
+            tg,
+            webhooks,
+            calendar,
+            calendar_pull_interval: Duration::from_secs(env.calendar_pull_interval_secs),
+            monthly_token_budget: env.monthly_token_budget,
+            delivery_max_attempts: env.delivery_max_attempts,
+            fire_batch_size: env.fire_batch_size.max(1),
+            missed_policy: env.missed_policy,
+            missed_threshold_minutes: env.missed_threshold_minutes,
+            expiry_notice: env.expiry_notice,
+            urgent_resend_window: chrono::Duration::minutes(env.urgent_resend_minutes),
+            urgent_max_resends: env.urgent_max_resends,
+            cleanup_retention: chrono::Duration::days(env.cleanup_retention_days),
+            cleanup_interval: Duration::from_secs(env.cleanup_interval_secs),
+            maintenance_hour: env.maintenance_hour,
+            last_maintenance: RwLock::new(None),
+            heartbeats: Heartbeats::new(),
+            subsystems: SubsystemHealth::new(),
+            plain_choices: StateStore::new(),
+            admin_id: env.admin_id,
+            started_at: Utc::now(),
+            parse_attempts: AtomicU64::new(0),
+            parse_failures: AtomicU64::new(0),
+            parser_debug: AtomicBool::new(false),
+            eval_corpus: env.parser_eval_corpus.clone(),
+            parser_queue: ParserQueue::new(env.parser_concurrency, env.parser_user_concurrency),
+            conflict_window: chrono::Duration::minutes(env.conflict_window_minutes),
+            summarize_threshold: env.summarize_threshold,
+            demo_token_budget: env.demo_mode.then_some(env.demo_token_budget),
+            demo_wipe_interval: Duration::from_secs(env.demo_wipe_interval_secs),
+            settings_files: SettingsFiles::default(),
+            standby: env.standby_mode,
+            log_redact: env.log_redact,
+            poll_timeout: Duration::from_secs(env.poll_timeout_secs.min(MAX_POLL_TIMEOUT_SECS)),
+            holiday_country: env.holiday_country.to_uppercase(),
+        })
+    }
+
+    pub fn with_settings_files(self, settings_files: SettingsFiles) -> BotDeps {
+        BotDeps { settings_files, ..self }
+    }
+
+    // seeds users newly listed in TG_USERS of the settings files and reads the list from the database again,
+    // e.g. after it was changed by another instance; returns how many users were added and removed
+    async fn reload_users(&self) -> Result<(usize, usize), BotError> {
+        let user_ids = self.settings_files.read("TG_USERS")?.ok_or(BotError::EnvIds)?.parse::<CommaSeparatedIds>()?;
+        self.event_repository.seed_users(user_ids.iter().copied().collect()).await?;
+        let users = allowed_users(&self.event_repository, self.admin_id).await?;
+        let before = self.user_repository.user_ids().into_iter().collect::<FnvHashSet<_>>();
+        let after = users.iter().map(|(user_id, _)| *user_id).collect::<FnvHashSet<_>>();
+        self.user_repository.replace(users.into_iter());
+        Ok((after.difference(&before).count(), before.difference(&after).count()))
+    }
+
+    fn is_demo(&self) -> bool {
+        self.demo_token_budget.is_some()
+    }
+
+    pub fn event_repository(&self) -> &EventRepository {
+        &self.event_repository
+    }
+
+    pub fn user_repository(&self) -> &UserRepository {
+        &self.user_repository
+    }
+
+    pub fn last_maintenance(&self) -> Option<MaintenanceReport> {
+        self.last_maintenance.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    pub fn tg(&self) -> &dyn TelegramApi {
+        self.tg.as_ref()
+    }
+
+    // the primary polls telegram and fires events, a standby only reads the replicated database
+    pub fn is_standby(&self) -> bool {
+        self.standby
+    }
+
+    pub fn heartbeats(&self) -> &Heartbeats {
+        &self.heartbeats
+    }
+
+    pub fn subsystems(&self) -> &SubsystemHealth {
+        &self.subsystems
+    }
+
+    fn with_status(&self, text: String) -> String {
+        match self.subsystems.banner() {
+            Some(banner) => format!("{}\n{}", banner, text),
+            None => text,
+        }
+    }
+
+    // an urgent reminder stays at the top of the chat until it's done, each resend takes the pin over;
+    // chats where the bot may not pin still get the message
+    async fn pin_fired(&self, chat_id: u64, event_id: u64, message_id: u64) {
+        let pinned = async {
+            if let Some(previous) = self.event_repository.swap_pinned_message(event_id, Some(message_id)).await? {
+                self.tg.unpin_chat_message(chat_id, previous).await?;
+            }
+            self.tg.pin_chat_message(chat_id, message_id).await
+        };
+        if let Err(err) = pinned.await {
+            warn!("Failed to pin event {}: {}", event_id, err);
+        }
+    }
+
+    async fn unpin_fired(&self, chat_id: u64, event_id: u64) -> Result<(), BotError> {
+        if let Some(message_id) = self.event_repository.swap_pinned_message(event_id, None).await? {
+            if let Err(err) = self.tg.unpin_chat_message(chat_id, message_id).await {
+                warn!("Failed to unpin event {}: {}", event_id, err);
+            }
+        }
+        Ok(())
+    }
+
+    // sends a message with buttons, or with a numbered list of options in plain mode
+    async fn send_with_markup(&self, chat_id: u64, text: String, markup: InlineKeyboardMarkup, plain: bool) -> Result<u64, BotError> {
+        self.send_notification(chat_id, text, markup, plain, None, None).await
+    }
+
+    async fn send_notification(&self, chat_id: u64, text: String, markup: InlineKeyboardMarkup, plain: bool, disable_notification: Option<bool>, reply_to: Option<u64>) -> Result<u64, BotError> {
+        if !plain {
+            return self.tg.send_notification(chat_id, text, Some(markup), disable_notification, reply_to).await;
+        }
+        let (text, options) = render::render_plain(&text, &markup);
+        let message_id = self.tg.send_notification(chat_id, text, None, disable_notification, reply_to).await?;
+        self.plain_choices.set(chat_id, PlainChoices { message_id, options });
+        Ok(message_id)
+    }
+
+    async fn edit_with_markup(&self, chat_id: u64, message_id: u64, text: String, markup: Option<InlineKeyboardMarkup>, plain: bool) -> Result<(), BotError> {
+        match markup {
+            Some(markup) if plain => {
+                let (text, options) = render::render_plain(&text, &markup);
+                self.plain_choices.set(chat_id, PlainChoices { message_id, options });
+                self.tg.edit_message_text(chat_id, message_id, text, None).await
+            }
+            markup => self.tg.edit_message_text(chat_id, message_id, text, markup).await,
+        }
+    }
+
+    // plain messages have no buttons to replace, so new options are sent as a separate numbered list
+    async fn edit_markup(&self, chat_id: u64, message_id: u64, markup: Option<InlineKeyboardMarkup>, plain: bool) -> Result<(), BotError> {
+        match markup {
+            Some(markup) if plain => {
+                let (text, options) = render::render_plain("", &markup);
+                self.plain_choices.set(chat_id, PlainChoices { message_id, options });
+                self.tg.send_message(chat_id, text, None).await
+            }
+            _ if plain => Ok(()),
+            markup => self.tg.edit_message_reply_markup(chat_id, message_id, markup).await,
+        }
+    }
+
+    // /start from an unknown user queues a join request and asks the admin to decide on it
+    async fn request_access(&self, message: &Message) -> Result<(), BotError> {
+        let admin_id = match self.admin_id {
+            Some(admin_id) => admin_id,
+            None => return Ok(()),
+        };
+        let chat_id = message.chat.id;
+        let from = message.from.as_ref();
+        let username = from.and_then(|user| user.username.clone());
+        if !self.event_repository.request_access(chat_id, username.clone()).await? {
+            return self.tg.send_message(chat_id, "Your request is waiting for approval".to_string(), None).await;
+        }
+
+        let name = username.map(|username| format!("@{}", username))
+            .or_else(|| from.and_then(|user| user.first_name.clone()))
+            .unwrap_or_else(|| "Someone".to_string());
+        let option = |text: &str, data: CallbackQuery| vec![InlineKeyboardButton { text: text.to_string(), callback_data: data.to_string() }];
+        let markup = InlineKeyboardMarkup {
+            inline_keyboard: vec![
+                option("Approve", CallbackQuery::Join(chat_id, true)),
+                option("Reject", CallbackQuery::Join(chat_id, false)),
+            ]
+        };
+        let plain = self.event_repository.get_user_settings(admin_id).await?.plain_mode;
+        self.send_with_markup(admin_id, format!("{} ({}) asks for access", name, chat_id), markup, plain).await?;
+        self.tg.send_message(chat_id, "Your request was sent to the admin, I'll let you know once it's approved".to_string(), None).await
+    }
+
+    // the owner of a business account connected the bot to their chats or disconnected it
+    async fn connect_business(&self, connection: BusinessConnection) -> Result<(), BotError> {
+        let owner = connection.user.id;
+        self.event_repository.upsert_business_connection(connection.id.clone(), owner, connection.user_chat_id, connection.is_enabled).await?;
+        if !connection.is_enabled {
+            self.tg.unroute_business_connection(&connection.id);
+        }
+        if !self.user_repository.is_chat_id_valid(owner) {
+            return Ok(());
+        }
+        let text = if connection.is_enabled {
+            "Business account connected, your customers can now set reminders in their chats with you"
+        } else {
+            "Business account disconnected"
+        };
+        self.tg.send_message(connection.user_chat_id, text.to_string(), None).await
+    }
+
+    // customers of a connected business account use the bot in their chat with the account,
+    // their messages are handled like private ones and replies are sent on behalf of the account
+    async fn route_business_message(&self, update: Update) -> Result<Option<Update>, BotError> {
+        let message = match update.business_message {
+            Some(message) => message,
+            None => return Ok(Some(update)),
+        };
+        let connection = match message.business_connection_id.clone() {
+            Some(id) => self.event_repository.get_business_connection(id).await?,
+            None => None,
+        };
+        let connection = match connection {
+            Some(connection) if connection.is_enabled && self.user_repository.is_chat_id_valid(connection.user_id) => connection,
+            _ => return Ok(None),
+        };
+        // the owner writes in the same chat, those messages are meant for the customer
+        if message.from.as_ref().map(|user| user.id) == Some(connection.user_id) {
+            return Ok(None);
+        }
+        if !self.tg.is_business_chat(message.chat.id) {
+            self.event_repository.route_business_chat(message.chat.id, connection.id.clone()).await?;
+            self.tg.route_business_chat(message.chat.id, connection.id);
+        }
+        Ok(Some(Update { message: Some(message), business_message: None, ..update }))
+    }
+
+    // calls the webhook and stores the outcome in the audit table
+    async fn run_webhook(&self, user_id: u64, webhook: &Webhook, event_id: Option<u64>, text: Option<&str>) -> Result<u16, BotError> {
+        let payload = WebhookPayload { name: &webhook.name, text, fired_at: Utc::now() };
+        let result = self.webhooks.call(&webhook.url, &payload).await;
+        let outcome = match &result {
+            Ok(status) => Ok(*status),
+            Err(err) => Err(err.to_string()),
+        };
+        self.event_repository.record_webhook_call(user_id, webhook.name.clone(), event_id, outcome).await?;
+        result
+    }
+
+    pub fn calendar(&self) -> Option<&GoogleCalendar> {
+        self.calendar.as_ref()
+    }
+
+    // finishes /connect_calendar once google sent the user back to the api, false for an unknown or expired link
+    pub async fn connect_calendar(&self, state: &str, code: &str) -> Result<bool, BotError> {
+        let Some(calendar) = &self.calendar else {
+            return Ok(false);
+        };
+        let Some(connection) = calendar.authorize(state, code, Utc::now()).await? else {
+            return Ok(false);
+        };
+        let user_id = connection.user_id;
+        self.event_repository.set_calendar_connection(connection).await?;
+        self.tg.send_message(user_id, "Google Calendar connected".to_string(), None).await?;
+        Ok(true)
+    }
+
+    // new reminders of a user who pushes to google calendar go there as events, reminders before an event stay in the bot;
+    // the push is one-way, cancelling the reminder later leaves its event in the calendar
+    async fn push_to_calendar(&self, user_id: u64, event_ids: &[u64], timezone: Tz) -> Result<(), BotError> {
+        let Some(calendar) = &self.calendar else {
+            return Ok(());
+        };
+        let Some(connection) = self.event_repository.get_calendar_connection(user_id).await?.filter(|connection| connection.push) else {
+            return Ok(());
+        };
+        let access_token = calendar.access_token(&connection.refresh_token).await?;
+        for event_id in event_ids {
+            let Some(event) = self.event_repository.get_event(user_id, *event_id).await? else {
+                continue;
+            };
+            if let Some(google_event) = gcal::to_google_event(&event, Utc::now(), timezone) {
+                let google_id = calendar.insert_event(&access_token, &google_event).await?;
+                self.event_repository.track_calendar_event(user_id, google_id).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn describe_schedule(notification: &StoredNotification, now: DateTime<Utc>, timezone: Tz, locale: Locale) -> String {
+    match notification {
+        StoredNotification::Absolute { time } => humanize::format_time(*time, now, timezone, locale),
+        StoredNotification::Recurrent { hours, minutes, days } =>
+            humanize::format_weekly(days.as_ref().map_or(&[][..], |days| days.as_slice()), *hours, *minutes, now, timezone, locale),
+        StoredNotification::Lead { minutes, .. } => i18n::heads_up(&humanize::format_duration(*minutes, locale), locale),
+    }
+}
+
+fn describe_notification(notification: &Notification, now: DateTime<Utc>, timezone: Tz, locale: Locale) -> String {
+    let text = describe_stored(notification.get_text(), &notification.create_stored_notifications(now, timezone), now, timezone, locale);
+    match describe_delivery(notification.get_delivery(), locale) {
+        Some(delivery) => format!("{} ({})", text, delivery),
+        None => text,
+    }
+}
+
+fn describe_delivery(delivery: Delivery, locale: Locale) -> Option<String> {
+    let mut parts = vec![];
+    match delivery.priority {
+        Priority::Low => parts.push(tr(Phrase::LowPriority, locale).to_string()),
+        Priority::Urgent => parts.push(tr(Phrase::Urgent, locale).to_string()),
+        Priority::Normal => {}
+    }
+    if delivery.silent && delivery.priority != Priority::Low {
+        parts.push(tr(Phrase::Silent, locale).to_string());
+    }
+    if delivery.nag_minutes > 0 {
+        parts.push(i18n::every_until_done(&humanize::format_duration(delivery.nag_minutes, locale), locale));
+    } else if delivery.priority == Priority::Urgent {
+        parts.push(tr(Phrase::RepeatedUntilDone, locale).to_string());
+    }
+    if delivery.expires_minutes > 0 {
+        parts.push(i18n::expires_after(&humanize::format_duration(delivery.expires_minutes, locale), locale));
+    }
+    (!parts.is_empty()).then(|| parts.join(", "))
+}
+
+fn describe_stored(text: &str, notifications: &[StoredNotification], now: DateTime<Utc>, timezone: Tz, locale: Locale) -> String {
+    let when = notifications.iter()
+        .map(|stored| describe_schedule(stored, now, timezone, locale))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{} — {}", text, when)
+}
+
+fn describe_event_time(event: &Event, now: DateTime<Utc>, timezone: Tz, locale: Locale) -> String {
+    match event.kind {
+        Kind::Absolute => event.time.map(|time| humanize::format_time(time, now, timezone, locale)).unwrap_or_default(),
+        Kind::Recurrent => {
+            // a slot kept in a timezone is on its wall clock, the others are in utc
+            let kept = event.timezone.as_deref().and_then(|kept| kept.parse::<Tz>().ok()).map_or(0, |kept| humanize::offset_minutes(kept, now));
+            let (days, hour, minute) = shift_weekly(event.day.as_slice(), event.hour.unwrap_or(0), event.minute.unwrap_or(0), -kept);
+            humanize::format_weekly(&days, hour, minute, now, timezone, locale)
+        }
+        Kind::Cron => i18n::cron_schedule(event.cron.as_deref().unwrap_or_default(),
+                                          &event.time.map(|time| humanize::format_time(time, now, timezone, locale)).unwrap_or_default(), locale),
+    }
+}
+
+fn describe_event(event: &Event, now: DateTime<Utc>, timezone: Tz, locale: Locale) -> String {
+    let mut text = format!("{} [{}] {} — {}", event.uid, event.source, event.text, describe_event_time(event, now, timezone, locale));
+    if event.lead_minutes > 0 {
+        let _ = write!(text, " ({})", i18n::heads_up(&humanize::format_duration(event.lead_minutes, locale), locale));
+    }
+    if let Some(delivery) = describe_delivery(event.delivery(), locale) {
+        let _ = write!(text, " ({})", delivery);
+    }
+    text
+}
+
+fn remind_again_markup(event_uid: &str, done: bool, skip: bool, locale: Locale) -> InlineKeyboardMarkup {
+    let mut inline_keyboard = vec![vec![InlineKeyboardButton {
+        text: tr(Phrase::RemindAgain, locale).to_string(),
+        callback_data: CallbackQuery::RemindAgain(event_uid.to_string()).to_string()
+    }]];
+    if skip {
+        inline_keyboard.push(vec![InlineKeyboardButton { text: tr(Phrase::SkipNext, locale).to_string(), callback_data: CallbackQuery::Skip(event_uid.to_string()).to_string() }]);
+    }
+    if done {
+        inline_keyboard.insert(0, vec![InlineKeyboardButton { text: tr(Phrase::Done, locale).to_string(), callback_data: CallbackQuery::Done(event_uid.to_string()).to_string() }]);
+    }
+    InlineKeyboardMarkup { inline_keyboard }
+}
+
+fn outcome_mark(outcome: Outcome) -> &'static str {
+    match outcome {
+        Outcome::Completed => "✅",
+        Outcome::Missed => "⚠️",
+        Outcome::Cancelled => "✖️",
+    }
 }
 
-pub struct BotDeps {
-    event_repository: EventRepository,
-    user_repository: UserRepository,
-    parser: OpenAIParser,
-    tg: Tg
-}
+fn describe_outcome(outcome: Outcome, locale: Locale) -> &'static str {
+    match outcome {
+        Outcome::Completed => tr(Phrase::OutcomeCompleted, locale),
+        Outcome::Missed => tr(Phrase::OutcomeMissed, locale),
+        Outcome::Cancelled => tr(Phrase::OutcomeCancelled, locale),
+    }
+}
+
+// one numbered line per reminder and one row of its buttons, numbered the same way
+fn digest(header: &str, events: &[EventToFire], locale: Locale) -> (String, InlineKeyboardMarkup) {
+    let mut text = header.to_string();
+    let mut inline_keyboard = Vec::with_capacity(events.len());
+    for (i, event) in events.iter().enumerate() {
+        let _ = write!(text, "\n{}. {}", i + 1, fired_text(event, locale));
+        let buttons = remind_again_markup(&event.event_uid, true, event.is_recurrent, locale).inline_keyboard;
+        inline_keyboard.push(number_buttons(buttons, &format!("{}. ", i + 1)));
+    }
+    (text, InlineKeyboardMarkup { inline_keyboard })
+}
+
+fn number_buttons(rows: Vec<Vec<InlineKeyboardButton>>, prefix: &str) -> Vec<InlineKeyboardButton> {
+    rows.into_iter()
+        .flatten()
+        .map(|button| InlineKeyboardButton { text: format!("{}{}", prefix, button.text), ..button })
+        .collect()
+}
+
+// swaps the buttons of one reminder, so pressing a button in a digest leaves the other reminders' rows alone;
+// a message without buttons (plain mode) or with only this reminder's buttons gets the replacement as is
+fn replace_event_rows(current: Option<&InlineKeyboardMarkup>, event_uid: &str, replacement: Option<InlineKeyboardMarkup>) -> Option<InlineKeyboardMarkup> {
+    let current = match current {
+        Some(current) => current,
+        None => return replacement,
+    };
+    let is_event_row = |row: &Vec<InlineKeyboardButton>| row.iter()
+        .any(|button| button.callback_data.parse::<CallbackQuery>().ok().is_some_and(|data| data.fired_event_uid() == Some(event_uid)));
+    let position = match current.inline_keyboard.iter().position(is_event_row) {
+        Some(position) => position,
+        None => return replacement,
+    };
+    let prefix = current.inline_keyboard[position].first()
+        .and_then(|button| button.text.split_once(". "))
+        .filter(|(number, _)| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+        .map(|(number, _)| format!("{}. ", number));
+    let mut inline_keyboard = current.inline_keyboard.iter().filter(|row| !is_event_row(row)).cloned().collect::<Vec<_>>();
+    if inline_keyboard.is_empty() {
+        return replacement;
+    }
+    let rows = replacement.map(|markup| markup.inline_keyboard).unwrap_or_default();
+    let rows = match &prefix {
+        Some(prefix) if !rows.is_empty() => vec![number_buttons(rows, prefix)],
+        _ => rows,
+    };
+    inline_keyboard.splice(position..position, rows);
+    Some(InlineKeyboardMarkup { inline_keyboard })
+}
+
+fn describe_maintenance(report: Option<MaintenanceReport>, now: DateTime<Utc>, timezone: Tz, locale: Locale) -> String {
+    let report = match report {
+        Some(report) => report,
+        None => return "not run yet".to_string(),
+    };
+    let steps = report.steps.iter()
+        .map(|(step, millis)| format!("{} {} ms", step.name(), millis))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{} ({})", humanize::format_time(report.finished_at, now, timezone, locale), steps)
+}
+
+fn describe_conflicts(draft: &str, conflicts: &[Event], now: DateTime<Utc>, timezone: Tz, locale: Locale) -> String {
+    let conflicts = conflicts.iter()
+        .map(|event| format!("\"{}\" {}", event.text, describe_event_time(event, now, timezone, locale)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{}\n\nYou also have {}", draft, conflicts)
+}
+
+// at most this many events are listed in the import preview, the rest are only counted
+const IMPORT_PREVIEW_LIMIT: usize = 20;
+
+fn describe_import(events: &[ImportedEvent], now: DateTime<Utc>, timezone: Tz, locale: Locale) -> String {
+    let mut text = format!("Found {} reminders to import:", events.len());
+    for event in events.iter().take(IMPORT_PREVIEW_LIMIT) {
+        let _ = write!(text, "\n{} — {}", event.text, describe_schedule(&event.notification, now, timezone, locale));
+    }
+    if events.len() > IMPORT_PREVIEW_LIMIT {
+        let _ = write!(text, "\n…and {} more", events.len() - IMPORT_PREVIEW_LIMIT);
+    }
+    text
+}
+
+fn local_today(timezone: Tz) -> NaiveDate {
+    Utc::now().with_timezone(&timezone).date_naive()
+}
+
+fn picker_button(text: String, data: CallbackQuery) -> InlineKeyboardButton {
+    InlineKeyboardButton { text, callback_data: data.to_string() }
+}
+
+// month grid of the fallback picker starting on monday, days before today and months before the current one can't be picked
+fn calendar_markup(year: i32, month: u32, today: NaiveDate, locale: Locale) -> InlineKeyboardMarkup {
+    let first = NaiveDate::from_ymd_opt(year, month, 1).unwrap_or(today).max(today.with_day(1).unwrap_or(today));
+    let blank = || picker_button(" ".to_string(), CallbackQuery::Noop);
+    let month_button = |arrow: &str, month: Option<NaiveDate>| match month {
+        Some(month) => picker_button(arrow.to_string(), CallbackQuery::CalendarMonth(month.year(), month.month())),
+        None => blank(),
+    };
+    let mut rows = vec![vec![
+        month_button("‹", first.checked_sub_months(Months::new(1)).filter(|_| first > today)),
+        picker_button(format!("{:02}.{}", first.month(), first.year()), CallbackQuery::Noop),
+        month_button("›", first.checked_add_months(Months::new(1))),
+    ]];
+    rows.push(i18n::weekdays(locale).iter().map(|day| picker_button(day.to_string(), CallbackQuery::Noop)).collect());
+    let mut week = (0..first.weekday().num_days_from_monday()).map(|_| blank()).collect::<Vec<_>>();
+    for date in first.iter_days().take_while(|date| date.month() == first.month()) {
+        week.push(if date < today { picker_button("·".to_string(), CallbackQuery::Noop) } else { picker_button(date.day().to_string(), CallbackQuery::PickDay(date)) });
+        if week.len() == 7 {
+            rows.push(std::mem::take(&mut week));
+        }
+    }
+    if !week.is_empty() {
+        week.resize_with(7, blank);
+        rows.push(week);
+    }
+    rows.push(vec![picker_button(tr(Phrase::Cancel, locale).to_string(), CallbackQuery::Cancel)]);
+    InlineKeyboardMarkup { inline_keyboard: rows }
+}
+
+fn hour_markup(locale: Locale) -> InlineKeyboardMarkup {
+    let mut rows = (0..24).collect::<Vec<u32>>().chunks(6)
+        .map(|hours| hours.iter().map(|hour| picker_button(format!("{:02}", hour), CallbackQuery::PickHour(*hour))).collect())
+        .collect::<Vec<_>>();
+    rows.push(vec![picker_button(tr(Phrase::Cancel, locale).to_string(), CallbackQuery::Cancel)]);
+    InlineKeyboardMarkup { inline_keyboard: rows }
+}
+
+fn minute_markup(locale: Locale) -> InlineKeyboardMarkup {
+    let mut rows = (0..60).step_by(5).collect::<Vec<u32>>().chunks(6)
+        .map(|minutes| minutes.iter().map(|minute| picker_button(format!(":{:02}", minute), CallbackQuery::PickMinute(*minute))).collect())
+        .collect::<Vec<_>>();
+    rows.push(vec![picker_button(tr(Phrase::Cancel, locale).to_string(), CallbackQuery::Cancel)]);
+    InlineKeyboardMarkup { inline_keyboard: rows }
+}
+
+// lists the readings of an ambiguous text by number, the first one is what Accept stores
+fn describe_candidates(notification: &Notification, alternatives: &[Notification], now: DateTime<Utc>, timezone: Tz, locale: Locale) -> String {
+    let mut text = describe_notification(notification, now, timezone, locale);
+    if !alternatives.is_empty() {
+        text = format!("{}\n1. {}", tr(Phrase::SeveralMeanings, locale), text);
+        for (index, alternative) in alternatives.iter().enumerate() {
+            let _ = write!(text, "\n{}. {}", index + 2, describe_notification(alternative, now, timezone, locale));
+        }
+    }
+    text
+}
+
+// a draft with several readings gets a numbered accept button for each of them, a weekly one can skip holidays
+fn draft_markup(options: usize, weekly: bool, context: &DraftContext, locale: Locale) -> InlineKeyboardMarkup {
+    let accept = if options > 1 {
+        (1..=options).map(|option| InlineKeyboardButton {
+            text: i18n::accept_option(option, locale),
+            callback_data: if option == 1 { CallbackQuery::Accept } else { CallbackQuery::Pick(option) }.to_string()
+        }).collect()
+    } else {
+        vec![InlineKeyboardButton {
+            text: tr(Phrase::Accept, locale).to_string(),
+            callback_data: CallbackQuery::Accept.to_string()
+        }]
+    };
+    InlineKeyboardMarkup {
+        inline_keyboard: vec![
+            accept,
+            std::iter::once(InlineKeyboardButton {
+                text: tr(if context.silent { Phrase::WithSound } else { Phrase::Silently }, locale).to_string(),
+                callback_data: CallbackQuery::Silent.to_string()
+            }).chain(weekly.then(|| InlineKeyboardButton {
+                text: tr(if context.skip_holidays { Phrase::OnHolidaysToo } else { Phrase::SkipHolidays }, locale).to_string(),
+                callback_data: CallbackQuery::Holidays.to_string()
+            })).collect(),
+            vec![InlineKeyboardButton {
+                text: tr(Phrase::Edit, locale).to_string(),
+                callback_data: CallbackQuery::Edit.to_string()
+            }],
+            vec![InlineKeyboardButton {
+                text: tr(Phrase::Repeat, locale).to_string(),
+                callback_data: CallbackQuery::Repeat.to_string()
+            }],
+            vec![InlineKeyboardButton {
+                text: tr(Phrase::Cancel, locale).to_string(),
+                callback_data: CallbackQuery::Cancel.to_string()
+            }]
+        ]
+    }
+}
+
+fn fired_text(event: &EventToFire, locale: Locale) -> String {
+    let mut text = if event.lead_minutes > 0 {
+        i18n::lead_reminder(&humanize::format_duration(event.lead_minutes, locale), &event.text, locale)
+    } else {
+        event.text.clone()
+    };
+    if event.overdue_minutes > 0 {
+        text = i18n::overdue_by(&humanize::format_duration(round_overdue(event.overdue_minutes), locale), &text, locale);
+    }
+    if let Some(quote) = event.quote.as_deref() {
+        let cut = if quote.chars().count() > QUOTE_CHARS { "…" } else { "" };
+        let _ = write!(text, "\n» {}{}", quote.chars().take(QUOTE_CHARS).collect::<String>(), cut);
+    }
+    if event.delivery.priority == Priority::Urgent { format!("❗ {}", text) } else { text }
+}
+
+// "overdue by 3 hours" reads better than by 3 hours 7 minutes
+fn round_overdue(minutes: u32) -> u32 {
+    match minutes {
+        0..=59 => minutes,
+        60..=1439 => minutes / 60 * 60,
+        _ => minutes / 1440 * 1440,
+    }
+}
+
+// id of the only result offered to inline queries
+const INLINE_RESULT_ID: &str = "create";
+
+// closed reminders shown by /list closed
+const CLOSED_LIST_LIMIT: u32 = 20;
+
+// forwarded texts are quoted back up to this length
+const QUOTE_CHARS: usize = 300;
+
+// the forwarded message is handed to the parser after the user's own words
+fn with_quote(text: &str, quote: &str) -> String {
+    format!("{}\n\nForwarded message: \"{}\"", text, quote)
+}
+
+fn is_settings_bundle(document: &Document) -> bool {
+    document.mime_type.as_deref() == Some("application/json")
+        || document.file_name.as_deref().is_some_and(|name| name.to_lowercase().ends_with(".json"))
+}
+
+fn is_calendar(document: &Document) -> bool {
+    document.mime_type.as_deref() == Some("text/calendar")
+        || document.file_name.as_deref().is_some_and(|name| name.to_lowercase().ends_with(".ics"))
+}
+
+// days ahead shown by /load
+const LOAD_DAYS: i64 = 14;
+
+const AGENDA_WEEK_DAYS: i64 = 7;
+
+// occurrences /upcoming lists without a count and at most
+const UPCOMING_DEFAULT: usize = 10;
+const UPCOMING_MAX: usize = 50;
+
+const CANCEL_CANDIDATES: usize = 5;
+
+const SEARCH_RESULTS: usize = 10;
+
+// reminders due this soon hold back the next maintenance step
+const MAINTENANCE_DUE_MARGIN_SECS: i64 = 60;
+
+// next moment the clock of the default timezone shows the hour, falls back to utc on nonexistent local times
+fn next_maintenance_time(now: DateTime<Utc>, hour: u32) -> DateTime<Utc> {
+    let today = now.with_timezone(&DEFAULT_TIMEZONE).date_naive();
+    today.iter_days()
+        .filter_map(|date| date.and_hms_opt(hour, 0, 0))
+        .map(|local| DEFAULT_TIMEZONE.from_local_datetime(&local).earliest()
+            .map_or_else(|| Utc.from_utc_datetime(&local), |time| time.with_timezone(&Utc)))
+        .find(|time| *time > now)
+        .unwrap_or(now)
+}
+
+// start of the given day in the timezone, as in "until 05.08" or "05.08.2031";
+// a day without a year that has already passed means the next year
+fn parse_pause_end(arg: &str, now: DateTime<Utc>, timezone: Tz) -> Option<DateTime<Utc>> {
+    let arg = arg.trim();
+    let date = arg.strip_prefix("until").or_else(|| arg.strip_prefix("до")).unwrap_or(arg).trim();
+    let today = now.with_timezone(&timezone).date_naive();
+    let date = match NaiveDate::parse_from_str(date, "%d.%m.%Y") {
+        Ok(date) => date,
+        Err(_) => {
+            let this_year = NaiveDate::parse_from_str(&format!("{}.{}", date, today.year()), "%d.%m.%Y").ok()?;
+            if this_year > today { this_year } else { this_year.with_year(today.year() + 1)? }
+        }
+    };
+    let midnight = date.and_hms_opt(0, 0, 0)?;
+    let end = timezone.from_local_datetime(&midnight).earliest()
+        .map_or_else(|| Utc.from_utc_datetime(&midnight), |time| time.with_timezone(&Utc));
+    (end > now).then_some(end)
+}
+
+// expression and reminder text of /cron, the expression either quoted or as the first five words;
+// phones tend to replace straight quotes with curly ones
+fn split_cron_args(arg: &str) -> Option<(String, String)> {
+    let arg = arg.trim();
+    let (expression, text) = match arg.strip_prefix(['"', '“']) {
+        Some(quoted) => quoted.split_once(['"', '”'])?,
+        None => {
+            let end = arg.match_indices(char::is_whitespace).map(|(index, _)| index).nth(4)?;
+            arg.split_at(end)
+        }
+    };
+    let text = text.trim();
+    (!expression.trim().is_empty() && !text.is_empty()).then(|| (expression.trim().to_string(), text.to_string()))
+}
+
+impl BotHandler {
+    // interactive replies carry a status line while some subsystem is degraded
+    async fn reply(&self, chat_id: u64, text: String, reply_markup: Option<InlineKeyboardMarkup>) -> Result<(), BotError> {
+        self.bot.tg.send_message(chat_id, self.bot.with_status(text), reply_markup).await
+    }
+
+    fn set_state(&self, chat_id: u64, state: State) {
+        if !self.states.compare_and_set(chat_id, self.version, state) {
+            warn!("State of chat {} was changed by another update, dropping stale transition", chat_id);
+        }
+    }
+
+    fn add_draft(&self, chat_id: u64, message_id: u64, draft: Draft) {
+        self.drafts.set((chat_id, message_id), Some(draft));
+        self.drafts.retain_latest(|(draft_chat_id, _)| *draft_chat_id == chat_id, MAX_PENDING_DRAFTS);
+    }
+
+    fn add_draft_source(&self, chat_id: u64, source_message_id: u64, message_id: u64) {
+        self.draft_sources.set((chat_id, source_message_id), Some(message_id));
+        self.draft_sources.retain_latest(|(source_chat_id, _)| *source_chat_id == chat_id, MAX_PENDING_DRAFTS);
+    }
+
+    fn set_draft(&self, slot: DraftSlot, draft: Option<Draft>) -> bool {
+        self.drafts.compare_and_set(slot.key, slot.version, draft)
+    }
+
+    // the filter is a source, a #tag or both, like "/list api #work"
+    async fn list(&self, chat_id: u64, filter: &str) -> Result<(), BotError> {
+        if filter.trim() == "closed" {
+            return self.list_closed(chat_id).await;
+        }
+        let mut source = None;
+        let mut tag = None;
+        for word in filter.split_whitespace() {
+            match word.strip_prefix('#') {
+                Some(name) => tag = Some(name.to_lowercase()),
+                None => match word.parse::<Source>() {
+                    Ok(parsed) => source = Some(parsed),
+                    Err(err) => {
+                        self.reply(chat_id, err.to_string(), None).await?;
+                        return Ok(());
+                    }
+                },
+            }
+        }
+
+        let events = self.bot.event_repository.get_events(chat_id, source, tag).await?;
+        let now = Utc::now();
+        let text = if events.is_empty() {
+            "No active notifications".to_string()
+        } else {
+            events.iter().map(|event| describe_event(event, now, self.timezone, self.locale)).collect::<Vec<_>>().join("\n")
+        };
+        self.reply(chat_id, text, None).await
+    }
+
+    // /list closed: the latest reminders that are over, marked by whether they were done, missed or cancelled
+    async fn list_closed(&self, chat_id: u64) -> Result<(), BotError> {
+        let events = self.bot.event_repository.get_closed_events(chat_id, CLOSED_LIST_LIMIT).await?;
+        let now = Utc::now();
+        let text = if events.is_empty() {
+            tr(Phrase::NoClosedReminders, self.locale).to_string()
+        } else {
+            events.iter()
+                .filter_map(|(event, transition)| Some(format!("{} {}", outcome_mark(Outcome::of(*transition)?), describe_event(event, now, self.timezone, self.locale))))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        self.reply(chat_id, text, None).await
+    }
+
+    // reserves what the completion of a parser call can spend before the call is made, so messages parsed at
+    // the same time count against the budgets together; the prompt tokens aren't known up front, so a call
+    // that starts under a budget can still end past it by the size of its prompt
+    async fn reserve_budget(&self, chat_id: u64, month: &str) -> Result<u64, BotError> {
+        if self.bot.monthly_token_budget.is_none() && self.bot.demo_token_budget.is_none() {
+            return Ok(0);
+        }
+        let tokens = self.bot.parser.max_completion_tokens();
+        let repository = &self.bot.event_repository;
+        if !repository.reserve_usage(chat_id, month.to_string(), tokens, self.bot.monthly_token_budget, self.bot.demo_token_budget).await? {
+            return Err(BotError::BudgetExceeded);
+        }
+        Ok(tokens)
+    }
+
+    // records what the call spent and releases its reservation, a failed call spent nothing
+    async fn settle_budget(&self, chat_id: u64, month: String, reserved: u64, spent: Option<Usage>) -> Result<(), BotError> {
+        let usage = match spent {
+            Some(usage) => usage,
+            None if reserved == 0 => return Ok(()),
+            None => Usage::default(),
+        };
+        self.bot.event_repository.record_usage(chat_id, month, usage, self.bot.parser.cost(usage), reserved).await
+    }
+
+    async fn parse(&self, chat_id: u64, text: &str) -> Result<Notification, BotError> {
+        Ok(self.parse_candidates(chat_id, text).await?.swap_remove(0))
+    }
+
+    // the most likely reading first, then the alternatives of an ambiguous text
+    async fn parse_candidates(&self, chat_id: u64, text: &str) -> Result<Vec<Notification>, BotError> {
+        let now = Utc::now();
+        if let Some(notification) = parser::parse_offset(text, now) {
+            return Ok(vec![notification]);
+        }
+        let month = now.format("%Y-%m").to_string();
+        let _slot = self.parser_slot(chat_id).await?;
+        let corrections = self.bot.event_repository.get_corrections(chat_id).await?;
+        let reserved = self.reserve_budget(chat_id, &month).await?;
+        self.bot.parse_attempts.fetch_add(1, Ordering::Relaxed);
+        let result = self.complete_and_parse(chat_id, now, month, reserved, text, &corrections).await;
+        if result.is_err() {
+            self.bot.parse_failures.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    // waits for a free parser slot, telling the user where they are in line when there is none
+    async fn parser_slot(&self, chat_id: u64) -> Result<ParserPermit, BotError> {
+        match self.bot.parser_queue.enter(chat_id) {
+            Admission::Granted(permit) => Ok(permit),
+            Admission::Queued { position, ticket } => {
+                let text = match self.locale {
+                    Locale::En => format!("Busy right now, you are #{} in line", position),
+                    Locale::Ru => format!("Сейчас много запросов, вы {}-й в очереди", position),
+                    Locale::He => format!("יש עומס כרגע, מקומך בתור: {}", position),
+                };
+                self.reply(chat_id, text, None).await?;
+                Ok(ticket.wait().await)
+            }
+        }
+    }
+
+    // pasted emails and the like are cut down to the reminder-relevant sentence, short messages are kept as is
+    async fn summarize_if_long(&self, chat_id: u64, text: String) -> Result<(String, Option<String>), BotError> {
+        if text.chars().count() <= self.bot.summarize_threshold {
+            return Ok((text, None));
+        }
+        let month = Utc::now().format("%Y-%m").to_string();
+        let _slot = self.parser_slot(chat_id).await?;
+        let reserved = self.reserve_budget(chat_id, &month).await?;
+        let completion = self.bot.parser.summarize(&text).await;
+        self.bot.subsystems.record(Subsystem::Parser, &completion);
+        self.settle_budget(chat_id, month, reserved, completion.as_ref().ok().map(|completion| completion.usage)).await?;
+        let completion = completion?;
+        if self.bot.log_redact {
+            info!("Summarized {} characters into {}", text.chars().count(), completion.content.chars().count());
+        } else {
+            info!("Summarized {} characters into {:?}", text.chars().count(), completion.content);
+        }
+        Ok((completion.content.clone(), Some(completion.content)))
+    }
+
+    async fn complete_and_parse(&self, chat_id: u64, now: DateTime<Utc>, month: String, reserved: u64, text: &str,
+                                corrections: &[ParseCorrection]) -> Result<Vec<Notification>, BotError> {
+        let completion = self.bot.parser.complete(chat_id, now, self.timezone, text, corrections).await;
+        self.bot.subsystems.record(Subsystem::Parser, &completion);
+        self.settle_budget(chat_id, month, reserved, completion.as_ref().ok().map(|completion| completion.usage)).await?;
+        let completion = completion?;
+        on_wall_clock(self.timezone, || LlmParser::parse_candidates(&completion.content))
+    }
+
+    // while the offline parser is not trusted with a text, this shows how often it would have been right
+    async fn debug_command(&self, chat_id: u64, arg: &str) -> Result<(), BotError> {
+        let parser_debug = match arg {
+            "on" => true,
+            "off" => false,
+            "" => !self.bot.parser_debug.load(Ordering::Relaxed),
+            _ => return self.reply(chat_id, "Usage: /debug [on|off]".to_string(), None).await,
+        };
+        self.bot.parser_debug.store(parser_debug, Ordering::Relaxed);
+        let reply = match (parser_debug, &self.bot.eval_corpus) {
+            (true, Some(path)) => format!("Debug is on: drafts show the offline parser's reading where it differs, every difference goes to {}", path.display()),
+            (true, None) => "Debug is on: drafts show the offline parser's reading where it differs, set PARSER_EVAL_CORPUS to keep them".to_string(),
+            (false, _) => "Debug is off".to_string(),
+        };
+        self.reply(chat_id, reply, None).await
+    }
+
+    // with /debug on, the reading of the offline parser when the model's one differs or the model failed;
+    // it goes to the eval corpus for every user and is shown below the draft to the admin only
+    async fn offline_reading(&self, text: &str, model: Option<&Notification>) -> Option<String> {
+        if !self.bot.parser_debug.load(Ordering::Relaxed) {
+            return None;
+        }
+        let now = Utc::now();
+        let offline = parser::parse_offline(text, now, self.timezone)?;
+        let stored = offline.create_stored_notifications(now, self.timezone);
+        if model.is_some_and(|model| model.create_stored_notifications(now, self.timezone) == stored) {
+            return None;
+        }
+        if let Some(path) = &self.bot.eval_corpus {
+            let disagreement = Disagreement { text, model, offline: &offline, timezone: self.timezone, parsed_at: now };
+            if let Err(err) = eval::append(path, &disagreement).await {
+                error!("Couldn't add to the eval corpus: {}", err);
+            }
+        }
+        (self.role == Role::Admin).then(|| describe_stored(offline.get_text(), &stored, now, self.timezone, self.locale))
+    }
+
+    async fn stats_command(&self, chat_id: u64) -> Result<(), BotError> {
+        let stats = self.bot.event_repository.get_stats().await?;
+        let size = self.bot.event_repository.database_size().await?;
+        let uptime = Utc::now() - self.bot.started_at;
+        let attempts = self.bot.parse_attempts.load(Ordering::Relaxed);
+        let failures = self.bot.parse_failures.load(Ordering::Relaxed);
+        let failure_rate = if attempts == 0 { 0.0 } else { failures as f64 * 100.0 / attempts as f64 };
+        let queue = self.bot.parser_queue.stats();
+        let reply = format!("Uptime: {}d {}h {}m\n\
+            Pending reminders: {} (dead letters: {})\n\
+            Users with reminders: {} of {}\n\
+            Parse failures: {} of {} ({:.1}%)\n\
+            Parser queue: {} waiting, {} queued so far, average wait {} ms (max {} ms)\n\
+            Database size: {:.1} MB\n\
+            Last maintenance: {}",
+            uptime.num_days(), uptime.num_hours() % 24, uptime.num_minutes() % 60,
+            stats.pending_events, stats.dead_letters,
+            stats.active_users, self.bot.user_repository.user_ids().len(),
+            failures, attempts, failure_rate,
+            queue.waiting, queue.queued, queue.average_wait.as_millis(), queue.max_wait.as_millis(),
+            size as f64 / (1024.0 * 1024.0),
+            describe_maintenance(self.bot.last_maintenance(), Utc::now(), self.timezone, self.locale));
+        self.reply(chat_id, reply, None).await
+    }
+
+    // progress of the checkpointed background jobs, the same as `notify-rs jobs` shows offline
+    async fn jobs_command(&self, chat_id: u64) -> Result<(), BotError> {
+        let now = Utc::now();
+        let jobs = self.bot.event_repository.get_jobs().await?;
+        let reply = if jobs.is_empty() {
+            "No background jobs".to_string()
+        } else {
+            jobs.iter()
+                .map(|job| format!("{}: {}, {} processed, checkpoint {}, updated {}", job.name, job.status, job.processed, job.checkpoint,
+                                   humanize::format_time(job.updated_at, now, self.timezone, self.locale)))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        self.reply(chat_id, reply, None).await
+    }
+
+    async fn broadcast_command(&self, chat_id: u64, text: &str) -> Result<(), BotError> {
+        if text.is_empty() {
+            return self.reply(chat_id, "Usage: /broadcast <text>".to_string(), None).await;
+        }
+        let mut delivered = 0;
+        let mut total = 0;
+        for user_id in self.bot.user_repository.user_ids() {
+            total += 1;
+            match self.reply(user_id, text.to_string(), None).await {
+                Ok(_) => delivered += 1,
+                Err(err) => warn!("Broadcast to {} failed: {}", user_id, err),
+            }
+        }
+        self.reply(chat_id, format!("Broadcast delivered to {} of {} users", delivered, total), None).await
+    }
+
+    async fn role_command(&self, chat_id: u64, args: &[&str]) -> Result<(), BotError> {
+        let (user_id, role) = match args {
+            [user_id, role] => (user_id.parse::<u64>()?, role.parse::<Role>()?),
+            _ => return self.reply(chat_id, "Usage: /role <user_id> <admin|user|read-only>".to_string(), None).await,
+        };
+        self.bot.event_repository.set_user_role(user_id, role).await?;
+        self.bot.user_repository.set_role(user_id, role);
+        self.reply(chat_id, format!("User {} is now {}", user_id, role.as_str()), None).await
+    }
+
+    // the optional timezone is told apart from the name by being a valid tz database name
+    async fn add_user_command(&self, chat_id: u64, args: &[&str]) -> Result<(), BotError> {
+        let (user_id, rest) = match args {
+            [user_id, rest @ ..] => (user_id.parse::<u64>()?, rest),
+            _ => return self.reply(chat_id, "Usage: /adduser <user_id> [timezone] [name]".to_string(), None).await,
+        };
+        let (timezone, name) = match rest {
+            [timezone, name @ ..] if timezone.parse::<chrono_tz::Tz>().is_ok() => (Some(timezone.to_string()), name),
+            name => (None, name),
+        };
+        let display_name = (!name.is_empty()).then(|| name.join(" "));
+        let created = self.bot.event_repository.upsert_user(user_id, display_name, timezone).await?;
+        self.bot.user_repository.add(user_id);
+        let reply = if created { format!("User {} added", user_id) } else { format!("User {} updated", user_id) };
+        self.reply(chat_id, reply, None).await
+    }
+
+    async fn remove_user_command(&self, chat_id: u64, args: &[&str]) -> Result<(), BotError> {
+        let user_id = match args {
+            [user_id] => user_id.parse::<u64>()?,
+            _ => return self.reply(chat_id, "Usage: /removeuser <user_id>".to_string(), None).await,
+        };
+        if user_id == chat_id || Some(user_id) == self.bot.admin_id {
+            return self.reply(chat_id, "The admin can't be removed".to_string(), None).await;
+        }
+        let reply = if self.bot.event_repository.remove_user(user_id).await? {
+            format!("User {} removed, their reminders are kept until purged", user_id)
+        } else {
+            format!("User {} has no access", user_id)
+        };
+        self.bot.user_repository.remove(user_id);
+        self.reply(chat_id, reply, None).await
+    }
+
+    async fn webhook_command(&self, chat_id: u64, args: &[&str]) -> Result<(), BotError> {
+        let reply = match args {
+            ["add", name, url] => match webhooks::check_url(url) {
+                Ok(_) => {
+                    self.bot.event_repository.upsert_webhook(chat_id, name.to_string(), url.to_string()).await?;
+                    format!("Webhook \"{}\" saved", name)
+                }
+                Err(err) => err.to_string(),
+            },
+            ["remove", name] => {
+                if self.bot.event_repository.remove_webhook(chat_id, name.to_string()).await? {
+                    format!("Webhook \"{}\" removed", name)
+                } else {
+                    format!("No webhook named \"{}\"", name)
+                }
+            }
+            [] => {
+                let webhooks = self.bot.event_repository.get_webhooks(chat_id).await?;
+                if webhooks.is_empty() {
+                    "No webhooks, add one with /webhook add <name> <url>".to_string()
+                } else {
+                    webhooks.iter().map(|w| format!("{} — {}", w.name, w.url)).collect::<Vec<_>>().join("\n")
+                }
+            }
+            _ => "Usage: /webhook [add <name> <url> | remove <name>]".to_string(),
+        };
+        self.reply(chat_id, reply, None).await
+    }
+
+    async fn trigger_command(&self, chat_id: u64, name: &str) -> Result<(), BotError> {
+        let reply = match self.bot.event_repository.get_webhook(chat_id, name.to_string()).await? {
+            Some(webhook) => match self.bot.run_webhook(chat_id, &webhook, None, None).await {
+                Ok(status) => format!("Webhook \"{}\" responded with {}", name, status),
+                Err(err) => format!("Webhook \"{}\" failed: {}", name, err),
+            },
+            None => format!("No webhook named \"{}\"", name),
+        };
+        self.reply(chat_id, reply, None).await
+    }
+
+    async fn attach_command(&self, chat_id: u64, args: &[&str]) -> Result<(), BotError> {
+        let reply = match args {
+            [event_uid, name] => {
+                if self.bot.event_repository.attach_webhook(chat_id, event_uid.to_string(), name.to_string()).await? {
+                    format!("Webhook \"{}\" will be called when the reminder fires", name)
+                } else {
+                    "Unknown reminder or webhook".to_string()
+                }
+            }
+            _ => "Usage: /attach <reminder id> <webhook name>".to_string(),
+        };
+        self.reply(chat_id, reply, None).await
+    }
+
+    async fn history_command(&self, chat_id: u64, event_uid: &str) -> Result<(), BotError> {
+        if event_uid.is_empty() {
+            return self.reply(chat_id, "Usage: /history <reminder id>".to_string(), None).await;
+        }
+        let history = self.bot.event_repository.get_event_history(chat_id, event_uid.to_string()).await?;
+        let now = Utc::now();
+        let reply = if history.is_empty() {
+            "No history found for this reminder".to_string()
+        } else {
+            let mut lines = history.iter()
+                .map(|entry| format!("{} — {}", humanize::format_time(entry.at, now, self.timezone, self.locale), entry.transition.as_str()))
+                .collect::<Vec<_>>();
+            lines.extend(Outcome::from_history(&history).map(|outcome| describe_outcome(outcome, self.locale).to_string()));
+            lines.join("\n")
+        };
+        self.reply(chat_id, reply, None).await
+    }
+
+    // occurrences from now until midnight of the user's timezone after the last of `days` days, today included
+    async fn agenda_command(&self, chat_id: u64, days: i64) -> Result<(), BotError> {
+        let timezone = self.timezone;
+        let now = Utc::now();
+        let end = now.with_timezone(&timezone).date_naive() + chrono::Duration::days(days);
+        let midnight = end.and_hms_opt(0, 0, 0).unwrap_or_default();
+        // a midnight skipped by a dst change falls back to the utc one, the agenda is only a bit longer then
+        let to = timezone.from_local_datetime(&midnight).earliest()
+            .map_or_else(|| Utc.from_utc_datetime(&midnight), |to| to.with_timezone(&Utc));
+        let events = self.bot.event_repository.get_events(chat_id, None, None).await?;
+        let exclusions = self.bot.event_repository.get_exclusions(chat_id).await?;
+        let holidays = self.bot.event_repository.get_holidays(now.date_naive() - chrono::Duration::days(1)).await?;
+        let entries = agenda::occurrences(&events, &exclusions, &holidays, now, to).into_iter()
+            .map(|(time, event)| (time, event.text.as_str()))
+            .collect::<Vec<_>>();
+        let heading = match (self.locale, days) {
+            (Locale::En, 1) => "Today:".to_string(),
+            (Locale::Ru, 1) => "Сегодня:".to_string(),
+            (Locale::En, days) => format!("Next {} days:", days),
+            (Locale::Ru, days) => format!("Ближайшие {} {}:", days, humanize::ru_days(days)),
+            (Locale::He, 1) => "היום:".to_string(),
+            (Locale::He, days) => format!("{} הימים הקרובים:", days),
+        };
+        self.reply(chat_id, humanize::format_agenda(&heading, &entries, timezone, self.locale), None).await
+    }
+
+    // /upcoming [N]: the next N times anything fires, shown on the user's wall clock to check weekly and cron schedules
+    async fn upcoming_command(&self, chat_id: u64, arg: &str) -> Result<(), BotError> {
+        let count = match arg {
+            "" => UPCOMING_DEFAULT,
+            arg => match arg.parse::<usize>() {
+                Ok(count @ 1..=UPCOMING_MAX) => count,
+                _ => return self.reply(chat_id, format!("Usage: /upcoming [1-{}]", UPCOMING_MAX), None).await,
+            },
+        };
+        let timezone = self.timezone;
+        let events = self.bot.event_repository.get_events(chat_id, None, None).await?;
+        let exclusions = self.bot.event_repository.get_exclusions(chat_id).await?;
+        let now = Utc::now();
+        let holidays = self.bot.event_repository.get_holidays(now.date_naive() - chrono::Duration::days(1)).await?;
+        let entries = agenda::upcoming(&events, &exclusions, &holidays, now, count, timezone).into_iter()
+            .map(|(time, event)| (time, event.text.as_str()))
+            .collect::<Vec<_>>();
+        let heading = i18n::upcoming_heading(count, timezone.name(), self.locale);
+        self.reply(chat_id, humanize::format_agenda(&heading, &entries, timezone, self.locale), None).await
+    }
+
+    // offers the reminders matching the text, nothing is deleted until one of them is picked
+    async fn cancel_command(&self, chat_id: u64, query: &str) -> Result<(), BotError> {
+        if query.trim().is_empty() {
+            return self.reply(chat_id, "Tell me which reminder to cancel, like /cancel dentist".to_string(), None).await;
+        }
+        let candidates = self.bot.event_repository.search_events(chat_id, query.to_string(), CANCEL_CANDIDATES).await?;
+        if candidates.is_empty() {
+            return self.reply(chat_id, format!("No reminders match \"{}\"", query), None).await;
+        }
+        let now = Utc::now();
+        let markup = InlineKeyboardMarkup {
+            inline_keyboard: candidates.iter()
+                .map(|(_, event)| vec![InlineKeyboardButton {
+                    text: format!("{} — {}", event.text, describe_event_time(event, now, self.timezone, self.locale)),
+                    callback_data: CallbackQuery::Forget(event.uid.clone()).to_string(),
+                }])
+                .collect()
+        };
+        self.bot.send_with_markup(chat_id, self.bot.with_status("Which reminder should I cancel?".to_string()), markup, self.plain).await?;
+        Ok(())
+    }
+
+    // pending results can be cancelled and past ones scheduled again right from the list
+    async fn search_command(&self, chat_id: u64, query: &str) -> Result<(), BotError> {
+        if query.trim().is_empty() {
+            return self.reply(chat_id, "Tell me what to look for, like /search dentist".to_string(), None).await;
+        }
+        let found = self.bot.event_repository.search_text(chat_id, query, SEARCH_RESULTS).await?;
+        if found.is_empty() {
+            return self.reply(chat_id, format!("No reminders match \"{}\"", query), None).await;
+        }
+        let now = Utc::now();
+        let text = found.iter()
+            .map(|(_, event)| {
+                let line = describe_event(event, now, self.timezone, self.locale);
+                if event.is_deleted { format!("{} (done)", line) } else { line }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let markup = InlineKeyboardMarkup {
+            inline_keyboard: found.iter()
+                .map(|(_, event)| vec![if event.is_deleted {
+                    InlineKeyboardButton { text: format!("Remind again: {}", event.text), callback_data: CallbackQuery::RemindAgain(event.uid.clone()).to_string() }
+                } else {
+                    InlineKeyboardButton { text: format!("Cancel: {}", event.text), callback_data: CallbackQuery::Forget(event.uid.clone()).to_string() }
+                }])
+                .collect()
+        };
+        self.bot.send_with_markup(chat_id, self.bot.with_status(text), markup, self.plain).await?;
+        Ok(())
+    }
+
+    async fn forget(&self, callback_query: &crate::models::CallbackQuery, event_id: u64) -> Result<String, BotError> {
+        let event = self.bot.event_repository.get_event(callback_query.from.id, event_id).await?.ok_or(BotError::InvalidCallbackQuery)?;
+        let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
+        if event.is_deleted {
+            self.bot.edit_markup(message.chat.id, message.message_id, None, self.plain).await?;
+            return Ok(tr(Phrase::AlreadyCancelled, self.locale).to_string());
+        }
+        let ids = self.bot.event_repository.delete_by_text(callback_query.from.id, event.text.clone()).await?;
+        self.bot.event_repository.record_action(callback_query.from.id, format!("cancelling \"{}\"", event.text), ids, Transition::Deleted).await?;
+        self.bot.edit_with_markup(message.chat.id, message.message_id, i18n::cancelled(&event.text, self.locale), None, self.plain).await?;
+        Ok(tr(Phrase::NotificationDeleted, self.locale).to_string())
+    }
+
+    // stops the repeats of an urgent or nagging reminder, the remind again button stays
+    async fn done(&self, callback_query: &crate::models::CallbackQuery, event_id: u64, event_uid: &str) -> Result<String, BotError> {
+        let acknowledged = self.bot.event_repository.acknowledge(callback_query.from.id, event_id).await?;
+        let completed = self.bot.event_repository.complete(callback_query.from.id, event_id, Utc::now()).await?;
+        self.bot.unpin_fired(callback_query.from.id, event_id).await?;
+        let is_recurrent = self.bot.event_repository.get_event(callback_query.from.id, event_id).await?
+            .is_some_and(|event| matches!(event.kind, Kind::Recurrent) && !event.is_deleted);
+        let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
+        let markup = replace_event_rows(message.reply_markup.as_ref(), event_uid, Some(remind_again_markup(event_uid, false, is_recurrent, self.locale)));
+        self.bot.edit_markup(message.chat.id, message.message_id, markup, self.plain).await?;
+        Ok(tr(if acknowledged || completed { Phrase::MarkedAsDone } else { Phrase::AlreadyDone }, self.locale).to_string())
+    }
+
+    // excludes the next occurrence of a recurrent reminder, every press skips one more
+    async fn skip(&self, callback_query: &crate::models::CallbackQuery, event_id: u64) -> Result<String, BotError> {
+        let now = Utc::now();
+        match self.bot.event_repository.skip_next_occurrence(callback_query.from.id, event_id, now).await? {
+            Some(time) => Ok(i18n::skipping(&humanize::format_time(time, now, self.timezone, self.locale), self.locale)),
+            None => Ok(tr(Phrase::NotRepeating, self.locale).to_string()),
+        }
+    }
+
+    async fn load_command(&self, chat_id: u64) -> Result<(), BotError> {
+        let now = Utc::now();
+        let offset = humanize::offset_minutes(self.timezone, now);
+        let today = (now + chrono::Duration::minutes(offset as i64)).date_naive();
+        // one more day is fetched, the last listed day is only over at local midnight
+        let load = self.bot.event_repository.get_load(chat_id, now, now + chrono::Duration::days(LOAD_DAYS + 1), offset).await?;
+        let days = today.iter_days()
+            .take(LOAD_DAYS as usize)
+            .map(|date| (date, load.count(date)))
+            .collect::<Vec<_>>();
+        self.reply(chat_id, humanize::format_load(&days, self.locale), None).await
+    }
+
+    async fn export_command(&self, chat_id: u64, arg: &str) -> Result<(), BotError> {
+        match arg {
+            "" => {}
+            "settings" => return self.export_settings(chat_id).await,
+            _ => return self.reply(chat_id, "Usage: /export [settings]".to_string(), None).await,
+        }
+        let events = self.bot.event_repository.get_events(chat_id, None, None).await?;
+        if events.is_empty() {
+            return self.reply(chat_id, "No active notifications".to_string(), None).await;
+        }
+        let calendar = ics::render_calendar(&events, Utc::now(), self.timezone);
+        self.bot.tg.send_document(chat_id, "reminders.ics", "text/calendar", calendar.into_bytes()).await
+    }
+
+    async fn export_settings(&self, chat_id: u64) -> Result<(), BotError> {
+        let bundle = SettingsBundle {
+            schema: bundle::SCHEMA,
+            exported_at: Utc::now(),
+            settings: self.bot.event_repository.get_user_settings(chat_id).await?,
+            webhooks: self.bot.event_repository.get_webhooks(chat_id).await?,
+            routes: self.bot.event_repository.get_webhook_routes(chat_id).await?,
+        };
+        self.bot.tg.send_document(chat_id, bundle::FILE_NAME, "application/json", serde_json::to_vec_pretty(&bundle)?).await
+    }
+
+    async fn import_settings(&self, chat_id: u64, document: &Document) -> Result<(), BotError> {
+        let content = self.bot.tg.download_file(&document.file_id).await?;
+        let bundle = match SettingsBundle::parse(&content) {
+            Ok(bundle) => bundle,
+            Err(err) => return self.reply(chat_id, err.to_string(), None).await,
+        };
+        let webhooks = bundle.webhooks.len();
+        let attached = self.bot.event_repository.import_settings(chat_id, bundle.settings, bundle.webhooks, bundle.routes).await?;
+        self.reply(chat_id, format!("Settings imported: {} webhooks, attached to {} reminders", webhooks, attached), None).await
+    }
+
+    // returns false when the text is not a known command and should be parsed as a reminder
+    async fn handle_command(&self, chat_id: u64, text: &str) -> Result<bool, BotError> {
+        let mut words = text.split_whitespace();
+        let typed = words.next().unwrap_or_default();
+        let args = words.collect::<Vec<_>>();
+        let command = match commands::resolve(typed, self.locale) {
+            Resolution::Known(command) => command,
+            Resolution::DidYouMean(suggestion) => {
+                let reply = match self.locale {
+                    Locale::En => format!("Unknown command {}, did you mean {}?", typed, suggestion),
+                    Locale::Ru => format!("Неизвестная команда {}, возможно, вы имели в виду {}?", typed, suggestion),
+                    Locale::He => format!("הפקודה {} לא מוכרת, אולי התכוונת ל-{}?", typed, suggestion),
+                };
+                self.reply(chat_id, reply, None).await?;
+                return Ok(true);
+            }
+            Resolution::Unknown => return Ok(false),
+        };
+        match command {
+            "/stats" | "/jobs" | "/broadcast" | "/role" | "/reload_users" | "/adduser" | "/removeuser" | "/debug" if self.role != Role::Admin =>
+                self.reply(chat_id, "This command is only available to admins".to_string(), None).await?,
+            "/webhook" | "/trigger" | "/attach" | "/cancel" | "/undo" | "/remind" | "/cron" | "/template" | "/connect_calendar" if !self.role.can_create() =>
+                self.reply(chat_id, tr(Phrase::ReadOnly, self.locale).to_string(), None).await?,
+            // visitors shouldn't get the bot to call arbitrary urls
+            "/webhook" | "/trigger" | "/attach" if self.bot.is_demo() =>
+                self.reply(chat_id, "Webhooks are not available in the demo".to_string(), None).await?,
+            // setting a timezone stores the user, which would outlive the demo access
+            "/timezone" if self.bot.is_demo() =>
+                self.reply(chat_id, "Timezones are not available in the demo".to_string(), None).await?,
+            // the wipe doesn't reach calendars reminders were pushed to
+            "/connect_calendar" if self.bot.is_demo() =>
+                self.reply(chat_id, "Google Calendar is not available in the demo".to_string(), None).await?,
+            "/start" => {
+                let mut text = "Send me what to remind you about and when, like \"call mom tomorrow at 10\"".to_string();
+                if self.bot.is_demo() {
+                    text = format!("{}\n\n{}", text, demo::NOTICE);
+                }
+                self.reply(chat_id, text, None).await?
+            }
+            "/list" => self.list(chat_id, &args.join(" ")).await?,
+            "/cancel" => self.cancel_command(chat_id, &args.join(" ")).await?,
+            "/search" => self.search_command(chat_id, &args.join(" ")).await?,
+            "/status" => self.reply(chat_id, self.bot.subsystems.describe(Utc::now().timestamp()), None).await?,
+            "/today" => self.agenda_command(chat_id, 1).await?,
+            "/week" => self.agenda_command(chat_id, AGENDA_WEEK_DAYS).await?,
+            "/upcoming" => self.upcoming_command(chat_id, &args.join(" ")).await?,
+            "/load" => self.load_command(chat_id).await?,
+            "/webhook" => self.webhook_command(chat_id, &args).await?,
+            "/trigger" => self.trigger_command(chat_id, &args.join(" ")).await?,
+            "/attach" => self.attach_command(chat_id, &args).await?,
+            "/history" => self.history_command(chat_id, &args.join(" ")).await?,
+            "/export" => self.export_command(chat_id, &args.join(" ")).await?,
+            "/plain" => self.plain_command(chat_id, &args.join(" ")).await?,
+            "/language" => self.language_command(chat_id, &args.join(" ")).await?,
+            "/timezone" => self.timezone_command(chat_id, &args.join(" ")).await?,
+            "/connect_calendar" => self.connect_calendar_command(chat_id, &args.join(" ")).await?,
+            "/remind" => self.remind_command(chat_id, &args.join(" ")).await?,
+            "/cron" => self.cron_command(chat_id, &args.join(" ")).await?,
+            "/template" => self.template_command(chat_id, &args).await?,
+            "/stats" => self.stats_command(chat_id).await?,
+            "/jobs" => self.jobs_command(chat_id).await?,
+            "/debug" => self.debug_command(chat_id, &args.join(" ")).await?,
+            "/broadcast" => self.broadcast_command(chat_id, text.trim_start().split_once(char::is_whitespace).map_or("", |(_, rest)| rest.trim())).await?,
+            "/role" => self.role_command(chat_id, &args).await?,
+            "/adduser" => self.add_user_command(chat_id, &args).await?,
+            "/removeuser" => self.remove_user_command(chat_id, &args).await?,
+            "/pause" => self.pause_command(chat_id, &args.join(" ")).await?,
+            "/resume" => self.resume_command(chat_id, &args.join(" ")).await?,
+            "/quiet" => self.quiet_command(chat_id, &args.join(" ")).await?,
+            "/undo" => self.undo_command(chat_id).await?,
+            "/reload_users" => {
+                let reply = match self.bot.reload_users().await {
+                    Ok((added, removed)) => format!("Allowed users reloaded: {} added, {} removed", added, removed),
+                    Err(err) => format!("Couldn't reload allowed users, the old list is kept: {}", err),
+                };
+                self.reply(chat_id, reply, None).await?
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // turns a number sent in plain mode into the callback of the button it stands for
+    fn pick_plain_choice(&self, message: &Message) -> Option<crate::models::CallbackQuery> {
+        if !self.plain {
+            return None;
+        }
+        let chat_id = message.chat.id;
+        let (_, choices) = self.bot.plain_choices.get(chat_id);
+        let data = render::pick(message.text.as_deref()?, &choices)?;
+        self.bot.plain_choices.set(chat_id, PlainChoices::default());
+        Some(crate::models::CallbackQuery {
+            id: String::new(),
+            from: User { id: chat_id, language_code: None, username: None, first_name: None },
+            chat: Some(message.chat),
+            message: Some(Message {
+                message_id: choices.message_id,
+                date: message.date,
+                chat: message.chat,
+                from: None,
+                text: None,
+                document: None,
+                business_connection_id: None,
+                reply_markup: None,
+                location: None,
+                venue: None,
+                caption: None,
+                forward_origin: None,
+            }),
+            data: Some(data),
+        })
+    }
+
+    async fn plain_command(&self, chat_id: u64, arg: &str) -> Result<(), BotError> {
+        let plain_mode = match arg {
+            "on" => true,
+            "off" => false,
+            "" => !self.plain,
+            _ => return self.reply(chat_id, "Usage: /plain [on|off]".to_string(), None).await,
+        };
+        self.bot.event_repository.set_plain_mode(chat_id, plain_mode).await?;
+        let reply = if plain_mode {
+            "Plain mode is on: buttons are replaced with numbered options, reply with a number to choose one"
+        } else {
+            "Plain mode is off"
+        };
+        self.reply(chat_id, reply.to_string(), None).await
+    }
+
+    // sends the link to google's consent screen, the api finishes the connection when google sends the user back
+    async fn connect_calendar_command(&self, chat_id: u64, arg: &str) -> Result<(), BotError> {
+        let Some(calendar) = &self.bot.calendar else {
+            return self.reply(chat_id, "Google Calendar sync is not set up on this bot".to_string(), None).await;
+        };
+        let directions = match arg {
+            "" => SyncDirections { push: true, pull: true },
+            "push" => SyncDirections { push: true, pull: false },
+            "pull" => SyncDirections { push: false, pull: true },
+            "off" => {
+                let reply = if self.bot.event_repository.remove_calendar_connection(chat_id).await? {
+                    "Google Calendar disconnected"
+                } else {
+                    "Google Calendar is not connected"
+                };
+                return self.reply(chat_id, reply.to_string(), None).await;
+            }
+            _ => return self.reply(chat_id, "Usage: /connect_calendar [push|pull|off]".to_string(), None).await,
+        };
+        let url = calendar.authorization_url(chat_id, directions, Utc::now())?;
+        let reply = format!("Open this link within 15 minutes to let me use your Google Calendar:\n{}\n\n\
+            Sync only adds: cancelling a reminder here keeps its event in the calendar, and deleting an event there keeps its reminder", url);
+        self.reply(chat_id, reply, None).await
+    }
+
+    // google answers slower than telegram, the reply to the user doesn't wait for the push
+    fn spawn_calendar_push(&self, user_id: u64, event_ids: Vec<u64>) {
+        if self.bot.calendar.is_none() {
+            return;
+        }
+        let (bot, timezone) = (self.bot.clone(), self.timezone);
+        tokio::spawn(async move {
+            if let Err(err) = bot.push_to_calendar(user_id, &event_ids, timezone).await {
+                error!("Pushing reminders of {} to google calendar failed: {}", user_id, err);
+            }
+        });
+    }
+
+    // /timezone <name> sets it by its tz database name, without one the next location pin sets it
+    async fn timezone_command(&self, chat_id: u64, arg: &str) -> Result<(), BotError> {
+        if arg.is_empty() {
+            self.set_state(chat_id, State::AwaitingTimezone);
+            return self.reply(chat_id, format!("{}\n{}", i18n::timezone_is(self.timezone.name(), self.locale), tr(Phrase::SendLocationForTimezone, self.locale)), None).await;
+        }
+        match arg.parse::<chrono_tz::Tz>() {
+            Ok(timezone) => self.set_timezone(chat_id, timezone).await,
+            Err(_) => self.reply(chat_id, "Usage: /timezone [name], like /timezone Europe/Berlin".to_string(), None).await,
+        }
+    }
+
+    // the zone of a pin is the one of the closest known city, which can be wrong near a border, so it's only offered
+    async fn confirm_timezone(&self, chat_id: u64, timezone: chrono_tz::Tz) -> Result<(), BotError> {
+        let option = |text: &str, data: CallbackQuery| vec![InlineKeyboardButton { text: text.to_string(), callback_data: data.to_string() }];
+        let markup = InlineKeyboardMarkup {
+            inline_keyboard: vec![
+                option(tr(Phrase::Accept, self.locale), CallbackQuery::Timezone(timezone)),
+                option(tr(Phrase::Cancel, self.locale), CallbackQuery::Cancel),
+            ]
+        };
+        self.bot.send_with_markup(chat_id, self.bot.with_status(i18n::timezone_found(timezone.name(), self.locale)), markup, self.plain).await?;
+        Ok(())
+    }
+
+    // weekly reminders created from now on are kept on the wall clock of the new timezone
+    async fn set_timezone(&self, chat_id: u64, timezone: chrono_tz::Tz) -> Result<(), BotError> {
+        self.set_state(chat_id, State::Idle);
+        self.bot.event_repository.upsert_user(chat_id, None, Some(timezone.name().to_string())).await?;
+        self.reply(chat_id, i18n::timezone_set(timezone.name(), self.locale), None).await
+    }
+
+    // auto goes back to the language of the telegram client
+    async fn language_command(&self, chat_id: u64, arg: &str) -> Result<(), BotError> {
+        let language = match arg {
+            "auto" => None,
+            code => match code.parse::<Locale>() {
+                Ok(locale) => Some(locale),
+                Err(_) => return self.reply(chat_id, "Usage: /language [en|ru|he|auto]".to_string(), None).await,
+            },
+        };
+        self.bot.event_repository.set_language(chat_id, language).await?;
+        self.reply(chat_id, i18n::language_set(language).to_string(), None).await
+    }
 
-impl BotDeps {
-    pub async fn new(env: &Env) -> Result<BotDeps, BotError> {
-        let event_repository = EventRepository::new(&env.connection_string).await?;
-        let user_repository = UserRepository::new(env.user_ids.iter().copied());
-        let parser = OpenAIParser::new(env.openai_token.to_string());
-        let tg = Tg::new(env.bot_token.to_string());
-        Ok(BotDeps { user_repository, event_repository, parser, tg })
+    // schedules the llm kinds can't express, evaluated by the background loop like any other reminder
+    // a reminder in a fixed syntax is stored right away, without a draft and without a request to the model
+    async fn remind_command(&self, chat_id: u64, arg: &str) -> Result<(), BotError> {
+        let usage = "Usage: /remind <dd.mm[.yyyy]> <hh:mm> <text> or /remind every <mon,wed|day|workday [except holidays]> <hh:mm> <text>, like /remind 21.07 15:00 call mom";
+        let now = Utc::now();
+        let Some(notification) = parser::parse_remind(arg, now, self.timezone) else {
+            return self.reply(chat_id, usage.to_string(), None).await;
+        };
+        let notifications = notification.create_stored_notifications(now, self.timezone);
+        if notifications.iter().all(|notification| matches!(notification, StoredNotification::Absolute { time } if time <= &now)) {
+            return self.reply(chat_id, tr(Phrase::TimePassed, self.locale).to_string(), None).await;
+        }
+        let text = notification.get_text().to_string();
+        let reply = describe_stored(&text, &notifications, now, self.timezone, self.locale);
+        let ids = self.bot.event_repository.insert_event_with_delivery(chat_id, text.clone(), Source::Telegram, notification.get_delivery(), notifications).await?;
+        self.bot.event_repository.record_action(chat_id, format!("adding \"{}\"", text), ids.clone(), Transition::Created).await?;
+        if notification.skips_holidays() {
+            self.bot.event_repository.set_holiday_country(ids.clone(), self.bot.holiday_country.clone()).await?;
+        }
+        self.spawn_calendar_push(chat_id, ids.clone());
+        let group_uid = self.group_uid(chat_id, &ids).await?;
+        self.bot.send_with_markup(chat_id, reply, InlineKeyboardMarkup {
+            inline_keyboard: vec![vec![InlineKeyboardButton {
+                text: tr(Phrase::Cancel, self.locale).to_string(),
+                callback_data: CallbackQuery::Delete(group_uid).to_string()
+            }]]
+        }, self.plain).await?;
+        Ok(())
     }
-}
 
-impl BotHandler {
-    async fn handle_message(&self, message: Message) -> Result<(), BotError> {
-        if let Some(text) = message.text {
-            let result = self.bot.parser.parse(Utc::now(), text.as_str()).await;
-            let (text, state) = match result {
-                Ok(notification) =>
-                    (serde_json::to_string(&notification)?, State::Parsed { text: text.clone(), notification }),
-                Err(error) =>
-                    (format!("{}", error), State::ParsedWithError { text })
+    async fn cron_command(&self, chat_id: u64, arg: &str) -> Result<(), BotError> {
+        let usage = "Usage: /cron \"<minute> <hour> <day> <month> <weekday>\" <text>, like /cron \"0 9 * * MON-FRI\" standup";
+        let Some((expression, text)) = split_cron_args(arg) else {
+            return self.reply(chat_id, usage.to_string(), None).await;
+        };
+        let schedule = match expression.parse::<CronSchedule>() {
+            Ok(schedule) => schedule,
+            Err(err) => return self.reply(chat_id, format!("{}\n{}", err, usage), None).await,
+        };
+        if schedule.next_after(Utc::now(), self.timezone).is_none() {
+            return self.reply(chat_id, format!("\"{}\" never fires", schedule), None).await;
+        }
+        let id = self.bot.event_repository.insert_cron_event(chat_id, text.clone(), Source::Telegram, schedule).await?;
+        self.bot.event_repository.record_action(chat_id, format!("adding \"{}\"", text), vec![id], Transition::Created).await?;
+        let when = self.bot.event_repository.get_event(chat_id, id).await?
+            .map(|event| describe_event_time(&event, Utc::now(), self.timezone, self.locale))
+            .unwrap_or_default();
+        let group_uid = self.group_uid(chat_id, &[id]).await?;
+        self.bot.send_with_markup(chat_id, format!("{} — {}", text, when), InlineKeyboardMarkup {
+            inline_keyboard: vec![vec![InlineKeyboardButton {
+                text: tr(Phrase::Cancel, self.locale).to_string(),
+                callback_data: CallbackQuery::Delete(group_uid).to_string()
+            }]]
+        }, self.plain).await?;
+        Ok(())
+    }
+
+    // /template lists saved templates as buttons, /template <name> starts one right away
+    async fn template_command(&self, chat_id: u64, args: &[&str]) -> Result<(), BotError> {
+        let usage = "Usage: /template [add <name> <text> | delete <name> | <name>], like /template add pills take {med} at {time}";
+        match args {
+            [] => {
+                let templates = self.bot.event_repository.get_templates(chat_id).await?;
+                if templates.is_empty() {
+                    return self.reply(chat_id, format!("No templates yet\n{}", usage), None).await;
+                }
+                let text = templates.iter()
+                    .fold("Templates:".to_string(), |text, template| format!("{}\n{} — {}", text, template.name, template.body));
+                let buttons = templates.into_iter()
+                    .map(|template| vec![InlineKeyboardButton { text: template.name, callback_data: CallbackQuery::Template(template.id).to_string() }])
+                    .collect();
+                self.bot.send_with_markup(chat_id, text, InlineKeyboardMarkup { inline_keyboard: buttons }, self.plain).await?;
+                Ok(())
+            }
+            ["add", name, body @ ..] if !body.is_empty() => {
+                let body = body.join(" ");
+                let placeholders = templates::placeholders(&body).join(", ");
+                self.bot.event_repository.save_template(chat_id, name.to_lowercase(), body).await?;
+                let reply = if placeholders.is_empty() {
+                    format!("Template {} saved", name.to_lowercase())
+                } else {
+                    format!("Template {} saved, it asks for {}", name.to_lowercase(), placeholders)
+                };
+                self.reply(chat_id, reply, None).await
+            }
+            ["delete", name] => {
+                let reply = if self.bot.event_repository.delete_template(chat_id, name.to_lowercase()).await? {
+                    format!("Template {} deleted", name.to_lowercase())
+                } else {
+                    format!("There is no template {}", name.to_lowercase())
+                };
+                self.reply(chat_id, reply, None).await
+            }
+            [name] => {
+                let template = self.bot.event_repository.get_templates(chat_id).await?
+                    .into_iter()
+                    .find(|template| template.name == name.to_lowercase());
+                match template {
+                    Some(template) => self.fill_template(chat_id, template.body).await,
+                    None => self.reply(chat_id, format!("There is no template {}\n{}", name.to_lowercase(), usage), None).await,
+                }
+            }
+            _ => self.reply(chat_id, usage.to_string(), None).await,
+        }
+    }
+
+    async fn pause_command(&self, chat_id: u64, arg: &str) -> Result<(), BotError> {
+        let now = Utc::now();
+        if arg.is_empty() {
+            let settings = self.bot.event_repository.get_user_settings(chat_id).await?;
+            let reply = match settings.paused_until.filter(|_| settings.is_paused(now)) {
+                Some(until) => format!("Notifications are paused until {}, send /resume to get them again", humanize::format_time(until, now, self.timezone, self.locale)),
+                None => "Usage: /pause until <dd.mm>".to_string(),
             };
-            let markup = InlineKeyboardMarkup {
-                inline_keyboard: vec![
-                    vec![InlineKeyboardButton {
-                        text: "Accept".to_string(),
-                        callback_data: CallbackQuery::Accept.to_string()
-                    }],
-                    vec![InlineKeyboardButton {
-                        text: "Repeat".to_string(),
-                        callback_data: CallbackQuery::Repeat.to_string()
-                    }],
-                    vec![InlineKeyboardButton {
-                        text: "Cancel".to_string(),
-                        callback_data: CallbackQuery::Cancel.to_string()
-                    }]
-                ]
+            return self.reply(chat_id, reply, None).await;
+        }
+        let until = match parse_pause_end(arg, now, self.timezone) {
+            Some(until) => until,
+            None => return self.reply(chat_id, "Usage: /pause until <dd.mm>".to_string(), None).await,
+        };
+        self.bot.event_repository.set_paused_until(chat_id, Some(until)).await?;
+        self.reply(chat_id, format!("Notifications are paused until {}, missed reminders will arrive after that", humanize::format_time(until, now, self.timezone, self.locale)), None).await
+    }
+
+    // missed absolute reminders are delivered by the next background loop, unless asked to skip them
+    async fn resume_command(&self, chat_id: u64, arg: &str) -> Result<(), BotError> {
+        let skip = match arg {
+            "" => false,
+            "skip" => true,
+            _ => return self.reply(chat_id, "Usage: /resume [skip]".to_string(), None).await,
+        };
+        let now = Utc::now();
+        if !self.bot.event_repository.get_user_settings(chat_id).await?.is_paused(now) {
+            return self.reply(chat_id, "Notifications aren't paused".to_string(), None).await;
+        }
+        if skip && !self.role.can_create() {
+            return self.reply(chat_id, tr(Phrase::ReadOnly, self.locale).to_string(), None).await;
+        }
+        self.bot.event_repository.set_paused_until(chat_id, None).await?;
+        let missed = self.bot.event_repository.get_missed(chat_id, now).await?;
+        let reply = match (missed.len(), skip) {
+            (0, _) => "Notifications are resumed".to_string(),
+            (count, true) => {
+                self.bot.event_repository.delete_events(missed.clone()).await?;
+                self.bot.event_repository.record_action(chat_id, format!("dropping {} missed reminders", count), missed, Transition::Deleted).await?;
+                format!("Notifications are resumed, {} missed reminders are dropped", count)
+            }
+            (count, false) => format!("Notifications are resumed, {} missed reminders will arrive shortly", count),
+        };
+        self.reply(chat_id, reply, None).await
+    }
+
+    async fn undo_command(&self, chat_id: u64) -> Result<(), BotError> {
+        let reply = match self.bot.event_repository.undo_last_action(chat_id).await? {
+            Some((label, 0)) => format!("Undid {}, its reminders had already changed since", label),
+            Some((label, _)) => format!("Undid {}", label),
+            None => "Nothing to undo".to_string(),
+        };
+        self.reply(chat_id, reply, None).await
+    }
+
+    async fn quiet_command(&self, chat_id: u64, arg: &str) -> Result<(), BotError> {
+        let quiet_hours = match arg {
+            "" => {
+                let reply = match self.bot.event_repository.get_user_settings(chat_id).await?.quiet_hours {
+                    Some(quiet_hours) => format!("Quiet hours are {}, reminders except urgent ones are held until they are over", quiet_hours),
+                    None => "Usage: /quiet <hh:mm-hh:mm | off>".to_string(),
+                };
+                return self.reply(chat_id, reply, None).await;
+            }
+            "off" => None,
+            _ => match arg.parse::<QuietHours>() {
+                Ok(quiet_hours) => Some(quiet_hours),
+                Err(err) => return self.reply(chat_id, err.to_string(), None).await,
+            },
+        };
+        self.bot.event_repository.set_quiet_hours(chat_id, quiet_hours).await?;
+        let reply = match quiet_hours {
+            Some(quiet_hours) => format!("Quiet hours are {}, reminders except urgent ones are held until they are over", quiet_hours),
+            None => "Quiet hours are off".to_string(),
+        };
+        self.reply(chat_id, reply, None).await
+    }
+
+    async fn import_calendar(&self, chat_id: u64, document: &Document) -> Result<(), BotError> {
+        let content = self.bot.tg.download_file(&document.file_id).await?;
+        let events = ics::parse_calendar(&String::from_utf8_lossy(&content), Utc::now(), self.timezone);
+        if events.is_empty() {
+            return self.reply(chat_id, "No upcoming events found in the calendar".to_string(), None).await;
+        }
+        let markup = InlineKeyboardMarkup {
+            inline_keyboard: vec![
+                vec![InlineKeyboardButton {
+                    text: "Accept".to_string(),
+                    callback_data: CallbackQuery::Accept.to_string()
+                }],
+                vec![InlineKeyboardButton {
+                    text: "Cancel".to_string(),
+                    callback_data: CallbackQuery::Cancel.to_string()
+                }]
+            ]
+        };
+        let message_id = self.bot.send_with_markup(chat_id, describe_import(&events, Utc::now(), self.timezone, self.locale), markup, self.plain).await?;
+        self.add_draft(chat_id, message_id, Draft::ImportPreview { events });
+        Ok(())
+    }
+
+    async fn handle_message(&self, message: Message) -> Result<(), BotError> {
+        if let Some(document) = message.document.as_ref().filter(|document| is_calendar(document)) {
+            if !self.role.can_create() {
+                return self.reply(message.chat.id, tr(Phrase::ReadOnly, self.locale).to_string(), None).await;
+            }
+            return self.import_calendar(message.chat.id, document).await;
+        }
+        if let Some(document) = message.document.as_ref().filter(|document| is_settings_bundle(document)) {
+            if !self.role.can_create() {
+                return self.reply(message.chat.id, tr(Phrase::ReadOnly, self.locale).to_string(), None).await;
+            }
+            // settings carry webhooks, which visitors of the demo can't add
+            if self.bot.is_demo() {
+                return self.reply(message.chat.id, "Importing settings is not available in the demo".to_string(), None).await;
+            }
+            return self.import_settings(message.chat.id, document).await;
+        }
+
+        if let (Some(location), State::AwaitingTimezone) = (message.shared_location(), &self.state) {
+            return match tzlookup::timezone_at(location.latitude, location.longitude) {
+                Some(timezone) => self.confirm_timezone(message.chat.id, timezone).await,
+                None => self.reply(message.chat.id, "Usage: /timezone [name], like /timezone Europe/Berlin".to_string(), None).await,
             };
-            self.bot.tg.send_message(message.chat.id, text, Some(markup)).await?;
-            self.state_channel.send((message.chat.id, state))?;
         }
+        if let Some(location) = message.shared_location() {
+            if !self.role.can_create() {
+                return self.reply(message.chat.id, tr(Phrase::ReadOnly, self.locale).to_string(), None).await;
+            }
+            let context = DraftContext { location: Some(location), ..self.held_context() };
+            self.set_state(message.chat.id, State::Holding { context });
+            return self.reply(message.chat.id, tr(Phrase::LocationHeld, self.locale).to_string(), None).await;
+        }
+        if let Some(quote) = message.forwarded_text() {
+            if !self.role.can_create() {
+                return self.reply(message.chat.id, tr(Phrase::ReadOnly, self.locale).to_string(), None).await;
+            }
+            return self.handle_forwarded(&message, quote.to_string()).await;
+        }
+
+        if let Some(callback_query) = self.pick_plain_choice(&message) {
+            return self.handle_callback_query(callback_query).await;
+        }
+
+        if let Some(text) = message.text {
+            if text.starts_with('/') && self.handle_command(message.chat.id, &text).await? {
+                return Ok(());
+            }
+            if !self.role.can_create() {
+                return self.reply(message.chat.id, tr(Phrase::ReadOnly, self.locale).to_string(), None).await;
+            }
+
+            if let State::AwaitingSnooze { event_id, text: original } = &self.state {
+                return self.snooze(message.chat.id, *event_id, original, &text).await;
+            }
+            if let State::EditingDraft { message_id } = &self.state {
+                return self.edit_draft(message.chat.id, *message_id, &text).await;
+            }
+            if let State::FillingTemplate { text: template } = &self.state {
+                let filled = templates::fill(template, templates::placeholders(template).first().copied().unwrap_or_default(), &text);
+                return self.fill_template(message.chat.id, filled).await;
+            }
+
+            self.send_draft(message.chat.id, text, Some(message.message_id)).await?;
+        }
+
+        Ok(())
+    }
+
+    // parses the text and replies with a draft to accept, an edit of the source message parses it again
+    async fn send_draft(&self, chat_id: u64, text: String, source_message_id: Option<u64>) -> Result<(), BotError> {
+        let text = match &self.state {
+            State::Holding { context: DraftContext { quote: Some(quote), .. } } => with_quote(&text, quote),
+            _ => text,
+        };
+        let (text, summary) = match self.summarize_if_long(chat_id, text).await {
+            Ok(summarized) => summarized,
+            Err(error) => return self.reply(chat_id, format!("{}", error), None).await,
+        };
+        let result = self.parse_candidates(chat_id, text.as_str()).await;
+        if let Ok([Notification::Cancel { text: query }, ..]) = result.as_deref() {
+            return self.cancel_command(chat_id, query).await;
+        }
+        let offline = self.offline_reading(&text, result.as_ref().ok().and_then(|candidates| candidates.first())).await;
+        let (reply, draft) = self.describe_draft(text, summary, result, offline);
+        // asking to leave out holidays turns the toggle on, it can still be flipped before Accept
+        let skip_holidays = matches!(&draft, Draft::Parsed { notification, .. } if notification.skips_holidays());
+        let context = DraftContext { source_message_id, skip_holidays, uid: Some(UuidV7Generator.generate()), ..self.held_context() };
+        let markup = draft_markup(draft.options(), draft.is_weekly(), &context, self.locale);
+        let message_id = self.bot.send_with_markup(chat_id, self.bot.with_status(reply), markup, self.plain).await?;
+        self.add_draft(chat_id, message_id, draft);
+        if let Some(source_message_id) = source_message_id {
+            self.add_draft_source(chat_id, source_message_id, message_id);
+        }
+        self.draft_context.set((chat_id, message_id), context);
+        self.draft_context.retain_latest(|(draft_chat_id, _)| *draft_chat_id == chat_id, MAX_PENDING_DRAFTS);
+        if let State::Holding { .. } = &self.state {
+            self.set_state(chat_id, State::Idle);
+        }
+        Ok(())
+    }
+
+    // asks for the next placeholder, once none is left the text goes through the parser like a typed message
+    async fn fill_template(&self, chat_id: u64, text: String) -> Result<(), BotError> {
+        match templates::placeholders(&text).first() {
+            Some(name) => {
+                self.bot.tg.send_force_reply(chat_id, i18n::template_value(name, self.locale), Some(name.to_string())).await?;
+                self.set_state(chat_id, State::FillingTemplate { text });
+                Ok(())
+            }
+            None => {
+                self.set_state(chat_id, State::Idle);
+                self.send_draft(chat_id, text, None).await
+            }
+        }
+    }
+
+    fn describe_draft(&self, text: String, summary: Option<String>, result: Result<Vec<Notification>, BotError>, offline: Option<String>) -> (String, Draft) {
+        let (reply, draft) = match result {
+            Ok(mut candidates) => {
+                let notification = candidates.remove(0);
+                let reply = describe_candidates(&notification, &candidates, Utc::now(), self.timezone, self.locale);
+                (reply, Draft::Parsed { text, notification, alternatives: candidates })
+            }
+            Err(error) => (format!("{}", error), Draft::ParsedWithError { text }),
+        };
+        let reply = match summary {
+            Some(summary) => format!("Summary: {}\n\n{}", summary, reply),
+            None => reply,
+        };
+        let reply = match offline {
+            Some(offline) => format!("{}\n\nOffline parser: {}", reply, offline),
+            None => reply,
+        };
+        (reply, draft)
+    }
+
+    // editing the text of a draft that still waits for Accept parses it again and updates the reply in place,
+    // edits of anything else are ignored
+    async fn handle_edited_message(&self, message: Message) -> Result<(), BotError> {
+        let chat_id = message.chat.id;
+        let text = match message.text {
+            Some(text) if self.role.can_create() && !text.starts_with('/') => text,
+            _ => return Ok(()),
+        };
+        let message_id = match self.draft_sources.get((chat_id, message.message_id)).1 {
+            Some(message_id) => message_id,
+            None => return Ok(()),
+        };
+        let text = match self.draft_context.get((chat_id, message_id)).1.quote {
+            Some(quote) => with_quote(&text, &quote),
+            None => text,
+        };
+        self.reparse_draft(chat_id, message_id, text).await
+    }
+
+    // a forward sent together with a comment arrives right after the comment, whose draft is parsed again with it;
+    // a forward on its own waits for the text that says when to remind about it
+    async fn handle_forwarded(&self, message: &Message, quote: String) -> Result<(), BotError> {
+        let chat_id = message.chat.id;
+        let comment = self.draft_sources.get((chat_id, message.message_id.saturating_sub(1))).1
+            .map(|message_id| (message_id, self.drafts.get((chat_id, message_id)).1));
+        if let Some((message_id, Some(Draft::Parsed { text, .. } | Draft::ParsedWithError { text }))) = comment {
+            let (_, mut context) = self.draft_context.get((chat_id, message_id));
+            if context.quote.is_none() {
+                context.quote = Some(quote.clone());
+                self.draft_context.set((chat_id, message_id), context);
+                self.draft_context.retain_latest(|(draft_chat_id, _)| *draft_chat_id == chat_id, MAX_PENDING_DRAFTS);
+                return self.reparse_draft(chat_id, message_id, with_quote(&text, &quote)).await;
+            }
+        }
+        let context = DraftContext { quote: Some(quote), ..self.held_context() };
+        self.set_state(chat_id, State::Holding { context });
+        self.reply(chat_id, tr(Phrase::ForwardHeld, self.locale).to_string(), None).await
+    }
+
+    fn held_context(&self) -> DraftContext {
+        match &self.state {
+            State::Holding { context } => context.clone(),
+            _ => DraftContext::default(),
+        }
+    }
+
+    // corrected json replaces the parsed reminder as is, anything else is a tweak the model applies to it;
+    // the draft keeps waiting for Accept with the result, and what gets accepted is kept as a correction
+    async fn edit_draft(&self, chat_id: u64, message_id: u64, edit: &str) -> Result<(), BotError> {
+        self.set_state(chat_id, State::Idle);
+        let key = (chat_id, message_id);
+        let (version, draft) = self.drafts.get(key);
+        let (text, notification) = match draft {
+            Some(Draft::Parsed { text, notification, .. }) => (text, notification),
+            _ => return self.reply(chat_id, tr(Phrase::DraftNotPending, self.locale).to_string(), None).await,
+        };
+        let revised = if edit.trim_start().starts_with('{') {
+            on_wall_clock(self.timezone, || LlmParser::parse_completion(edit))
+        } else {
+            self.revise(chat_id, &notification, edit).await
+        };
+        let revised = match revised {
+            Ok(Notification::Cancel { .. }) => Err(BotError::InvalidEdit),
+            revised => revised,
+        };
+        let revised = match revised {
+            Ok(revised) => revised,
+            Err(err) => return self.reply(chat_id, format!("{}: {}", tr(Phrase::EditFailed, self.locale), err), None).await,
+        };
+
+        let reply = describe_notification(&revised, Utc::now(), self.timezone, self.locale);
+        let weekly = matches!(revised, Notification::Recurrent { .. });
+        if !self.set_draft(DraftSlot { key, version }, Some(Draft::Parsed { text, notification: revised, alternatives: vec![] })) {
+            warn!("Draft of chat {} was changed by another update, dropping the edit", chat_id);
+            return Ok(());
+        }
+        let (_, context) = self.draft_context.get(key);
+        let markup = draft_markup(1, weekly, &context, self.locale);
+        self.draft_context.set(key, DraftContext { corrected: true, ..context });
+        self.draft_context.retain_latest(|(draft_chat_id, _)| *draft_chat_id == chat_id, MAX_PENDING_DRAFTS);
+        self.bot.edit_with_markup(chat_id, message_id, self.bot.with_status(reply), Some(markup), self.plain).await?;
+        self.reply(chat_id, tr(Phrase::DraftUpdated, self.locale).to_string(), None).await
+    }
+
+    async fn revise(&self, chat_id: u64, notification: &Notification, correction: &str) -> Result<Notification, BotError> {
+        let now = Utc::now();
+        let month = now.format("%Y-%m").to_string();
+        let _slot = self.parser_slot(chat_id).await?;
+        let current = on_wall_clock(self.timezone, || serde_json::to_string(notification))?;
+        let reserved = self.reserve_budget(chat_id, &month).await?;
+        let completion = self.bot.parser.revise(now, self.timezone, &current, correction).await;
+        self.bot.subsystems.record(Subsystem::Parser, &completion);
+        self.settle_budget(chat_id, month, reserved, completion.as_ref().ok().map(|completion| completion.usage)).await?;
+        let completion = completion?;
+        on_wall_clock(self.timezone, || LlmParser::parse_completion(&completion.content))
+    }
+
+    // parses the text of a draft that still waits for Accept again and updates the reply in place
+    async fn reparse_draft(&self, chat_id: u64, message_id: u64, text: String) -> Result<(), BotError> {
+        let key = (chat_id, message_id);
+        let (version, draft) = self.drafts.get(key);
+        if !matches!(draft, Some(Draft::Parsed { .. } | Draft::ParsedWithError { .. })) {
+            return Ok(());
+        }
+        let slot = DraftSlot { key, version };
+
+        let (text, summary) = match self.summarize_if_long(chat_id, text).await {
+            Ok(summarized) => summarized,
+            Err(error) => return self.reply(chat_id, format!("{}", error), None).await,
+        };
+        let result = self.parse_candidates(chat_id, text.as_str()).await;
+        if let Ok([Notification::Cancel { text: query }, ..]) = result.as_deref() {
+            // the edited text asks to cancel something instead, so the draft is gone
+            if self.set_draft(slot, None) {
+                self.bot.edit_markup(chat_id, message_id, None, self.plain).await?;
+            }
+            return self.cancel_command(chat_id, query).await;
+        }
+        let offline = self.offline_reading(&text, result.as_ref().ok().and_then(|candidates| candidates.first())).await;
+        let (reply, draft) = self.describe_draft(text, summary, result, offline);
+        let markup = draft_markup(draft.options(), draft.is_weekly(), &self.draft_context.get(slot.key).1, self.locale);
+        if !self.set_draft(slot, Some(draft)) {
+            warn!("Draft of chat {} was changed by another update, dropping the new parse", chat_id);
+            return Ok(());
+        }
+        self.bot.edit_with_markup(chat_id, message_id, self.bot.with_status(reply), Some(markup), self.plain).await
+    }
+
+    // a single result that turns the typed text into a reminder once it's picked, read-only users get none
+    async fn handle_inline_query(&self, inline_query: InlineQuery) -> Result<(), BotError> {
+        let text = inline_query.query.trim();
+        let results = if text.is_empty() || !self.role.can_create() {
+            vec![]
+        } else {
+            vec![InlineQueryResultArticle {
+                kind: "article".to_string(),
+                id: INLINE_RESULT_ID.to_string(),
+                title: tr(Phrase::CreateReminder, self.locale).to_string(),
+                description: Some(text.to_string()),
+                input_message_content: InputTextMessageContent { message_text: format!("⏰ {}", text) },
+            }]
+        };
+        self.bot.tg.answer_inline_query(inline_query.id, results).await
+    }
 
+    // picking the result already confirms the reminder, so it's stored without a draft
+    // and the details with a cancel button arrive in the private chat
+    async fn handle_chosen_inline_result(&self, chosen: ChosenInlineResult) -> Result<(), BotError> {
+        if chosen.result_id != INLINE_RESULT_ID || !self.role.can_create() {
+            return Ok(());
+        }
+        let chat_id = chosen.from.id;
+        let notification = match self.parse(chat_id, &chosen.query).await {
+            Ok(Notification::Cancel { text: query }) => return self.cancel_command(chat_id, &query).await,
+            Ok(notification) => notification,
+            Err(err) => return self.reply(chat_id, format!("{}: {}", tr(Phrase::ParseFailed, self.locale), err), None).await,
+        };
+        let notifications = notification.create_stored_notifications(Utc::now(), self.timezone);
+        let text = describe_stored(notification.get_text(), &notifications, Utc::now(), self.timezone, self.locale);
+        let ids = self.bot.event_repository.insert_event_with_delivery(chat_id, notification.get_text().to_string(), Source::Telegram,
+                                                                       notification.get_delivery(), notifications).await?;
+        self.bot.event_repository.record_action(chat_id, format!("adding \"{}\"", notification.get_text()), ids.clone(), Transition::Created).await?;
+        let group_uid = self.group_uid(chat_id, &ids).await?;
+        self.bot.send_with_markup(chat_id, text, InlineKeyboardMarkup {
+            inline_keyboard: vec![vec![InlineKeyboardButton {
+                text: tr(Phrase::Cancel, self.locale).to_string(),
+                callback_data: CallbackQuery::Delete(group_uid).to_string()
+            }]]
+        }, self.plain).await?;
         Ok(())
     }
 
@@ -75,6 +2199,12 @@ impl BotHandler {
             self.handle_callback_query(callback_query).await
         } else if let Some(message) = update.message {
             self.handle_message(message).await
+        } else if let Some(message) = update.edited_message {
+            self.handle_edited_message(message).await
+        } else if let Some(inline_query) = update.inline_query {
+            self.handle_inline_query(inline_query).await
+        } else if let Some(chosen) = update.chosen_inline_result {
+            self.handle_chosen_inline_result(chosen).await
         } else {
             Ok(())
         }
@@ -83,84 +2213,495 @@ impl BotHandler {
     async fn handle_callback_query(&self, callback_query: crate::models::CallbackQuery) -> Result<(), BotError> {
         let data: CallbackQuery = callback_query.data.as_ref().ok_or(BotError::InvalidCallbackQuery)?.parse::<CallbackQuery>()?;
         let chat_id = callback_query.from.id;
-        info!("{:?}, {:?}", self.state, data);
-        let (answer_text, new_state) = match (self.state.clone(), data) {
-            (_, CallbackQuery::Cancel) => {
+        // buttons act on the draft of their own message, whatever was sent after it
+        let key = callback_query.message.as_ref().map(|message| (chat_id, message.message_id));
+        let (draft, slot) = match key {
+            Some(key) => {
+                let (version, draft) = self.drafts.get(key);
+                (draft, Some(DraftSlot { key, version }))
+            }
+            None => (None, None),
+        };
+        info!("{:?}", data);
+        // everything but cancelling a message, marking a reminder done, deciding on join requests and confirming a timezone
+        // changes reminders
+        if !self.role.can_create() && !matches!(data, CallbackQuery::Cancel | CallbackQuery::Join(..) | CallbackQuery::Done(_) | CallbackQuery::Timezone(_)) {
+            return self.answer(&callback_query, Some(tr(Phrase::ReadOnly, self.locale).to_string())).await;
+        }
+        let answer_text = match (draft, slot, data) {
+            (_, _, CallbackQuery::Cancel) => {
+                if let Some(slot) = slot {
+                    self.set_draft(slot, None);
+                }
                 self.cancel(&callback_query).await?
             },
-            (State::ParsedWithError { text}, CallbackQuery::Repeat) => {
-                self.repeat(&callback_query, &text).await?
+            (Some(Draft::ParsedWithError { text }), Some(slot), CallbackQuery::Repeat) => {
+                self.repeat_draft(&callback_query, slot, &text, true).await?
+            },
+            (Some(Draft::Parsed { text, .. }), Some(slot), CallbackQuery::Repeat) => {
+                self.repeat_draft(&callback_query, slot, &text, false).await?
+            },
+            (Some(Draft::Picking { .. }), _, CallbackQuery::CalendarMonth(year, month)) => {
+                let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
+                let markup = calendar_markup(year, month, local_today(self.timezone), self.locale);
+                self.bot.edit_with_markup(chat_id, message.message_id, tr(Phrase::PickDate, self.locale).to_string(), Some(markup), self.plain).await?;
+                None
+            },
+            (Some(Draft::Picking { text, .. }), Some(slot), CallbackQuery::PickDay(date)) => {
+                self.pick(slot, Draft::Picking { text, date: Some(date), hour: None }, Phrase::PickHour, hour_markup(self.locale)).await?;
+                None
+            },
+            (Some(Draft::Picking { text, date: Some(date), .. }), Some(slot), CallbackQuery::PickHour(hour)) => {
+                self.pick(slot, Draft::Picking { text, date: Some(date), hour: Some(hour) }, Phrase::PickMinute, minute_markup(self.locale)).await?;
+                None
+            },
+            (Some(Draft::Picking { text, date: Some(date), hour: Some(hour) }), Some(slot), CallbackQuery::PickMinute(minute)) => {
+                self.picked(slot, text, date, hour, minute).await?
+            },
+            (Some(draft @ Draft::Parsed { .. }), Some(slot), CallbackQuery::Silent) => {
+                let (_, context) = self.draft_context.get(slot.key);
+                let context = DraftContext { silent: !context.silent, ..context };
+                self.bot.edit_markup(slot.key.0, slot.key.1, Some(draft_markup(draft.options(), draft.is_weekly(), &context, self.locale)), self.plain).await?;
+                self.draft_context.set(slot.key, context);
+                None
+            },
+            (Some(draft @ Draft::Parsed { .. }), Some(slot), CallbackQuery::Holidays) if draft.is_weekly() => {
+                let (_, context) = self.draft_context.get(slot.key);
+                let context = DraftContext { skip_holidays: !context.skip_holidays, ..context };
+                self.bot.edit_markup(slot.key.0, slot.key.1, Some(draft_markup(draft.options(), true, &context, self.locale)), self.plain).await?;
+                self.draft_context.set(slot.key, context);
+                None
+            },
+            (Some(Draft::Parsed { .. }), Some(slot), CallbackQuery::Edit) => {
+                self.bot.tg.send_force_reply(chat_id, tr(Phrase::EditPrompt, self.locale).to_string(), Some("18:00, not 8:00…".to_string())).await?;
+                self.set_state(chat_id, State::EditingDraft { message_id: slot.key.1 });
+                None
+            },
+            (Some(Draft::ParsedWithError { .. }), _, CallbackQuery::Accept) => {
+                Some(tr(Phrase::AcceptWithErrors, self.locale).to_string())
+            },
+            (Some(Draft::Parsed { text, notification, alternatives }), Some(slot), CallbackQuery::Accept) => {
+                return self.accept_draft(&callback_query, slot, text, notification, alternatives, 1).await;
+            },
+            (Some(Draft::Parsed { text, notification, alternatives }), Some(slot), CallbackQuery::Pick(option)) if (2..=alternatives.len() + 1).contains(&option) => {
+                return self.accept_draft(&callback_query, slot, text, notification, alternatives, option).await;
+            },
+            (Some(Draft::ImportPreview { events }), Some(slot), CallbackQuery::Accept) => {
+                return self.accept_import(&callback_query, slot, events).await;
             },
-            (state @ State::ParsedWithError { .. }, CallbackQuery::Accept) => {
-                (Some("Impossible to accept notification with errors".to_string()), state)
+            (Some(Draft::Conflicting { text, delivery, notifications }), Some(slot), CallbackQuery::KeepBoth) => {
+                return self.accept_conflicting(&callback_query, slot, text, delivery, notifications, false).await;
             },
-            (State::Parsed { notification, .. }, CallbackQuery::Accept) => {
-                self.accept(&callback_query, notification).await?
+            (Some(Draft::Conflicting { text, delivery, notifications }), Some(slot), CallbackQuery::Shift) => {
+                return self.accept_conflicting(&callback_query, slot, text, delivery, notifications, true).await;
             },
-            (State::Parsed { text, .. }, CallbackQuery::Repeat) => {
-                self.repeat(&callback_query, &text).await?
+            // accepted a moment ago, or dropped as one of the older drafts of the chat
+            (None, _, CallbackQuery::Accept | CallbackQuery::Pick(_) | CallbackQuery::Edit | CallbackQuery::Silent | CallbackQuery::Holidays | CallbackQuery::Repeat | CallbackQuery::KeepBoth | CallbackQuery::Shift
+                | CallbackQuery::CalendarMonth(..) | CallbackQuery::PickDay(_) | CallbackQuery::PickHour(_) | CallbackQuery::PickMinute(_)) => {
+                Some(tr(Phrase::DraftNotPending, self.locale).to_string())
             },
-            (state, CallbackQuery::Delete(ids)) => {
-                self.bot.event_repository.delete_events(ids).await?;
+            (_, _, CallbackQuery::Delete(group_uid)) => {
+                let ids = self.bot.event_repository.find_group(chat_id, group_uid).await?;
+                self.bot.event_repository.delete_events(ids.clone()).await?;
+                self.bot.event_repository.record_action(chat_id, "cancelling a reminder".to_string(), ids, Transition::Deleted).await?;
                 self.bot.tg.delete_message(
                     callback_query.from.id,
-                    callback_query.message
+                    callback_query.message.as_ref()
                         .ok_or(BotError::InvalidCallbackQuery)?
                         .message_id
                 ).await?;
-                (Some("Notification deleted".to_string()), state)
+                Some(tr(Phrase::NotificationDeleted, self.locale).to_string())
+            }
+            (_, _, CallbackQuery::Forget(event_uid)) => {
+                let event_id = self.resolve_event(chat_id, &event_uid).await?;
+                Some(self.forget(&callback_query, event_id).await?)
+            }
+            (_, _, CallbackQuery::Join(user_id, approve)) => {
+                Some(self.decide_access(&callback_query, user_id, approve).await?)
+            }
+            (_, _, CallbackQuery::Done(event_uid)) => {
+                let event_id = self.resolve_event(chat_id, &event_uid).await?;
+                Some(self.done(&callback_query, event_id, &event_uid).await?)
+            }
+            (_, _, CallbackQuery::Skip(event_uid)) => {
+                let event_id = self.resolve_event(chat_id, &event_uid).await?;
+                Some(self.skip(&callback_query, event_id).await?)
+            }
+            (_, _, CallbackQuery::Template(template_id)) => {
+                let template = self.bot.event_repository.get_templates(chat_id).await?
+                    .into_iter()
+                    .find(|template| template.id == template_id)
+                    .ok_or(BotError::InvalidCallbackQuery)?;
+                self.fill_template(chat_id, template.body).await?;
+                None
+            }
+            (_, _, CallbackQuery::Timezone(timezone)) => {
+                self.cancel(&callback_query).await?;
+                self.set_timezone(chat_id, timezone).await?;
+                None
+            }
+            (_, _, CallbackQuery::RemindAgain(event_uid)) => {
+                // choosing when to be reminded again stops the repeats as well
+                let event_id = self.resolve_event(chat_id, &event_uid).await?;
+                self.bot.event_repository.acknowledge(chat_id, event_id).await?;
+                self.bot.unpin_fired(chat_id, event_id).await?;
+                self.show_remind_again_options(&callback_query, &event_uid).await?;
+                None
+            }
+            (_, _, CallbackQuery::RemindAgainIn(event_uid, days)) => {
+                let event_id = self.resolve_event(chat_id, &event_uid).await?;
+                Some(self.remind_again_in(&callback_query, event_id, &event_uid, days).await?)
+            }
+            (_, _, CallbackQuery::RemindAgainCustom(event_uid)) => {
+                let event_id = self.resolve_event(chat_id, &event_uid).await?;
+                self.prompt_snooze(&callback_query, event_id, &event_uid).await?
+            }
+            _ => None
+        };
+
+        self.answer(&callback_query, answer_text).await
+    }
+
+    // buttons carry public ids, a reminder of another user is as unknown as a made up one
+    async fn resolve_event(&self, chat_id: u64, event_uid: &str) -> Result<u64, BotError> {
+        self.bot.event_repository.resolve_event_id(chat_id, event_uid.to_string()).await?.ok_or(BotError::InvalidCallbackQuery)
+    }
+
+    // id of the group the rows were created in, carried by their Cancel button
+    async fn group_uid(&self, chat_id: u64, ids: &[u64]) -> Result<String, BotError> {
+        let event_id = *ids.first().ok_or(BotError::InvalidCallbackQuery)?;
+        self.bot.event_repository.get_group_uid(chat_id, event_id).await?.ok_or(BotError::InvalidCallbackQuery)
+    }
+
+    // choices picked in plain mode have no callback id, so the answer is sent as a regular message
+    async fn answer(&self, callback_query: &crate::models::CallbackQuery, text: Option<String>) -> Result<(), BotError> {
+        match text {
+            Some(text) if callback_query.id.is_empty() => self.reply(callback_query.from.id, text, None).await,
+            _ if callback_query.id.is_empty() => Ok(()),
+            text => self.bot.tg.answer_callback_query(callback_query.id.clone(), text).await,
+        }
+    }
+
+    // the option is the number of the reading as listed in the draft, 1 being the most likely one
+    async fn accept_draft(&self, callback_query: &crate::models::CallbackQuery, slot: DraftSlot, text: String, notification: Notification,
+                          alternatives: Vec<Notification>, option: usize) -> Result<(), BotError> {
+        let chat_id = callback_query.from.id;
+        // claiming the draft before inserting turns a second tap on Accept into a no-op
+        if !self.set_draft(slot, None) {
+            return self.answer(callback_query, Some(tr(Phrase::AlreadyAccepted, self.locale).to_string())).await;
+        }
+
+        let chosen = if option > 1 { &alternatives[option - 2] } else { &notification };
+        let notifications = chosen.create_stored_notifications(Utc::now(), self.timezone);
+        // nothing is stored until the insert, so the draft is handed back on any error before it to retry accepting
+        let retry = Draft::Parsed { text: text.clone(), notification: notification.clone(), alternatives: alternatives.clone() };
+        let answer_text = match self.find_conflicts(chat_id, &notifications).await {
+            Ok(conflicts) if !conflicts.is_empty() =>
+                self.warn_conflicts(callback_query, slot.next(), chosen.get_text(), chosen.get_delivery(), notifications, &conflicts).await
+                    .inspect_err(|_| { self.set_draft(slot.next(), Some(retry)); })?,
+            Ok(_) => self.accept(callback_query, slot.next(), retry, chosen.get_text(), chosen.get_delivery(), notifications).await?,
+            Err(err) => {
+                self.set_draft(slot.next(), Some(retry));
+                return Err(err);
+            }
+        };
+        if option > 1 || self.draft_context.get(slot.key).1.corrected {
+            self.record_correction(chat_id, text, chosen).await;
+        }
+        self.answer(callback_query, answer_text).await
+    }
+
+    // the reading the user settled on becomes an example for their next prompts, losing it only makes parsing no better
+    async fn record_correction(&self, chat_id: u64, text: String, accepted: &Notification) {
+        let recorded = match on_wall_clock(self.timezone, || serde_json::to_string(accepted)) {
+            Ok(accepted) => self.bot.event_repository.record_correction(chat_id, text, accepted, Utc::now()).await,
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = recorded {
+            warn!("Failed to record parse correction of {}: {}", chat_id, err);
+        }
+    }
+
+    async fn find_conflicts(&self, chat_id: u64, notifications: &[StoredNotification]) -> Result<Vec<Event>, BotError> {
+        if self.bot.conflict_window <= chrono::Duration::zero() {
+            return Ok(vec![]);
+        }
+        self.bot.event_repository.find_conflicts(chat_id, notifications.to_vec(), self.bot.conflict_window).await
+    }
+
+    // nothing is stored yet, the draft waits until the user decides what to do with the overlap
+    async fn warn_conflicts(&self, callback_query: &crate::models::CallbackQuery, slot: DraftSlot, text: &str, delivery: Delivery, notifications: Vec<StoredNotification>, conflicts: &[Event]) -> Result<Option<String>, BotError> {
+        let now = Utc::now();
+        let draft = describe_stored(text, &notifications, now, self.timezone, self.locale);
+        let option = |text: String, data: CallbackQuery| vec![InlineKeyboardButton { text, callback_data: data.to_string() }];
+        let markup = InlineKeyboardMarkup {
+            inline_keyboard: vec![
+                option(tr(Phrase::KeepBoth, self.locale).to_string(), CallbackQuery::KeepBoth),
+                option(i18n::shift_by(self.bot.conflict_window.num_minutes(), self.locale), CallbackQuery::Shift),
+                option(tr(Phrase::Cancel, self.locale).to_string(), CallbackQuery::Cancel),
+            ]
+        };
+        let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
+        self.bot.edit_with_markup(message.chat.id, message.message_id, describe_conflicts(&draft, conflicts, now, self.timezone, self.locale), Some(markup), self.plain).await?;
+        self.set_draft(slot, Some(Draft::Conflicting { text: text.to_string(), delivery, notifications }));
+        Ok(Some(tr(Phrase::ScheduleConflict, self.locale).to_string()))
+    }
+
+    async fn accept_conflicting(&self, callback_query: &crate::models::CallbackQuery, slot: DraftSlot, text: String, delivery: Delivery, notifications: Vec<StoredNotification>, shift: bool) -> Result<(), BotError> {
+        if !self.set_draft(slot, None) {
+            return self.answer(callback_query, Some(tr(Phrase::AlreadyAccepted, self.locale).to_string())).await;
+        }
+
+        let accepted = if shift {
+            let offset = self.bot.conflict_window.num_minutes() as i32;
+            notifications.iter().map(|notification| notification.shifted(offset)).collect()
+        } else {
+            notifications.clone()
+        };
+        let retry = Draft::Conflicting { text: text.clone(), delivery, notifications };
+        let answer_text = self.accept(callback_query, slot.next(), retry, &text, delivery, accepted).await?;
+        self.answer(callback_query, answer_text).await
+    }
+
+    async fn accept_import(&self, callback_query: &crate::models::CallbackQuery, slot: DraftSlot, events: Vec<ImportedEvent>) -> Result<(), BotError> {
+        let chat_id = callback_query.from.id;
+        if !self.set_draft(slot, None) {
+            return self.answer(callback_query, Some(tr(Phrase::AlreadyAccepted, self.locale).to_string())).await;
+        }
+
+        let mut imported = 0;
+        let mut ids = vec![];
+        for event in &events {
+            match self.bot.event_repository.insert_event(chat_id, event.text.clone(), Source::Import, vec![event.notification.clone()]).await {
+                Ok(event_ids) => {
+                    imported += 1;
+                    ids.extend(event_ids);
+                }
+                Err(err) => {
+                    // only the events that were not stored yet are offered again
+                    self.set_draft(slot.next(), Some(Draft::ImportPreview { events: events[imported..].to_vec() }));
+                    return Err(err);
+                }
+            }
+        }
+        self.bot.event_repository.record_action(chat_id, format!("import of {} reminders", imported), ids, Transition::Created).await?;
+        let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
+        self.bot.tg.edit_message_text(message.chat.id, message.message_id, format!("Imported {} reminders", imported), None).await?;
+        self.answer(callback_query, Some(tr(Phrase::CalendarImported, self.locale).to_string())).await
+    }
+
+    // the retry draft is put back into the slot only if the insert fails, after it the reminder exists
+    // and accepting again would store it twice
+    async fn accept(&self, callback_query: &crate::models::CallbackQuery, slot: DraftSlot, retry: Draft, text: &str, delivery: Delivery,
+                    notifications: Vec<StoredNotification>) -> Result<Option<String>, BotError> {
+        let new_text = describe_stored(text, &notifications, Utc::now(), self.timezone, self.locale);
+        let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
+        let (_, context) = self.draft_context.get(slot.key);
+        let delivery = Delivery { silent: context.silent, ..delivery };
+        let ids = match self.bot.event_repository.insert_event_with_delivery(callback_query.from.id, text.to_string(), Source::Telegram, delivery, notifications).await {
+            Ok(ids) => ids,
+            Err(err) => {
+                self.set_draft(slot, Some(retry));
+                return Err(err);
+            }
+        };
+        info!("{:?}", ids);
+        self.bot.event_repository.record_action(callback_query.from.id, format!("adding \"{}\"", text), ids.clone(), Transition::Created).await?;
+        if let Some(location) = context.location {
+            self.bot.event_repository.attach_location(callback_query.from.id, ids.clone(), location).await?;
+        }
+        if let Some(quote) = context.quote {
+            self.bot.event_repository.set_quote(ids.clone(), quote).await?;
+        }
+        if let Some(source_message_id) = context.source_message_id {
+            self.bot.event_repository.set_source_message(ids.clone(), source_message_id).await?;
+        }
+        if context.skip_holidays {
+            self.bot.event_repository.set_holiday_country(ids.clone(), self.bot.holiday_country.clone()).await?;
+        }
+        self.spawn_calendar_push(callback_query.from.id, ids.clone());
+        let group_uid = match context.uid {
+            Some(uid) => {
+                self.bot.event_repository.set_group_uid(ids.clone(), uid.clone()).await?;
+                uid
+            }
+            None => self.group_uid(callback_query.from.id, &ids).await?,
+        };
+        self.bot.edit_with_markup(message.chat.id, message.message_id, new_text, Some(InlineKeyboardMarkup {
+            inline_keyboard: vec![
+                vec![
+                    InlineKeyboardButton {
+                        text: tr(Phrase::Cancel, self.locale).to_string(),
+                        callback_data: CallbackQuery::Delete(group_uid).to_string()
+                    }
+                ]
+            ]
+        }), self.plain).await?;
+
+        Ok(Some(tr(Phrase::NotificationAccepted, self.locale).to_string()))
+    }
+
+    async fn repeat_draft(&self, callback_query: &crate::models::CallbackQuery, slot: DraftSlot, text: &str, failed_before: bool) -> Result<Option<String>, BotError> {
+        let chat_id = callback_query.from.id;
+        let (answer_text, draft) = self.repeat(callback_query, text, failed_before).await?;
+        if !self.set_draft(slot, Some(draft)) {
+            warn!("Draft of chat {} was changed by another update, dropping repeated parse", chat_id);
+        }
+        let (_, context) = self.draft_context.get(slot.key);
+        self.draft_context.set(slot.key, DraftContext { corrected: true, ..context });
+        self.draft_context.retain_latest(|(draft_chat_id, _)| *draft_chat_id == chat_id, MAX_PENDING_DRAFTS);
+        Ok(answer_text)
+    }
+
+    // the next step of the fallback picker replaces the buttons of the draft message
+    async fn pick(&self, slot: DraftSlot, draft: Draft, phrase: Phrase, markup: InlineKeyboardMarkup) -> Result<(), BotError> {
+        if self.set_draft(slot, Some(draft)) {
+            self.bot.edit_with_markup(slot.key.0, slot.key.1, tr(phrase, self.locale).to_string(), Some(markup), self.plain).await?;
+        }
+        Ok(())
+    }
+
+    // the picked time in the user's timezone turns the draft into a regular one waiting for Accept
+    async fn picked(&self, slot: DraftSlot, text: String, date: NaiveDate, hour: u32, minute: u32) -> Result<Option<String>, BotError> {
+        let time = date.and_hms_opt(hour, minute, 0)
+            .and_then(|local| self.timezone.from_local_datetime(&local).earliest())
+            .map(|time| time.with_timezone(&Utc))
+            .filter(|time| *time > Utc::now());
+        let Some(time) = time else {
+            self.pick(slot, Draft::Picking { text, date: Some(date), hour: None }, Phrase::PickHour, hour_markup(self.locale)).await?;
+            return Ok(Some(tr(Phrase::TimePassed, self.locale).to_string()));
+        };
+        let notification = Notification::Absolute {
+            text: text.clone(), times: vec![FormattedTime { time }], leads: vec![], priority: Priority::Normal, nag: None, valid: None,
+        };
+        let reply = describe_notification(&notification, Utc::now(), self.timezone, self.locale);
+        if self.set_draft(slot, Some(Draft::Parsed { text, notification, alternatives: vec![] })) {
+            let markup = draft_markup(1, false, &self.draft_context.get(slot.key).1, self.locale);
+            self.bot.edit_with_markup(slot.key.0, slot.key.1, reply, Some(markup), self.plain).await?;
+        }
+        Ok(None)
+    }
+
+    // a second failure in a row gives up on the parser and offers the picker for the same text
+    async fn repeat(&self, callback_query: &crate::models::CallbackQuery, text: &str, failed_before: bool) -> Result<(Option<String>, Draft), BotError> {
+        let result = self.parse_candidates(callback_query.from.id, text).await;
+        match result {
+            Ok(mut candidates) => {
+                let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
+                let notification = candidates.remove(0);
+                let new_text = describe_candidates(&notification, &candidates, Utc::now(), self.timezone, self.locale);
+                let weekly = matches!(notification, Notification::Recurrent { .. });
+                let markup = draft_markup(candidates.len() + 1, weekly, &self.draft_context.get((message.chat.id, message.message_id)).1, self.locale);
+                self.bot.edit_with_markup(message.chat.id, message.message_id, new_text, Some(markup), self.plain).await?;
+                Ok((Some(tr(Phrase::RequestRepeated, self.locale).to_string()), Draft::Parsed { text: text.to_string(), notification, alternatives: candidates }))
+            }
+            Err(err) if failed_before => {
+                warn!("Parsing failed again, offering the picker: {}", err);
+                let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
+                let today = local_today(self.timezone);
+                let markup = calendar_markup(today.year(), today.month(), today, self.locale);
+                self.bot.edit_with_markup(message.chat.id, message.message_id, tr(Phrase::PickDate, self.locale).to_string(), Some(markup), self.plain).await?;
+                Ok((Some(tr(Phrase::ParseFailed, self.locale).to_string()), Draft::Picking { text: text.to_string(), date: None, hour: None }))
             }
-            (state, _) => (None, state)
-        };
-
-        self.state_channel.send((chat_id, new_state))?;
-        self.bot.tg.answer_callback_query(callback_query.id.clone(), answer_text).await?;
-        Ok(())
+            Err(err) => {
+                let new_text = format!("Error: {}", err);
+                let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
+                let markup = draft_markup(1, false, &self.draft_context.get((message.chat.id, message.message_id)).1, self.locale);
+                self.bot.edit_with_markup(message.chat.id, message.message_id, new_text, Some(markup), self.plain).await?;
+                Ok((Some(tr(Phrase::ParseFailed, self.locale).to_string()), Draft::ParsedWithError { text: text.to_string() }))
+            }
+        }
     }
 
-    async fn accept(&self, callback_query: &crate::models::CallbackQuery, notification: Notification) -> Result<(Option<String>, State), BotError> {
-        let as_json = serde_json::to_string(&notification)?;
-        let new_text = format!("Response: {}", as_json);
-        let ids = self.bot.event_repository.insert_event(callback_query.from.id,  notification.get_text().to_string(), notification.create_stored_notifications(Utc::now())).await?;
-        info!("{:?}", ids);
+    async fn show_remind_again_options(&self, callback_query: &crate::models::CallbackQuery, event_uid: &str) -> Result<(), BotError> {
         let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
-        self.bot.tg.edit_message_text(message.chat.id, message.message_id, new_text, Some(InlineKeyboardMarkup {
+        let option = |text: &str, data: CallbackQuery| vec![InlineKeyboardButton {
+            text: text.to_string(),
+            callback_data: data.to_string()
+        }];
+        let options = InlineKeyboardMarkup {
             inline_keyboard: vec![
-                vec![
-                    InlineKeyboardButton {
-                        text: "Cancel".to_string(),
-                        callback_data: CallbackQuery::Delete(ids).to_string()
-                    }
-                ]
+                option(tr(Phrase::InOneDay, self.locale), CallbackQuery::RemindAgainIn(event_uid.to_string(), 1)),
+                option(tr(Phrase::InOneWeek, self.locale), CallbackQuery::RemindAgainIn(event_uid.to_string(), 7)),
+                option(tr(Phrase::Snooze, self.locale), CallbackQuery::RemindAgainCustom(event_uid.to_string())),
             ]
-        })).await?;
+        };
+        let markup = replace_event_rows(message.reply_markup.as_ref(), event_uid, Some(options));
+        self.bot.edit_markup(message.chat.id, message.message_id, markup, self.plain).await
+    }
 
-        Ok((Some("Notification accepted".to_string()), State::Idle))
+    async fn remind_again_in(&self, callback_query: &crate::models::CallbackQuery, event_id: u64, event_uid: &str, days: u32) -> Result<String, BotError> {
+        let event = self.bot.event_repository.get_event(callback_query.from.id, event_id).await?.ok_or(BotError::InvalidCallbackQuery)?;
+        let time = Utc::now() + chrono::Duration::days(days as i64);
+        let ids = self.bot.event_repository.insert_event_with_delivery(callback_query.from.id, event.text.clone(), Source::Telegram, event.delivery(),
+                                                                       vec![StoredNotification::Absolute { time }]).await?;
+        self.bot.event_repository.record_action(callback_query.from.id, format!("rescheduling \"{}\"", event.text), ids, Transition::Created).await?;
+        self.bot.event_repository.mark_snoozed(callback_query.from.id, event_id).await?;
+        let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
+        let markup = replace_event_rows(message.reply_markup.as_ref(), event_uid, None);
+        self.bot.edit_markup(message.chat.id, message.message_id, markup, self.plain).await?;
+        Ok(i18n::remind_again_at(&humanize::format_time(time, Utc::now(), self.timezone, self.locale), self.locale))
     }
 
-    async fn repeat(&self, callback_query: &crate::models::CallbackQuery, text: &String) -> Result<(Option<String>, State), BotError> {
-        let result = self.bot.parser.parse(Utc::now(), &text).await;
-        match result {
-            Ok(result) => {
-                let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
-                let as_json = serde_json::to_string(&result)?;
-                let new_text = format!("Response: {}", as_json);
-                self.bot.tg.edit_message_text(message.chat.id, message.message_id, new_text, None).await?;
-                Ok((Some("Request was repeated".to_string()), State::Parsed { text: text.clone(), notification: result }))
-            }
+    async fn prompt_snooze(&self, callback_query: &crate::models::CallbackQuery, event_id: u64, event_uid: &str) -> Result<Option<String>, BotError> {
+        let event = self.bot.event_repository.get_event(callback_query.from.id, event_id).await?.ok_or(BotError::InvalidCallbackQuery)?;
+        let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
+        let markup = replace_event_rows(message.reply_markup.as_ref(), event_uid, None);
+        self.bot.edit_markup(message.chat.id, message.message_id, markup, self.plain).await?;
+        self.bot.tg.send_force_reply(callback_query.from.id, i18n::snooze_prompt(&event.text, self.locale),
+                                     Some("in 45 min, after lunch…".to_string())).await?;
+        self.set_state(callback_query.from.id, State::AwaitingSnooze { event_id, text: event.text });
+        Ok(None)
+    }
+
+    // the answer only says when, so it's parsed together with the reminder text and scheduled right away
+    async fn snooze(&self, chat_id: u64, event_id: u64, original: &str, when: &str) -> Result<(), BotError> {
+        let notification = match self.parse(chat_id, &format!("Remind me about \"{}\" {}", original, when)).await {
+            Ok(notification) => notification,
             Err(err) => {
-                let new_text = format!("Error: {}", err);
-                let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
-                self.bot.tg.edit_message_text(message.chat.id, message.message_id, new_text, None).await?;
-                Ok((Some("Error while parsing command".to_string()), State::ParsedWithError { text: text.clone() }))
+                self.set_state(chat_id, State::Idle);
+                return self.reply(chat_id, format!("Couldn't understand when to remind you again: {}", err), None).await;
             }
+        };
+        let notifications = notification.create_stored_notifications(Utc::now(), self.timezone);
+        let text = describe_stored(original, &notifications, Utc::now(), self.timezone, self.locale);
+        let ids = self.bot.event_repository.insert_event_with_delivery(chat_id, original.to_string(), Source::Telegram, notification.get_delivery(), notifications).await?;
+        self.bot.event_repository.record_action(chat_id, format!("rescheduling \"{}\"", original), ids.clone(), Transition::Created).await?;
+        self.bot.event_repository.mark_snoozed(chat_id, event_id).await?;
+        self.set_state(chat_id, State::Idle);
+        let group_uid = self.group_uid(chat_id, &ids).await?;
+        self.bot.send_with_markup(chat_id, text, InlineKeyboardMarkup {
+            inline_keyboard: vec![vec![InlineKeyboardButton {
+                text: "Cancel".to_string(),
+                callback_data: CallbackQuery::Delete(group_uid).to_string()
+            }]]
+        }, self.plain).await?;
+        Ok(())
+    }
+
+    async fn decide_access(&self, callback_query: &crate::models::CallbackQuery, user_id: u64, approve: bool) -> Result<String, BotError> {
+        if self.role != Role::Admin {
+            return Ok("Only admins can decide on join requests".to_string());
+        }
+        let status = if approve { AccessStatus::Approved } else { AccessStatus::Rejected };
+        if !self.bot.event_repository.decide_access(user_id, status).await? {
+            return Ok("This request was already decided".to_string());
+        }
+        if approve {
+            self.bot.user_repository.add(user_id);
+            self.reply(user_id, "Your access was approved, send me what to remind you about".to_string(), None).await?;
+        } else {
+            self.reply(user_id, "Your access request was declined".to_string(), None).await?;
         }
+        let message = callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?;
+        self.bot.tg.edit_message_text(message.chat.id, message.message_id, format!("User {} {}", user_id, status.as_str()), None).await?;
+        Ok(format!("User {}", status.as_str()))
     }
 
-    async fn cancel(&self, callback_query: &crate::models::CallbackQuery) -> Result<(Option<String>, State), BotError> {
+    async fn cancel(&self, callback_query: &crate::models::CallbackQuery) -> Result<Option<String>, BotError> {
         self.bot.tg.delete_message( callback_query.from.id,
                                 callback_query.message.as_ref().ok_or(BotError::InvalidCallbackQuery)?.message_id).await?;
-        Ok((Some("Canceled".to_string()), State::Idle))
+        Ok(Some("Canceled".to_string()))
     }
 }
 
@@ -172,12 +2713,71 @@ pub struct Bot {
 pub struct BotHandler {
     bot: Arc<BotDeps>,
     state: State,
-    state_channel: tokio::sync::mpsc::UnboundedSender<(u64, State)>
+    version: u64,
+    states: StateStore<u64, State>,
+    drafts: StateStore<DraftKey, Option<Draft>>,
+    draft_sources: StateStore<DraftSourceKey, Option<u64>>,
+    draft_context: StateStore<DraftKey, DraftContext>,
+    locale: Locale,
+    // times are read and shown on the wall clock of the user
+    timezone: Tz,
+    plain: bool,
+    role: Role,
 }
 
 #[derive(Debug)]
 enum CallbackQuery {
-    Repeat, Accept, Cancel, Delete(Vec<u64>)
+    // reminders are referred to by their public ids, Delete by the one of the group its rows were created in
+    Repeat, Accept, Pick(usize), Edit, Silent, Holidays, Cancel, KeepBoth, Shift, Delete(String),
+    RemindAgain(String), RemindAgainIn(String, u32), RemindAgainCustom(String),
+    Join(u64, bool), Forget(String), Done(String), Skip(String), Template(u64),
+    CalendarMonth(i32, u32), PickDay(NaiveDate), PickHour(u32), PickMinute(u32), Noop,
+    // timezone found from a location pin, set once the user confirms it
+    Timezone(Tz),
+}
+
+impl CallbackQuery {
+    // name of the button for the update span, without the ids it carries
+    fn kind(&self) -> &'static str {
+        match self {
+            CallbackQuery::Repeat => "repeat",
+            CallbackQuery::Accept => "accept",
+            CallbackQuery::Pick(_) => "pick",
+            CallbackQuery::Edit => "edit",
+            CallbackQuery::Silent => "silent",
+            CallbackQuery::Holidays => "holidays",
+            CallbackQuery::Cancel => "cancel",
+            CallbackQuery::KeepBoth => "keep",
+            CallbackQuery::Shift => "shift",
+            CallbackQuery::Delete(_) => "delete",
+            CallbackQuery::RemindAgain(_) | CallbackQuery::RemindAgainIn(..) | CallbackQuery::RemindAgainCustom(_) => "again",
+            CallbackQuery::Join(..) => "join",
+            CallbackQuery::Forget(_) => "forget",
+            CallbackQuery::Done(_) => "done",
+            CallbackQuery::Skip(_) => "skip",
+            CallbackQuery::Template(_) => "template",
+            CallbackQuery::CalendarMonth(..) | CallbackQuery::PickDay(_) | CallbackQuery::PickHour(_) | CallbackQuery::PickMinute(_) => "picker",
+            CallbackQuery::Noop => "noop",
+            CallbackQuery::Timezone(_) => "timezone",
+        }
+    }
+
+    // reminder a button of a fired message acts on
+    fn fired_event_uid(&self) -> Option<&str> {
+        match self {
+            CallbackQuery::RemindAgain(event_uid) | CallbackQuery::RemindAgainIn(event_uid, _) | CallbackQuery::RemindAgainCustom(event_uid)
+            | CallbackQuery::Done(event_uid) | CallbackQuery::Skip(event_uid) => Some(event_uid),
+            _ => None,
+        }
+    }
+}
+
+// uuids only, so the separators of the callback data can't end up inside an id
+fn parse_uid(s: &str) -> Result<String, BotError> {
+    if s.is_empty() || !s.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+        return Err(BotError::InvalidCallbackQuery);
+    }
+    Ok(s.to_string())
 }
 
 impl FromStr for CallbackQuery {
@@ -187,57 +2787,309 @@ impl FromStr for CallbackQuery {
         match s {
             "repeat" => Ok(CallbackQuery::Repeat),
             "accept" => Ok(CallbackQuery::Accept),
+            "edit" => Ok(CallbackQuery::Edit),
+            "silent" => Ok(CallbackQuery::Silent),
+            "holidays" => Ok(CallbackQuery::Holidays),
+            "noop" => Ok(CallbackQuery::Noop),
+            _ if s.starts_with("cal:") => NaiveDate::parse_from_str(&format!("{}-01", &s["cal:".len()..]), "%Y-%m-%d")
+                .map(|month| CallbackQuery::CalendarMonth(month.year(), month.month()))
+                .map_err(|_| BotError::InvalidCallbackQuery),
+            _ if s.starts_with("day:") => NaiveDate::parse_from_str(&s["day:".len()..], "%Y-%m-%d")
+                .map(CallbackQuery::PickDay)
+                .map_err(|_| BotError::InvalidCallbackQuery),
+            _ if s.starts_with("hour:") => s["hour:".len()..].parse::<u32>().ok()
+                .filter(|hour| *hour < 24)
+                .map(CallbackQuery::PickHour)
+                .ok_or(BotError::InvalidCallbackQuery),
+            _ if s.starts_with("minute:") => s["minute:".len()..].parse::<u32>().ok()
+                .filter(|minute| *minute < 60)
+                .map(CallbackQuery::PickMinute)
+                .ok_or(BotError::InvalidCallbackQuery),
             "cancel" => Ok(CallbackQuery::Cancel),
-            _ => {
-                let ids: Result<Vec<u64>, _> = s.split(',').map(|s| u64::from_str(s).map_err(|_| BotError::InvalidCallbackQuery)).collect();
-                Ok(CallbackQuery::Delete(ids?))
+            "keep" => Ok(CallbackQuery::KeepBoth),
+            "shift" => Ok(CallbackQuery::Shift),
+            _ if s.starts_with("pick:") => s["pick:".len()..].parse::<usize>()
+                .map(CallbackQuery::Pick)
+                .map_err(|_| BotError::InvalidCallbackQuery),
+            _ if s.starts_with("delete:") => parse_uid(&s["delete:".len()..]).map(CallbackQuery::Delete),
+            _ if s.starts_with("done:") => parse_uid(&s["done:".len()..]).map(CallbackQuery::Done),
+            _ if s.starts_with("skip:") => parse_uid(&s["skip:".len()..]).map(CallbackQuery::Skip),
+            _ if s.starts_with("template:") => s["template:".len()..].parse::<u64>()
+                .map(CallbackQuery::Template)
+                .map_err(|_| BotError::InvalidCallbackQuery),
+            _ if s.starts_with("tz:") => s["tz:".len()..].parse::<Tz>()
+                .map(CallbackQuery::Timezone)
+                .map_err(|_| BotError::InvalidCallbackQuery),
+            _ if s.starts_with("forget:") => parse_uid(&s["forget:".len()..]).map(CallbackQuery::Forget),
+            _ if s.starts_with("join:") => {
+                let (user_id, decision) = s["join:".len()..].split_once(':').ok_or(BotError::InvalidCallbackQuery)?;
+                let user_id = user_id.parse::<u64>().map_err(|_| BotError::InvalidCallbackQuery)?;
+                match decision {
+                    "approve" => Ok(CallbackQuery::Join(user_id, true)),
+                    "reject" => Ok(CallbackQuery::Join(user_id, false)),
+                    _ => Err(BotError::InvalidCallbackQuery),
+                }
+            }
+            _ if s.starts_with("again:") => {
+                let mut parts = s["again:".len()..].split(':');
+                let event_uid = parse_uid(parts.next().unwrap_or_default())?;
+                match parts.next() {
+                    None => Ok(CallbackQuery::RemindAgain(event_uid)),
+                    Some("custom") => Ok(CallbackQuery::RemindAgainCustom(event_uid)),
+                    Some(days) => days.parse::<u32>()
+                        .map(|days| CallbackQuery::RemindAgainIn(event_uid, days))
+                        .map_err(|_| BotError::InvalidCallbackQuery),
+                }
             }
+            _ => Err(BotError::InvalidCallbackQuery),
         }
     }
 }
 
-impl ToString for CallbackQuery {
-    fn to_string(&self) -> String {
+impl Display for CallbackQuery {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            CallbackQuery::Repeat => "repeat".to_string(),
-            CallbackQuery::Accept => "accept".to_string(),
-            CallbackQuery::Cancel => "cancel".to_string(),
-            CallbackQuery::Delete(ids) => {
-                // write ids as string separated by comma with only one allocation
-                let mut s = String::with_capacity(ids.len() * 10);
-                for id in ids {
-                    let _ = write!(s, "{},", id);
+            CallbackQuery::Repeat => f.write_str("repeat"),
+            CallbackQuery::Accept => f.write_str("accept"),
+            CallbackQuery::Pick(option) => write!(f, "pick:{}", option),
+            CallbackQuery::Edit => f.write_str("edit"),
+            CallbackQuery::Silent => f.write_str("silent"),
+            CallbackQuery::Holidays => f.write_str("holidays"),
+            CallbackQuery::CalendarMonth(year, month) => write!(f, "cal:{}-{:02}", year, month),
+            CallbackQuery::PickDay(date) => write!(f, "day:{}", date.format("%Y-%m-%d")),
+            CallbackQuery::PickHour(hour) => write!(f, "hour:{}", hour),
+            CallbackQuery::PickMinute(minute) => write!(f, "minute:{}", minute),
+            CallbackQuery::Noop => f.write_str("noop"),
+            CallbackQuery::Cancel => f.write_str("cancel"),
+            CallbackQuery::KeepBoth => f.write_str("keep"),
+            CallbackQuery::Shift => f.write_str("shift"),
+            CallbackQuery::Delete(group_uid) => write!(f, "delete:{}", group_uid),
+            CallbackQuery::RemindAgain(event_uid) => write!(f, "again:{}", event_uid),
+            CallbackQuery::RemindAgainIn(event_uid, days) => write!(f, "again:{}:{}", event_uid, days),
+            CallbackQuery::RemindAgainCustom(event_uid) => write!(f, "again:{}:custom", event_uid),
+            CallbackQuery::Join(user_id, approve) => write!(f, "join:{}:{}", user_id, if *approve { "approve" } else { "reject" }),
+            CallbackQuery::Forget(event_uid) => write!(f, "forget:{}", event_uid),
+            CallbackQuery::Done(event_uid) => write!(f, "done:{}", event_uid),
+            CallbackQuery::Template(template_id) => write!(f, "template:{}", template_id),
+            CallbackQuery::Skip(event_uid) => write!(f, "skip:{}", event_uid),
+            CallbackQuery::Timezone(timezone) => write!(f, "tz:{}", timezone.name()),
+        }
+    }
+}
+
+impl Bot {
+
+    async fn run_one_background_loop(&self) -> Result<(), BotError> {
+        let now = Utc::now();
+        let events_to_fire = self.dependency.event_repository.get_events_to_fire(now, self.dependency.fire_batch_size).await;
+        self.dependency.subsystems.record(Subsystem::Database, &events_to_fire);
+        let events_to_fire = events_to_fire?;
+        if events_to_fire.len() == self.dependency.fire_batch_size {
+            info!("Firing a full batch of {} events, the rest follow on the next ticks", events_to_fire.len());
+        }
+        let mut due = Vec::with_capacity(events_to_fire.len());
+        for mut event in events_to_fire {
+            if self.dependency.log_redact {
+                info!(event_id = event.event_id, user_id = event.user_id, "Firing event");
+            } else {
+                info!(event = ?event, "Firing event");
+            }
+            let overdue = event.due_at.map_or(0, |due_at| (now - due_at).num_minutes().max(0)) as u32;
+            // past its own validity window the reminder is pointless, at most the user learns they missed it
+            if event.delivery.expires_minutes > 0 && overdue > event.delivery.expires_minutes {
+                info!("Event {} expired {} minutes after it was due", event.event_id, overdue);
+                self.dependency.event_repository.expire(vec![event.event_id], now).await?;
+                if self.dependency.expiry_notice {
+                    self.send_expiry_notice(&event).await;
+                }
+                continue;
+            }
+            // reminders that piled up while the bot was down are handled by the missed policy
+            if overdue >= self.dependency.missed_threshold_minutes.max(1) {
+                match self.dependency.missed_policy {
+                    MissedPolicy::Deliver => {}
+                    MissedPolicy::Prefix | MissedPolicy::Summary => event.overdue_minutes = overdue,
+                    MissedPolicy::Expire => {
+                        info!("Expiring event {}, overdue by {} minutes", event.event_id, overdue);
+                        self.dependency.event_repository.expire(vec![event.event_id], now).await?;
+                        continue;
+                    }
                 }
-                if s.ends_with(",") {
-                    s.pop();
+            }
+            if let Some(release_at) = self.quiet_until(&event, now).await? {
+                info!("Holding event {} back until {}", event.event_id, release_at);
+                self.dependency.event_repository.defer_delivery(event.event_id, event.user_id, release_at).await?;
+                self.dependency.event_repository.mark_fired(vec![event.event_id], now).await?;
+                continue;
+            }
+            due.push(event);
+        }
+
+        // reminders of a user that are due in the same tick arrive as one digest,
+        // with the summary policy the missed ones get a digest of their own
+        let summary = self.dependency.missed_policy == MissedPolicy::Summary;
+        due.sort_by_key(|event| (event.user_id, summary && event.overdue_minutes > 0));
+        for events in due.chunk_by(|a, b| a.user_id == b.user_id && (!summary || (a.overdue_minutes > 0) == (b.overdue_minutes > 0))) {
+            let delivered = match events {
+                [event] => self.deliver(event).await,
+                _ if summary && events[0].overdue_minutes > 0 => self.deliver_digest(|locale| i18n::missed_summary(events.len(), locale), events).await,
+                _ => self.deliver_digest(|locale| i18n::due_digest(events.len(), locale), events).await,
+            };
+            // every user is settled on their own so one failed send can't hold back or drop the others
+            match delivered {
+                Ok(_) => self.dependency.event_repository.mark_fired(events.iter().map(|event| event.event_id).collect(), now).await?,
+                Err(err) => {
+                    for event in events {
+                        let dead_lettered = self.dependency.event_repository
+                            .record_delivery_failure(event.event_id, err.to_string(), self.dependency.delivery_max_attempts)
+                            .await?;
+                        if dead_lettered {
+                            error!("Giving up on delivering event {}: {}", event.event_id, err);
+                        } else {
+                            warn!("Failed to deliver event {}, will retry: {}", event.event_id, err);
+                        }
+                    }
                 }
-                s
             }
+        }
+
+        self.release_deferred(now).await?;
+        self.resend_unacknowledged().await
+    }
 
+    // a failed notice is only logged, the reminder has already expired
+    async fn send_expiry_notice(&self, event: &EventToFire) {
+        let sent = async {
+            let locale = self.dependency.event_repository.get_user_settings(event.user_id).await?.language.unwrap_or_default();
+            self.dependency.tg.send_notification(event.user_id, i18n::missed_notice(&event.text, locale), None, Some(true), None).await
+        };
+        if let Err(err) = sent.await {
+            warn!("Failed to tell {} about expired event {}: {}", event.user_id, event.event_id, err);
         }
     }
-}
 
-impl Bot {
+    // end of the quiet hours the user is in, urgent reminders are never held
+    async fn quiet_until(&self, event: &EventToFire, now: DateTime<Utc>) -> Result<Option<DateTime<Utc>>, BotError> {
+        if event.delivery.priority == Priority::Urgent {
+            return Ok(None);
+        }
+        let Some(quiet_hours) = self.dependency.event_repository.get_user_settings(event.user_id).await?.quiet_hours else {
+            return Ok(None);
+        };
+        let timezone = self.dependency.event_repository.get_timezone(event.user_id).await?;
+        Ok(quiet_hours.contains(now, timezone).then(|| quiet_hours.end_after(now, timezone)))
+    }
 
-    async fn run_one_background_loop(&self) -> Result<(), BotError> {
-        let events_to_fire = self.dependency.event_repository.get_events_to_fire(Utc::now()).await?;
-        let event_ids = events_to_fire.iter().map(|e| e.event_id).collect::<Vec<_>>();
-        let reply_markup = InlineKeyboardMarkup {
-            inline_keyboard: vec![]
+    // reminders held during the quiet hours arrive together as a digest
+    async fn release_deferred(&self, now: DateTime<Utc>) -> Result<(), BotError> {
+        let deferred = self.dependency.event_repository.get_due_deferred(now).await?;
+        for events in deferred.chunk_by(|a, b| a.user_id == b.user_id) {
+            let user_id = events[0].user_id;
+            let sent = match events {
+                [event] => self.send_fired(event).await,
+                _ => self.send_digest(|locale| tr(Phrase::QuietHoursDigest, locale).to_string(), events).await,
+            };
+            if let Err(err) = sent {
+                warn!("Failed to release held reminders of {}: {}", user_id, err);
+                continue;
+            }
+            for event in events {
+                self.after_delivery(event).await?;
+            }
+            self.dependency.event_repository.remove_deferred(events.iter().map(|event| event.event_id).collect()).await?;
+        }
+        Ok(())
+    }
+
+    async fn deliver(&self, event: &EventToFire) -> Result<(), BotError> {
+        self.send_fired(event).await?;
+        self.after_delivery(event).await
+    }
+
+    // the map pin follows the reminder, nagging starts and webhooks are called once the user got it
+    async fn after_delivery(&self, event: &EventToFire) -> Result<(), BotError> {
+        if let Some(location) = self.dependency.event_repository.get_event_location(event.event_id).await? {
+            if let Err(err) = self.dependency.tg.send_location(event.user_id, location).await {
+                warn!("Failed to send location of event {}: {}", event.event_id, err);
+            }
+        }
+        if event.delivery.awaits_done() {
+            self.dependency.event_repository.track_delivery(event.event_id, Utc::now(), 0).await?;
+        }
+
+        // a slow endpoint shouldn't hold back the reminders that fire after this one
+        for webhook in self.dependency.event_repository.get_event_webhooks(event.event_id).await? {
+            let dependency = self.dependency.clone();
+            let (user_id, event_id, text) = (event.user_id, event.event_id, event.text.clone());
+            tokio::spawn(async move {
+                if let Err(err) = dependency.run_webhook(user_id, &webhook, Some(event_id), Some(&text)).await {
+                    error!("Webhook {} failed for event {}: {}", webhook.name, event_id, err);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    async fn deliver_digest(&self, header: impl Fn(Locale) -> String, events: &[EventToFire]) -> Result<(), BotError> {
+        self.send_digest(header, events).await?;
+        for event in events {
+            self.after_delivery(event).await?;
+        }
+        Ok(())
+    }
+
+    // a digest rings if any of its reminders has to ring and is silent only when all of them are low priority or silent
+    async fn send_digest(&self, header: impl Fn(Locale) -> String, events: &[EventToFire]) -> Result<u64, BotError> {
+        let user_id = events[0].user_id;
+        let settings = self.dependency.event_repository.get_user_settings(user_id).await?;
+        let locale = settings.language.unwrap_or_default();
+        let (text, markup) = digest(&header(locale), events, locale);
+        let disable_notification = if events.iter().any(|event| event.delivery.disable_notification() == Some(false)) {
+            Some(false)
+        } else if events.iter().all(|event| event.delivery.disable_notification() == Some(true)) {
+            Some(true)
+        } else {
+            None
         };
-        for event in events_to_fire {
-            info!("{:?}", event);
-            self.dependency.tg.send_message(event.user_id, event.text, Some(reply_markup.clone())).await?;
+        self.dependency.send_notification(user_id, text, markup, settings.plain_mode, disable_notification, None).await
+    }
+
+    // urgent reminders ring even in muted chats, low priority and silent ones arrive without sound;
+    // every one carries a done button that marks it completed and stops the repeats of urgent and nagging ones,
+    // recurrent ones can skip their next occurrence;
+    // a reminder parsed from a message replies to it, so the original context is one tap away
+    async fn send_fired(&self, event: &EventToFire) -> Result<u64, BotError> {
+        let settings = self.dependency.event_repository.get_user_settings(event.user_id).await?;
+        let locale = settings.language.unwrap_or_default();
+        let text = fired_text(event, locale);
+        let message_id = self.dependency.send_notification(event.user_id, text, remind_again_markup(&event.event_uid, true, event.is_recurrent, locale),
+                                                           settings.plain_mode, event.delivery.disable_notification(), event.source_message_id).await?;
+        if event.delivery.priority == Priority::Urgent {
+            self.dependency.pin_fired(event.user_id, event.event_id, message_id).await;
         }
-        self.dependency.event_repository.delete_events(event_ids).await?;
+        Ok(message_id)
+    }
 
+    async fn resend_unacknowledged(&self) -> Result<(), BotError> {
+        let deliveries = self.dependency.event_repository
+            .get_unacknowledged(Utc::now(), self.dependency.urgent_resend_window, self.dependency.urgent_max_resends).await?;
+        for (event, resends) in deliveries {
+            if self.quiet_until(&event, Utc::now()).await?.is_some() {
+                continue;
+            }
+            info!("Sending unacknowledged event {} again after {} resends", event.event_id, resends);
+            match self.send_fired(&event).await {
+                Ok(_) => self.dependency.event_repository.track_delivery(event.event_id, Utc::now(), resends + 1).await?,
+                Err(err) => warn!("Failed to send event {} again: {}", event.event_id, err),
+            }
+        }
         Ok(())
     }
 
     async fn run_background(&self) {
         info!("Background loop started");
         loop {
+            self.dependency.heartbeats.beat(Task::Background);
             match self.run_one_background_loop().await {
                 Ok(_) => (),
                 Err(err) => {
@@ -249,53 +3101,768 @@ impl Bot {
         }
     }
 
+    async fn run_cleanup(&self) -> Result<(), BotError> {
+        let cutoff = Utc::now() - self.dependency.cleanup_retention;
+        let purged = self.dependency.event_repository.purge_closed_events(cutoff).await?;
+        info!("Cleanup purged {} closed events", purged);
+        self.dependency.event_repository.vacuum().await
+    }
+
+    // steps wait while reminders are due, so maintenance never holds the database when one has to go out
+    async fn run_maintenance(&self) -> Result<MaintenanceReport, BotError> {
+        let repository = &self.dependency.event_repository;
+        let mut steps = vec![];
+        for step in MaintenanceStep::ALL {
+            while !repository.get_events_to_fire(Utc::now() + chrono::Duration::seconds(MAINTENANCE_DUE_MARGIN_SECS), 1).await?.is_empty() {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+            let started = Instant::now();
+            repository.run_maintenance_step(step).await?;
+            steps.push((step, started.elapsed().as_millis() as u64));
+        }
+        Ok(MaintenanceReport { finished_at: Utc::now(), steps })
+    }
+
+    pub fn run_maintenance_task(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let next = next_maintenance_time(Utc::now(), self.dependency.maintenance_hour);
+                tokio::time::sleep((next - Utc::now()).to_std().unwrap_or_default()).await;
+                match self.run_maintenance().await {
+                    Ok(report) => {
+                        info!("Database maintenance finished: {:?}", report.steps);
+                        *self.dependency.last_maintenance.write().unwrap_or_else(PoisonError::into_inner) = Some(report);
+                    }
+                    Err(err) => error!("Error in maintenance task: {}", err),
+                }
+            }
+        })
+    }
+
+    // upcoming events of the calendars connected for pulling become reminders, every event once;
+    // an event moved or deleted in the calendar after the pull keeps its reminder as it was
+    async fn pull_calendars(&self) -> Result<(), BotError> {
+        let Some(calendar) = &self.dependency.calendar else {
+            return Ok(());
+        };
+        for connection in self.dependency.event_repository.get_calendar_connections().await? {
+            if !connection.pull || !self.dependency.user_repository.is_chat_id_valid(connection.user_id) {
+                continue;
+            }
+            // a revoked or broken connection of one user shouldn't stop the others
+            if let Err(err) = self.pull_calendar(calendar, &connection).await {
+                warn!("Pulling google calendar of {} failed: {}", connection.user_id, err);
+            }
+        }
+        Ok(())
+    }
+
+    async fn pull_calendar(&self, calendar: &GoogleCalendar, connection: &CalendarConnection) -> Result<(), BotError> {
+        let repository = &self.dependency.event_repository;
+        let user_id = connection.user_id;
+        let access_token = calendar.access_token(&connection.refresh_token).await?;
+        let now = Utc::now();
+        let events = calendar.list_events(&access_token, now, now + chrono::Duration::days(CALENDAR_PULL_DAYS)).await?;
+        let timezone = repository.get_timezone(user_id).await?;
+        let locale = repository.get_user_settings(user_id).await?.language.unwrap_or_default();
+        for event in &events {
+            let (Some(google_id), Some((text, time))) = (event.id(), gcal::to_reminder(event, timezone)) else {
+                continue;
+            };
+            if time <= now {
+                continue;
+            }
+            let reply = describe_stored(&text, &[StoredNotification::Absolute { time }], now, timezone, locale);
+            if repository.insert_pulled_event(user_id, google_id.to_string(), text, time).await?.is_none() {
+                continue;
+            }
+            self.dependency.tg.send_message(user_id, format!("From Google Calendar:\n{}", reply), None).await?;
+        }
+        Ok(())
+    }
+
+    pub fn run_calendar_task(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = self.pull_calendars().await {
+                    error!("Error in calendar task: {}", err);
+                }
+                tokio::time::sleep(self.dependency.calendar_pull_interval).await;
+            }
+        })
+    }
+
+    pub fn run_demo_wipe_task(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(self.dependency.demo_wipe_interval).await;
+                match self.dependency.event_repository.wipe().await {
+                    Ok(deleted) => info!("Demo wipe removed {} events", deleted),
+                    Err(err) => error!("Error in demo wipe task: {}", err),
+                }
+            }
+        })
+    }
+
+    pub fn run_cleanup_task(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = self.run_cleanup().await {
+                    error!("Error in cleanup task: {}", err);
+                }
+                tokio::time::sleep(self.dependency.cleanup_interval).await;
+            }
+        })
+    }
+
     pub fn run_background_task(self) -> JoinHandle<()> {
         tokio::spawn(async move { self.run_background().await })
     }
 
     pub async fn run(&self) -> Result<(), BotError> {
-        let mut last_offset = 0_u64;
-        let mut state = FnvHashMap::default();
-        let (state_sender, mut state_receiver) = tokio::sync::mpsc::unbounded_channel();
+        // telegram only forgets updates once they are asked for with a greater offset,
+        // so without the stored one a restart would handle the last batch again
+        let mut last_offset = self.dependency.event_repository.get_update_offset().await?;
+        if last_offset > 0 {
+            info!("Resuming updates from offset {}", last_offset);
+        }
+        let mut stored_offset = last_offset;
+        let states = StateStore::new();
+        let drafts = StateStore::new();
+        let draft_sources = StateStore::new();
+        let draft_context = StateStore::new();
         info!("Bot is started");
         loop {
-            let updates = self.dependency.tg.get_updates(last_offset).await;
+            self.dependency.heartbeats.beat(Task::Polling);
+            let updates = self.dependency.tg.get_updates(last_offset, self.dependency.poll_timeout).await;
+            self.dependency.subsystems.record(Subsystem::Telegram, &updates);
             match updates {
                 Ok(updates) => {
                     for update in updates {
                         last_offset = update.update_id + 1;
 
-                        if let Some(chat_id) = update.get_chat_id() {
-                            if !self.dependency.user_repository.is_chat_id_valid(chat_id) {
+                        if let Some(connection) = update.business_connection {
+                            let bot = self.dependency.clone();
+                            tokio::spawn(async move {
+                                if let Err(err) = bot.connect_business(connection).await {
+                                    error!("Error while updating business connection: {}", err);
+                                }
+                            });
+                            continue;
+                        }
+                        let update = match self.dependency.route_business_message(update).await {
+                            Ok(Some(update)) => update,
+                            Ok(None) => continue,
+                            Err(err) => {
+                                error!("Error while routing business message: {}", err);
                                 continue;
                             }
+                        };
 
-                            info!("{:?}", update);
-
-                            let bot_handler = BotHandler {
-                                bot: self.dependency.clone(),
-                                state: state.get(&chat_id).cloned().unwrap_or(State::Idle),
-                                state_channel: state_sender.clone()
+                        if let Some(chat_id) = update.get_chat_id() {
+                            // customers of a connected business account are regular users
+                            let role = match self.dependency.user_repository.role(chat_id) {
+                                Some(role) => role,
+                                None if self.dependency.tg.is_business_chat(chat_id) => Role::User,
+                                None => {
+                                    let start = update.message.as_ref()
+                                        .and_then(|message| message.text.as_deref())
+                                        .and_then(|text| text.split_whitespace().next())
+                                        .is_some_and(|command| commands::resolve(command, Locale::En) == Resolution::Known("/start"));
+                                    if let (true, Some(message)) = (start, update.message) {
+                                        let bot = self.dependency.clone();
+                                        tokio::spawn(async move {
+                                            if let Err(err) = bot.request_access(&message).await {
+                                                error!("Error while requesting access for chat {}: {}", chat_id, err);
+                                            }
+                                        });
+                                    }
+                                    continue;
+                                }
                             };
-                            let _ = tokio::spawn(async move {
+
+                            let span = info_span!("update", update_id = update.update_id, chat_id, callback = field::Empty);
+                            if let Some(data) = update.callback_query.as_ref().and_then(|query| query.data.as_deref()) {
+                                span.record("callback", data.parse::<CallbackQuery>().map_or("invalid", |query| query.kind()));
+                            }
+                            span.in_scope(|| if self.dependency.log_redact {
+                                info!(update = ?update.redacted(), "Received update")
+                            } else {
+                                info!(update = ?update, "Received update")
+                            });
+
+                            let (version, state) = states.get(chat_id);
+                            let bot = self.dependency.clone();
+                            let states = states.clone();
+                            let drafts = drafts.clone();
+                            let draft_sources = draft_sources.clone();
+                            let draft_context = draft_context.clone();
+                            tokio::spawn(async move {
+                                let settings = match bot.event_repository.get_user_settings(chat_id).await {
+                                    Ok(settings) => settings,
+                                    Err(err) => {
+                                        error!("Error while loading settings of chat {}: {}", chat_id, err);
+                                        Default::default()
+                                    }
+                                };
+                                let timezone = match bot.event_repository.get_timezone(chat_id).await {
+                                    Ok(timezone) => timezone,
+                                    Err(err) => {
+                                        error!("Error while loading timezone of chat {}: {}", chat_id, err);
+                                        DEFAULT_TIMEZONE
+                                    }
+                                };
+                                let bot_handler = BotHandler {
+                                    bot,
+                                    state,
+                                    version,
+                                    states,
+                                    drafts,
+                                    draft_sources,
+                                    draft_context,
+                                    // a language picked with /language wins over the one of the telegram client
+                                    locale: settings.language.unwrap_or_else(|| Locale::from_language_code(update.get_language_code())),
+                                    timezone,
+                                    plain: settings.plain_mode,
+                                    role,
+                                };
                                 let err = bot_handler.handle_update(update).await;
                                 if let Err(err) = err {
                                     info!("Error in update handler: {}", err);
                                 }
-                            });
+                            }.instrument(span));
+                        }
+                    }
+                    // stored once the batch is dispatched, a crash in between is the only case an update is handled twice
+                    if last_offset != stored_offset {
+                        match self.dependency.event_repository.set_update_offset(last_offset).await {
+                            Ok(_) => stored_offset = last_offset,
+                            Err(err) => error!("Error while storing update offset {}: {}", last_offset, err),
                         }
                     }
                 },
                 Err(err) => {
                     info!("Error: {}", err);
+                    // the long poll returns right away while telegram is unreachable
+                    tokio::time::sleep(POLL_ERROR_DELAY).await;
                 }
             }
+        }
+    }
+}
 
-            while let Ok((chat_id, new_state)) = state_receiver.try_recv() {
-                state.insert(chat_id, new_state);
-            }
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use chrono::{Datelike, DateTime, Duration, TimeZone, Utc};
+    use envconfig::Envconfig;
+    use crate::db::{Outcome, Role, Source, IN_MEMORY};
+    use crate::humanize::Locale;
+    use crate::state::StateStore;
+    use crate::tg::{RecordingTg, TgCall};
+    use super::{digest, next_maintenance_time, parse_pause_end, replace_event_rows, split_cron_args, BotDeps, BotHandler, CallbackQuery, Draft, DraftContext, State};
+    use crate::models::{Delivery, Env, EventToFire, FormattedTime, Notification, Priority, StoredNotification, Time, DEFAULT_TIMEZONE};
+
+    async fn create_handler(tg: Arc<RecordingTg>, role: Role) -> BotHandler {
+        let env = Env::init_from_hashmap(&HashMap::from([
+            ("TG_KEY", "key"), ("OAI_TOKEN", "key"), ("TG_USERS", "1"), ("CONN_STRING", IN_MEMORY), ("CONFLICT_WINDOW_MINUTES", "0"),
+        ].map(|(name, value)| (name.to_string(), value.to_string())))).unwrap();
+        let deps = BotDeps::new(&env).await.unwrap();
+        BotHandler {
+            bot: Arc::new(BotDeps { tg, ..deps }),
+            state: State::Idle,
+            version: 0,
+            states: StateStore::new(),
+            drafts: StateStore::new(),
+            draft_sources: StateStore::new(),
+            draft_context: StateStore::new(),
+            locale: Locale::En,
+            timezone: DEFAULT_TIMEZONE,
+            plain: false,
+            role,
+        }
+    }
+
+    // a button of the draft under message 10 of chat 1
+    fn press(data: &str) -> crate::models::CallbackQuery {
+        serde_json::from_value(serde_json::json!({
+            "id": "query",
+            "from": { "id": 1 },
+            "message": { "message_id": 10, "date": 0, "chat": { "id": 1 } },
+            "data": data,
+        })).unwrap()
+    }
+
+    fn answer(text: &str) -> TgCall {
+        TgCall::AnswerCallbackQuery { text: Some(text.to_string()) }
+    }
+
+    #[test]
+    fn should_round_trip_callback_data() {
+        let uid = "0192f1a2-7b3c-7d4e-8f50-6a7b8c9d0e1f";
+        for data in ["accept", "keep", "shift", &format!("delete:{}", uid), &format!("again:{}", uid), &format!("again:{}:7", uid), &format!("again:{}:custom", uid),
+                     "join:7:approve", "join:7:reject", &format!("forget:{}", uid), &format!("done:{}", uid), &format!("skip:{}", uid), "template:3", "pick:2", "edit", "silent", "holidays", "cal:2026-10", "day:2026-10-15", "hour:18", "minute:30", "noop", "tz:America/New_York"] {
+            let query = data.parse::<CallbackQuery>().unwrap();
+            assert_eq!(query.to_string(), data);
+        }
+        assert!(matches!(format!("again:{}:7", uid).parse::<CallbackQuery>().unwrap(), CallbackQuery::RemindAgainIn(id, 7) if id == uid));
+        // row ids are not accepted anymore
+        assert!("1,2,3".parse::<CallbackQuery>().is_err());
+        assert!("again:x".parse::<CallbackQuery>().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_set_timezone_of_location_pin_once_confirmed() {
+        let tg = Arc::new(RecordingTg::default());
+        let handler = BotHandler { state: State::AwaitingTimezone, ..create_handler(tg.clone(), Role::User).await };
+        // Atlanta
+        let pin = serde_json::from_value(serde_json::json!({
+            "message_id": 5, "date": 0, "chat": { "id": 1 }, "location": { "latitude": 33.76, "longitude": -84.42 },
+        })).unwrap();
+
+        handler.handle_message(pin).await.unwrap();
+        let calls = tg.take_calls();
+        assert!(matches!(&calls[..], [TgCall::SendMessage { text, buttons, .. }] if text.contains("America/New_York") && buttons == &["Accept", "Cancel"]), "{:?}", calls);
+        assert_eq!(handler.bot.event_repository.get_timezone(1).await.unwrap(), DEFAULT_TIMEZONE);
+
+        handler.handle_callback_query(press("tz:America/New_York")).await.unwrap();
+        let calls = tg.take_calls();
+        assert!(matches!(&calls[..], [TgCall::DeleteMessage { message_id: 10, .. }, TgCall::SendMessage { text, .. }, _] if text == "Timezone set to America/New_York"), "{:?}", calls);
+        assert_eq!(handler.bot.event_repository.get_timezone(1).await.unwrap(), chrono_tz::America::New_York);
+    }
+
+    #[tokio::test]
+    async fn should_accept_draft_only_once() {
+        let tg = Arc::new(RecordingTg::default());
+        let handler = create_handler(tg.clone(), Role::User).await;
+        let time = Utc::now() + Duration::hours(1);
+        let notification = Notification::Absolute {
+            text: "water plants".to_string(), times: vec![FormattedTime { time }], leads: vec![], priority: Priority::Normal, nag: None, valid: None,
+        };
+        handler.drafts.set((1, 10), Some(Draft::Parsed { text: "water plants".to_string(), notification, alternatives: vec![] }));
+
+        handler.handle_callback_query(press("accept")).await.unwrap();
+        assert!(handler.drafts.get((1, 10)).1.is_none());
+        assert_eq!(handler.bot.event_repository.get_all_user_events(1).await.unwrap().len(), 1);
+        let calls = tg.take_calls();
+        assert!(matches!(&calls[0], TgCall::EditMessageText { chat_id: 1, message_id: 10, text, buttons }
+            if text.starts_with("water plants — ") && buttons == &["Cancel"]), "{:?}", calls);
+        assert_eq!(calls[1..], [answer("Notification accepted")]);
+
+        handler.handle_callback_query(press("accept")).await.unwrap();
+        assert_eq!(tg.take_calls(), [answer("This draft is no longer pending")]);
+        assert_eq!(handler.bot.event_repository.get_all_user_events(1).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_keep_draft_accepted_when_only_the_edit_fails() {
+        let tg = Arc::new(RecordingTg::default());
+        let handler = create_handler(tg.clone(), Role::User).await;
+        let time = Utc::now() + Duration::hours(1);
+        let notification = Notification::Absolute {
+            text: "water plants".to_string(), times: vec![FormattedTime { time }], leads: vec![], priority: Priority::Normal, nag: None, valid: None,
+        };
+        handler.drafts.set((1, 10), Some(Draft::Parsed { text: "water plants".to_string(), notification, alternatives: vec![] }));
+
+        tg.fail_edits.store(true, std::sync::atomic::Ordering::SeqCst);
+        assert!(handler.handle_callback_query(press("accept")).await.is_err());
+        // the reminder is stored, so the draft is not handed back for a second insert
+        assert!(handler.drafts.get((1, 10)).1.is_none());
+        tg.fail_edits.store(false, std::sync::atomic::Ordering::SeqCst);
+        handler.handle_callback_query(press("accept")).await.unwrap();
+        assert_eq!(tg.take_calls(), [answer("This draft is no longer pending")]);
+        assert_eq!(handler.bot.event_repository.get_all_user_events(1).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_cancel_accepted_draft_by_its_public_id() {
+        let tg = Arc::new(RecordingTg::default());
+        let handler = create_handler(tg.clone(), Role::User).await;
+        let repository = &handler.bot.event_repository;
+        let uid = "0192f1a2-7b3c-7d4e-8f50-6a7b8c9d0e1f";
+        let notification = Notification::Recurrent {
+            text: "standup".to_string(), days: Some([1, 3].into_iter().collect()), times: vec![Time { hours: 10, minutes: 0 }], leads: vec![], priority: Priority::Normal, nag: None, valid: None,
+            workdays: false, skip_holidays: false,
+        };
+        handler.drafts.set((1, 10), Some(Draft::Parsed { text: "standup".to_string(), notification, alternatives: vec![] }));
+        handler.draft_context.set((1, 10), DraftContext { uid: Some(uid.to_string()), ..DraftContext::default() });
+        handler.handle_callback_query(press("accept")).await.unwrap();
+        assert_eq!(repository.find_group(1, uid.to_string()).await.unwrap().len(), 2);
+
+        // the id of another user's reminder deletes nothing
+        let foreign: crate::models::CallbackQuery = serde_json::from_value(serde_json::json!({
+            "id": "query", "from": { "id": 2 }, "message": { "message_id": 10, "date": 0, "chat": { "id": 2 } }, "data": format!("delete:{}", uid),
+        })).unwrap();
+        handler.handle_callback_query(foreign).await.unwrap();
+        assert_eq!(repository.find_group(1, uid.to_string()).await.unwrap().len(), 2);
+
+        handler.handle_callback_query(press(&format!("delete:{}", uid))).await.unwrap();
+        assert!(repository.find_group(1, uid.to_string()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_accept_picked_reading_of_ambiguous_draft() {
+        let tg = Arc::new(RecordingTg::default());
+        let handler = create_handler(tg.clone(), Role::User).await;
+        let reading = |hours: i64| Notification::Absolute {
+            text: "call Dana".to_string(), times: vec![FormattedTime { time: Utc::now() + Duration::hours(hours) }],
+            leads: vec![], priority: Priority::Normal, nag: None, valid: None,
+        };
+        let draft = Draft::Parsed { text: "call Dana at 8".to_string(), notification: reading(2), alternatives: vec![reading(14)] };
+        let markup = super::draft_markup(draft.options(), draft.is_weekly(), &DraftContext::default(), Locale::En);
+        assert_eq!(markup.inline_keyboard[0].iter().map(|button| button.callback_data.as_str()).collect::<Vec<_>>(), ["accept", "pick:2"]);
+        handler.drafts.set((1, 10), Some(draft));
+
+        // there is no third reading to pick
+        handler.handle_callback_query(press("pick:3")).await.unwrap();
+        assert!(handler.drafts.get((1, 10)).1.is_some());
+        handler.handle_callback_query(press("pick:2")).await.unwrap();
+        let events = handler.bot.event_repository.get_all_user_events(1).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].time.is_some_and(|time| time > Utc::now() + Duration::hours(13)));
+        // picking other than the first reading is remembered for the next prompts
+        let corrections = handler.bot.event_repository.get_corrections(1).await.unwrap();
+        assert_eq!(corrections.iter().map(|correction| correction.input.as_str()).collect::<Vec<_>>(), ["call Dana at 8"]);
+    }
+
+    #[tokio::test]
+    async fn should_create_draft_with_picker_after_parser_failures() {
+        let tg = Arc::new(RecordingTg::default());
+        let handler = create_handler(tg.clone(), Role::User).await;
+        let today = super::local_today(handler.timezone);
+        let calendar = super::calendar_markup(today.year(), today.month(), today, Locale::En);
+        // the current month can't be left backwards and the past days are not buttons
+        assert_eq!(calendar.inline_keyboard[0][0].callback_data, "noop");
+        assert!(calendar.inline_keyboard.iter().flatten().all(|button| !button.callback_data.starts_with("day:")
+            || button.callback_data >= format!("day:{}", today.format("%Y-%m-%d"))));
+        handler.drafts.set((1, 10), Some(Draft::Picking { text: "water plants".to_string(), date: None, hour: None }));
+
+        // the hour comes only after the date
+        handler.handle_callback_query(press("hour:9")).await.unwrap();
+        assert!(matches!(handler.drafts.get((1, 10)).1, Some(Draft::Picking { hour: None, .. })));
+        let tomorrow = today + Duration::days(1);
+        for data in [format!("day:{}", tomorrow.format("%Y-%m-%d")), "hour:9".to_string(), "minute:30".to_string()] {
+            handler.handle_callback_query(press(&data)).await.unwrap();
+        }
+        let Some(Draft::Parsed { text, notification: Notification::Absolute { times, .. }, .. }) = handler.drafts.get((1, 10)).1 else {
+            panic!("the picked time should make a regular draft");
+        };
+        assert_eq!(text, "water plants");
+        assert_eq!(times[0].time.with_timezone(&chrono_tz::Israel).format("%Y-%m-%d %H:%M").to_string(), format!("{} 09:30", tomorrow.format("%Y-%m-%d")));
+    }
+
+    #[tokio::test]
+    async fn should_store_reminder_toggled_silent_on_draft() {
+        let tg = Arc::new(RecordingTg::default());
+        let handler = create_handler(tg.clone(), Role::User).await;
+        let notification = Notification::Absolute {
+            text: "call Dana".to_string(), times: vec![FormattedTime { time: Utc::now() + Duration::hours(1) }],
+            leads: vec![], priority: Priority::Urgent, nag: None, valid: None,
+        };
+        handler.drafts.set((1, 10), Some(Draft::Parsed { text: "call Dana at 8".to_string(), notification, alternatives: vec![] }));
+
+        handler.handle_callback_query(press("silent")).await.unwrap();
+        let toggled = tg.take_calls().into_iter().any(|call| matches!(call, TgCall::EditMessageReplyMarkup { buttons, .. }
+            if buttons.iter().any(|button| button == "🔔 Send with sound")));
+        assert!(toggled);
+        handler.handle_callback_query(press("accept")).await.unwrap();
+        let events = handler.bot.event_repository.get_all_user_events(1).await.unwrap();
+        // silent wins over the urgent priority
+        assert!(events[0].silent);
+        assert_eq!(events[0].delivery().disable_notification(), Some(true));
+    }
+
+    #[tokio::test]
+    async fn should_store_weekly_reminder_toggled_to_skip_holidays() {
+        let tg = Arc::new(RecordingTg::default());
+        let handler = create_handler(tg.clone(), Role::User).await;
+        let notification = Notification::Recurrent {
+            text: "gym".to_string(), days: Some([1, 3].into_iter().collect()), times: vec![Time { hours: 18, minutes: 0 }],
+            leads: vec![], priority: Priority::Normal, nag: None, valid: None, workdays: false, skip_holidays: false,
+        };
+        handler.drafts.set((1, 10), Some(Draft::Parsed { text: "gym on mon and wed at 18".to_string(), notification, alternatives: vec![] }));
+
+        handler.handle_callback_query(press("holidays")).await.unwrap();
+        let toggled = tg.take_calls().into_iter().any(|call| matches!(call, TgCall::EditMessageReplyMarkup { buttons, .. }
+            if buttons.iter().any(|button| button == "📅 On holidays too")));
+        assert!(toggled);
+        handler.handle_callback_query(press("accept")).await.unwrap();
+        let events = handler.bot.event_repository.get_all_user_events(1).await.unwrap();
+        assert!(events.iter().all(|event| event.holiday_country.as_deref() == Some("IL")));
+    }
 
-            tokio::time::sleep(Duration::from_millis(500)).await;
+    #[tokio::test]
+    async fn should_fire_reminder_as_reply_to_its_source_message() {
+        let tg = Arc::new(RecordingTg::default());
+        let handler = create_handler(tg.clone(), Role::User).await;
+        let notification = Notification::Absolute {
+            text: "call Dana".to_string(), times: vec![FormattedTime { time: Utc::now() + Duration::hours(1) }],
+            leads: vec![], priority: Priority::Normal, nag: None, valid: None,
+        };
+        handler.drafts.set((1, 10), Some(Draft::Parsed { text: "call Dana at 8".to_string(), notification, alternatives: vec![] }));
+        handler.draft_context.set((1, 10), DraftContext { source_message_id: Some(9), ..DraftContext::default() });
+
+        handler.handle_callback_query(press("accept")).await.unwrap();
+        let fired = handler.bot.event_repository.get_events_to_fire(Utc::now() + Duration::hours(2), 100).await.unwrap();
+        assert_eq!(fired.iter().map(|event| event.source_message_id).collect::<Vec<_>>(), [Some(9)]);
+    }
+
+    #[tokio::test]
+    async fn should_bound_today_by_midnight_of_user_timezone() {
+        let tg = Arc::new(RecordingTg::default());
+        let timezone = chrono_tz::Pacific::Kiritimati;
+        let handler = BotHandler { timezone, ..create_handler(tg.clone(), Role::User).await };
+        let midnight = (Utc::now().with_timezone(&timezone).date_naive() + Duration::days(1)).and_hms_opt(0, 0, 0).unwrap();
+        let midnight = timezone.from_local_datetime(&midnight).unwrap().with_timezone(&Utc);
+        for (text, time) in [("before midnight", midnight - Duration::minutes(1)), ("after midnight", midnight + Duration::minutes(1))] {
+            handler.bot.event_repository.insert_event(1, text.to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
         }
+
+        handler.handle_command(1, "/today").await.unwrap();
+        let calls = tg.take_calls();
+        assert!(matches!(&calls[..], [TgCall::SendMessage { text, .. }] if text.ends_with("\n23:59 before midnight")), "{:?}", calls);
+    }
+
+    #[tokio::test]
+    async fn should_show_offline_reading_to_admin_when_it_differs_from_the_model() {
+        let tg = Arc::new(RecordingTg::default());
+        let admin = create_handler(tg.clone(), Role::Admin).await;
+        let text = "21.07.2030 15:00 call mom";
+        let offline = crate::parser::parse_offline(text, Utc::now(), admin.timezone).unwrap();
+        assert_eq!(admin.offline_reading(text, None).await, None);
+
+        admin.handle_command(1, "/debug on").await.unwrap();
+        let shown = admin.offline_reading(text, None).await;
+        assert!(shown.as_deref().is_some_and(|shown| shown.contains("call mom")), "{:?}", shown);
+        assert_eq!(admin.offline_reading(text, Some(&offline)).await, None);
+        let user = BotHandler { bot: admin.bot.clone(), ..create_handler(tg.clone(), Role::User).await };
+        assert_eq!(user.offline_reading(text, None).await, None);
+    }
+
+    #[tokio::test]
+    async fn should_pin_urgent_reminder_until_done() {
+        let tg = Arc::new(RecordingTg::default());
+        let handler = create_handler(tg.clone(), Role::User).await;
+        let delivery = Delivery { priority: Priority::Urgent, ..Delivery::default() };
+        let time = Utc::now() - Duration::minutes(1);
+        handler.bot.event_repository.insert_event_with_delivery(1, "take pills".to_string(), Source::Telegram, delivery, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        let event = handler.bot.event_repository.get_events_to_fire(Utc::now(), 100).await.unwrap().remove(0);
+        let background = super::Bot { dependency: handler.bot.clone() };
+
+        let first = background.send_fired(&event).await.unwrap();
+        let second = background.send_fired(&event).await.unwrap();
+        let pins = tg.take_calls().into_iter()
+            .filter(|call| matches!(call, TgCall::PinChatMessage { .. } | TgCall::UnpinChatMessage { .. }))
+            .collect::<Vec<_>>();
+        assert_eq!(pins, [
+            TgCall::PinChatMessage { chat_id: 1, message_id: first },
+            TgCall::UnpinChatMessage { chat_id: 1, message_id: first },
+            TgCall::PinChatMessage { chat_id: 1, message_id: second },
+        ]);
+        handler.handle_callback_query(press(&format!("done:{}", event.event_uid))).await.unwrap();
+        assert!(tg.take_calls().contains(&TgCall::UnpinChatMessage { chat_id: 1, message_id: second }));
+    }
+
+    #[tokio::test]
+    async fn should_complete_fired_reminder_with_done_button() {
+        let tg = Arc::new(RecordingTg::default());
+        let handler = create_handler(tg.clone(), Role::User).await;
+        let repository = &handler.bot.event_repository;
+        let time = Utc::now() - Duration::minutes(1);
+        repository.insert_event_with_delivery(1, "call mom".to_string(), Source::Telegram, Delivery::default(), vec![StoredNotification::Absolute { time }]).await.unwrap();
+        repository.insert_event_with_delivery(1, "water plants".to_string(), Source::Telegram, Delivery::default(), vec![StoredNotification::Absolute { time }]).await.unwrap();
+        let events = repository.get_events_to_fire(Utc::now(), 100).await.unwrap();
+        let background = super::Bot { dependency: handler.bot.clone() };
+        background.send_fired(&events[0]).await.unwrap();
+        assert!(matches!(&tg.take_calls()[0], TgCall::SendMessage { buttons, .. } if buttons.iter().any(|button| button == "Done")), "{:?}", tg.take_calls());
+        repository.mark_fired(events.iter().map(|event| event.event_id).collect(), Utc::now()).await.unwrap();
+
+        handler.handle_callback_query(press(&format!("done:{}", events[0].event_uid))).await.unwrap();
+        assert!(!repository.complete(1, events[0].event_id, Utc::now()).await.unwrap());
+        let closed = repository.get_closed_events(1, 10).await.unwrap().into_iter()
+            .map(|(event, transition)| (event.text, Outcome::of(transition)))
+            .collect::<Vec<_>>();
+        assert_eq!(closed, [("water plants".to_string(), Some(Outcome::Missed)), ("call mom".to_string(), Some(Outcome::Completed))]);
+    }
+
+    #[tokio::test]
+    async fn should_replace_draft_with_edited_json() {
+        let tg = Arc::new(RecordingTg::default());
+        let handler = create_handler(tg.clone(), Role::User).await;
+        let notification = Notification::Absolute {
+            text: "call Dana".to_string(), times: vec![FormattedTime { time: Utc::now() + Duration::hours(1) }],
+            leads: vec![], priority: Priority::Normal, nag: None, valid: None,
+        };
+        handler.drafts.set((1, 10), Some(Draft::Parsed { text: "call Dana at 8".to_string(), notification, alternatives: vec![] }));
+
+        handler.handle_callback_query(press("edit")).await.unwrap();
+        assert!(matches!(&tg.take_calls()[0], TgCall::SendForceReply { chat_id: 1, .. }));
+        handler.edit_draft(1, 10, "{\"kind\": \"cancel\", \"text\": \"call Dana\"}").await.unwrap();
+        assert!(matches!(handler.drafts.get((1, 10)).1, Some(Draft::Parsed { notification: Notification::Absolute { .. }, .. })));
+
+        handler.edit_draft(1, 10, "{\"kind\": \"absolute\", \"text\": \"call Dana\", \"times\": [\"01.01.2100 18:00:00\"]}").await.unwrap();
+        let calls = tg.take_calls();
+        assert!(matches!(&calls[1], TgCall::EditMessageText { message_id: 10, text, .. } if text.starts_with("call Dana — ")), "{:?}", calls);
+        handler.handle_callback_query(press("accept")).await.unwrap();
+        let events = handler.bot.event_repository.get_all_user_events(1).await.unwrap();
+        assert_eq!(events[0].time.map(|time| time.format("%Y %H").to_string()).as_deref(), Some("2100 16"));
+        assert_eq!(handler.bot.event_repository.get_corrections(1).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_keep_draft_with_errors_until_cancelled() {
+        let tg = Arc::new(RecordingTg::default());
+        let handler = create_handler(tg.clone(), Role::User).await;
+        handler.drafts.set((1, 10), Some(Draft::ParsedWithError { text: "someday".to_string() }));
+
+        handler.handle_callback_query(press("accept")).await.unwrap();
+        assert_eq!(tg.take_calls(), [answer("Impossible to accept notification with errors")]);
+        assert!(handler.drafts.get((1, 10)).1.is_some());
+
+        handler.handle_callback_query(press("cancel")).await.unwrap();
+        assert_eq!(tg.take_calls(), [TgCall::DeleteMessage { chat_id: 1, message_id: 10 }, answer("Canceled")]);
+        assert!(handler.drafts.get((1, 10)).1.is_none());
+
+        let read_only = create_handler(tg.clone(), Role::ReadOnly).await;
+        read_only.drafts.set((1, 10), Some(Draft::ParsedWithError { text: "someday".to_string() }));
+        read_only.handle_callback_query(press("repeat")).await.unwrap();
+        assert_eq!(tg.take_calls(), [answer("You have read-only access, ask an admin to let you create reminders")]);
+    }
+
+    #[test]
+    fn should_split_cron_expression_from_text() {
+        let split = |expression: &str, text: &str| Some((expression.to_string(), text.to_string()));
+        assert_eq!(split_cron_args("\"0 9 * * MON-FRI\" standup"), split("0 9 * * MON-FRI", "standup"));
+        assert_eq!(split_cron_args("“*/15 9-17 * * *” drink water"), split("*/15 9-17 * * *", "drink water"));
+        assert_eq!(split_cron_args("0 9 1 * * pay rent"), split("0 9 1 * *", "pay rent"));
+        assert_eq!(split_cron_args("\"0 9 * * *\""), None);
+        assert_eq!(split_cron_args("0 9 * *"), None);
+    }
+
+    #[test]
+    fn should_schedule_maintenance_at_next_local_hour() {
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        // 04:00 in Israel is 02:00 utc in winter
+        assert_eq!(next_maintenance_time(at("2023-01-26T01:00:00Z"), 4), at("2023-01-26T02:00:00Z"));
+        assert_eq!(next_maintenance_time(at("2023-01-26T02:00:00Z"), 4), at("2023-01-27T02:00:00Z"));
+    }
+
+    #[test]
+    fn should_pause_until_local_midnight() {
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        let now = at("2023-07-20T12:00:00Z");
+        let israel = chrono_tz::Israel;
+        // israel is utc+3 in summer
+        assert_eq!(parse_pause_end("until 05.08", now, israel), Some(at("2023-08-04T21:00:00Z")));
+        assert_eq!(parse_pause_end("до 05.08.2024", now, israel), Some(at("2024-08-04T21:00:00Z")));
+        assert_eq!(parse_pause_end("01.01", now, israel), Some(at("2023-12-31T22:00:00Z")));
+        assert_eq!(parse_pause_end("10.07", now, israel), Some(at("2024-07-09T21:00:00Z")));
+        assert_eq!(parse_pause_end("10.07.2023", now, israel), None);
+        assert_eq!(parse_pause_end("tomorrow", now, israel), None);
+        // and new york is utc-4
+        assert_eq!(parse_pause_end("until 05.08", now, chrono_tz::America::New_York), Some(at("2023-08-05T04:00:00Z")));
+    }
+
+    #[test]
+    fn should_quote_forwarded_message_when_fired() {
+        let event = |quote: String| EventToFire {
+            event_id: 1,
+            event_uid: "0192f1a2-7b3c-7d4e-8f50-6a7b8c9d0e1f".to_string(),
+            user_id: 1,
+            text: "reply to Dana".to_string(),
+            lead_minutes: 0,
+            delivery: Delivery::default(),
+            is_recurrent: false,
+            quote: Some(quote),
+            source_message_id: None,
+            due_at: None,
+            overdue_minutes: 0,
+        };
+        assert_eq!(super::fired_text(&event("can we meet?".to_string()), Locale::En), "reply to Dana\n» can we meet?");
+        let long = super::fired_text(&event("ab".repeat(super::QUOTE_CHARS)), Locale::En);
+        assert!(long.ends_with("ab…") && long.chars().count() == "reply to Dana\n» ".chars().count() + super::QUOTE_CHARS + 1);
+    }
+
+    #[test]
+    fn should_prefix_overdue_reminder_with_rounded_delay() {
+        let event = |overdue_minutes: u32| EventToFire {
+            event_id: 1,
+            event_uid: "0192f1a2-7b3c-7d4e-8f50-6a7b8c9d0e1f".to_string(),
+            user_id: 1,
+            text: "call mom".to_string(),
+            lead_minutes: 0,
+            delivery: Delivery::default(),
+            is_recurrent: false,
+            quote: None,
+            source_message_id: None,
+            due_at: None,
+            overdue_minutes,
+        };
+        assert_eq!(super::fired_text(&event(0), Locale::En), "call mom");
+        assert_eq!(super::fired_text(&event(45), Locale::En), "Overdue by 45 minutes: call mom");
+        assert_eq!(super::fired_text(&event(187), Locale::En), "Overdue by 3 hours: call mom");
+        assert_eq!(super::fired_text(&event(3000), Locale::Ru), "Просрочено на 2 дня: call mom");
+    }
+
+    #[test]
+    fn should_replace_only_rows_of_pressed_reminder_in_digest() {
+        let event = |event_id: u64, text: &str, priority: Priority| EventToFire {
+            event_id,
+            event_uid: format!("0192f1a2-7b3c-7d4e-8f50-6a7b8c9d0e1{}", event_id),
+            user_id: 1,
+            text: text.to_string(),
+            lead_minutes: 0,
+            delivery: Delivery { priority, nag_minutes: 0, expires_minutes: 0, silent: false },
+            is_recurrent: event_id == 2,
+            quote: None,
+            source_message_id: None,
+            due_at: None,
+            overdue_minutes: 0,
+        };
+        let (text, markup) = digest("2 reminders are due:", &[event(1, "call mom", Priority::Urgent), event(2, "water plants", Priority::Normal)], Locale::En);
+        assert_eq!(text, "2 reminders are due:\n1. ❗ call mom\n2. water plants");
+        let texts = |markup: &super::InlineKeyboardMarkup| markup.inline_keyboard.iter()
+            .map(|row| row.iter().map(|button| button.text.as_str()).collect::<Vec<_>>().join(" | "))
+            .collect::<Vec<_>>();
+        assert_eq!(texts(&markup), ["1. Done | 1. Remind again…", "2. Done | 2. Remind again… | 2. Skip next"]);
+
+        let (first, second) = ("0192f1a2-7b3c-7d4e-8f50-6a7b8c9d0e11", "0192f1a2-7b3c-7d4e-8f50-6a7b8c9d0e12");
+        let replaced = replace_event_rows(Some(&markup), first, Some(super::remind_again_markup(first, false, false, Locale::En))).unwrap();
+        assert_eq!(texts(&replaced), ["1. Remind again…", "2. Done | 2. Remind again… | 2. Skip next"]);
+        let replaced = replace_event_rows(Some(&replaced), second, None).unwrap();
+        assert_eq!(texts(&replaced), ["1. Remind again…"]);
+        assert!(replace_event_rows(Some(&replaced), first, None).is_none());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn should_tell_calendar_sync_is_not_set_up() {
+        let tg = Arc::new(RecordingTg::default());
+        let handler = create_handler(tg.clone(), Role::User).await;
+
+        handler.handle_command(1, "/connect_calendar").await.unwrap();
+
+        let texts = tg.take_calls().into_iter().filter_map(|call| match call {
+            TgCall::SendMessage { text, .. } => Some(text),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["Google Calendar sync is not set up on this bot".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn should_list_background_jobs_to_admins_only() {
+        let tg = Arc::new(RecordingTg::default());
+        let admin = create_handler(tg.clone(), Role::Admin).await;
+        admin.handle_command(1, "/jobs").await.unwrap();
+        let user = BotHandler { bot: admin.bot.clone(), ..create_handler(tg.clone(), Role::User).await };
+        user.handle_command(1, "/jobs").await.unwrap();
+
+        let texts = tg.take_calls().into_iter().filter_map(|call| match call {
+            TgCall::SendMessage { text, .. } => Some(text),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(texts.len(), 2);
+        assert!(texts[0].starts_with("uid-backfill: done, 0 processed, checkpoint 0, updated "), "{}", texts[0]);
+        assert_eq!(texts[1], "This command is only available to admins");
+    }
+}
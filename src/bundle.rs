@@ -0,0 +1,85 @@
+use chrono::{DateTime, Utc};
+use fnv::FnvHashSet;
+use serde::{Deserialize, Serialize};
+use crate::db::{UserSettings, Webhook, WebhookRoute};
+use crate::errors::BotError;
+use crate::webhooks;
+
+// bumped on every incompatible change of the file layout, older files keep being accepted
+pub const SCHEMA: u32 = 1;
+
+pub const FILE_NAME: &str = "settings.json";
+
+// everything a user configured besides the reminders themselves, which move between deployments as ics;
+// webhook routes are keyed by reminder text, so they reattach to reminders imported from that ics
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SettingsBundle {
+    pub schema: u32,
+    pub exported_at: DateTime<Utc>,
+    pub settings: UserSettings,
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    #[serde(default)]
+    pub routes: Vec<WebhookRoute>,
+}
+
+impl SettingsBundle {
+    pub fn parse(content: &[u8]) -> Result<SettingsBundle, BotError> {
+        let bundle: SettingsBundle = serde_json::from_slice(content)?;
+        bundle.validate()?;
+        Ok(bundle)
+    }
+
+    fn validate(&self) -> Result<(), BotError> {
+        if self.schema == 0 || self.schema > SCHEMA {
+            return Err(BotError::InvalidBundle(format!("schema {} is not supported, expected at most {}", self.schema, SCHEMA)));
+        }
+        let mut names = FnvHashSet::default();
+        for webhook in self.webhooks.iter() {
+            if webhook.name.is_empty() || webhook.name.contains(char::is_whitespace) {
+                return Err(BotError::InvalidBundle(format!("webhook name \"{}\" is empty or has spaces", webhook.name)));
+            }
+            if !names.insert(webhook.name.as_str()) {
+                return Err(BotError::InvalidBundle(format!("webhook \"{}\" is listed twice", webhook.name)));
+            }
+            webhooks::check_url(&webhook.url)
+                .map_err(|err| BotError::InvalidBundle(format!("webhook \"{}\" has invalid url: {}", webhook.name, err)))?;
+        }
+        if let Some(route) = self.routes.iter().find(|route| !names.contains(route.webhook.as_str())) {
+            return Err(BotError::InvalidBundle(format!("route of \"{}\" refers to unknown webhook \"{}\"", route.event_text, route.webhook)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use crate::db::{UserSettings, Webhook, WebhookRoute};
+    use super::{SettingsBundle, SCHEMA};
+
+    #[test]
+    fn should_round_trip_and_validate_bundle() {
+        let bundle = SettingsBundle {
+            schema: SCHEMA,
+            exported_at: Utc::now(),
+            settings: UserSettings { plain_mode: true, paused_until: None, quiet_hours: "23:00-08:00".parse().ok(), language: None },
+            webhooks: vec![Webhook { name: "lights".to_string(), url: "https://example.com/hook".to_string() }],
+            routes: vec![WebhookRoute { event_text: "wake up".to_string(), webhook: "lights".to_string() }],
+        };
+        let content = serde_json::to_vec(&bundle).unwrap();
+        let parsed = SettingsBundle::parse(&content).unwrap();
+        assert!(parsed.settings.plain_mode);
+        assert_eq!(parsed.settings.quiet_hours.unwrap().to_string(), "23:00-08:00");
+        assert_eq!(parsed.routes[0].webhook, "lights");
+
+        let newer = String::from_utf8(content).unwrap().replacen(&format!("\"schema\":{}", SCHEMA), "\"schema\":99", 1);
+        assert!(SettingsBundle::parse(newer.as_bytes()).is_err());
+        let dangling = "{\"schema\":1,\"exported_at\":\"2030-01-01T00:00:00Z\",\"settings\":{\"plain_mode\":false,\"paused_until\":null},\
+            \"routes\":[{\"event_text\":\"wake up\",\"webhook\":\"lights\"}]}";
+        assert!(SettingsBundle::parse(dangling.as_bytes()).is_err());
+        let bad_url = "{\"schema\":1,\"exported_at\":\"2030-01-01T00:00:00Z\",\"settings\":{\"plain_mode\":false,\"paused_until\":null},\
+            \"webhooks\":[{\"name\":\"lights\",\"url\":\"not a url\"}]}";
+        assert!(SettingsBundle::parse(bad_url.as_bytes()).is_err());
+    }
+}
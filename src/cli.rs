@@ -0,0 +1,111 @@
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use crate::db::{AccessRequest, AllowedUser, BusinessConnection, CalendarConnection, CalendarEvent, ConnectionOptions, Event, Role, DeferredDelivery, EventExclusion, EventLocation, EventRepository, EventTag, HistoryEntry, MonthlyUsage, ParseCorrection, Template, UndoAction, UserSettings, Webhook, WebhookCall};
+use crate::errors::BotError;
+use crate::ids::UuidV7Generator;
+use crate::models::Env;
+
+// archive of everything stored about a single user
+#[derive(Debug, Serialize)]
+struct UserExport {
+    user_id: u64,
+    exported_at: DateTime<Utc>,
+    events: Vec<Event>,
+    history: Vec<HistoryEntry>,
+    usage: Vec<MonthlyUsage>,
+    webhooks: Vec<Webhook>,
+    webhook_calls: Vec<WebhookCall>,
+    settings: UserSettings,
+    access: Option<AccessRequest>,
+    business_connections: Vec<BusinessConnection>,
+    role: Option<Role>,
+    tags: Vec<EventTag>,
+    exclusions: Vec<EventExclusion>,
+    deferred: Vec<DeferredDelivery>,
+    undo: Vec<UndoAction>,
+    templates: Vec<Template>,
+    corrections: Vec<ParseCorrection>,
+    locations: Vec<EventLocation>,
+    user: Option<AllowedUser>,
+    calendar: Option<CalendarConnection>,
+    calendar_events: Vec<CalendarEvent>,
+}
+
+pub enum Command {
+    Export { user_id: u64 },
+    Purge { user_id: u64 },
+    Jobs,
+    DeadLetters,
+}
+
+impl Command {
+    // returns None when the binary should start the bot as usual
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Option<Command>, BotError> {
+        let command = match args.next() {
+            Some(command) => command,
+            None => return Ok(None),
+        };
+        let user_id = |arg: Option<String>| -> Result<u64, BotError> {
+            Ok(arg.ok_or(BotError::Usage("notify-rs export|purge <user_id>"))?.parse()?)
+        };
+
+        match command.as_str() {
+            "export" => Ok(Some(Command::Export { user_id: user_id(args.next())? })),
+            "purge" => Ok(Some(Command::Purge { user_id: user_id(args.next())? })),
+            "jobs" => Ok(Some(Command::Jobs)),
+            "dead-letters" => Ok(Some(Command::DeadLetters)),
+            _ => Err(BotError::Usage("notify-rs [--config <path>] [--profile <name>] [export|purge <user_id> | jobs | dead-letters]")),
+        }
+    }
+
+    pub async fn run(self, env: &Env) -> Result<(), BotError> {
+        let event_repository = EventRepository::open(&env.connection_string, Arc::new(UuidV7Generator), ConnectionOptions::from_env(env)).await?;
+        match self {
+            Command::Export { user_id } => {
+                let export = UserExport {
+                    user_id,
+                    exported_at: Utc::now(),
+                    events: event_repository.get_all_user_events(user_id).await?,
+                    history: event_repository.get_user_history(user_id).await?,
+                    usage: event_repository.get_user_usage(user_id).await?,
+                    webhooks: event_repository.get_webhooks(user_id).await?,
+                    webhook_calls: event_repository.get_webhook_calls(user_id).await?,
+                    settings: event_repository.get_user_settings(user_id).await?,
+                    access: event_repository.get_access_request(user_id).await?,
+                    business_connections: event_repository.get_business_connections(user_id).await?,
+                    role: event_repository.get_user_role(user_id).await?,
+                    tags: event_repository.get_user_tags(user_id).await?,
+                    exclusions: event_repository.get_exclusions(user_id).await?,
+                    deferred: event_repository.get_deferred(user_id).await?,
+                    undo: event_repository.get_undo_actions(user_id).await?,
+                    templates: event_repository.get_templates(user_id).await?,
+                    corrections: event_repository.get_corrections(user_id).await?,
+                    locations: event_repository.get_user_locations(user_id).await?,
+                    user: event_repository.get_user(user_id).await?,
+                    calendar: event_repository.get_calendar_connection(user_id).await?,
+                    calendar_events: event_repository.get_calendar_events(user_id).await?,
+                };
+                println!("{}", serde_json::to_string_pretty(&export)?);
+            }
+            Command::Purge { user_id } => {
+                let events = event_repository.purge_user(user_id).await?;
+                tracing::info!("Purged user {}: {} events", user_id, events);
+            }
+            Command::Jobs => {
+                for job in event_repository.get_jobs().await? {
+                    println!("{}: {}, {} processed, checkpoint {}, updated {}",
+                             job.name, job.status, job.processed, job.checkpoint, job.updated_at);
+                }
+            }
+            Command::DeadLetters => {
+                for dead_letter in event_repository.get_dead_letters().await? {
+                    println!("event {} for user {} after {} attempts: {} ({})",
+                             dead_letter.event_id, dead_letter.user_id, dead_letter.attempts, dead_letter.text,
+                             dead_letter.last_error.unwrap_or_default());
+                }
+            }
+        }
+        Ok(())
+    }
+}
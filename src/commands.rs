@@ -0,0 +1,106 @@
+use crate::humanize::Locale;
+
+pub const COMMANDS: [&str; 33] = ["/start", "/status", "/list", "/search", "/cancel", "/today", "/week", "/upcoming", "/load", "/webhook", "/trigger", "/attach", "/history", "/export", "/plain", "/language", "/timezone", "/connect_calendar", "/remind", "/cron", "/template", "/stats", "/jobs", "/debug", "/broadcast", "/role", "/pause", "/resume", "/quiet", "/undo", "/reload_users", "/adduser", "/removeuser"];
+
+const EN_ALIASES: [(&str, &str); 3] = [("/ls", "/list"), ("/hooks", "/webhook"), ("/ics", "/export")];
+const RU_ALIASES: [(&str, &str); 22] = [
+    ("/поиск", "/search"),
+    ("/статус", "/status"),
+    ("/отменить", "/cancel"),
+    ("/список", "/list"),
+    ("/сегодня", "/today"),
+    ("/неделя", "/week"),
+    ("/ближайшие", "/upcoming"),
+    ("/нагрузка", "/load"),
+    ("/вебхук", "/webhook"),
+    ("/запустить", "/trigger"),
+    ("/привязать", "/attach"),
+    ("/история", "/history"),
+    ("/экспорт", "/export"),
+    ("/простой", "/plain"),
+    ("/язык", "/language"),
+    ("/пояс", "/timezone"),
+    ("/напомнить", "/remind"),
+    ("/шаблон", "/template"),
+    ("/пауза", "/pause"),
+    ("/продолжить", "/resume"),
+    ("/тишина", "/quiet"),
+    ("/вернуть", "/undo"),
+];
+
+// typos further than this from every known name are treated as reminder text
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Resolution {
+    Known(&'static str),
+    DidYouMean(&'static str),
+    Unknown,
+}
+
+// english aliases work for everyone, localized ones only for users with that locale
+fn aliases(locale: Locale) -> impl Iterator<Item = &'static (&'static str, &'static str)> {
+    let localized: &'static [(&str, &str)] = match locale {
+        Locale::En => &[],
+        Locale::Ru => &RU_ALIASES,
+        Locale::He => &[],
+    };
+    EN_ALIASES.iter().chain(localized)
+}
+
+pub fn resolve(command: &str, locale: Locale) -> Resolution {
+    // in group chats telegram appends the bot name, as in /list@notify_bot
+    let command = command.split('@').next().unwrap_or_default().to_lowercase();
+    if let Some(known) = COMMANDS.iter().find(|known| **known == command) {
+        return Resolution::Known(known);
+    }
+    if let Some((_, target)) = aliases(locale).find(|(alias, _)| *alias == command) {
+        return Resolution::Known(target);
+    }
+
+    let names = COMMANDS.iter().map(|name| (*name, *name))
+        .chain(aliases(locale).copied());
+    names
+        .map(|(name, target)| (distance(&command, name), target))
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .min_by_key(|(distance, _)| *distance)
+        .map_or(Resolution::Unknown, |(_, target)| Resolution::DidYouMean(target))
+}
+
+// levenshtein distance over chars, so cyrillic typos count the same as latin ones
+pub fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::humanize::Locale;
+    use super::{distance, resolve, Resolution};
+
+    #[test]
+    fn should_resolve_commands_and_aliases() {
+        assert_eq!(resolve("/list", Locale::En), Resolution::Known("/list"));
+        assert_eq!(resolve("/LS@notify_bot", Locale::En), Resolution::Known("/list"));
+        assert_eq!(resolve("/список", Locale::Ru), Resolution::Known("/list"));
+        assert_eq!(resolve("/список", Locale::En), Resolution::Unknown);
+    }
+
+    #[test]
+    fn should_suggest_close_commands() {
+        assert_eq!(resolve("/lsit", Locale::En), Resolution::DidYouMean("/list"));
+        assert_eq!(resolve("/histroy", Locale::En), Resolution::DidYouMean("/history"));
+        assert_eq!(resolve("/спсиок", Locale::Ru), Resolution::DidYouMean("/list"));
+        assert_eq!(resolve("/birthday", Locale::En), Resolution::Unknown);
+        assert_eq!(distance("кот", "кит"), 1);
+    }
+}
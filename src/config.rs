@@ -0,0 +1,178 @@
+use std::path::Path;
+use crate::errors::BotError;
+use crate::profile::Profile;
+
+// read when it exists and no other file is given with `--config <path>`
+const DEFAULT_PATH: &str = "config.toml";
+
+// every setting Env reads, config keys are these names in lowercase with tables as prefixes,
+// so `oai_model = "..."` and `[oai] model = "..."` both set OAI_MODEL
+const KNOWN: &[&str] = &[
+    "TG_KEY", "TG_POLL_TIMEOUT_SECS", "LLM_PROVIDER", "OAI_TOKEN", "OAI_MODEL", "OAI_TEMPERATURE", "OAI_MAX_TOKENS", "OAI_BASE_URL", "OAI_ORG",
+    "OAI_PROJECT", "AZURE_API_VERSION", "OAI_MAX_RETRIES", "OAI_RETRY_BASE_MS", "OAI_TIMEOUT_SECS", "OAI_PROMPT_PRICE",
+    "OAI_COMPLETION_PRICE", "PARSER_CACHE_TTL_SECS", "PARSER_FIXTURES", "SYSTEM_PROMPT_FILE", "PARSER_EVAL_CORPUS", "SUMMARY_MODEL", "PARSER_CONCURRENCY", "PARSER_USER_CONCURRENCY",
+    "SUMMARIZE_THRESHOLD", "DELIVERY_MAX_ATTEMPTS", "FIRE_BATCH_SIZE", "MISSED_POLICY", "MISSED_THRESHOLD_MINUTES", "EXPIRY_NOTICE", "URGENT_RESEND_MINUTES", "URGENT_MAX_RESENDS", "CLEANUP_RETENTION_DAYS",
+    "CLEANUP_INTERVAL_SECS", "MAINTENANCE_HOUR", "MONTHLY_TOKEN_BUDGET", "TG_USERS", "ADMIN_ID", "CONN_STRING",
+    "SNAPSHOT_INTERVAL_SECS", "SNAPSHOT_PATH", "SNAPSHOT_HOOK", "RESTORE_HOOK", "API_BIND", "API_TOKEN", "HEALTH_BIND",
+    "CONFLICT_WINDOW_MINUTES", "LOG_LEVEL", "LOG_FORMAT", "LOG_REDACT", "MESSAGE_PREFIX", "DEMO_MODE", "DEMO_TOKEN_BUDGET", "DEMO_WIPE_INTERVAL_SECS",
+    "STANDBY_MODE", "DATABASE_KEY", "SQLITE_JOURNAL_MODE", "SQLITE_SYNCHRONOUS", "SQLITE_BUSY_TIMEOUT_MS",
+    "HOLIDAY_COUNTRY", "HOLIDAYS_FILE", "GOOGLE_CLIENT_ID", "GOOGLE_CLIENT_SECRET", "GOOGLE_REDIRECT_URL",
+    "CALENDAR_PULL_INTERVAL_SECS",
+];
+
+// optional toml file with the same settings as the environment, given with `--config <path>`
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ConfigFile {
+    pub path: Option<String>,
+}
+
+impl ConfigFile {
+    // takes `--config <path>` out of the arguments, the rest is left for the profile and cli commands
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<(ConfigFile, Vec<String>), BotError> {
+        let mut path = None;
+        let mut rest = vec![];
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--config") {
+                Some("") => path = Some(args.next().ok_or(BotError::Usage("notify-rs --config <path> [command]"))?),
+                Some(value) if value.starts_with('=') => path = Some(value[1..].to_string()),
+                _ => rest.push(arg),
+            }
+        }
+        Ok((ConfigFile { path }, rest))
+    }
+
+    fn existing_path(&self) -> Option<&str> {
+        match &self.path {
+            Some(path) => Some(path.as_str()),
+            None => Path::new(DEFAULT_PATH).exists().then_some(DEFAULT_PATH),
+        }
+    }
+
+    // environment variables and .env files win over the file, so it only fills in what they leave unset;
+    // an explicitly given file has to exist, the default one is skipped when missing
+    pub fn load(&self) -> Result<(), BotError> {
+        let path = match &self.path {
+            Some(path) if !Path::new(path).exists() => return Err(BotError::Config(format!("{} not found", path))),
+            Some(path) => path.as_str(),
+            None if Path::new(DEFAULT_PATH).exists() => DEFAULT_PATH,
+            None => return Ok(()),
+        };
+        let content = std::fs::read_to_string(path)?;
+        for (name, value) in parse(&content).map_err(|err| BotError::Config(format!("{}: {}", path, err)))? {
+            if std::env::var_os(&name).is_none() {
+                std::env::set_var(name, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+// files the settings were loaded from at startup, read again when a setting is reloaded at runtime
+#[derive(Debug, Clone, Default)]
+pub struct SettingsFiles {
+    pub profile: Profile,
+    pub config: ConfigFile,
+}
+
+impl SettingsFiles {
+    // current value of the setting in the .env files and the config file, in the startup order;
+    // the process environment is only a fallback, as it still holds the values read at startup;
+    // the iterator is deprecated in favour of loading into the environment, which is exactly what this avoids
+    #[allow(deprecated)]
+    pub fn read(&self, name: &str) -> Result<Option<String>, BotError> {
+        for path in self.profile.env_files().iter().filter(|path| Path::new(path).exists()) {
+            for item in dotenv::from_filename_iter(path).map_err(|err| BotError::Config(format!("{}: {}", path, err)))? {
+                let (key, value) = item.map_err(|err| BotError::Config(format!("{}: {}", path, err)))?;
+                if key == name {
+                    return Ok(Some(value));
+                }
+            }
+        }
+        if let Some(path) = self.config.existing_path() {
+            let content = std::fs::read_to_string(path)?;
+            let settings = parse(&content).map_err(|err| BotError::Config(format!("{}: {}", path, err)))?;
+            if let Some((_, value)) = settings.into_iter().find(|(key, _)| key == name) {
+                return Ok(Some(value));
+            }
+        }
+        Ok(std::env::var(name).ok())
+    }
+}
+
+// settings of the file as environment variables, unknown keys and values that don't fit a variable are errors
+fn parse(content: &str) -> Result<Vec<(String, String)>, String> {
+    let table: toml::value::Table = toml::from_str(content).map_err(|err| err.to_string())?;
+    let mut settings = vec![];
+    flatten("", &table, &mut settings)?;
+    Ok(settings)
+}
+
+fn flatten(prefix: &str, table: &toml::value::Table, settings: &mut Vec<(String, String)>) -> Result<(), String> {
+    for (key, value) in table.iter() {
+        let name = format!("{}{}", prefix, key.to_uppercase());
+        if let toml::Value::Table(table) = value {
+            flatten(&format!("{}_", name), table, settings)?;
+            continue;
+        }
+        if !KNOWN.contains(&name.as_str()) {
+            return Err(format!("unknown setting {}", name.to_lowercase()));
+        }
+        settings.push((name.clone(), scalar(value).ok_or_else(|| format!("{} has to be a string, number, boolean or list of them", name.to_lowercase()))?));
+    }
+    Ok(())
+}
+
+// lists, like the allowed user ids, become comma separated
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value.clone()),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Float(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        toml::Value::Array(values) => values.iter()
+            .map(|value| match value {
+                toml::Value::Array(_) => None,
+                value => scalar(value),
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|values| values.join(",")),
+        toml::Value::Datetime(_) | toml::Value::Table(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, ConfigFile, KNOWN};
+
+    #[test]
+    fn should_flatten_tables_into_env_names() {
+        let settings = parse("tg_key = \"1:abc\"\ntg_users = [1, 2]\n\n[oai]\nmodel = \"gpt-4o\"\ntemperature = 0.2\n\n[demo]\nmode = true\n").unwrap();
+        let settings = settings.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect::<Vec<_>>();
+        assert_eq!(settings, vec![("DEMO_MODE", "true"), ("OAI_MODEL", "gpt-4o"), ("OAI_TEMPERATURE", "0.2"), ("TG_KEY", "1:abc"), ("TG_USERS", "1,2")]);
+
+        assert_eq!(parse("[oai]\nmodle = \"gpt-4o\"").unwrap_err(), "unknown setting oai_modle");
+        assert!(parse("conn_string = 1979-05-27T07:32:00Z").is_err());
+        assert!(parse("tg_key = ").is_err());
+    }
+
+    #[test]
+    fn should_know_every_env_setting() {
+        let names = include_str!("models.rs").split("envconfig(from = \"").skip(1)
+            .filter_map(|rest| rest.split('"').next())
+            .collect::<Vec<_>>();
+        assert!(!names.is_empty());
+        for name in names {
+            assert!(KNOWN.contains(&name), "{} is missing from the config keys", name);
+        }
+    }
+
+    #[test]
+    fn should_take_config_path_out_of_arguments() {
+        let args = ["--config", "bot.toml", "--profile", "staging"].iter().map(|arg| arg.to_string());
+        let (config, rest) = ConfigFile::from_args(args).unwrap();
+        assert_eq!(config.path.as_deref(), Some("bot.toml"));
+        assert_eq!(rest, vec!["--profile", "staging"]);
+        assert!(ConfigFile::from_args(["--config".to_string()].into_iter()).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use crate::errors::BotError;
+
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+// a classic five field expression: minute, hour, day of month, month and day of week,
+// with lists, ranges, steps and english names of days and months
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // like cron, a restricted day of month and day of week match when either of them does
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    // the first matching minute after `after` on the wall clock of the timezone, times skipped by a dst switch don't fire
+    pub fn next_after(&self, after: DateTime<Utc>, timezone: Tz) -> Option<DateTime<Utc>> {
+        let start = after.with_timezone(&timezone).naive_local();
+        // schedules like 30 february never match, a few years of days is enough to find the rest
+        for date in start.date().iter_days().take(366 * 5) {
+            if !self.matches_date(date) {
+                continue;
+            }
+            for hour in (0..24).filter(|hour| has(self.hours, *hour)) {
+                for minute in (0..60).filter(|minute| has(self.minutes, *minute)) {
+                    let time = date.and_hms_opt(hour, minute, 0)
+                        .and_then(|local| timezone.from_local_datetime(&local).earliest())
+                        .map(|time| time.with_timezone(&Utc));
+                    match time {
+                        Some(time) if time > after => return Some(time),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        let day = has(self.days, date.day());
+        let weekday = has(self.weekdays, date.weekday().num_days_from_sunday());
+        has(self.months, date.month()) && match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+}
+
+fn has(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+impl Display for CronSchedule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = BotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || BotError::InvalidCron(s.to_string());
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        let [minute, hour, day, month, weekday] = fields.as_slice() else {
+            return Err(invalid());
+        };
+        let weekdays = parse_field(weekday, 0, 7, &WEEKDAYS, 0).ok_or_else(invalid)?;
+        Ok(CronSchedule {
+            expression: fields.join(" "),
+            minutes: parse_field(minute, 0, 59, &[], 0).ok_or_else(invalid)?,
+            hours: parse_field(hour, 0, 23, &[], 0).ok_or_else(invalid)?,
+            days: parse_field(day, 1, 31, &[], 1).ok_or_else(invalid)?,
+            months: parse_field(month, 1, 12, &MONTHS, 1).ok_or_else(invalid)?,
+            // both 0 and 7 are sunday
+            weekdays: (weekdays | weekdays >> 7) & 0x7f,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+}
+
+// set of values as bits, names are numbered from `first_name`
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str], first_name: u32) -> Option<u64> {
+    let value = |part: &str| {
+        let lowercase = part.to_lowercase();
+        names.iter().position(|name| *name == lowercase)
+            .map(|position| position as u32 + first_name)
+            .or_else(|| part.parse().ok())
+            .filter(|value| (min..=max).contains(value))
+    };
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0)?),
+            None => (part, 1),
+        };
+        let (from, to) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((from, to)) => (value(from)?, value(to)?),
+            // a single value with a step runs to the end of the range, like 5/15
+            None if step > 1 => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if from > to {
+            return None;
+        }
+        for value in (from..=to).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Some(set)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+    use chrono_tz::Asia::Jerusalem;
+    use chrono_tz::UTC;
+    use super::CronSchedule;
+
+    fn time(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn should_find_next_occurrence_of_cron_schedule() {
+        let weekdays = "0 9 * * MON-FRI".parse::<CronSchedule>().unwrap();
+        // friday 2023-01-27 after nine, the next one is on monday
+        assert_eq!(weekdays.next_after(time("2023-01-27T10:00:00Z"), UTC), Some(time("2023-01-30T09:00:00Z")));
+        assert_eq!(weekdays.next_after(time("2023-01-30T08:59:30Z"), UTC), Some(time("2023-01-30T09:00:00Z")));
+        assert_eq!(weekdays.next_after(time("2023-01-30T09:00:00Z"), UTC), Some(time("2023-01-31T09:00:00Z")));
+        // nine in jerusalem is seven in utc in winter
+        assert_eq!(weekdays.next_after(time("2023-01-30T08:00:00Z"), Jerusalem), Some(time("2023-01-31T07:00:00Z")));
+
+        let steps = "*/20 8-9 1,15 * 0".parse::<CronSchedule>().unwrap();
+        // sunday 2023-01-29 matches by day of week, 2023-02-01 by day of month
+        assert_eq!(steps.next_after(time("2023-01-29T09:40:00Z"), UTC), Some(time("2023-02-01T08:00:00Z")));
+        assert_eq!(steps.next_after(time("2023-01-29T08:10:00Z"), UTC), Some(time("2023-01-29T08:20:00Z")));
+
+        assert_eq!("0 0 30 feb *".parse::<CronSchedule>().unwrap().next_after(time("2023-01-29T00:00:00Z"), UTC), None);
+        for invalid in ["0 9 * *", "60 9 * * *", "0 9 * * FUN", "0 9-5 * * *", "*/0 9 * * *"] {
+            assert!(invalid.parse::<CronSchedule>().is_err(), "{}", invalid);
+        }
+    }
+}
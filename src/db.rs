@@ -1,38 +1,136 @@
-use chrono::{Datelike, DateTime, Timelike, Utc};
-use deadpool_sqlite::Runtime;
-use fnv::FnvHashSet;
-use rusqlite::ToSql;
-use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ValueRef};
+use chrono::{Datelike, DateTime, NaiveDate, Offset, Timelike, TimeZone, Utc};
+use chrono_tz::Tz;
+use deadpool_sqlite::{Hook, HookError, HookErrorCause, PoolConfig, Runtime};
+use fnv::{FnvHashMap, FnvHashSet};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock};
+use rusqlite::{OptionalExtension, Row, ToSql, Transaction, TransactionBehavior};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use crate::commands;
+use crate::cron::CronSchedule;
 use crate::errors::BotError;
-use crate::models::{EventToFire, StoredNotification};
+use crate::humanize::Locale;
+use crate::ics::next_weekly_occurrence;
+use crate::ids::IdGenerator;
+use crate::migrations;
+use crate::models::{shift_weekly, Delivery, Env, EventToFire, Location, Priority, QuietHours, StoredNotification, DEFAULT_TIMEZONE};
+use crate::parser::Usage;
 
 
-#[derive(Clone, Debug)]
+// what a user may do, read-only users can look at their reminders but not change them
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    Admin,
+    User,
+    ReadOnly,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::User => "user",
+            Role::ReadOnly => "read-only",
+        }
+    }
+
+    pub fn can_create(&self) -> bool {
+        *self != Role::ReadOnly
+    }
+}
+
+impl FromStr for Role {
+    type Err = BotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "user" => Ok(Role::User),
+            "read-only" => Ok(Role::ReadOnly),
+            _ => Err(BotError::UnknownRole(s.to_string()))
+        }
+    }
+}
+
+impl FromSql for Role {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|_| FromSqlError::InvalidType)
+    }
+}
+
+impl ToSql for Role {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+// users from TG_USERS plus the ones approved or given a role by an admin while the bot is running
+#[derive(Debug)]
 pub struct UserRepository {
-    users: FnvHashSet<u64>
+    users: RwLock<FnvHashMap<u64, Role>>,
+    // role of everyone else, the demo lets anybody in
+    default_role: Option<Role>,
 }
 
 impl UserRepository {
-    pub fn new(users: impl Iterator<Item = u64>) -> Self {
+    // later entries win, so stored roles can override the defaults from the environment
+    pub fn new(users: impl Iterator<Item = (u64, Role)>) -> Self {
         Self {
-            users: FnvHashSet::from_iter(users)
+            users: RwLock::new(FnvHashMap::from_iter(users)),
+            default_role: None,
         }
     }
 
+    pub fn with_default_role(self, role: Role) -> Self {
+        Self { default_role: Some(role), ..self }
+    }
+
     pub fn is_chat_id_valid(&self, chat_id: u64) -> bool {
-        self.users.contains(&chat_id)
+        self.role(chat_id).is_some()
+    }
+
+    pub fn role(&self, chat_id: u64) -> Option<Role> {
+        self.users.read().unwrap_or_else(PoisonError::into_inner).get(&chat_id).copied().or(self.default_role)
+    }
+
+    pub fn user_ids(&self) -> Vec<u64> {
+        self.users.read().unwrap_or_else(PoisonError::into_inner).keys().copied().collect()
+    }
+
+    pub fn add(&self, user_id: u64) {
+        self.users.write().unwrap_or_else(PoisonError::into_inner).entry(user_id).or_insert(Role::User);
+    }
+
+    pub fn remove(&self, user_id: u64) {
+        self.users.write().unwrap_or_else(PoisonError::into_inner).remove(&user_id);
+    }
+
+    pub fn set_role(&self, user_id: u64, role: Role) {
+        self.users.write().unwrap_or_else(PoisonError::into_inner).insert(user_id, role);
+    }
+
+    // swaps the whole list at once, so a check running meanwhile sees either the old or the new one
+    pub fn replace(&self, users: impl Iterator<Item = (u64, Role)>) {
+        *self.users.write().unwrap_or_else(PoisonError::into_inner) = FnvHashMap::from_iter(users);
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct EventRepository {
     pool: deadpool_sqlite::Pool,
+    id_generator: Arc<dyn IdGenerator>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Kind {
     Absolute,
     Recurrent,
+    Cron,
 }
 
 impl FromSql for Kind {
@@ -41,6 +139,7 @@ impl FromSql for Kind {
             ValueRef::Text(text) => match text {
                 b"absolute" => Ok(Kind::Absolute),
                 b"recurrent" => Ok(Kind::Recurrent),
+                b"cron" => Ok(Kind::Cron),
                 _ => Err(FromSqlError::InvalidType)
             },
             _ => Err(FromSqlError::InvalidType)
@@ -48,118 +147,3519 @@ impl FromSql for Kind {
     }
 }
 
-#[derive(Debug)]
+// channel or integration through which an event was created
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    Telegram,
+    Api,
+    EmailIngest,
+    CalendarSync,
+    Cli,
+    Import,
+}
+
+impl Source {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Source::Telegram => "telegram",
+            Source::Api => "api",
+            Source::EmailIngest => "email-ingest",
+            Source::CalendarSync => "calendar-sync",
+            Source::Cli => "cli",
+            Source::Import => "import",
+        }
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Source {
+    type Err = BotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "telegram" => Ok(Source::Telegram),
+            "api" => Ok(Source::Api),
+            "email-ingest" => Ok(Source::EmailIngest),
+            "calendar-sync" => Ok(Source::CalendarSync),
+            "cli" => Ok(Source::Cli),
+            "import" => Ok(Source::Import),
+            _ => Err(BotError::UnknownSource(s.to_string()))
+        }
+    }
+}
+
+impl FromSql for Source {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|_| FromSqlError::InvalidType)
+    }
+}
+
+impl ToSql for Source {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for Priority {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|_| FromSqlError::InvalidType)
+    }
+}
+
+impl ToSql for Priority {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Event {
-    pub id: u64,
+    pub uid: String,
     pub kind: Kind,
-    pub user_id: u64,
+    pub source: Source,
     pub text: String,
     pub time: Option<DateTime<Utc>>,
     pub day: Option<u8>,
     pub hour: Option<u8>,
     pub minute: Option<u8>,
-    pub is_deleted: bool
+    pub is_deleted: bool,
+    pub lead_minutes: u32,
+    pub priority: Priority,
+    pub nag_minutes: u32,
+    pub expires_minutes: u32,
+    pub silent: bool,
+    // cron events fire at `time` and move it to the next match of the expression
+    pub cron: Option<String>,
+    // forwarded message the reminder was created from, quoted back when it fires
+    pub quote: Option<String>,
+    // wall clock weekly and cron events are kept on, the bot's one when missing
+    pub timezone: Option<String>,
+    // weekly events skip the days off of this country
+    pub holiday_country: Option<String>,
 }
 
+// progress of a resumable background job
+#[derive(Debug)]
+pub struct Job {
+    pub name: String,
+    pub status: String,
+    pub checkpoint: i64,
+    pub processed: i64,
+    pub updated_at: DateTime<Utc>,
+}
 
-impl EventRepository {
-    pub async fn new(connection_string: &str) -> Result<EventRepository, BotError> {
-        let cfg = deadpool_sqlite::Config::new(connection_string);
-        let pool = cfg.create_pool(Runtime::Tokio1)?;
-        let connection = pool.get().await?;
-        connection.interact(|connection| {
-            // in sqlite syntax create table event if not exists
-            // create also indexes on (user_id, is_deleted)
-            let sql = "create table if not exists event (
-                id integer primary key autoincrement,
-                kind text not null,
-                user_id integer not null,
-                event_text text not null,
-                event_time datetime,
-                day integer,
-                hour integer,
-                minute integer,
-                is_deleted integer
-            );
+// lifecycle step recorded in event_history
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transition {
+    Created,
+    Fired,
+    Deleted,
+    DeadLettered,
+    // one occurrence of a recurrent event was skipped, the series goes on
+    Skipped,
+    // compensations written by /undo: a created event is closed again, a deleted one is reopened
+    Undone,
+    Restored,
+    // closed unsent by the missed policy, having been due too long ago
+    Expired,
+    // done was pressed on the fired reminder
+    Completed,
+    // the fired reminder was asked to come back later, the new time is a separate event created along
+    Snoozed,
+    // the text was changed, the schedule stays
+    Edited,
+}
 
-            create index if not exists event_user_id_is_deleted on event (user_id, is_deleted);
-            create index if not exists event_is_deleted on event (is_deleted);";
-            connection.execute(sql, ())
-        }).await??;
-        Ok(EventRepository { pool })
+impl Transition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Transition::Created => "created",
+            Transition::Fired => "fired",
+            Transition::Deleted => "deleted",
+            Transition::DeadLettered => "dead-lettered",
+            Transition::Skipped => "skipped",
+            Transition::Undone => "undone",
+            Transition::Restored => "restored",
+            Transition::Expired => "expired",
+            Transition::Completed => "completed",
+            Transition::Snoozed => "snoozed",
+            Transition::Edited => "edited",
+        }
     }
+}
 
-    pub async fn insert_event(&self, user_id: u64, text: String, stored_notification: Vec<StoredNotification>) -> Result<Vec<u64>, BotError> {
-        let ids = self.pool.get().await?.interact(move |connection| {
-            let tx = connection.transaction()?;
-            let mut ids = vec![];
-            {
-                let mut stmt = tx.prepare_cached("insert into event (kind, user_id, event_text, event_time, day, hour, minute, is_deleted) values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);")?;
+impl FromSql for Transition {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "created" => Ok(Transition::Created),
+            "fired" => Ok(Transition::Fired),
+            "deleted" => Ok(Transition::Deleted),
+            "dead-lettered" => Ok(Transition::DeadLettered),
+            "skipped" => Ok(Transition::Skipped),
+            "undone" => Ok(Transition::Undone),
+            "restored" => Ok(Transition::Restored),
+            "expired" => Ok(Transition::Expired),
+            "completed" => Ok(Transition::Completed),
+            "snoozed" => Ok(Transition::Snoozed),
+            "edited" => Ok(Transition::Edited),
+            _ => Err(FromSqlError::InvalidType)
+        }
+    }
+}
 
-                for notification in stored_notification {
-                    match notification {
-                        StoredNotification::Absolute { time, .. } => {
-                            let u: Option<u8> = None;
-                            let u: &dyn ToSql = &u;
-                            stmt.execute(&[&"absolute" as &dyn ToSql, &user_id, &text, &Some(time), u, u, u, &0 as &dyn ToSql])?;
-                            // get last inserted rowid
-                            ids.push(tx.last_insert_rowid() as u64);
-                        }
-                        StoredNotification::Recurrent { hours, minutes, days, .. } => {
-                            if let Some(days) = days {
-                                for day in days.iter() {
-                                    let none: Option<DateTime<Utc>> = None;
-                                    stmt.execute(&[&"recurrent" as &dyn ToSql, &user_id, &text, &none, &Some(*day), &Some(hours), &Some(minutes), &0 as &dyn ToSql])?;
-                                    ids.push(tx.last_insert_rowid() as u64);
-                                }
-                            }
-                        }
-                    };
-                }
+impl ToSql for Transition {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+// decision on a join request sent with /start by a user missing from TG_USERS
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl AccessStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccessStatus::Pending => "pending",
+            AccessStatus::Approved => "approved",
+            AccessStatus::Rejected => "rejected",
+        }
+    }
+}
+
+impl FromSql for AccessStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "pending" => Ok(AccessStatus::Pending),
+            "approved" => Ok(AccessStatus::Approved),
+            "rejected" => Ok(AccessStatus::Rejected),
+            _ => Err(FromSqlError::InvalidType)
+        }
+    }
+}
+
+impl ToSql for AccessStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccessRequest {
+    pub username: Option<String>,
+    pub status: AccessStatus,
+    pub requested_at: DateTime<Utc>,
+    pub decided_at: Option<DateTime<Utc>>,
+}
+
+// hashtag from a reminder text, stored without the # and in lower case
+#[derive(Debug, Serialize)]
+pub struct EventTag {
+    pub event_id: u64,
+    pub tag: String,
+}
+
+// map pin shared together with the reminder text
+#[derive(Debug, Serialize)]
+pub struct EventLocation {
+    pub event_id: u64,
+    pub location: Location,
+}
+
+// utc date on which a recurrent event row doesn't fire
+#[derive(Debug, Serialize)]
+pub struct EventExclusion {
+    pub event_id: u64,
+    pub event_uid: String,
+    pub occurs_on: NaiveDate,
+}
+
+// day off in a country, from the embedded calendar or HOLIDAYS_FILE
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Holiday {
+    pub country: String,
+    pub day: NaiveDate,
+    pub name: String,
+}
+
+// a user with access to the bot, TG_USERS only seeds them
+#[derive(Debug, Clone, Serialize)]
+pub struct AllowedUser {
+    pub user_id: u64,
+    pub display_name: Option<String>,
+    pub timezone: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub removed_at: Option<DateTime<Utc>>,
+}
+
+// mutating action of a user that /undo can take back
+#[derive(Debug, Serialize)]
+pub struct UndoAction {
+    pub label: String,
+    pub at: DateTime<Utc>,
+}
+
+// reminder text with {placeholders} saved by /template add
+#[derive(Debug, Clone, Serialize)]
+pub struct Template {
+    pub id: u64,
+    pub name: String,
+    pub body: String,
+}
+
+// text the user had parsed again or picked another reading of, with the reading they accepted;
+// shown to the model as an example when they write something alike
+#[derive(Debug, Clone, Serialize)]
+pub struct ParseCorrection {
+    pub input: String,
+    pub accepted: String,
+    pub parsed_at: DateTime<Utc>,
+}
+
+// corrections kept per user, older ones are dropped
+const MAX_CORRECTIONS: usize = 50;
+
+// last actions kept per user for /undo
+const UNDO_DEPTH: usize = 5;
+
+// fired event held back by the quiet hours of its user
+#[derive(Debug, Serialize)]
+pub struct DeferredDelivery {
+    pub event_id: u64,
+    pub release_at: DateTime<Utc>,
+}
+
+// webhook called when any active reminder with the text fires
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookRoute {
+    pub event_text: String,
+    pub webhook: String,
+}
+
+// business account whose chats the bot serves, user_id is the owner of the account
+#[derive(Debug, Serialize)]
+pub struct BusinessConnection {
+    pub id: String,
+    pub user_id: u64,
+    pub user_chat_id: u64,
+    pub is_enabled: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl BusinessConnection {
+    const COLUMNS: &'static str = "id, user_id, user_chat_id, is_enabled, updated_at";
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<BusinessConnection> {
+        Ok(BusinessConnection {
+            id: row.get(0)?,
+            user_id: row.get(1)?,
+            user_chat_id: row.get(2)?,
+            is_enabled: row.get(3)?,
+            updated_at: row.get(4)?,
+        })
+    }
+}
+
+// scheduled occurrences of a user grouped by local day, shown by /load
+#[derive(Debug, Default)]
+pub struct Load {
+    // one-off reminders per date
+    pub dates: Vec<(NaiveDate, u32)>,
+    // weekly reminders per weekday, numbered from 1 (Monday) to 7
+    pub weekdays: Vec<(u8, u32)>,
+}
+
+impl Load {
+    pub fn count(&self, date: NaiveDate) -> u32 {
+        let weekday = date.weekday().number_from_monday() as u8;
+        self.dates.iter().filter(|(day, _)| *day == date).map(|(_, count)| count).sum::<u32>()
+            + self.weekdays.iter().filter(|(day, _)| *day == weekday).map(|(_, count)| count).sum::<u32>()
+    }
+}
+
+// event that could not be delivered after all attempts
+#[derive(Debug)]
+pub struct DeadLetter {
+    pub event_id: u64,
+    pub user_id: u64,
+    pub text: String,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryEntry {
+    pub event_id: u64,
+    pub transition: Transition,
+    pub at: DateTime<Utc>,
+}
+
+// how a reminder ended up, told by the last of its closing transitions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Completed,
+    // fired and never marked done, expired unsent or given up on
+    Missed,
+    Cancelled,
+}
+
+impl Outcome {
+    pub fn of(transition: Transition) -> Option<Outcome> {
+        match transition {
+            Transition::Completed => Some(Outcome::Completed),
+            Transition::Fired | Transition::Expired | Transition::DeadLettered => Some(Outcome::Missed),
+            Transition::Deleted | Transition::Undone => Some(Outcome::Cancelled),
+            Transition::Created | Transition::Skipped | Transition::Restored | Transition::Snoozed | Transition::Edited => None,
+        }
+    }
+
+    // none while the reminder is pending, reopened or snoozed; a recurrent one that fired after its last completion is missed again
+    pub fn from_history(history: &[HistoryEntry]) -> Option<Outcome> {
+        history.iter().rev()
+            .find(|entry| !matches!(entry.transition, Transition::Skipped | Transition::Edited))
+            .and_then(|entry| Outcome::of(entry.transition))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub name: String,
+    pub url: String,
+}
+
+// google calendar of a user, reminders accepted in the bot are pushed to it and its upcoming events pulled from it;
+// the refresh token is stored as is, it's encrypted at rest only with DATABASE_KEY, and never exported
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CalendarConnection {
+    pub user_id: u64,
+    #[serde(serialize_with = "redacted")]
+    pub refresh_token: String,
+    pub push: bool,
+    pub pull: bool,
+}
+
+fn redacted<S: Serializer>(_: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("<redacted>")
+}
+
+// id of a google calendar event pushed from the bot or pulled into it
+#[derive(Debug, Serialize)]
+pub struct CalendarEvent {
+    pub google_id: String,
+}
+
+// audit record of a single webhook invocation
+#[derive(Debug, Serialize)]
+pub struct WebhookCall {
+    pub name: String,
+    pub event_id: Option<u64>,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub called_at: DateTime<Utc>,
+}
+
+// llm tokens spent by a user during one calendar month
+#[derive(Debug, Default, Serialize)]
+pub struct MonthlyUsage {
+    pub month: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost: f64,
+}
+
+// per-user preferences changed with bot commands
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UserSettings {
+    pub plain_mode: bool,
+    pub paused_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>,
+    #[serde(default)]
+    pub language: Option<Locale>,
+}
+
+impl UserSettings {
+    pub fn is_paused(&self, now: DateTime<Utc>) -> bool {
+        self.paused_until.is_some_and(|until| until > now)
+    }
+}
+
+// counters reported by the management api
+#[derive(Debug, Default, Serialize)]
+pub struct Stats {
+    pub pending_events: u64,
+    pub dead_letters: u64,
+    pub active_users: u64,
+}
+
+// one statement of the nightly database maintenance, each runs on its own so due reminders can go in between
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceStep {
+    IncrementalVacuum,
+    Optimize,
+    Analyze,
+    Checkpoint,
+}
+
+impl MaintenanceStep {
+    pub const ALL: [MaintenanceStep; 4] = [MaintenanceStep::IncrementalVacuum, MaintenanceStep::Optimize, MaintenanceStep::Analyze, MaintenanceStep::Checkpoint];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MaintenanceStep::IncrementalVacuum => "incremental_vacuum",
+            MaintenanceStep::Optimize => "optimize",
+            MaintenanceStep::Analyze => "analyze",
+            MaintenanceStep::Checkpoint => "checkpoint",
+        }
+    }
+
+    fn sql(&self) -> &'static str {
+        match self {
+            // auto_vacuum only switches to incremental on the next full vacuum, until then this frees nothing
+            MaintenanceStep::IncrementalVacuum => "pragma auto_vacuum = incremental; pragma incremental_vacuum;",
+            MaintenanceStep::Optimize => "pragma optimize;",
+            MaintenanceStep::Analyze => "analyze;",
+            MaintenanceStep::Checkpoint => "pragma wal_checkpoint(truncate);",
+        }
+    }
+}
+
+// how long every step of the latest maintenance run took
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceReport {
+    pub finished_at: DateTime<Utc>,
+    pub steps: Vec<(MaintenanceStep, u64)>,
+}
+
+impl MonthlyUsage {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<MonthlyUsage> {
+        Ok(MonthlyUsage {
+            month: row.get(0)?,
+            prompt_tokens: row.get(1)?,
+            completion_tokens: row.get(2)?,
+            cost: row.get(3)?,
+        })
+    }
+}
+
+impl HistoryEntry {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<HistoryEntry> {
+        Ok(HistoryEntry {
+            event_id: row.get(0)?,
+            transition: row.get(1)?,
+            at: row.get(2)?,
+        })
+    }
+}
+
+impl Event {
+    pub fn delivery(&self) -> Delivery {
+        Delivery { priority: self.priority, nag_minutes: self.nag_minutes, expires_minutes: self.expires_minutes, silent: self.silent }
+    }
+
+    const COLUMNS: &'static str = "uid, kind, source, event_text, event_time, day, hour, minute, is_deleted, lead_minutes, priority, nag_minutes, timezone, cron, quote, expires_minutes, silent, holiday_country";
+
+    // weekly times kept in a timezone are handed out in utc as of now, like the rows without one
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Event> {
+        let timezone: Option<String> = row.get(12)?;
+        let (day, hour, minute) = match (row.get(5)?, row.get(6)?, row.get(7)?) {
+            (Some(day), Some(hour), Some(minute)) => {
+                let (day, hour, minute) = slot_to_utc(day, hour, minute, timezone.as_deref(), Utc::now());
+                (Some(day), Some(hour), Some(minute))
             }
-            tx.commit().map(|_| ids)
-        }).await??;
-        Ok(ids)
+            slot => slot,
+        };
+        Ok(Event {
+            uid: row.get(0)?,
+            kind: row.get(1)?,
+            source: row.get(2)?,
+            text: row.get(3)?,
+            time: row.get(4)?,
+            day,
+            hour,
+            minute,
+            is_deleted: row.get(8)?,
+            lead_minutes: row.get(9)?,
+            priority: row.get(10)?,
+            nag_minutes: row.get(11)?,
+            cron: row.get(13)?,
+            quote: row.get(14)?,
+            expires_minutes: row.get(15)?,
+            silent: row.get(16)?,
+            timezone,
+            holiday_country: row.get(17)?,
+        })
     }
+}
 
-    pub async fn delete_events(&self, event_ids: Vec<u64>) -> Result<(), BotError> {
-        self.pool.get().await?.interact(move |connection| {
-            rusqlite::vtab::array::load_module(&connection)?;
-            let array = rusqlite::vtab::array::Array::new(
-                event_ids.iter()
-                    .map(|x| rusqlite::types::Value::Integer(*x as i64))
-                    .collect()
-            );
-            connection.execute("update event set is_deleted = 1 where id in rarray(?);", [array])
-        }).await??;
-        Ok(())
+fn zone_offset_minutes(timezone: Tz, at: DateTime<Utc>) -> i32 {
+    timezone.offset_from_utc_datetime(&at.naive_utc()).fix().local_minus_utc() / 60
+}
+
+// utc weekday, hour and minute of a weekly slot stored as wall time in the timezone, using the offset it has at `at`
+fn slot_to_utc(day: u8, hour: u8, minute: u8, timezone: Option<&str>, at: DateTime<Utc>) -> (u8, u8, u8) {
+    match timezone.and_then(|timezone| timezone.parse::<Tz>().ok()) {
+        Some(timezone) => {
+            let (days, hour, minute) = shift_weekly(&[day], hour, minute, -zone_offset_minutes(timezone, at));
+            (days[0], hour, minute)
+        }
+        None => (day, hour, minute),
     }
+}
 
-    pub async fn get_events_to_fire(&self, current_time: DateTime<Utc>) -> Result<Vec<EventToFire>, BotError> {
-        // select only rows which has kind absolute and time is after current time or
-        // kind recurrent and current day is equal to day and hour + minute is after current time
-        let events = self.pool.get().await?
-            .interact(move |connection| {
-                let current_day = current_time.weekday().num_days_from_monday() + 1;
-                let minutes = current_time.hour() * 60 + current_time.minute();
-                let mut stmt = connection
-                    .prepare("select id, user_id, event_text from event where \
-                is_deleted = 0 and (
-                kind = 'absolute' and event_time < ? or \
-                kind = 'recurrent' and day = ? and hour * 60 + minute < ?)")?;
-
-                let result = stmt.query_map(&[&current_time as &dyn ToSql, &current_day, &minutes], |row| {
-                    let event_id: u64 = row.get(0)?;
-                    let user_id: u64 = row.get(1)?;
-                    let text: String = row.get(2)?;
-                    Ok(EventToFire {
-                        event_id,
-                        user_id,
-                        text
-                    })
-                })?.collect::<Result<Vec<_>, _>>();
-                result
-            }).await??;
-        Ok(events)
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+impl JournalMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            JournalMode::Delete => "delete",
+            JournalMode::Truncate => "truncate",
+            JournalMode::Persist => "persist",
+            JournalMode::Memory => "memory",
+            JournalMode::Wal => "wal",
+            JournalMode::Off => "off",
+        }
+    }
+}
+
+impl FromStr for JournalMode {
+    type Err = BotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "delete" => Ok(JournalMode::Delete),
+            "truncate" => Ok(JournalMode::Truncate),
+            "persist" => Ok(JournalMode::Persist),
+            "memory" => Ok(JournalMode::Memory),
+            "wal" => Ok(JournalMode::Wal),
+            "off" => Ok(JournalMode::Off),
+            _ => Err(BotError::UnknownJournalMode(s.to_string()))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Synchronous::Off => "off",
+            Synchronous::Normal => "normal",
+            Synchronous::Full => "full",
+            Synchronous::Extra => "extra",
+        }
     }
-}
\ No newline at end of file
+}
+
+impl FromStr for Synchronous {
+    type Err = BotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(Synchronous::Off),
+            "normal" => Ok(Synchronous::Normal),
+            "full" => Ok(Synchronous::Full),
+            "extra" => Ok(Synchronous::Extra),
+            _ => Err(BotError::UnknownSynchronous(s.to_string()))
+        }
+    }
+}
+
+// set on every pooled connection as soon as it's opened
+#[derive(Debug, Clone)]
+pub struct ConnectionOptions {
+    // encrypts the file at rest with sqlcipher, the same key has to be given on every start
+    pub key: Option<String>,
+    // wal lets the fire loop read while an update handler writes
+    pub journal_mode: JournalMode,
+    pub synchronous: Synchronous,
+    // how long a connection waits for another one's write lock before failing with "database is locked"
+    pub busy_timeout: std::time::Duration,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        ConnectionOptions {
+            key: None,
+            journal_mode: JournalMode::Wal,
+            synchronous: Synchronous::Normal,
+            busy_timeout: std::time::Duration::from_secs(5),
+        }
+    }
+}
+
+impl ConnectionOptions {
+    pub fn from_env(env: &Env) -> ConnectionOptions {
+        ConnectionOptions {
+            key: env.database_key.clone(),
+            journal_mode: env.sqlite_journal_mode,
+            synchronous: env.sqlite_synchronous,
+            busy_timeout: std::time::Duration::from_millis(env.sqlite_busy_timeout_ms),
+        }
+    }
+
+    fn apply(&self, connection: &rusqlite::Connection, read_only: bool) -> Result<(), HookError> {
+        let backend = |err| HookError::Abort(HookErrorCause::Backend(err));
+        // the key has to come before anything else touches the file;
+        // plain sqlite silently ignores the key pragma and would keep the file readable, so such a build refuses the key
+        if let Some(key) = &self.key {
+            connection.pragma_update(None, "key", key).map_err(backend)?;
+            let cipher = connection.query_row("pragma cipher_version", [], |row| row.get::<_, String>(0)).optional().map_err(backend)?;
+            if cipher.is_none() {
+                return Err(HookError::Abort(HookErrorCause::StaticMessage("DATABASE_KEY is set, but the bot is built without the sqlcipher feature")));
+            }
+        }
+        connection.busy_timeout(self.busy_timeout).map_err(backend)?;
+        // the journal mode is stored in the file, a read-only connection can't change it and uses what the primary set
+        if !read_only {
+            connection.execute_batch(&format!("pragma journal_mode = {};", self.journal_mode.as_str())).map_err(backend)?;
+        }
+        connection.execute_batch(&format!("pragma synchronous = {};", self.synchronous.as_str())).map_err(backend)
+    }
+}
+
+fn create_pool(config: deadpool_sqlite::Config, options: ConnectionOptions, read_only: bool) -> Result<deadpool_sqlite::Pool, BotError> {
+    config.builder(Runtime::Tokio1).unwrap_or_else(|never| match never {})
+        .post_create(Hook::sync_fn(move |connection, _| {
+            let connection = connection.lock()
+                .map_err(|_| HookError::Abort(HookErrorCause::StaticMessage("database connection is poisoned")))?;
+            options.apply(&connection, read_only)
+        }))
+        .build()
+        .map_err(|err| BotError::CreatePool(deadpool_sqlite::CreatePoolError::Build(err)))
+}
+
+// history, search and tag rows of freshly inserted events
+fn record_created(tx: &Transaction<'_>, user_id: u64, text: &str, ids: &[u64]) -> rusqlite::Result<()> {
+    let mut history = tx.prepare_cached("insert into event_history (event_id, user_id, transition, at) values (?1, ?2, ?3, ?4)")?;
+    let now = Utc::now();
+    for id in ids.iter() {
+        history.execute([id as &dyn ToSql, &user_id, &Transition::Created, &now])?;
+    }
+
+    let mut search = tx.prepare_cached("insert into event_search (rowid, text) select id, event_text from event where id = ?1 and lead_minutes = 0")?;
+    for id in ids.iter() {
+        search.execute([id])?;
+    }
+
+    let mut tag = tx.prepare_cached("insert or ignore into event_tag (event_id, user_id, tag) values (?1, ?2, ?3)")?;
+    for name in extract_tags(text) {
+        for id in ids.iter() {
+            tag.execute([id as &dyn ToSql, &user_id, &name])?;
+        }
+    }
+    Ok(())
+}
+
+// share of query words found in the text, allowing a typo per three letters and inflected endings
+fn text_similarity(query: &str, text: &str) -> f64 {
+    let words = |value: &str| value.split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    let (query, text) = (words(query), words(text));
+    if query.is_empty() {
+        return 0.0;
+    }
+    let matched = query.iter()
+        .filter(|word| text.iter().any(|candidate| candidate.starts_with(word.as_str())
+            || commands::distance(word, candidate) <= word.chars().count() / 3))
+        .count();
+    matched as f64 / query.len() as f64
+}
+
+// #hashtags of a text without the #, lower case and without repeats, in the order they appear
+pub fn extract_tags(text: &str) -> Vec<String> {
+    let mut tags = vec![];
+    for word in text.split_whitespace() {
+        let tag = word.strip_prefix('#').unwrap_or_default()
+            .chars()
+            .take_while(|ch| ch.is_alphanumeric() || *ch == '_')
+            .collect::<String>()
+            .to_lowercase();
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+// every word of the user's query becomes a quoted prefix term, so fts syntax in the query is taken literally
+fn fts_query(query: &str) -> Option<String> {
+    let terms = query.split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{}\"*", word))
+        .collect::<Vec<_>>();
+    if terms.is_empty() { None } else { Some(terms.join(" ")) }
+}
+
+// CONN_STRING that keeps the whole database in memory, for tests and throwaway runs that shouldn't leave files behind
+pub const IN_MEMORY: &str = ":memory:";
+
+// matches below this share of query words aren't offered for cancellation
+const MIN_SIMILARITY: f64 = 0.5;
+
+impl EventRepository {
+    const JOB_BATCH_SIZE: i64 = 500;
+
+    pub async fn new(connection_string: &str, id_generator: Arc<dyn IdGenerator>) -> Result<EventRepository, BotError> {
+        Self::open(connection_string, id_generator, ConnectionOptions::default()).await
+    }
+
+    pub async fn open(connection_string: &str, id_generator: Arc<dyn IdGenerator>, options: ConnectionOptions) -> Result<EventRepository, BotError> {
+        let mut config = deadpool_sqlite::Config::new(connection_string);
+        // every connection to :memory: opens its own empty database, so the pool keeps just the one
+        if connection_string == IN_MEMORY {
+            config.pool = Some(PoolConfig::new(1));
+        }
+        let pool = create_pool(config, options, false)?;
+        pool.get().await?.interact(migrations::migrate).await??;
+
+        let repository = EventRepository { pool, id_generator };
+        repository.backfill_uids().await?;
+        Ok(repository)
+    }
+
+    // for a standby on a replicated file: sqlite refuses every write, and nothing is migrated or backfilled
+    pub async fn open_read_only(connection_string: &str, id_generator: Arc<dyn IdGenerator>, options: ConnectionOptions) -> Result<EventRepository, BotError> {
+        let pool = create_pool(deadpool_sqlite::Config::new(format!("file:{}?mode=ro", connection_string)), options, true)?;
+        let connection = pool.get().await?;
+        if let Some((applied, expected)) = connection.interact(|connection| migrations::check_current(connection)).await?? {
+            return Err(BotError::SchemaMismatch(applied, expected));
+        }
+        Ok(EventRepository { pool, id_generator })
+    }
+
+    // public identifiers were introduced after rowids, so existing rows get them in batches
+    async fn backfill_uids(&self) -> Result<(), BotError> {
+        let generator = self.id_generator.clone();
+        self.run_checkpointed_job("uid-backfill", move |tx, checkpoint| {
+            let ids = tx
+                .prepare_cached("select id from event where id > ?1 and uid is null order by id limit ?2")?
+                .query_map([checkpoint, Self::JOB_BATCH_SIZE], |row| row.get::<_, i64>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut stmt = tx.prepare_cached("update event set uid = ?1, group_uid = coalesce(group_uid, ?1) where id = ?2")?;
+            for id in ids.iter() {
+                stmt.execute([&generator.generate() as &dyn ToSql, id])?;
+            }
+            Ok(ids.last().map(|last| (*last, ids.len() as i64)))
+        }).await
+    }
+
+    // runs `step` until it reports no more work, committing every batch together with its checkpoint
+    // so a restart resumes after the last committed batch instead of starting over
+    async fn run_checkpointed_job<F>(&self, name: &'static str, step: F) -> Result<(), BotError>
+        where F: Fn(&Transaction<'_>, i64) -> rusqlite::Result<Option<(i64, i64)>> + Send + 'static {
+        self.pool.get().await?.interact(move |connection| {
+            let job = connection
+                .query_row("select status, checkpoint from job where name = ?1", [name], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+                })
+                .optional()?;
+            let mut checkpoint = match job {
+                Some((status, _)) if status == "done" => return Ok(()),
+                Some((_, checkpoint)) => checkpoint,
+                None => {
+                    connection.execute("insert into job (name, status, updated_at) values (?1, 'running', ?2)",
+                                       [&name as &dyn ToSql, &Utc::now()])?;
+                    0
+                }
+            };
+
+            loop {
+                let tx = connection.transaction()?;
+                match step(&tx, checkpoint)? {
+                    Some((next, processed)) => {
+                        tx.execute("update job set checkpoint = ?1, processed = processed + ?2, updated_at = ?3 where name = ?4",
+                                   [&next as &dyn ToSql, &processed, &Utc::now(), &name])?;
+                        tx.commit()?;
+                        checkpoint = next;
+                    }
+                    None => {
+                        tx.execute("update job set status = 'done', updated_at = ?1 where name = ?2",
+                                   [&Utc::now() as &dyn ToSql, &name])?;
+                        return tx.commit();
+                    }
+                }
+            }
+        }).await??;
+        Ok(())
+    }
+
+    pub async fn get_jobs(&self) -> Result<Vec<Job>, BotError> {
+        let jobs = self.pool.get().await?
+            .interact(|connection| {
+                let mut stmt = connection.prepare("select name, status, checkpoint, processed, updated_at from job order by name")?;
+                let result = stmt.query_map([], |row| {
+                    Ok(Job {
+                        name: row.get(0)?,
+                        status: row.get(1)?,
+                        checkpoint: row.get(2)?,
+                        processed: row.get(3)?,
+                        updated_at: row.get(4)?,
+                    })
+                })?.collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(jobs)
+    }
+
+    pub async fn insert_event(&self, user_id: u64, text: String, source: Source, stored_notification: Vec<StoredNotification>) -> Result<Vec<u64>, BotError> {
+        self.insert_event_with_delivery(user_id, text, source, Delivery::default(), stored_notification).await
+    }
+
+    pub async fn insert_event_with_delivery(&self, user_id: u64, text: String, source: Source, delivery: Delivery, stored_notification: Vec<StoredNotification>) -> Result<Vec<u64>, BotError> {
+        let generator = self.id_generator.clone();
+        let group_uid = self.id_generator.generate();
+        let now = Utc::now();
+        let ids = self.pool.get().await?.interact(move |connection| {
+            let tx = connection.transaction()?;
+            let mut ids = vec![];
+            {
+                let mut stmt = tx.prepare_cached("insert into event (kind, user_id, event_text, event_time, day, hour, minute, is_deleted, source, uid, last_fired_at, lead_minutes, priority, nag_minutes, timezone, expires_minutes, silent, group_uid) values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18);")?;
+                let today = now.weekday().num_days_from_monday() as u8 + 1;
+                let minutes_now = now.hour() * 60 + now.minute();
+                // weekly times of users with a known timezone are kept as wall time there
+                let timezone = tx.query_row("select timezone from allowed_user where user_id = ?1 and removed_at is null", [user_id], |row| row.get::<_, Option<String>>(0))
+                    .optional()?
+                    .flatten()
+                    .and_then(|timezone| timezone.parse::<Tz>().ok());
+                let no_timezone: Option<&str> = None;
+
+                // heads-ups are regular rows that remember how long before the main time they fire
+                let notifications = stored_notification.into_iter().map(|notification| match notification {
+                    StoredNotification::Lead { minutes, notification } => (*notification, minutes),
+                    notification => (notification, 0),
+                });
+                for (notification, lead_minutes) in notifications {
+                    match notification {
+                        StoredNotification::Absolute { time, .. } => {
+                            let u: Option<u8> = None;
+                            let u: &dyn ToSql = &u;
+                            let none: Option<DateTime<Utc>> = None;
+                            stmt.execute([&"absolute" as &dyn ToSql, &user_id, &text, &Some(time), u, u, u, &0 as &dyn ToSql, &source, &generator.generate(), &none, &lead_minutes, &delivery.priority, &delivery.nag_minutes, &no_timezone, &delivery.expires_minutes, &delivery.silent, &group_uid])?;
+                            // get last inserted rowid
+                            ids.push(tx.last_insert_rowid() as u64);
+                        }
+                        StoredNotification::Recurrent { hours, minutes, days, .. } => {
+                            if let Some(days) = days {
+                                for day in days.iter() {
+                                    let none: Option<DateTime<Utc>> = None;
+                                    // today's occurrence has already passed, the first one is next week
+                                    let passed = *day == today && (hours as u32) * 60 + (minutes as u32) <= minutes_now;
+                                    let last_fired_at = if passed { Some(now) } else { None };
+                                    let (day, hours, minutes) = match timezone {
+                                        Some(timezone) => {
+                                            let (days, hours, minutes) = shift_weekly(&[*day], hours, minutes, zone_offset_minutes(timezone, now));
+                                            (days[0], hours, minutes)
+                                        }
+                                        None => (*day, hours, minutes),
+                                    };
+                                    let name = timezone.map(|timezone| timezone.name());
+                                    stmt.execute([&"recurrent" as &dyn ToSql, &user_id, &text, &none, &Some(day), &Some(hours), &Some(minutes), &0 as &dyn ToSql, &source, &generator.generate(), &last_fired_at, &lead_minutes, &delivery.priority, &delivery.nag_minutes, &name, &delivery.expires_minutes, &delivery.silent, &group_uid])?;
+                                    ids.push(tx.last_insert_rowid() as u64);
+                                }
+                            }
+                        }
+                        // leads of leads are never created
+                        StoredNotification::Lead { .. } => {}
+                    };
+                }
+
+            }
+            record_created(&tx, user_id, &text, &ids)?;
+            tx.commit().map(|_| ids)
+        }).await??;
+        Ok(ids)
+    }
+
+    // a cron event keeps only its next occurrence, evaluated on the wall clock of the user's timezone or the bot's one
+    pub async fn insert_cron_event(&self, user_id: u64, text: String, source: Source, schedule: CronSchedule) -> Result<u64, BotError> {
+        let uid = self.id_generator.generate();
+        let id = self.pool.get().await?.interact(move |connection| {
+            let tx = connection.transaction()?;
+            let timezone = tx.query_row("select timezone from allowed_user where user_id = ?1 and removed_at is null", [user_id], |row| row.get::<_, Option<String>>(0))
+                .optional()?
+                .flatten()
+                .and_then(|timezone| timezone.parse::<Tz>().ok())
+                .unwrap_or(DEFAULT_TIMEZONE);
+            let next = schedule.next_after(Utc::now(), timezone);
+            tx.execute("insert into event (kind, user_id, event_text, event_time, is_deleted, source, uid, timezone, cron, group_uid) values ('cron', ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?6)",
+                       [&user_id as &dyn ToSql, &text, &next, &next.is_none(), &source, &uid, &timezone.name(), &schedule.expression()])?;
+            let id = tx.last_insert_rowid() as u64;
+            record_created(&tx, user_id, &text, &[id])?;
+            tx.commit().map(|_| id)
+        }).await??;
+        Ok(id)
+    }
+
+    pub async fn delete_events(&self, event_ids: Vec<u64>) -> Result<(), BotError> {
+        self.close_events(event_ids, Transition::Deleted).await
+    }
+
+    // delivered absolute events leave the active set the same way deleted ones do, but are recorded differently,
+    // while recurrent ones stay active until their next occurrence and cron ones move on to it
+    pub async fn mark_fired(&self, event_ids: Vec<u64>, fired_at: DateTime<Utc>) -> Result<(), BotError> {
+        self.settle_fired(event_ids, fired_at, Transition::Fired).await
+    }
+
+    // like firing, a cron reminder moves on to its next occurrence, only nothing was sent
+    pub async fn expire(&self, event_ids: Vec<u64>, expired_at: DateTime<Utc>) -> Result<(), BotError> {
+        self.settle_fired(event_ids, expired_at, Transition::Expired).await
+    }
+
+    async fn settle_fired(&self, event_ids: Vec<u64>, fired_at: DateTime<Utc>, transition: Transition) -> Result<(), BotError> {
+        self.pool.get().await?.interact(move |connection| {
+            rusqlite::vtab::array::load_module(connection)?;
+            let array = || rusqlite::vtab::array::Array::new(
+                event_ids.iter()
+                    .map(|x| rusqlite::types::Value::Integer(*x as i64))
+                    .collect()
+            );
+            let tx = connection.transaction()?;
+            tx.execute("insert into event_history (event_id, user_id, transition, at) \
+                select id, user_id, ?1, ?2 from event where is_deleted = 0 and id in rarray(?3)",
+                [&transition as &dyn ToSql, &fired_at, &array()])?;
+            tx.execute("update event set is_deleted = 1 where kind = 'absolute' and id in rarray(?1)", [array()])?;
+            tx.execute("update event set last_fired_at = ?1, delivery_attempts = 0, next_attempt_at = null \
+                where kind = 'recurrent' and id in rarray(?2)", [&fired_at as &dyn ToSql, &array()])?;
+            let crons = tx.prepare("select id, cron, timezone, event_time from event where kind = 'cron' and is_deleted = 0 and id in rarray(?1)")?
+                .query_map([array()], |row| Ok((row.get::<_, u64>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, Option<String>>(2)?, row.get::<_, DateTime<Utc>>(3)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            for (id, cron, timezone, event_time) in crons {
+                // occurrences missed while the bot was down or the user paused are fired once, not one by one
+                let next = cron.and_then(|cron| cron.parse::<CronSchedule>().ok())
+                    .and_then(|schedule| schedule.next_after(fired_at.max(event_time), timezone.and_then(|timezone| timezone.parse().ok()).unwrap_or(DEFAULT_TIMEZONE)));
+                tx.execute("update event set event_time = coalesce(?1, event_time), is_deleted = ?2, last_fired_at = ?3, delivery_attempts = 0, next_attempt_at = null where id = ?4",
+                           [&next as &dyn ToSql, &next.is_none(), &fired_at, &id])?;
+            }
+            tx.commit()
+        }).await??;
+        Ok(())
+    }
+
+    async fn close_events(&self, event_ids: Vec<u64>, transition: Transition) -> Result<(), BotError> {
+        self.pool.get().await?.interact(move |connection| {
+            rusqlite::vtab::array::load_module(connection)?;
+            let array = || rusqlite::vtab::array::Array::new(
+                event_ids.iter()
+                    .map(|x| rusqlite::types::Value::Integer(*x as i64))
+                    .collect()
+            );
+            let tx = connection.transaction()?;
+            tx.execute("insert into event_history (event_id, user_id, transition, at) \
+                select id, user_id, ?1, ?2 from event where is_deleted = 0 and id in rarray(?3)",
+                [&transition as &dyn ToSql, &Utc::now(), &array()])?;
+            // a cancelled reminder stops nagging as well
+            tx.execute("update event set is_deleted = 1, ack_sent_at = null where id in rarray(?);", [array()])?;
+            tx.commit()
+        }).await??;
+        Ok(())
+    }
+
+    pub async fn get_event_history(&self, user_id: u64, event_uid: String) -> Result<Vec<HistoryEntry>, BotError> {
+        let history = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select event_history.event_id, transition, at from event_history \
+                    join event on event.id = event_history.event_id \
+                    where event.uid = ?1 and event_history.user_id = ?2 order by event_history.id")?;
+                let result = stmt.query_map([&event_uid as &dyn ToSql, &user_id], HistoryEntry::from_row)?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(history)
+    }
+
+    pub async fn get_user_history(&self, user_id: u64) -> Result<Vec<HistoryEntry>, BotError> {
+        let history = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select event_id, transition, at from event_history \
+                    where user_id = ?1 order by id")?;
+                let result = stmt.query_map([user_id], HistoryEntry::from_row)?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(history)
+    }
+
+    pub async fn get_events(&self, user_id: u64, source: Option<Source>, tag: Option<String>) -> Result<Vec<Event>, BotError> {
+        let events = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection
+                    .prepare(&format!("select {} from event \
+                    where user_id = ?1 and is_deleted = 0 and (?2 is null or source = ?2) \
+                    and (?3 is null or id in (select event_id from event_tag where tag = ?3)) \
+                    order by id", Event::COLUMNS))?;
+
+                let result = stmt.query_map([&user_id as &dyn ToSql, &source, &tag], Event::from_row)?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(events)
+    }
+
+    // looks up deleted events too, since fired events are soft-deleted right after delivery
+    pub async fn get_event(&self, user_id: u64, event_id: u64) -> Result<Option<Event>, BotError> {
+        let event = self.pool.get().await?
+            .interact(move |connection| {
+                connection.query_row(&format!("select {} from event where id = ?1 and user_id = ?2", Event::COLUMNS), [event_id, user_id], Event::from_row)
+                    .optional()
+            }).await??;
+        Ok(event)
+    }
+
+    // like find_event_id, but fired and cancelled events are found too, buttons of sent reminders outlive them
+    pub async fn resolve_event_id(&self, user_id: u64, event_uid: String) -> Result<Option<u64>, BotError> {
+        let id = self.pool.get().await?
+            .interact(move |connection| {
+                connection.query_row("select id from event where uid = ?1 and user_id = ?2",
+                                     [&event_uid as &dyn ToSql, &user_id], |row| row.get(0))
+                    .optional()
+            }).await??;
+        Ok(id)
+    }
+
+    pub async fn get_group_uid(&self, user_id: u64, event_id: u64) -> Result<Option<String>, BotError> {
+        let uid = self.pool.get().await?
+            .interact(move |connection| {
+                connection.query_row("select group_uid from event where id = ?1 and user_id = ?2", [event_id, user_id], |row| row.get(0))
+                    .optional()
+            }).await??;
+        Ok(uid.flatten())
+    }
+
+    // active rows of the group, none once it was cancelled or belongs to another user
+    pub async fn find_group(&self, user_id: u64, group_uid: String) -> Result<Vec<u64>, BotError> {
+        let ids = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select id from event where group_uid = ?1 and user_id = ?2 and is_deleted = 0 order by id")?;
+                let result = stmt.query_map([&group_uid as &dyn ToSql, &user_id], |row| row.get(0))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(ids)
+    }
+
+    // an accepted draft keeps its id as the id of the reminder it became
+    pub async fn set_group_uid(&self, event_ids: Vec<u64>, group_uid: String) -> Result<(), BotError> {
+        self.pool.get().await?.interact(move |connection| {
+            rusqlite::vtab::array::load_module(connection)?;
+            let array = rusqlite::vtab::array::Array::new(event_ids.into_iter().map(|id| rusqlite::types::Value::Integer(id as i64)).collect());
+            connection.execute("update event set group_uid = ?1 where id in rarray(?2)", [&group_uid as &dyn ToSql, &array])
+        }).await??;
+        Ok(())
+    }
+
+    // resolves the public id of an active event into its row id
+    pub async fn find_event_id(&self, user_id: u64, event_uid: String) -> Result<Option<u64>, BotError> {
+        let id = self.pool.get().await?
+            .interact(move |connection| {
+                connection.query_row("select id from event where uid = ?1 and user_id = ?2 and is_deleted = 0",
+                                     [&event_uid as &dyn ToSql, &user_id], |row| row.get(0))
+                    .optional()
+            }).await??;
+        Ok(id)
+    }
+
+    // active events of the user that land within the window of any of the given notifications;
+    // weekly times are compared within the same day, so a window doesn't reach over midnight
+    // active reminders whose text is close to the query, best matches first; rows that share a text
+    // are one reminder, each comes once with the id of its first row
+    pub async fn search_events(&self, user_id: u64, query: String, limit: usize) -> Result<Vec<(u64, Event)>, BotError> {
+        let events = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare(&format!("select {}, id from event \
+                    where user_id = ?1 and is_deleted = 0 and lead_minutes = 0 order by id", Event::COLUMNS))?;
+                let result = stmt.query_map([user_id], |row| Ok((row.get::<_, u64>(18)?, Event::from_row(row)?)))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        let mut seen = FnvHashSet::default();
+        let mut matches = events.into_iter()
+            .filter(|(_, event)| seen.insert(event.text.to_lowercase()))
+            .map(|(id, event)| (text_similarity(&query, &event.text), id, event))
+            .filter(|(score, _, _)| *score >= MIN_SIMILARITY)
+            .collect::<Vec<_>>();
+        matches.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(matches.into_iter().take(limit).map(|(_, id, event)| (id, event)).collect())
+    }
+
+    // reminders containing every word of the query as a word prefix and carrying every #tag of it,
+    // pending ones before past ones, best matches first
+    pub async fn search_text(&self, user_id: u64, query: &str, limit: usize) -> Result<Vec<(u64, Event)>, BotError> {
+        let tags = extract_tags(query);
+        let words = query.split_whitespace().filter(|word| !word.starts_with('#')).collect::<Vec<_>>().join(" ");
+        let words = fts_query(&words);
+        if words.is_none() && tags.is_empty() {
+            return Ok(vec![]);
+        }
+        let events = self.pool.get().await?
+            .interact(move |connection| {
+                rusqlite::vtab::array::load_module(connection)?;
+                let tag_count = tags.len() as i64;
+                let tags = rusqlite::vtab::array::Array::new(tags.into_iter().map(rusqlite::types::Value::Text).collect());
+                let tagged = "(select count(*) from event_tag where event_tag.event_id = event.id and event_tag.tag in rarray(?4)) = ?5";
+                let sql = match &words {
+                    Some(_) => format!("select {}, event.id from event_search \
+                        join event on event.id = event_search.rowid \
+                        where event_search match ?1 and event.user_id = ?2 and {} \
+                        order by event.is_deleted, event_search.rank, event.id desc limit ?3", Event::COLUMNS, tagged),
+                    None => format!("select {}, event.id from event \
+                        where ?1 is null and event.user_id = ?2 and event.lead_minutes = 0 and {} \
+                        order by event.is_deleted, event.id desc limit ?3", Event::COLUMNS, tagged),
+                };
+                let mut stmt = connection.prepare(&sql)?;
+                let result = stmt.query_map([&words as &dyn ToSql, &user_id, &(limit as i64), &tags, &tag_count], |row| Ok((row.get::<_, u64>(18)?, Event::from_row(row)?)))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(events)
+    }
+
+    pub async fn get_user_tags(&self, user_id: u64) -> Result<Vec<EventTag>, BotError> {
+        let tags = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select event_id, tag from event_tag where user_id = ?1 order by event_id, tag")?;
+                let result = stmt.query_map([user_id], |row| Ok(EventTag { event_id: row.get(0)?, tag: row.get(1)? }))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(tags)
+    }
+
+    pub async fn set_quote(&self, event_ids: Vec<u64>, quote: String) -> Result<(), BotError> {
+        self.pool.get().await?.interact(move |connection| {
+            rusqlite::vtab::array::load_module(connection)?;
+            let array = rusqlite::vtab::array::Array::new(event_ids.into_iter().map(|id| rusqlite::types::Value::Integer(id as i64)).collect());
+            connection.execute("update event set quote = ?1 where id in rarray(?2)", [&quote as &dyn ToSql, &array])
+        }).await??;
+        Ok(())
+    }
+
+    pub async fn set_source_message(&self, event_ids: Vec<u64>, message_id: u64) -> Result<(), BotError> {
+        self.pool.get().await?.interact(move |connection| {
+            rusqlite::vtab::array::load_module(connection)?;
+            let array = rusqlite::vtab::array::Array::new(event_ids.into_iter().map(|id| rusqlite::types::Value::Integer(id as i64)).collect());
+            connection.execute("update event set source_message_id = ?1 where id in rarray(?2)", [&message_id as &dyn ToSql, &array])
+        }).await??;
+        Ok(())
+    }
+
+    // only weekly events have occurrences to skip
+    pub async fn set_holiday_country(&self, event_ids: Vec<u64>, country: String) -> Result<(), BotError> {
+        self.pool.get().await?.interact(move |connection| {
+            rusqlite::vtab::array::load_module(connection)?;
+            let array = rusqlite::vtab::array::Array::new(event_ids.into_iter().map(|id| rusqlite::types::Value::Integer(id as i64)).collect());
+            connection.execute("update event set holiday_country = ?1 where kind = 'recurrent' and id in rarray(?2)", [&country as &dyn ToSql, &array])
+        }).await??;
+        Ok(())
+    }
+
+    // a day listed again gets the new name
+    pub async fn load_holidays(&self, holidays: Vec<Holiday>) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                {
+                    let mut stmt = tx.prepare_cached("insert or replace into holiday (country, day, name) values (?1, ?2, ?3)")?;
+                    for holiday in holidays {
+                        stmt.execute([&holiday.country as &dyn ToSql, &holiday.day, &holiday.name])?;
+                    }
+                }
+                tx.commit()
+            }).await??;
+        Ok(())
+    }
+
+    pub async fn get_holidays(&self, from: NaiveDate) -> Result<Vec<Holiday>, BotError> {
+        let holidays = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select country, day, name from holiday where day >= ?1 order by day")?;
+                let result = stmt.query_map([from], |row| Ok(Holiday { country: row.get(0)?, day: row.get(1)?, name: row.get(2)? }))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(holidays)
+    }
+
+    pub async fn attach_location(&self, user_id: u64, event_ids: Vec<u64>, location: Location) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                {
+                    let mut stmt = tx.prepare_cached("insert or replace into event_location (event_id, user_id, latitude, longitude) values (?1, ?2, ?3, ?4)")?;
+                    for event_id in event_ids {
+                        stmt.execute([&event_id as &dyn ToSql, &user_id, &location.latitude, &location.longitude])?;
+                    }
+                }
+                tx.commit()
+            }).await??;
+        Ok(())
+    }
+
+    pub async fn get_event_location(&self, event_id: u64) -> Result<Option<Location>, BotError> {
+        let location = self.pool.get().await?
+            .interact(move |connection| {
+                connection.query_row("select latitude, longitude from event_location where event_id = ?1", [event_id],
+                                     |row| Ok(Location { latitude: row.get(0)?, longitude: row.get(1)? }))
+                    .optional()
+            }).await??;
+        Ok(location)
+    }
+
+    pub async fn get_user_locations(&self, user_id: u64) -> Result<Vec<EventLocation>, BotError> {
+        let locations = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select event_id, latitude, longitude from event_location where user_id = ?1 order by event_id")?;
+                let result = stmt.query_map([user_id], |row| Ok(EventLocation {
+                    event_id: row.get(0)?,
+                    location: Location { latitude: row.get(1)?, longitude: row.get(2)? },
+                }))?.collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(locations)
+    }
+
+    pub async fn get_exclusions(&self, user_id: u64) -> Result<Vec<EventExclusion>, BotError> {
+        let exclusions = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select x.event_id, e.uid, x.occurs_on from event_exclusion x \
+                    join event e on e.id = x.event_id where x.user_id = ?1 order by x.occurs_on, x.event_id")?;
+                let result = stmt.query_map([user_id], |row| Ok(EventExclusion { event_id: row.get(0)?, event_uid: row.get(1)?, occurs_on: row.get(2)? }))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(exclusions)
+    }
+
+    // excludes the next occurrence of the recurrent reminder the row belongs to, over all of its weekdays,
+    // heads-ups of that occurrence included; returns the skipped time, none when the row isn't an active recurrent one
+    pub async fn skip_next_occurrence(&self, user_id: u64, event_id: u64, now: DateTime<Utc>) -> Result<Option<DateTime<Utc>>, BotError> {
+        let skipped = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                let text: Option<String> = tx.query_row("select event_text from event where id = ?1 and user_id = ?2 and kind = 'recurrent' and is_deleted = 0",
+                                                        [event_id, user_id], |row| row.get(0)).optional()?;
+                let text = match text {
+                    Some(text) => text,
+                    None => return Ok(None),
+                };
+                let rows = {
+                    let mut stmt = tx.prepare("select id, day, hour, minute, lead_minutes, timezone from event \
+                        where user_id = ?1 and event_text = ?2 and kind = 'recurrent' and is_deleted = 0")?;
+                    let result = stmt.query_map([&user_id as &dyn ToSql, &text], |row| {
+                        let (day, hour, minute) = slot_to_utc(row.get(1)?, row.get(2)?, row.get(3)?, row.get::<_, Option<String>>(5)?.as_deref(), now);
+                        Ok((row.get::<_, u64>(0)?, day, hour, minute, row.get::<_, u32>(4)?))
+                    })?
+                        .collect::<Result<Vec<_>, _>>();
+                    result?
+                };
+                // occurrences skipped before are passed over, so every press skips one more
+                let mut excluded = tx.prepare("select 1 from event_exclusion where event_id = ?1 and occurs_on = ?2")?;
+                let mut next = |id: u64, day: u8, hour: u8, minute: u8| -> rusqlite::Result<DateTime<Utc>> {
+                    let mut time = next_weekly_occurrence(now, day, hour, minute);
+                    while excluded.exists([&id as &dyn ToSql, &time.date_naive()])? {
+                        time += chrono::Duration::weeks(1);
+                    }
+                    Ok(time)
+                };
+                let mut occurrences = vec![];
+                for (id, day, hour, minute, lead_minutes) in rows {
+                    occurrences.push((id, next(id, day, hour, minute)?, lead_minutes));
+                }
+                drop(excluded);
+                let skipped = match occurrences.iter().filter(|(_, _, lead)| *lead == 0).map(|(_, time, _)| *time).min() {
+                    Some(skipped) => skipped,
+                    None => return Ok(None),
+                };
+
+                for (id, time, lead_minutes) in occurrences {
+                    if time + chrono::Duration::minutes(lead_minutes as i64) != skipped {
+                        continue;
+                    }
+                    tx.execute("insert or ignore into event_exclusion (event_id, user_id, occurs_on) values (?1, ?2, ?3)",
+                               [&id as &dyn ToSql, &user_id, &time.date_naive()])?;
+                    if lead_minutes == 0 {
+                        tx.execute("insert into event_history (event_id, user_id, transition, at) values (?1, ?2, ?3, ?4)",
+                                   [&id as &dyn ToSql, &user_id, &Transition::Skipped, &now])?;
+                    }
+                }
+                tx.commit().map(|_| Some(skipped))
+            }).await??;
+        Ok(skipped)
+    }
+
+    // soft-deletes every active row of the user with the same text, heads-ups included
+    pub async fn delete_by_text(&self, user_id: u64, text: String) -> Result<Vec<u64>, BotError> {
+        let ids = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select id from event where user_id = ?1 and is_deleted = 0 and event_text = ?2")?;
+                let result = stmt.query_map([&user_id as &dyn ToSql, &text], |row| row.get::<_, u64>(0))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        self.close_events(ids.clone(), Transition::Deleted).await?;
+        Ok(ids)
+    }
+
+    // groups the history rows the action wrote for the events, only the latest UNDO_DEPTH actions of the user are kept
+    pub async fn record_action(&self, user_id: u64, label: String, event_ids: Vec<u64>, transition: Transition) -> Result<(), BotError> {
+        self.pool.get().await?.interact(move |connection| {
+            rusqlite::vtab::array::load_module(connection)?;
+            let array = rusqlite::vtab::array::Array::new(event_ids.into_iter().map(|id| rusqlite::types::Value::Integer(id as i64)).collect());
+            let tx = connection.transaction()?;
+            tx.execute("insert into undo_action (user_id, label, at) values (?1, ?2, ?3)", [&user_id as &dyn ToSql, &label, &Utc::now()])?;
+            let action_id = tx.last_insert_rowid();
+            tx.execute("update event_history set action_id = ?1 where user_id = ?2 and transition = ?3 and action_id is null and event_id in rarray(?4)",
+                       [&action_id as &dyn ToSql, &user_id, &transition, &array])?;
+            tx.execute("delete from undo_action where user_id = ?1 and id not in (select id from undo_action where user_id = ?1 order by id desc limit ?2)",
+                       [user_id, UNDO_DEPTH as u64])?;
+            tx.commit()
+        }).await??;
+        Ok(())
+    }
+
+    // compensates the latest action of the user: events it created are closed, events it deleted are reopened;
+    // returns the label of the action and the number of events changed back
+    pub async fn undo_last_action(&self, user_id: u64) -> Result<Option<(String, usize)>, BotError> {
+        let undone = self.pool.get().await?.interact(move |connection| {
+            let tx = connection.transaction()?;
+            let action: Option<(i64, String)> = tx.query_row("select id, label from undo_action where user_id = ?1 order by id desc limit 1",
+                                                             [user_id], |row| Ok((row.get(0)?, row.get(1)?))).optional()?;
+            let (action_id, label) = match action {
+                Some(action) => action,
+                None => return Ok(None),
+            };
+            let now = Utc::now();
+            let mut changed = 0;
+            for (transition, compensation, is_deleted) in [(Transition::Created, Transition::Undone, 0), (Transition::Deleted, Transition::Restored, 1)] {
+                let affected = "select event_id from event_history where action_id = ?1 and transition = ?2";
+                tx.execute(&format!("insert into event_history (event_id, user_id, transition, at) \
+                    select id, user_id, ?3, ?4 from event where is_deleted = ?5 and id in ({})", affected),
+                           [&action_id as &dyn ToSql, &transition, &compensation, &now, &is_deleted])?;
+                changed += tx.execute(&format!("update event set is_deleted = 1 - is_deleted, ack_sent_at = null where is_deleted = ?3 and id in ({})", affected),
+                                      [&action_id as &dyn ToSql, &transition, &is_deleted])?;
+            }
+            tx.execute("delete from undo_action where id = ?1", [action_id])?;
+            tx.commit().map(|_| Some((label, changed)))
+        }).await??;
+        Ok(undone)
+    }
+
+    pub async fn get_undo_actions(&self, user_id: u64) -> Result<Vec<UndoAction>, BotError> {
+        let actions = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select label, at from undo_action where user_id = ?1 order by id")?;
+                let result = stmt.query_map([user_id], |row| Ok(UndoAction { label: row.get(0)?, at: row.get(1)? }))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(actions)
+    }
+
+    pub async fn find_conflicts(&self, user_id: u64, notifications: Vec<StoredNotification>, window: chrono::Duration) -> Result<Vec<Event>, BotError> {
+        let events = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare_cached(&format!("select {} from event \
+                    where user_id = ?1 and is_deleted = 0 and lead_minutes = 0 and kind in ('absolute', 'cron') and event_time between ?2 and ?3 \
+                    order by id", Event::COLUMNS))?;
+                // weekly rows are compared in utc after reading, some of them are kept in a timezone
+                let weekly = connection.prepare_cached(&format!("select {} from event \
+                    where user_id = ?1 and is_deleted = 0 and lead_minutes = 0 and kind = 'recurrent' order by id", Event::COLUMNS))?
+                    .query_map([user_id], Event::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                let window_minutes = window.num_minutes();
+                let mut seen = FnvHashSet::default();
+                let mut events = vec![];
+                for notification in notifications {
+                    // every occurrence as (time range for one-off events, weekday, minute of the day)
+                    let occurrences = match notification {
+                        StoredNotification::Absolute { time } => vec![(Some(time - window), Some(time + window),
+                                                                       time.weekday().num_days_from_monday() as u8 + 1, time.hour() * 60 + time.minute())],
+                        StoredNotification::Recurrent { hours, minutes, days } => days.unwrap_or_default().iter()
+                            .map(|day| (None, None, *day, hours as u32 * 60 + minutes as u32))
+                            .collect::<Vec<_>>(),
+                        // a heads-up isn't a reminder of its own, only main times can clash
+                        StoredNotification::Lead { .. } => vec![],
+                    };
+                    for (from, to, day, minute) in occurrences {
+                        if from.is_some() {
+                            let found = stmt.query_map([&user_id as &dyn ToSql, &from, &to], Event::from_row)?
+                                .collect::<Result<Vec<_>, _>>()?;
+                            events.extend(found.into_iter().filter(|event| seen.insert(event.uid.clone())));
+                        }
+                        let close = weekly.iter().filter(|event| match (event.day, event.hour, event.minute) {
+                            (Some(event_day), Some(hour), Some(event_minute)) =>
+                                event_day == day && (hour as i64 * 60 + event_minute as i64 - minute as i64).abs() <= window_minutes,
+                            _ => false,
+                        });
+                        events.extend(close.filter(|event| seen.insert(event.uid.clone())).cloned());
+                    }
+                }
+                Ok::<_, rusqlite::Error>(events)
+            }).await??;
+        Ok(events)
+    }
+
+    // active reminders between from and to grouped by the day they fire on, offset_minutes moves utc times
+    // into the bot timezone; weekly ones are grouped by weekday and counted for every matching date
+    pub async fn get_load(&self, user_id: u64, from: DateTime<Utc>, to: DateTime<Utc>, offset_minutes: i32) -> Result<Load, BotError> {
+        let load = self.pool.get().await?
+            .interact(move |connection| {
+                let modifier = format!("{:+} minutes", offset_minutes);
+                let mut stmt = connection.prepare("select date(event_time, ?4) as local_date, count(*) from event \
+                    where user_id = ?1 and is_deleted = 0 and lead_minutes = 0 and kind in ('absolute', 'cron') and event_time >= ?2 and event_time < ?3 \
+                    group by local_date order by local_date")?;
+                let dates = stmt.query_map([&user_id as &dyn ToSql, &from, &to, &modifier], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut stmt = connection.prepare("select day, hour, minute, timezone from event \
+                    where user_id = ?1 and is_deleted = 0 and lead_minutes = 0 and kind = 'recurrent'")?;
+                let slots = stmt.query_map([user_id], |row| Ok(slot_to_utc(row.get(0)?, row.get(1)?, row.get(2)?, row.get::<_, Option<String>>(3)?.as_deref(), from)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut weekdays = std::collections::BTreeMap::new();
+                for (day, hour, minute) in slots {
+                    let (days, _, _) = shift_weekly(&[day], hour, minute, offset_minutes);
+                    *weekdays.entry(days[0]).or_insert(0) += 1;
+                }
+                let weekdays = weekdays.into_iter().collect();
+                Ok::<_, rusqlite::Error>(Load { dates, weekdays })
+            }).await??;
+        Ok(load)
+    }
+
+    pub async fn get_stats(&self) -> Result<Stats, BotError> {
+        let stats = self.pool.get().await?
+            .interact(|connection| {
+                connection.query_row("select coalesce(sum(is_deleted = 0), 0), coalesce(sum(is_dead_lettered = 1), 0), \
+                    count(distinct case when is_deleted = 0 then user_id end) from event", [], |row| {
+                    Ok(Stats {
+                        pending_events: row.get(0)?,
+                        dead_letters: row.get(1)?,
+                        active_users: row.get(2)?,
+                    })
+                })
+            }).await??;
+        Ok(stats)
+    }
+
+    // every event ever stored for the user, including fired and deleted ones
+    pub async fn get_all_user_events(&self, user_id: u64) -> Result<Vec<Event>, BotError> {
+        let events = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection
+                    .prepare(&format!("select {} from event where user_id = ?1 order by id", Event::COLUMNS))?;
+
+                let result = stmt.query_map([user_id], Event::from_row)?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(events)
+    }
+
+    // a template with the same name is replaced
+    pub async fn save_template(&self, user_id: u64, name: String, body: String) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert into template (user_id, name, body) values (?1, ?2, ?3) \
+                    on conflict (user_id, name) do update set body = excluded.body", [&user_id as &dyn ToSql, &name, &body])
+            }).await??;
+        Ok(())
+    }
+
+    pub async fn get_templates(&self, user_id: u64) -> Result<Vec<Template>, BotError> {
+        let templates = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select id, name, body from template where user_id = ?1 order by name")?;
+                let result = stmt.query_map([user_id], |row| Ok(Template { id: row.get(0)?, name: row.get(1)?, body: row.get(2)? }))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(templates)
+    }
+
+    // returns whether there was such a template
+    pub async fn delete_template(&self, user_id: u64, name: String) -> Result<bool, BotError> {
+        let deleted = self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("delete from template where user_id = ?1 and name = ?2", [&user_id as &dyn ToSql, &name])
+            }).await??;
+        Ok(deleted > 0)
+    }
+
+    // a new correction of the same text replaces the older one
+    pub async fn record_correction(&self, user_id: u64, input: String, accepted: String, parsed_at: DateTime<Utc>) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                tx.execute("delete from parse_correction where user_id = ?1 and input = ?2", [&user_id as &dyn ToSql, &input])?;
+                tx.execute("insert into parse_correction (user_id, input, accepted, parsed_at) values (?1, ?2, ?3, ?4)",
+                           [&user_id as &dyn ToSql, &input, &accepted, &parsed_at])?;
+                tx.execute("delete from parse_correction where user_id = ?1 and id not in \
+                    (select id from parse_correction where user_id = ?1 order by id desc limit ?2)", [user_id, MAX_CORRECTIONS as u64])?;
+                tx.commit()
+            }).await??;
+        Ok(())
+    }
+
+    // newest first
+    pub async fn get_corrections(&self, user_id: u64) -> Result<Vec<ParseCorrection>, BotError> {
+        let corrections = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select input, accepted, parsed_at from parse_correction where user_id = ?1 order by id desc")?;
+                let result = stmt.query_map([user_id], |row| Ok(ParseCorrection { input: row.get(0)?, accepted: row.get(1)?, parsed_at: row.get(2)? }))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(corrections)
+    }
+
+    pub async fn purge_user(&self, user_id: u64) -> Result<usize, BotError> {
+        let deleted = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                tx.execute("delete from event_search where rowid in (select id from event where user_id = ?1)", [user_id])?;
+                tx.execute("delete from event_tag where user_id = ?1", [user_id])?;
+                tx.execute("delete from event_exclusion where user_id = ?1", [user_id])?;
+                tx.execute("delete from deferred_delivery where user_id = ?1", [user_id])?;
+                tx.execute("delete from undo_action where user_id = ?1", [user_id])?;
+                tx.execute("delete from template where user_id = ?1", [user_id])?;
+                tx.execute("delete from parse_correction where user_id = ?1", [user_id])?;
+                tx.execute("delete from event_location where user_id = ?1", [user_id])?;
+                let deleted = tx.execute("delete from event where user_id = ?1", [user_id])?;
+                tx.execute("delete from usage where user_id = ?1", [user_id])?;
+                tx.execute("delete from webhook where user_id = ?1", [user_id])?;
+                tx.execute("delete from event_webhook where user_id = ?1", [user_id])?;
+                tx.execute("delete from webhook_call where user_id = ?1", [user_id])?;
+                tx.execute("delete from event_history where user_id = ?1", [user_id])?;
+                tx.execute("delete from user_settings where user_id = ?1", [user_id])?;
+                tx.execute("delete from calendar_connection where user_id = ?1", [user_id])?;
+                tx.execute("delete from calendar_event where user_id = ?1", [user_id])?;
+                tx.execute("delete from access_request where user_id = ?1", [user_id])?;
+                tx.execute("delete from user_role where user_id = ?1", [user_id])?;
+                tx.execute("delete from allowed_user where user_id = ?1", [user_id])?;
+                tx.execute("delete from business_chat where chat_id = ?1 \
+                    or connection_id in (select id from business_connection where user_id = ?1)", [user_id])?;
+                tx.execute("delete from business_connection where user_id = ?1", [user_id])?;
+                tx.commit().map(|_| deleted)
+            }).await??;
+        Ok(deleted)
+    }
+
+    // drops everything users stored, the schema and jobs stay; used to reset the demo
+    pub async fn wipe(&self) -> Result<usize, BotError> {
+        let deleted = self.pool.get().await?
+            .interact(|connection| {
+                let tx = connection.transaction()?;
+                tx.execute("delete from event_search", [])?;
+                tx.execute("delete from event_tag", [])?;
+                tx.execute("delete from event_exclusion", [])?;
+                tx.execute("delete from deferred_delivery", [])?;
+                tx.execute("delete from undo_action", [])?;
+                tx.execute("delete from template", [])?;
+                tx.execute("delete from parse_correction", [])?;
+                tx.execute("delete from event_location", [])?;
+                let deleted = tx.execute("delete from event", [])?;
+                tx.execute_batch("delete from usage;
+                    delete from webhook;
+                    delete from event_webhook;
+                    delete from webhook_call;
+                    delete from event_history;
+                    delete from user_settings;
+                    delete from calendar_connection;
+                    delete from calendar_event;
+                    delete from access_request;
+                    delete from user_role;
+                    delete from allowed_user;
+                    delete from business_chat;
+                    delete from business_connection;")?;
+                tx.commit().map(|_| deleted)
+            }).await??;
+        Ok(deleted)
+    }
+
+    pub async fn get_user_settings(&self, user_id: u64) -> Result<UserSettings, BotError> {
+        let settings = self.pool.get().await?
+            .interact(move |connection| {
+                connection.query_row("select plain_mode, paused_until, quiet_start, quiet_end, language from user_settings where user_id = ?1", [user_id],
+                                     |row| Ok(UserSettings {
+                                         plain_mode: row.get(0)?,
+                                         paused_until: row.get(1)?,
+                                         quiet_hours: match (row.get(2)?, row.get(3)?) {
+                                             (Some(start), Some(end)) => Some(QuietHours { start, end }),
+                                             _ => None,
+                                         },
+                                         language: row.get::<_, Option<String>>(4)?.and_then(|code| code.parse().ok()),
+                                     }))
+                    .optional()
+            }).await??;
+        Ok(settings.unwrap_or_default())
+    }
+
+    pub async fn set_plain_mode(&self, user_id: u64, plain_mode: bool) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert into user_settings (user_id, plain_mode) values (?1, ?2) \
+                    on conflict (user_id) do update set plain_mode = excluded.plain_mode", [user_id, plain_mode as u64])
+            }).await??;
+        Ok(())
+    }
+
+    // none goes back to the language of the telegram client
+    pub async fn set_language(&self, user_id: u64, language: Option<Locale>) -> Result<(), BotError> {
+        let code = language.map(|language| language.code());
+        self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert into user_settings (user_id, language) values (?1, ?2) \
+                    on conflict (user_id) do update set language = excluded.language", [&user_id as &dyn ToSql, &code])
+            }).await??;
+        Ok(())
+    }
+
+    // none resumes the notifications right away
+    pub async fn set_paused_until(&self, user_id: u64, paused_until: Option<DateTime<Utc>>) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert into user_settings (user_id, paused_until) values (?1, ?2) \
+                    on conflict (user_id) do update set paused_until = excluded.paused_until", [&user_id as &dyn ToSql, &paused_until])
+            }).await??;
+        Ok(())
+    }
+
+    pub async fn set_quiet_hours(&self, user_id: u64, quiet_hours: Option<QuietHours>) -> Result<(), BotError> {
+        let (quiet_start, quiet_end) = quiet_hours.map(|quiet| (quiet.start, quiet.end)).unzip();
+        self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert into user_settings (user_id, quiet_start, quiet_end) values (?1, ?2, ?3) \
+                    on conflict (user_id) do update set quiet_start = excluded.quiet_start, quiet_end = excluded.quiet_end",
+                                   [&user_id as &dyn ToSql, &quiet_start, &quiet_end])
+            }).await??;
+        Ok(())
+    }
+
+    // holds a fired event back until release_at, the event itself is marked fired as usual
+    pub async fn defer_delivery(&self, event_id: u64, user_id: u64, release_at: DateTime<Utc>) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert or replace into deferred_delivery (event_id, user_id, release_at) values (?1, ?2, ?3)",
+                                   [&event_id as &dyn ToSql, &user_id, &release_at])
+            }).await??;
+        Ok(())
+    }
+
+    // held back events that are due, grouped by user in the order they fired
+    pub async fn get_due_deferred(&self, now: DateTime<Utc>) -> Result<Vec<EventToFire>, BotError> {
+        let events = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select event.id, event.user_id, event.event_text, event.lead_minutes, event.priority, \
+                    event.nag_minutes, event.kind = 'recurrent', event.quote, event.expires_minutes, event.silent, event.source_message_id, event.uid from deferred_delivery \
+                    join event on event.id = deferred_delivery.event_id where deferred_delivery.release_at <= ?1 \
+                    and not exists (select 1 from user_settings where user_settings.user_id = event.user_id and paused_until > ?1) \
+                    order by event.user_id, deferred_delivery.rowid")?;
+                let result = stmt.query_map([now], |row| Ok(EventToFire {
+                    event_id: row.get(0)?,
+                    event_uid: row.get(11)?,
+                    user_id: row.get(1)?,
+                    text: row.get(2)?,
+                    lead_minutes: row.get(3)?,
+                    delivery: Delivery { priority: row.get(4)?, nag_minutes: row.get(5)?, expires_minutes: row.get(8)?, silent: row.get(9)? },
+                    is_recurrent: row.get(6)?,
+                    quote: row.get(7)?,
+                    source_message_id: row.get(10)?,
+                    due_at: None,
+                    overdue_minutes: 0,
+                }))?.collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(events)
+    }
+
+    pub async fn get_deferred(&self, user_id: u64) -> Result<Vec<DeferredDelivery>, BotError> {
+        let deferred = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select event_id, release_at from deferred_delivery where user_id = ?1 order by release_at, event_id")?;
+                let result = stmt.query_map([user_id], |row| Ok(DeferredDelivery { event_id: row.get(0)?, release_at: row.get(1)? }))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(deferred)
+    }
+
+    pub async fn remove_deferred(&self, event_ids: Vec<u64>) -> Result<(), BotError> {
+        self.pool.get().await?.interact(move |connection| {
+            rusqlite::vtab::array::load_module(connection)?;
+            let array = rusqlite::vtab::array::Array::new(event_ids.into_iter().map(|id| rusqlite::types::Value::Integer(id as i64)).collect());
+            connection.execute("delete from deferred_delivery where event_id in rarray(?1)", [array])
+        }).await??;
+        Ok(())
+    }
+
+    // active absolute events that are already due, while paused they pile up here
+    pub async fn get_missed(&self, user_id: u64, now: DateTime<Utc>) -> Result<Vec<u64>, BotError> {
+        let missed = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select id from event where user_id = ?1 and kind in ('absolute', 'cron') and is_deleted = 0 and event_time < ?2")?;
+                let result = stmt.query_map([&user_id as &dyn ToSql, &now], |row| row.get(0))?.collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(missed)
+    }
+
+    // webhooks attached to active reminders, by reminder text
+    pub async fn get_webhook_routes(&self, user_id: u64) -> Result<Vec<WebhookRoute>, BotError> {
+        let routes = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select distinct event.event_text, event_webhook.name from event_webhook \
+                    join event on event.id = event_webhook.event_id \
+                    where event_webhook.user_id = ?1 and event.is_deleted = 0 and event.lead_minutes = 0 order by 1, 2")?;
+                let result = stmt.query_map([user_id], |row| Ok(WebhookRoute { event_text: row.get(0)?, webhook: row.get(1)? }))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(routes)
+    }
+
+    // settings and webhooks are replaced by the imported ones, routes are added to the active reminders with the same text;
+    // returns the number of reminders a webhook was attached to
+    pub async fn import_settings(&self, user_id: u64, settings: UserSettings, webhooks: Vec<Webhook>, routes: Vec<WebhookRoute>) -> Result<usize, BotError> {
+        let attached = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                let (quiet_start, quiet_end) = settings.quiet_hours.map(|quiet| (quiet.start, quiet.end)).unzip();
+                let language = settings.language.map(|language| language.code());
+                tx.execute("insert into user_settings (user_id, plain_mode, paused_until, quiet_start, quiet_end, language) values (?1, ?2, ?3, ?4, ?5, ?6) \
+                    on conflict (user_id) do update set plain_mode = excluded.plain_mode, paused_until = excluded.paused_until, \
+                    quiet_start = excluded.quiet_start, quiet_end = excluded.quiet_end, language = excluded.language",
+                           [&user_id as &dyn ToSql, &settings.plain_mode, &settings.paused_until, &quiet_start, &quiet_end, &language])?;
+                for webhook in webhooks.iter() {
+                    tx.execute("insert into webhook (user_id, name, url) values (?1, ?2, ?3) \
+                        on conflict (user_id, name) do update set url = excluded.url", [&user_id as &dyn ToSql, &webhook.name, &webhook.url])?;
+                }
+                let mut attached = 0;
+                for route in routes.iter() {
+                    attached += tx.execute("insert or ignore into event_webhook (event_id, user_id, name) \
+                        select id, user_id, ?3 from event where user_id = ?1 and event_text = ?2 and is_deleted = 0 and lead_minutes = 0",
+                                           [&user_id as &dyn ToSql, &route.event_text, &route.webhook])?;
+                }
+                tx.commit().map(|_| attached)
+            }).await??;
+        Ok(attached)
+    }
+
+    // returns false when the user has already asked, so the admin is notified only once
+    pub async fn request_access(&self, user_id: u64, username: Option<String>) -> Result<bool, BotError> {
+        let inserted = self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert or ignore into access_request (user_id, username, status, requested_at) values (?1, ?2, ?3, ?4)",
+                                   [&user_id as &dyn ToSql, &username, &AccessStatus::Pending, &Utc::now()])
+            }).await??;
+        Ok(inserted > 0)
+    }
+
+    // only pending requests can be decided, a second tap on Approve or Reject changes nothing;
+    // an approved user joins the allowed users under their username
+    pub async fn decide_access(&self, user_id: u64, status: AccessStatus) -> Result<bool, BotError> {
+        let updated = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                let now = Utc::now();
+                let updated = tx.execute("update access_request set status = ?2, decided_at = ?3 where user_id = ?1 and status = ?4",
+                                         [&user_id as &dyn ToSql, &status, &now, &AccessStatus::Pending])?;
+                if updated > 0 && status == AccessStatus::Approved {
+                    tx.execute("insert into allowed_user (user_id, display_name, created_at) \
+                        select user_id, username, ?2 from access_request where user_id = ?1 \
+                        on conflict (user_id) do update set removed_at = null", [&user_id as &dyn ToSql, &now])?;
+                }
+                tx.commit().map(|_| updated)
+            }).await??;
+        Ok(updated > 0)
+    }
+
+    pub async fn get_access_request(&self, user_id: u64) -> Result<Option<AccessRequest>, BotError> {
+        let request = self.pool.get().await?
+            .interact(move |connection| {
+                connection.query_row("select username, status, requested_at, decided_at from access_request where user_id = ?1", [user_id], |row| {
+                    Ok(AccessRequest {
+                        username: row.get(0)?,
+                        status: row.get(1)?,
+                        requested_at: row.get(2)?,
+                        decided_at: row.get(3)?,
+                    })
+                }).optional()
+            }).await??;
+        Ok(request)
+    }
+
+    // adds users that were never in the list, ones removed with /removeuser stay removed
+    pub async fn seed_users(&self, user_ids: Vec<u64>) -> Result<usize, BotError> {
+        let seeded = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                let now = Utc::now();
+                let mut seeded = 0;
+                for user_id in user_ids {
+                    seeded += tx.execute("insert or ignore into allowed_user (user_id, created_at) values (?1, ?2)", [&user_id as &dyn ToSql, &now])?;
+                }
+                tx.commit().map(|_| seeded)
+            }).await??;
+        Ok(seeded)
+    }
+
+    // returns true when the user had no access before; metadata that isn't given is kept
+    pub async fn upsert_user(&self, user_id: u64, display_name: Option<String>, timezone: Option<String>) -> Result<bool, BotError> {
+        let created = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                let existed = tx.prepare("select 1 from allowed_user where user_id = ?1 and removed_at is null")?.exists([user_id])?;
+                tx.execute("insert into allowed_user (user_id, display_name, timezone, created_at) values (?1, ?2, ?3, ?4) \
+                    on conflict (user_id) do update set display_name = coalesce(excluded.display_name, display_name), \
+                    timezone = coalesce(excluded.timezone, timezone), removed_at = null",
+                           [&user_id as &dyn ToSql, &display_name, &timezone, &Utc::now()])?;
+                tx.commit().map(|_| !existed)
+            }).await??;
+        Ok(created)
+    }
+
+    // a removed user loses their role and join request too, so they can ask for access again; reminders stay
+    pub async fn remove_user(&self, user_id: u64) -> Result<bool, BotError> {
+        let removed = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                let removed = tx.execute("update allowed_user set removed_at = ?2 where user_id = ?1 and removed_at is null",
+                                         [&user_id as &dyn ToSql, &Utc::now()])?;
+                tx.execute("delete from user_role where user_id = ?1", [user_id])?;
+                tx.execute("delete from access_request where user_id = ?1", [user_id])?;
+                tx.commit().map(|_| removed)
+            }).await??;
+        Ok(removed > 0)
+    }
+
+    pub async fn get_users(&self) -> Result<Vec<AllowedUser>, BotError> {
+        let users = self.pool.get().await?
+            .interact(|connection| {
+                let mut stmt = connection.prepare("select user_id, display_name, timezone, created_at, removed_at from allowed_user \
+                    where removed_at is null order by created_at, user_id")?;
+                let result = stmt.query_map([], Self::allowed_user)?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(users)
+    }
+
+    pub async fn get_user(&self, user_id: u64) -> Result<Option<AllowedUser>, BotError> {
+        let user = self.pool.get().await?
+            .interact(move |connection| {
+                connection.query_row("select user_id, display_name, timezone, created_at, removed_at from allowed_user where user_id = ?1",
+                                     [user_id], Self::allowed_user).optional()
+            }).await??;
+        Ok(user)
+    }
+
+    // the zone set with /timezone, the default one until then
+    pub async fn get_timezone(&self, user_id: u64) -> Result<Tz, BotError> {
+        let timezone = self.get_user(user_id).await?
+            .and_then(|user| user.timezone)
+            .and_then(|timezone| timezone.parse::<Tz>().ok());
+        Ok(timezone.unwrap_or(DEFAULT_TIMEZONE))
+    }
+
+    fn allowed_user(row: &Row<'_>) -> rusqlite::Result<AllowedUser> {
+        Ok(AllowedUser {
+            user_id: row.get(0)?,
+            display_name: row.get(1)?,
+            timezone: row.get(2)?,
+            created_at: row.get(3)?,
+            removed_at: row.get(4)?,
+        })
+    }
+
+    pub async fn set_user_role(&self, user_id: u64, role: Role) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert into user_role (user_id, role) values (?1, ?2) \
+                    on conflict(user_id) do update set role = ?2", [&user_id as &dyn ToSql, &role])
+            }).await??;
+        Ok(())
+    }
+
+    pub async fn get_user_role(&self, user_id: u64) -> Result<Option<Role>, BotError> {
+        let role = self.pool.get().await?
+            .interact(move |connection| {
+                connection.query_row("select role from user_role where user_id = ?1", [user_id], |row| row.get(0))
+                    .optional()
+            }).await??;
+        Ok(role)
+    }
+
+    pub async fn get_user_roles(&self) -> Result<Vec<(u64, Role)>, BotError> {
+        let roles = self.pool.get().await?
+            .interact(|connection| {
+                let mut stmt = connection.prepare("select user_id, role from user_role")?;
+                let result = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(roles)
+    }
+
+    // a disabled connection forgets its chats, they have to write again once it is enabled
+    pub async fn upsert_business_connection(&self, id: String, user_id: u64, user_chat_id: u64, is_enabled: bool) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                tx.execute("insert into business_connection (id, user_id, user_chat_id, is_enabled, updated_at) values (?1, ?2, ?3, ?4, ?5) \
+                    on conflict(id) do update set user_id = ?2, user_chat_id = ?3, is_enabled = ?4, updated_at = ?5",
+                           [&id as &dyn ToSql, &user_id, &user_chat_id, &is_enabled, &Utc::now()])?;
+                if !is_enabled {
+                    tx.execute("delete from business_chat where connection_id = ?1", [&id])?;
+                }
+                tx.commit()
+            }).await??;
+        Ok(())
+    }
+
+    pub async fn get_business_connection(&self, id: String) -> Result<Option<BusinessConnection>, BotError> {
+        let connection = self.pool.get().await?
+            .interact(move |connection| {
+                connection.query_row(&format!("select {} from business_connection where id = ?1", BusinessConnection::COLUMNS),
+                                     [id], BusinessConnection::from_row)
+                    .optional()
+            }).await??;
+        Ok(connection)
+    }
+
+    pub async fn get_business_connections(&self, user_id: u64) -> Result<Vec<BusinessConnection>, BotError> {
+        let connections = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare(&format!("select {} from business_connection where user_id = ?1 order by updated_at", BusinessConnection::COLUMNS))?;
+                let result = stmt.query_map([user_id], BusinessConnection::from_row)?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(connections)
+    }
+
+    pub async fn route_business_chat(&self, chat_id: u64, connection_id: String) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert into business_chat (chat_id, connection_id) values (?1, ?2) \
+                    on conflict(chat_id) do update set connection_id = ?2", [&chat_id as &dyn ToSql, &connection_id])
+            }).await??;
+        Ok(())
+    }
+
+    // chats of enabled connections, restored into the telegram client on start
+    pub async fn get_business_chats(&self) -> Result<Vec<(u64, String)>, BotError> {
+        let chats = self.pool.get().await?
+            .interact(|connection| {
+                let mut stmt = connection.prepare("select chat_id, connection_id from business_chat \
+                    join business_connection on business_connection.id = business_chat.connection_id \
+                    where business_connection.is_enabled = 1")?;
+                let result = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(chats)
+    }
+
+    // adds the call's usage and releases the tokens reserved for it, a failed call records no usage
+    pub async fn record_usage(&self, user_id: u64, month: String, usage: Usage, cost: f64, reserved: u64) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert into usage (user_id, month, prompt_tokens, completion_tokens, cost) \
+                    values (?1, ?2, ?3, ?4, ?5) \
+                    on conflict (user_id, month) do update set \
+                    prompt_tokens = prompt_tokens + excluded.prompt_tokens, \
+                    completion_tokens = completion_tokens + excluded.completion_tokens, \
+                    cost = cost + excluded.cost, \
+                    reserved_tokens = max(reserved_tokens - ?6, 0)",
+                    [&user_id as &dyn ToSql, &month, &usage.prompt_tokens, &usage.completion_tokens, &cost, &reserved])
+            }).await??;
+        Ok(())
+    }
+
+    // reserves tokens for a parser call unless the user's or everyone's usage of the month, reservations of calls
+    // in flight included, reached its budget; checked and reserved in one transaction, so messages parsed at
+    // the same time can't all pass on the same usage. false when a budget is reached
+    pub async fn reserve_usage(&self, user_id: u64, month: String, tokens: u64, budget: Option<u64>, total_budget: Option<u64>) -> Result<bool, BotError> {
+        let reserved = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                let spent = "select coalesce(sum(prompt_tokens + completion_tokens + reserved_tokens), 0) from usage";
+                let used: u64 = tx.query_row(&format!("{} where user_id = ?1 and month = ?2", spent), [&user_id as &dyn ToSql, &month], |row| row.get(0))?;
+                let total: u64 = tx.query_row(&format!("{} where month = ?1", spent), [&month], |row| row.get(0))?;
+                if budget.is_some_and(|budget| used >= budget) || total_budget.is_some_and(|budget| total >= budget) {
+                    return Ok(false);
+                }
+                tx.execute("insert into usage (user_id, month, reserved_tokens) values (?1, ?2, ?3) \
+                    on conflict (user_id, month) do update set reserved_tokens = reserved_tokens + excluded.reserved_tokens",
+                    [&user_id as &dyn ToSql, &month, &tokens])?;
+                tx.commit()?;
+                Ok::<_, rusqlite::Error>(true)
+            }).await??;
+        Ok(reserved)
+    }
+
+    // reservations of calls that were in flight when the previous process stopped
+    pub async fn clear_reservations(&self) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(|connection| connection.execute("update usage set reserved_tokens = 0 where reserved_tokens > 0", ()))
+            .await??;
+        Ok(())
+    }
+
+    pub async fn get_user_usage(&self, user_id: u64) -> Result<Vec<MonthlyUsage>, BotError> {
+        let usage = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select month, prompt_tokens, completion_tokens, cost from usage \
+                    where user_id = ?1 order by month")?;
+                let result = stmt.query_map([user_id], MonthlyUsage::from_row)?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(usage)
+    }
+
+    // hard-deletes events that left the active set before the cutoff, together with their satellite rows
+    pub async fn purge_closed_events(&self, cutoff: DateTime<Utc>) -> Result<usize, BotError> {
+        let purged = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                tx.execute("create temp table purged_event as select id from event where is_deleted = 1 and ( \
+                    id in (select event_id from event_history group by event_id having max(at) < ?1) or \
+                    id not in (select event_id from event_history) and kind in ('absolute', 'cron') and event_time < ?1)", [cutoff])?;
+                tx.execute("delete from event_webhook where event_id in (select id from purged_event)", ())?;
+                tx.execute("delete from event_history where event_id in (select id from purged_event)", ())?;
+                tx.execute("delete from event_search where rowid in (select id from purged_event)", ())?;
+                tx.execute("delete from event_tag where event_id in (select id from purged_event)", ())?;
+                tx.execute("delete from event_location where event_id in (select id from purged_event)", ())?;
+                tx.execute("delete from event_exclusion where event_id in (select id from purged_event) or occurs_on < ?1", [cutoff.date_naive()])?;
+                tx.execute("delete from deferred_delivery where event_id in (select id from purged_event)", ())?;
+                tx.execute("delete from undo_action where at < ?1", [cutoff])?;
+                let purged = tx.execute("delete from event where id in (select id from purged_event)", ())?;
+                tx.execute("drop table purged_event", ())?;
+                tx.commit().map(|_| purged)
+            }).await??;
+        Ok(purged)
+    }
+
+    // size of the database file in bytes
+    pub async fn database_size(&self) -> Result<u64, BotError> {
+        let size = self.pool.get().await?
+            .interact(|connection| {
+                connection.query_row("select page_count * page_size from pragma_page_count(), pragma_page_size()", [], |row| row.get(0))
+            }).await??;
+        Ok(size)
+    }
+
+    // next update to ask telegram for, updates below it were already handled
+    pub async fn get_update_offset(&self) -> Result<u64, BotError> {
+        let offset = self.pool.get().await?
+            .interact(|connection| {
+                connection.query_row("select value from bot_state where name = 'update_offset'", [], |row| row.get(0))
+                    .optional()
+            }).await??;
+        Ok(offset.unwrap_or_default())
+    }
+
+    pub async fn set_update_offset(&self, offset: u64) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert into bot_state (name, value, updated_at) values ('update_offset', ?1, ?2) \
+                    on conflict (name) do update set value = excluded.value, updated_at = excluded.updated_at",
+                                   [&offset as &dyn ToSql, &Utc::now()])
+            }).await??;
+        Ok(())
+    }
+
+    pub async fn ping(&self) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(|connection| connection.query_row("select 1", [], |_| Ok(())))
+            .await??;
+        Ok(())
+    }
+
+    pub async fn run_maintenance_step(&self, step: MaintenanceStep) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| connection.execute_batch(step.sql()))
+            .await??;
+        Ok(())
+    }
+
+    pub async fn vacuum(&self) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(|connection| connection.execute_batch("vacuum; analyze;"))
+            .await??;
+        Ok(())
+    }
+
+    // consistent copy of the database that is safe to ship elsewhere while the bot keeps writing
+    pub async fn snapshot(&self, path: PathBuf) -> Result<(), BotError> {
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+        }
+        self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("vacuum into ?1", [path.to_string_lossy()])
+            }).await??;
+        Ok(())
+    }
+
+    pub async fn upsert_webhook(&self, user_id: u64, name: String, url: String) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert into webhook (user_id, name, url) values (?1, ?2, ?3) \
+                    on conflict (user_id, name) do update set url = excluded.url",
+                    [&user_id as &dyn ToSql, &name, &url])
+            }).await??;
+        Ok(())
+    }
+
+    pub async fn remove_webhook(&self, user_id: u64, name: String) -> Result<bool, BotError> {
+        let removed = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                let removed = tx.execute("delete from webhook where user_id = ?1 and name = ?2", [&user_id as &dyn ToSql, &name])?;
+                tx.execute("delete from event_webhook where user_id = ?1 and name = ?2", [&user_id as &dyn ToSql, &name])?;
+                tx.commit().map(|_| removed > 0)
+            }).await??;
+        Ok(removed)
+    }
+
+    pub async fn get_webhooks(&self, user_id: u64) -> Result<Vec<Webhook>, BotError> {
+        let webhooks = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select name, url from webhook where user_id = ?1 order by name")?;
+                let result = stmt.query_map([user_id], |row| Ok(Webhook { name: row.get(0)?, url: row.get(1)? }))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(webhooks)
+    }
+
+    pub async fn get_webhook(&self, user_id: u64, name: String) -> Result<Option<Webhook>, BotError> {
+        let webhook = self.pool.get().await?
+            .interact(move |connection| {
+                connection.query_row("select name, url from webhook where user_id = ?1 and name = ?2",
+                                     [&user_id as &dyn ToSql, &name],
+                                     |row| Ok(Webhook { name: row.get(0)?, url: row.get(1)? }))
+                    .optional()
+            }).await??;
+        Ok(webhook)
+    }
+
+    // attaches the webhook to every row of the event, returns false if either of them is unknown
+    pub async fn attach_webhook(&self, user_id: u64, event_uid: String, name: String) -> Result<bool, BotError> {
+        let attached = self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert or ignore into event_webhook (event_id, user_id, name) \
+                    select event.id, event.user_id, webhook.name from event \
+                    join webhook on webhook.user_id = event.user_id and webhook.name = ?3 \
+                    where event.uid = ?1 and event.user_id = ?2",
+                    [&event_uid as &dyn ToSql, &user_id, &name])
+            }).await??;
+        Ok(attached > 0)
+    }
+
+    // connecting again replaces the token and the directions, the events seen so far are kept
+    pub async fn set_calendar_connection(&self, calendar: CalendarConnection) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert into calendar_connection (user_id, refresh_token, push, pull, connected_at) values (?1, ?2, ?3, ?4, ?5) \
+                    on conflict (user_id) do update set refresh_token = excluded.refresh_token, push = excluded.push, \
+                    pull = excluded.pull, connected_at = excluded.connected_at",
+                    [&calendar.user_id as &dyn ToSql, &calendar.refresh_token, &calendar.push, &calendar.pull, &Utc::now()])
+            }).await??;
+        Ok(())
+    }
+
+    pub async fn get_calendar_connection(&self, user_id: u64) -> Result<Option<CalendarConnection>, BotError> {
+        let connection = self.pool.get().await?
+            .interact(move |connection| {
+                connection.query_row("select user_id, refresh_token, push, pull from calendar_connection where user_id = ?1",
+                                     [user_id], Self::calendar_connection).optional()
+            }).await??;
+        Ok(connection)
+    }
+
+    pub async fn get_calendar_connections(&self) -> Result<Vec<CalendarConnection>, BotError> {
+        let connections = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select user_id, refresh_token, push, pull from calendar_connection order by user_id")?;
+                let result = stmt.query_map([], Self::calendar_connection)?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(connections)
+    }
+
+    fn calendar_connection(row: &Row<'_>) -> rusqlite::Result<CalendarConnection> {
+        Ok(CalendarConnection { user_id: row.get(0)?, refresh_token: row.get(1)?, push: row.get(2)?, pull: row.get(3)? })
+    }
+
+    pub async fn remove_calendar_connection(&self, user_id: u64) -> Result<bool, BotError> {
+        let removed = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                let removed = tx.execute("delete from calendar_connection where user_id = ?1", [user_id])?;
+                tx.execute("delete from calendar_event where user_id = ?1", [user_id])?;
+                tx.commit().map(|_| removed > 0)
+            }).await??;
+        Ok(removed)
+    }
+
+    // a reminder for an event pulled from google calendar, stored in one transaction with the event's id,
+    // so a failed insert leaves the event to the next pull; none when it was pushed or pulled before
+    pub async fn insert_pulled_event(&self, user_id: u64, google_id: String, text: String, time: DateTime<Utc>) -> Result<Option<u64>, BotError> {
+        let uid = self.id_generator.generate();
+        let id = self.pool.get().await?.interact(move |connection| {
+            let tx = connection.transaction()?;
+            if tx.execute("insert or ignore into calendar_event (user_id, google_id) values (?1, ?2)", [&user_id as &dyn ToSql, &google_id])? == 0 {
+                return Ok(None);
+            }
+            tx.execute("insert into event (kind, user_id, event_text, event_time, is_deleted, source, uid, group_uid) values ('absolute', ?1, ?2, ?3, 0, ?4, ?5, ?5)",
+                       [&user_id as &dyn ToSql, &text, &time, &Source::CalendarSync, &uid])?;
+            let id = tx.last_insert_rowid() as u64;
+            record_created(&tx, user_id, &text, &[id])?;
+            tx.commit().map(|_| Some(id))
+        }).await??;
+        Ok(id)
+    }
+
+    pub async fn get_calendar_events(&self, user_id: u64) -> Result<Vec<CalendarEvent>, BotError> {
+        let events = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select google_id from calendar_event where user_id = ?1 order by google_id")?;
+                let result = stmt.query_map([user_id], |row| Ok(CalendarEvent { google_id: row.get(0)? }))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(events)
+    }
+
+    // true when the calendar event wasn't pushed or pulled before
+    pub async fn track_calendar_event(&self, user_id: u64, google_id: String) -> Result<bool, BotError> {
+        let added = self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("insert or ignore into calendar_event (user_id, google_id) values (?1, ?2)",
+                                   [&user_id as &dyn ToSql, &google_id])
+            }).await??;
+        Ok(added > 0)
+    }
+
+    pub async fn get_event_webhooks(&self, event_id: u64) -> Result<Vec<Webhook>, BotError> {
+        let webhooks = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select webhook.name, webhook.url from event_webhook \
+                    join webhook on webhook.user_id = event_webhook.user_id and webhook.name = event_webhook.name \
+                    where event_webhook.event_id = ?1")?;
+                let result = stmt.query_map([event_id], |row| Ok(Webhook { name: row.get(0)?, url: row.get(1)? }))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(webhooks)
+    }
+
+    pub async fn record_webhook_call(&self, user_id: u64, name: String, event_id: Option<u64>, result: Result<u16, String>) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                let (status, error) = match result {
+                    Ok(status) => (Some(status), None),
+                    Err(error) => (None, Some(error)),
+                };
+                connection.execute("insert into webhook_call (user_id, name, event_id, status, error, called_at) \
+                    values (?1, ?2, ?3, ?4, ?5, ?6)",
+                    [&user_id as &dyn ToSql, &name, &event_id, &status, &error, &Utc::now()])
+            }).await??;
+        Ok(())
+    }
+
+    pub async fn get_webhook_calls(&self, user_id: u64) -> Result<Vec<WebhookCall>, BotError> {
+        let calls = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select name, event_id, status, error, called_at from webhook_call \
+                    where user_id = ?1 order by id")?;
+                let result = stmt.query_map([user_id], |row| {
+                    Ok(WebhookCall {
+                        name: row.get(0)?,
+                        event_id: row.get(1)?,
+                        status: row.get(2)?,
+                        error: row.get(3)?,
+                        called_at: row.get(4)?,
+                    })
+                })?.collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(calls)
+    }
+
+    // schedules another attempt with exponential backoff, or dead-letters the event once attempts run out;
+    // returns true when the event was dead-lettered
+    pub async fn record_delivery_failure(&self, event_id: u64, error: String, max_attempts: u32) -> Result<bool, BotError> {
+        let dead_lettered = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                let attempts: u32 = tx.query_row("update event set delivery_attempts = delivery_attempts + 1, last_error = ?1 \
+                    where id = ?2 returning delivery_attempts", [&error as &dyn ToSql, &event_id], |row| row.get(0))?;
+                let now = Utc::now();
+                let dead_lettered = attempts >= max_attempts;
+                if dead_lettered {
+                    tx.execute("update event set is_deleted = 1, is_dead_lettered = 1 where id = ?1", [event_id])?;
+                    tx.execute("insert into event_history (event_id, user_id, transition, at) \
+                        select id, user_id, ?1, ?2 from event where id = ?3",
+                        [&Transition::DeadLettered as &dyn ToSql, &now, &event_id])?;
+                } else {
+                    let next_attempt_at = now + Self::delivery_backoff(attempts);
+                    tx.execute("update event set next_attempt_at = ?1 where id = ?2", [&next_attempt_at as &dyn ToSql, &event_id])?;
+                }
+                tx.commit().map(|_| dead_lettered)
+            }).await??;
+        Ok(dead_lettered)
+    }
+
+    // marks the reminder as waiting for the done button, every send starts the wait over
+    pub async fn track_delivery(&self, event_id: u64, sent_at: DateTime<Utc>, resends: u32) -> Result<(), BotError> {
+        self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("update event set ack_sent_at = ?1, ack_resends = ?2 where id = ?3", [&sent_at as &dyn ToSql, &resends, &event_id])
+            }).await??;
+        Ok(())
+    }
+
+    // reminders still waiting for the done button that are due to be sent again, with the number of times they were;
+    // nagging ones repeat every nag_minutes until done, urgent ones after urgent_window and are given up on after max_resends
+    pub async fn get_unacknowledged(&self, now: DateTime<Utc>, urgent_window: chrono::Duration, max_resends: u32) -> Result<Vec<(EventToFire, u32)>, BotError> {
+        let waiting = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare("select id, user_id, event_text, lead_minutes, priority, nag_minutes, ack_sent_at, ack_resends, kind = 'recurrent', quote, expires_minutes, silent, source_message_id, uid \
+                    from event where ack_sent_at is not null \
+                    and not exists (select 1 from user_settings where user_settings.user_id = event.user_id and paused_until > ?1) order by ack_sent_at")?;
+                let result = stmt.query_map([now], |row| Ok((EventToFire {
+                    event_id: row.get(0)?,
+                    event_uid: row.get(13)?,
+                    user_id: row.get(1)?,
+                    text: row.get(2)?,
+                    lead_minutes: row.get(3)?,
+                    delivery: Delivery { priority: row.get(4)?, nag_minutes: row.get(5)?, expires_minutes: row.get(10)?, silent: row.get(11)? },
+                    is_recurrent: row.get(8)?,
+                    quote: row.get(9)?,
+                    source_message_id: row.get(12)?,
+                    due_at: None,
+                    overdue_minutes: 0,
+                }, row.get::<_, DateTime<Utc>>(6)?, row.get::<_, u32>(7)?)))?.collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+
+        let mut due = vec![];
+        let mut given_up = vec![];
+        for (event, sent_at, resends) in waiting {
+            let nag_minutes = event.delivery.nag_minutes;
+            let window = if nag_minutes > 0 { chrono::Duration::minutes(nag_minutes as i64) } else { urgent_window };
+            if sent_at + window > now {
+                continue;
+            }
+            if nag_minutes == 0 && resends >= max_resends {
+                given_up.push(event.event_id);
+            } else {
+                due.push((event, resends));
+            }
+        }
+        if !given_up.is_empty() {
+            self.pool.get().await?.interact(move |connection| {
+                rusqlite::vtab::array::load_module(connection)?;
+                let array = rusqlite::vtab::array::Array::new(given_up.into_iter().map(|id| rusqlite::types::Value::Integer(id as i64)).collect());
+                connection.execute("update event set ack_sent_at = null where id in rarray(?1)", [array])
+            }).await??;
+        }
+        Ok(due)
+    }
+
+    // returns false when the reminder was already marked done or given up on
+    // keeps the new pinned message of the event and hands back the one pinned before
+    pub async fn swap_pinned_message(&self, event_id: u64, message_id: Option<u64>) -> Result<Option<u64>, BotError> {
+        let previous = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                let previous = tx.query_row("select pinned_message_id from event where id = ?1", [event_id], |row| row.get::<_, Option<u64>>(0))
+                    .optional()?
+                    .flatten();
+                tx.execute("update event set pinned_message_id = ?1 where id = ?2", [&message_id as &dyn ToSql, &event_id])?;
+                tx.commit().map(|_| previous)
+            }).await??;
+        Ok(previous)
+    }
+
+    // a fired event asked to come back later keeps its row closed, the rows of the new time are created separately
+    pub async fn mark_snoozed(&self, user_id: u64, event_id: u64) -> Result<(), BotError> {
+        self.pool.get().await?.interact(move |connection| {
+            connection.execute("insert into event_history (event_id, user_id, transition, at) select id, user_id, ?3, ?4 from event where id = ?1 and user_id = ?2",
+                               [&event_id as &dyn ToSql, &user_id, &Transition::Snoozed, &Utc::now()])
+        }).await??;
+        Ok(())
+    }
+
+    // changes the text of an active event and its heads-ups, the schedule stays as it is
+    pub async fn edit_event_text(&self, user_id: u64, event_id: u64, text: String) -> Result<bool, BotError> {
+        let edited = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                let ids = tx.prepare("select id from event where user_id = ?1 and is_deleted = 0 \
+                    and group_uid = (select group_uid from event where id = ?2 and user_id = ?1)")?
+                    .query_map([&user_id as &dyn ToSql, &event_id], |row| row.get::<_, u64>(0))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                let now = Utc::now();
+                for id in ids.iter() {
+                    tx.execute("update event set event_text = ?2 where id = ?1", [id as &dyn ToSql, &text])?;
+                    tx.execute("update event_search set text = ?2 where rowid = ?1", [id as &dyn ToSql, &text])?;
+                    tx.execute("delete from event_tag where event_id = ?1", [id])?;
+                    for name in extract_tags(&text) {
+                        tx.execute("insert or ignore into event_tag (event_id, user_id, tag) values (?1, ?2, ?3)", [id as &dyn ToSql, &user_id, &name])?;
+                    }
+                    tx.execute("insert into event_history (event_id, user_id, transition, at) values (?1, ?2, ?3, ?4)",
+                               [id as &dyn ToSql, &user_id, &Transition::Edited, &now])?;
+                }
+                tx.commit().map(|_| !ids.is_empty())
+            }).await??;
+        Ok(edited)
+    }
+
+    // the occurrence that fired last is completed once, a recurrent event can be completed again after its next firing
+    pub async fn complete(&self, user_id: u64, event_id: u64, at: DateTime<Utc>) -> Result<bool, BotError> {
+        let completed = self.pool.get().await?
+            .interact(move |connection| {
+                let tx = connection.transaction()?;
+                let completed = tx.execute("update event set completed_at = ?3 where id = ?1 and user_id = ?2 \
+                    and (completed_at is null or completed_at < coalesce(last_fired_at, event_time))",
+                                           [&event_id as &dyn ToSql, &user_id, &at])?;
+                if completed > 0 {
+                    tx.execute("insert into event_history (event_id, user_id, transition, at) values (?1, ?2, ?3, ?4)",
+                               [&event_id as &dyn ToSql, &user_id, &Transition::Completed, &at])?;
+                }
+                tx.commit().map(|_| completed > 0)
+            }).await??;
+        Ok(completed)
+    }
+
+    // closed reminders, newest first, with the transition that closed them last
+    pub async fn get_closed_events(&self, user_id: u64, limit: u32) -> Result<Vec<(Event, Transition)>, BotError> {
+        let events = self.pool.get().await?
+            .interact(move |connection| {
+                let mut stmt = connection.prepare(&format!("select {}, (select transition from event_history \
+                    where event_history.event_id = event.id and transition in ('completed', 'fired', 'expired', 'dead-lettered', 'deleted', 'undone') \
+                    order by event_history.id desc limit 1) as closed_by from event \
+                    where user_id = ?1 and is_deleted = 1 and lead_minutes = 0 and closed_by is not null order by id desc limit ?2", Event::COLUMNS))?;
+                let result = stmt.query_map([user_id, limit as u64], |row| Ok((Event::from_row(row)?, row.get::<_, Transition>(18)?)))?
+                    .collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(events)
+    }
+
+    pub async fn acknowledge(&self, user_id: u64, event_id: u64) -> Result<bool, BotError> {
+        let updated = self.pool.get().await?
+            .interact(move |connection| {
+                connection.execute("update event set ack_sent_at = null where id = ?1 and user_id = ?2 and ack_sent_at is not null", [event_id, user_id])
+            }).await??;
+        Ok(updated > 0)
+    }
+
+    fn delivery_backoff(attempts: u32) -> chrono::Duration {
+        let seconds = 30_i64.saturating_mul(1 << attempts.saturating_sub(1).min(10));
+        chrono::Duration::seconds(seconds.min(3600))
+    }
+
+    pub async fn get_dead_letters(&self) -> Result<Vec<DeadLetter>, BotError> {
+        let dead_letters = self.pool.get().await?
+            .interact(|connection| {
+                let mut stmt = connection.prepare("select id, user_id, event_text, delivery_attempts, last_error \
+                    from event where is_dead_lettered = 1 order by id")?;
+                let result = stmt.query_map([], |row| {
+                    Ok(DeadLetter {
+                        event_id: row.get(0)?,
+                        user_id: row.get(1)?,
+                        text: row.get(2)?,
+                        attempts: row.get(3)?,
+                        last_error: row.get(4)?,
+                    })
+                })?.collect::<Result<Vec<_>, _>>();
+                result
+            }).await??;
+        Ok(dead_letters)
+    }
+
+    pub async fn get_events_to_fire(&self, current_time: DateTime<Utc>, limit: usize) -> Result<Vec<EventToFire>, BotError> {
+        // select only rows which has kind absolute and time is after current time or
+        // kind recurrent and current day is equal to day and hour + minute is after current time;
+        // weekly rows are checked once in utc and once per timezone they are kept in, against the wall time there;
+        // at most `limit` of the oldest rows, the rest stay due until the fired ones are marked
+        let events = self.pool.get().await?
+            .interact(move |connection| {
+                let mut zones = vec![None];
+                zones.extend(connection.prepare("select distinct timezone from event where kind = 'recurrent' and is_deleted = 0 and timezone is not null")?
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>, _>>()?
+                    .into_iter()
+                    .filter_map(|name| name.parse::<Tz>().ok().map(Some)));
+                let mut stmt = connection
+                    .prepare("select id, user_id, event_text, lead_minutes, priority, nag_minutes, kind = 'recurrent', quote, \
+                case when kind = 'recurrent' then null else event_time end, case when kind = 'recurrent' then hour * 60 + minute end, \
+                expires_minutes, silent, source_message_id, uid from event where \
+                is_deleted = 0 and (next_attempt_at is null or next_attempt_at <= ?1) and (
+                kind in ('absolute', 'cron') and ?6 is null and event_time < ?1 or \
+                kind = 'recurrent' and timezone is ?6 and day = ?2 and hour * 60 + minute <= ?3 and (last_fired_at is null or last_fired_at < ?4) \
+                and not exists (select 1 from event_exclusion where event_exclusion.event_id = event.id and occurs_on = ?5) \
+                and not exists (select 1 from holiday where holiday.country = event.holiday_country and holiday.day = ?8)) \
+                and not exists (select 1 from user_settings where user_settings.user_id = event.user_id and paused_until > ?1) \
+                order by id limit ?7")?;
+
+                let today = current_time.date_naive();
+                let mut events = vec![];
+                for zone in zones {
+                    let remaining = limit - events.len();
+                    if remaining == 0 {
+                        break;
+                    }
+                    let (local, start_of_day) = match zone {
+                        Some(zone) => {
+                            let local = current_time.with_timezone(&zone).naive_local();
+                            // the zone's midnight, or the first moment of the day when a dst switch skips it
+                            let start_of_day = local.date().and_hms_opt(0, 0, 0)
+                                .and_then(|midnight| zone.from_local_datetime(&midnight).earliest())
+                                .map(|midnight| midnight.with_timezone(&Utc));
+                            (local, start_of_day)
+                        }
+                        None => (current_time.naive_utc(), current_time.date_naive().and_hms_opt(0, 0, 0).map(|day| Utc.from_utc_datetime(&day))),
+                    };
+                    let current_day = local.weekday().num_days_from_monday() + 1;
+                    let minutes = local.hour() * 60 + local.minute();
+                    let name = zone.map(|zone| zone.name());
+                    // days off are told by the local date of the zone
+                    let found = stmt.query_map([&current_time as &dyn ToSql, &current_day, &minutes, &start_of_day, &today, &name, &remaining, &local.date()], |row| {
+                        let event_id: u64 = row.get(0)?;
+                        let user_id: u64 = row.get(1)?;
+                        let text: String = row.get(2)?;
+                        let lead_minutes: u32 = row.get(3)?;
+                        let priority: Priority = row.get(4)?;
+                        let nag_minutes: u32 = row.get(5)?;
+                        let is_recurrent: bool = row.get(6)?;
+                        let quote: Option<String> = row.get(7)?;
+                        // a weekly occurrence was due at its time of the current day
+                        let weekly_minute: Option<i64> = row.get(9)?;
+                        let due_at = row.get::<_, Option<DateTime<Utc>>>(8)?
+                            .or_else(|| Some(start_of_day? + chrono::Duration::minutes(weekly_minute?)));
+                        let expires_minutes: u32 = row.get(10)?;
+                        let silent: bool = row.get(11)?;
+                        let source_message_id: Option<u64> = row.get(12)?;
+                        Ok(EventToFire {
+                            event_id,
+                            event_uid: row.get(13)?,
+                            user_id,
+                            text,
+                            lead_minutes,
+                            delivery: Delivery { priority, nag_minutes, expires_minutes, silent },
+                            is_recurrent,
+                            quote,
+                            source_message_id,
+                            due_at,
+                            overdue_minutes: 0,
+                        })
+                    })?.collect::<Result<Vec<_>, _>>()?;
+                    events.extend(found);
+                }
+                Ok::<_, rusqlite::Error>(events)
+            }).await??;
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, NaiveDate, Timelike, Utc};
+    use std::sync::Arc;
+    use crate::ids::UuidV7Generator;
+    use crate::humanize::Locale;
+    use crate::models::{Delivery, Priority, StoredNotification, DEFAULT_TIMEZONE};
+    use crate::parser::{LlmParser, Usage};
+    use super::{extract_tags, AccessStatus, CalendarConnection, ConnectionOptions, Event, EventRepository, Holiday, IN_MEMORY, MaintenanceStep, Role, Source, Transition, UserRepository, UserSettings, Webhook, WebhookRoute};
+
+    fn database_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("notify-rs-{}-{}.sqlite", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    async fn create_repository() -> EventRepository {
+        EventRepository::new(IN_MEMORY, Arc::new(UuidV7Generator)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_filter_events_by_source() {
+        let repository = create_repository().await;
+        let time = Utc::now() + Duration::hours(1);
+        repository.insert_event(1, "from chat".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        repository.insert_event(1, "from api".to_string(), Source::Api, vec![StoredNotification::Absolute { time }]).await.unwrap();
+
+        let all = repository.get_events(1, None, None).await.unwrap();
+        assert_eq!(all.len(), 2);
+
+        let api = repository.get_events(1, Some(Source::Api), None).await.unwrap();
+        assert_eq!(api.len(), 1);
+        assert_eq!(api[0].text, "from api");
+        assert_eq!(api[0].source, Source::Api);
+        assert_ne!(all[0].uid, all[1].uid);
+    }
+
+    #[tokio::test]
+    async fn should_search_events_by_fuzzy_text() {
+        let repository = create_repository().await;
+        let time = Utc::now() + Duration::hours(1);
+        let dentist = repository.insert_event(1, "Dentist appointment".to_string(), Source::Telegram, vec![
+            StoredNotification::Absolute { time },
+            StoredNotification::Lead { minutes: 30, notification: Box::new(StoredNotification::Absolute { time: time - Duration::minutes(30) }) },
+        ]).await.unwrap();
+        repository.insert_event(1, "water the plants".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        repository.insert_event(2, "dentist".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+
+        let found = repository.search_events(1, "dentsit".to_string(), 5).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, dentist[0]);
+        assert!(repository.search_events(1, "gym".to_string(), 5).await.unwrap().is_empty());
+
+        assert_eq!(repository.delete_by_text(1, "Dentist appointment".to_string()).await.unwrap().len(), 2);
+        assert!(repository.search_events(1, "dentist".to_string(), 5).await.unwrap().is_empty());
+        assert_eq!(repository.search_events(2, "dentist".to_string(), 5).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_search_pending_and_past_events_by_text() {
+        let repository = create_repository().await;
+        let time = Utc::now() + Duration::hours(1);
+        let past = repository.insert_event(1, "проверить плиту".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        repository.mark_fired(past.clone(), Utc::now()).await.unwrap();
+        let pending = repository.insert_event(1, "Плита на даче".to_string(), Source::Telegram, vec![
+            StoredNotification::Absolute { time },
+            StoredNotification::Lead { minutes: 30, notification: Box::new(StoredNotification::Absolute { time: time - Duration::minutes(30) }) },
+        ]).await.unwrap();
+        repository.insert_event(2, "плита".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+
+        let found = repository.search_text(1, "плит", 10).await.unwrap();
+        let ids = found.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![pending[0], past[0]]);
+        assert!(found[1].1.is_deleted);
+        assert!(repository.search_text(1, "\"*) OR", 10).await.unwrap().is_empty());
+
+        repository.purge_user(1).await.unwrap();
+        assert!(repository.search_text(1, "плита", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_run_every_maintenance_step() {
+        let repository = create_repository().await;
+        repository.insert_event(1, "kept".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time: Utc::now() }]).await.unwrap();
+
+        for step in MaintenanceStep::ALL {
+            repository.run_maintenance_step(step).await.unwrap();
+        }
+        assert_eq!(repository.get_events(1, None, None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_filter_events_by_tags() {
+        let repository = create_repository().await;
+        let time = Utc::now() + Duration::hours(1);
+        let work = repository.insert_event(1, "send the report #Work #urgent #work".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        repository.insert_event(1, "report to the doctor #health".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+
+        assert_eq!(extract_tags("send #Work, #urgent and #work"), vec!["work", "urgent"]);
+        let listed = repository.get_events(1, None, Some("work".to_string())).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].text, "send the report #Work #urgent #work");
+
+        let ids = |found: Vec<(u64, Event)>| found.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        assert_eq!(ids(repository.search_text(1, "report #work", 10).await.unwrap()), vec![work[0]]);
+        assert_eq!(ids(repository.search_text(1, "#urgent #work", 10).await.unwrap()), vec![work[0]]);
+        assert_eq!(repository.search_text(1, "report", 10).await.unwrap().len(), 2);
+        assert!(repository.search_text(1, "#health #work", 10).await.unwrap().is_empty());
+        assert_eq!(repository.get_user_tags(1).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn should_purge_all_user_events() {
+        let repository = create_repository().await;
+        let time = Utc::now() + Duration::hours(1);
+        let ids = repository.insert_event(1, "first".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        repository.insert_event(1, "second".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        repository.insert_event(2, "other user".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        repository.delete_events(ids).await.unwrap();
+        repository.set_plain_mode(1, true).await.unwrap();
+        assert!(repository.get_user_settings(1).await.unwrap().plain_mode);
+        repository.request_access(1, None).await.unwrap();
+        repository.set_user_role(1, Role::ReadOnly).await.unwrap();
+
+        assert_eq!(repository.get_all_user_events(1).await.unwrap().len(), 2);
+        assert_eq!(repository.purge_user(1).await.unwrap(), 2);
+        assert!(repository.get_all_user_events(1).await.unwrap().is_empty());
+        assert!(!repository.get_user_settings(1).await.unwrap().plain_mode);
+        assert!(repository.get_access_request(1).await.unwrap().is_none());
+        assert!(repository.get_user_role(1).await.unwrap().is_none());
+        assert_eq!(repository.get_all_user_events(2).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_fire_heads_up_before_main_reminder() {
+        let repository = create_repository().await;
+        let completion = "{\"kind\": \"absolute\", \"text\": \"dentist\", \"times\": [\"27.01.2030 10:00:00\"], \"leads\": [30]}";
+        let notification = LlmParser::parse_completion(completion).unwrap();
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        repository.insert_event(1, notification.get_text().to_string(), Source::Telegram, notification.create_stored_notifications(at("2030-01-26T12:00:00Z"), DEFAULT_TIMEZONE)).await.unwrap();
+
+        let events = repository.get_events(1, None, None).await.unwrap();
+        assert_eq!(events.iter().map(|event| event.lead_minutes).collect::<Vec<_>>(), vec![0, 30]);
+        assert!(repository.get_events_to_fire(at("2030-01-27T07:29:00Z"), 100).await.unwrap().is_empty());
+        let heads_up = repository.get_events_to_fire(at("2030-01-27T07:31:00Z"), 100).await.unwrap();
+        assert_eq!((heads_up.len(), heads_up[0].lead_minutes), (1, 30));
+        repository.mark_fired(vec![heads_up[0].event_id], at("2030-01-27T07:31:00Z")).await.unwrap();
+        let main = repository.get_events_to_fire(at("2030-01-27T08:01:00Z"), 100).await.unwrap();
+        assert_eq!((main.len(), main[0].lead_minutes), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn should_resend_until_done() {
+        let repository = create_repository().await;
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        let window = Duration::minutes(10);
+        let insert = |text: &str| {
+            let completion = format!("{{\"kind\": \"absolute\", \"text\": \"{}\", \"times\": [\"27.01.2030 10:00:00\"]{}}}", text,
+                                     if text == "oven" { ", \"priority\": \"urgent\"" } else { ", \"nag\": 5" });
+            let notification = LlmParser::parse_completion(&completion).unwrap();
+            repository.insert_event_with_delivery(1, text.to_string(), Source::Telegram, notification.get_delivery(),
+                                                  notification.create_stored_notifications(at("2030-01-26T12:00:00Z"), DEFAULT_TIMEZONE))
+        };
+        insert("oven").await.unwrap();
+        insert("pills").await.unwrap();
+
+        let fired = repository.get_events_to_fire(at("2030-01-27T08:01:00Z"), 100).await.unwrap();
+        assert_eq!(fired.iter().map(|event| event.delivery.priority).collect::<Vec<_>>(), vec![Priority::Urgent, Priority::Normal]);
+        assert_eq!(fired[1].delivery.nag_minutes, 5);
+        repository.mark_fired(fired.iter().map(|event| event.event_id).collect(), at("2030-01-27T08:01:00Z")).await.unwrap();
+        for event in fired.iter() {
+            repository.track_delivery(event.event_id, at("2030-01-27T08:01:00Z"), 0).await.unwrap();
+        }
+        let (oven, pills) = (fired[0].event_id, fired[1].event_id);
+        let due = |time: &str| {
+            let time = at(time);
+            let repository = &repository;
+            async move {
+                repository.get_unacknowledged(time, window, 1).await.unwrap().into_iter()
+                    .map(|(event, resends)| (event.text, resends)).collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(due("2030-01-27T08:05:00Z").await, vec![]);
+        assert_eq!(due("2030-01-27T08:06:00Z").await, vec![("pills".to_string(), 0)]);
+        assert_eq!(due("2030-01-27T08:11:00Z").await, vec![("oven".to_string(), 0), ("pills".to_string(), 0)]);
+        repository.track_delivery(oven, at("2030-01-27T08:11:00Z"), 1).await.unwrap();
+        repository.track_delivery(pills, at("2030-01-27T08:11:00Z"), 5).await.unwrap();
+        // the urgent one used up its resends, the nagging one goes on until done
+        assert_eq!(due("2030-01-27T08:21:00Z").await, vec![("pills".to_string(), 5)]);
+        assert!(!repository.acknowledge(1, oven).await.unwrap());
+        assert!(!repository.acknowledge(2, pills).await.unwrap());
+        assert!(repository.acknowledge(1, pills).await.unwrap());
+        assert_eq!(due("2030-01-27T08:31:00Z").await, vec![]);
+    }
+
+    #[tokio::test]
+    async fn should_group_upcoming_occurrences_by_local_day() {
+        let repository = create_repository().await;
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        let absolute = |time: &str| vec![StoredNotification::Absolute { time: at(time) }];
+        repository.insert_event(1, "late call".to_string(), Source::Telegram, absolute("2030-01-07T22:30:00Z")).await.unwrap();
+        repository.insert_event(1, "lunch".to_string(), Source::Telegram, absolute("2030-01-08T10:00:00Z")).await.unwrap();
+        repository.insert_event(1, "next month".to_string(), Source::Telegram, absolute("2030-02-08T10:00:00Z")).await.unwrap();
+        repository.insert_event(2, "other user".to_string(), Source::Telegram, absolute("2030-01-08T10:00:00Z")).await.unwrap();
+        // sunday 23:00 utc is monday in the bot timezone
+        repository.insert_event(1, "gym".to_string(), Source::Telegram, vec![StoredNotification::Recurrent { hours: 23, minutes: 0, days: Some([7].into_iter().collect()) }]).await.unwrap();
+
+        let load = repository.get_load(1, at("2030-01-07T00:00:00Z"), at("2030-01-21T00:00:00Z"), 120).await.unwrap();
+        let date = |value: &str| NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap();
+        assert_eq!(load.dates, vec![(date("2030-01-08"), 2)]);
+        assert_eq!(load.weekdays, vec![(1, 1)]);
+        assert_eq!(load.count(date("2030-01-08")), 2);
+        assert_eq!(load.count(date("2030-01-14")), 1);
+        assert_eq!(load.count(date("2030-01-09")), 0);
+    }
+
+    #[tokio::test]
+    async fn should_fire_weekly_event_at_same_wall_time_across_dst() {
+        let repository = create_repository().await;
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        repository.upsert_user(1, None, Some("Asia/Jerusalem".to_string())).await.unwrap();
+        // mondays at 06:00 utc, which is the wall time it has in Jerusalem today
+        repository.insert_event(1, "standup".to_string(), Source::Telegram, vec![StoredNotification::Recurrent { hours: 6, minutes: 0, days: Some([1].into_iter().collect()) }]).await.unwrap();
+        repository.insert_event(2, "fixed".to_string(), Source::Telegram, vec![StoredNotification::Recurrent { hours: 6, minutes: 0, days: Some([1].into_iter().collect()) }]).await.unwrap();
+        let wall_time = 6 * 60 + super::zone_offset_minutes(chrono_tz::Asia::Jerusalem, Utc::now()) as i64;
+        let fired = |time: DateTime<Utc>| {
+            let repository = &repository;
+            async move { repository.get_events_to_fire(time, 100).await.unwrap().into_iter().map(|event| event.text).collect::<Vec<_>>() }
+        };
+
+        // 2030-01-07 is a monday in winter (utc+2), 2030-07-01 one in summer (utc+3)
+        for (monday, offset) in [("2030-01-07T00:00:00Z", 120), ("2030-07-01T00:00:00Z", 180)] {
+            let due = at(monday) + Duration::minutes(wall_time - offset);
+            assert!(!fired(due - Duration::minutes(1)).await.contains(&"standup".to_string()));
+            assert!(fired(due).await.contains(&"standup".to_string()));
+        }
+        // a user without a timezone keeps the fixed utc time
+        assert_eq!(fired(at("2030-07-01T06:00:00Z")).await.into_iter().filter(|text| text == "fixed").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_move_cron_event_to_next_occurrence_when_fired() {
+        let repository = create_repository().await;
+        repository.upsert_user(1, None, Some("UTC".to_string())).await.unwrap();
+        let id = repository.insert_cron_event(1, "stretch".to_string(), Source::Telegram, "*/30 * * * *".parse().unwrap()).await.unwrap();
+        let first = repository.get_event(1, id).await.unwrap().unwrap().time.unwrap();
+        assert_eq!(first.minute() % 30, 0);
+
+        let due = repository.get_events_to_fire(first + Duration::seconds(1), 100).await.unwrap();
+        assert_eq!(due.iter().map(|event| (event.event_id, event.is_recurrent)).collect::<Vec<_>>(), vec![(id, false)]);
+        repository.mark_fired(vec![id], first + Duration::seconds(1)).await.unwrap();
+
+        let event = repository.get_event(1, id).await.unwrap().unwrap();
+        assert_eq!(event.time, Some(first + Duration::minutes(30)));
+        assert!(!event.is_deleted);
+        assert!(repository.get_events_to_fire(first + Duration::seconds(2), 100).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_skip_next_occurrence_of_recurrent_event() {
+        let repository = create_repository().await;
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        // mondays and wednesdays, 2030-01-07 is a monday
+        repository.insert_event(1, "gym".to_string(), Source::Telegram, vec![StoredNotification::Recurrent { hours: 18, minutes: 0, days: Some([1, 3].into_iter().collect()) }]).await.unwrap();
+        let fired = |time: &str| {
+            let time = at(time);
+            let repository = &repository;
+            async move { repository.get_events_to_fire(time, 100).await.unwrap().into_iter().map(|event| event.event_id).collect::<Vec<_>>() }
+        };
+        let monday = fired("2030-01-07T18:01:00Z").await;
+        assert_eq!(monday.len(), 1);
+
+        let now = at("2030-01-07T12:00:00Z");
+        assert_eq!(repository.skip_next_occurrence(2, monday[0], now).await.unwrap(), None);
+        assert_eq!(repository.skip_next_occurrence(1, monday[0], now).await.unwrap(), Some(at("2030-01-07T18:00:00Z")));
+        assert_eq!(repository.skip_next_occurrence(1, monday[0], now).await.unwrap(), Some(at("2030-01-09T18:00:00Z")));
+
+        assert!(fired("2030-01-07T18:01:00Z").await.is_empty());
+        assert!(fired("2030-01-09T18:01:00Z").await.is_empty());
+        assert_eq!(fired("2030-01-14T18:01:00Z").await, monday);
+        let skipped = repository.get_exclusions(1).await.unwrap().into_iter().map(|exclusion| exclusion.occurs_on.to_string()).collect::<Vec<_>>();
+        assert_eq!(skipped, vec!["2030-01-07", "2030-01-09"]);
+    }
+
+    #[tokio::test]
+    async fn should_not_fire_weekly_event_on_holidays_of_its_country() {
+        let repository = create_repository().await;
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        let day = |value: &str| value.parse::<NaiveDate>().unwrap();
+        // mondays and wednesdays, 2030-01-07 is a monday
+        let weekly = || vec![StoredNotification::Recurrent { hours: 18, minutes: 0, days: Some([1, 3].into_iter().collect()) }];
+        let ids = repository.insert_event(1, "gym".to_string(), Source::Telegram, weekly()).await.unwrap();
+        repository.insert_event(1, "pool".to_string(), Source::Telegram, weekly()).await.unwrap();
+        repository.set_holiday_country(ids, "IL".to_string()).await.unwrap();
+        repository.load_holidays(vec![
+            Holiday { country: "IL".to_string(), day: day("2030-01-07"), name: "day off".to_string() },
+            Holiday { country: "DE".to_string(), day: day("2030-01-09"), name: "Feiertag".to_string() },
+        ]).await.unwrap();
+        let fired = |time: &str| {
+            let time = at(time);
+            let repository = &repository;
+            async move { repository.get_events_to_fire(time, 100).await.unwrap().into_iter().map(|event| event.text).collect::<Vec<_>>() }
+        };
+
+        assert_eq!(fired("2030-01-07T18:01:00Z").await, ["pool"]);
+        // holidays of other countries don't count
+        assert_eq!(fired("2030-01-09T18:01:00Z").await, ["gym", "pool"]);
+        let events = repository.get_all_user_events(1).await.unwrap();
+        assert_eq!(events.iter().map(|event| event.holiday_country.as_deref()).collect::<Vec<_>>(), [Some("IL"), Some("IL"), None, None]);
+        let holidays = repository.get_holidays(day("2030-01-08")).await.unwrap();
+        assert_eq!(holidays.iter().map(|holiday| holiday.country.as_str()).collect::<Vec<_>>(), ["DE"]);
+    }
+
+    #[tokio::test]
+    async fn should_hold_events_of_paused_user() {
+        let repository = create_repository().await;
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        repository.insert_event(1, "dentist".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time: at("2030-01-07T10:00:00Z") }]).await.unwrap();
+        repository.insert_event(2, "other user".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time: at("2030-01-07T10:00:00Z") }]).await.unwrap();
+        repository.set_paused_until(1, Some(at("2030-01-10T00:00:00Z"))).await.unwrap();
+        assert!(repository.get_user_settings(1).await.unwrap().is_paused(at("2030-01-09T00:00:00Z")));
+
+        let fired = |time: &str| {
+            let time = at(time);
+            let repository = &repository;
+            async move { repository.get_events_to_fire(time, 100).await.unwrap().into_iter().map(|event| event.text).collect::<Vec<_>>() }
+        };
+        assert_eq!(fired("2030-01-07T10:01:00Z").await, vec!["other user"]);
+        assert_eq!(repository.get_missed(1, at("2030-01-08T00:00:00Z")).await.unwrap().len(), 1);
+        // the missed one arrives once the pause is over
+        assert_eq!(fired("2030-01-10T00:01:00Z").await, vec!["dentist", "other user"]);
+        repository.set_paused_until(1, None).await.unwrap();
+        assert!(!repository.get_user_settings(1).await.unwrap().is_paused(at("2030-01-09T00:00:00Z")));
+    }
+
+    #[tokio::test]
+    async fn should_reattach_imported_webhook_routes_by_text() {
+        let repository = create_repository().await;
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        repository.insert_event(1, "wake up".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time: at("2030-01-07T05:00:00Z") }]).await.unwrap();
+        repository.insert_event(1, "wake up".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time: at("2030-01-08T05:00:00Z") }]).await.unwrap();
+        let settings = UserSettings { plain_mode: true, paused_until: None, quiet_hours: None, language: Some(Locale::He) };
+        let webhooks = vec![Webhook { name: "lights".to_string(), url: "https://example.com/hook".to_string() }];
+        let routes = vec![WebhookRoute { event_text: "wake up".to_string(), webhook: "lights".to_string() },
+                          WebhookRoute { event_text: "not here".to_string(), webhook: "lights".to_string() }];
+
+        assert_eq!(repository.import_settings(1, settings, webhooks, routes).await.unwrap(), 2);
+        assert!(repository.get_user_settings(1).await.unwrap().plain_mode);
+        assert_eq!(repository.get_user_settings(1).await.unwrap().language, Some(Locale::He));
+        assert_eq!(repository.get_webhooks(1).await.unwrap().len(), 1);
+        let routes = repository.get_webhook_routes(1).await.unwrap();
+        assert_eq!(routes.iter().map(|route| (route.event_text.as_str(), route.webhook.as_str())).collect::<Vec<_>>(), vec![("wake up", "lights")]);
+    }
+
+    #[tokio::test]
+    async fn should_release_deferred_deliveries_when_due() {
+        let repository = create_repository().await;
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        repository.insert_event(1, "water plants".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time: at("2030-01-07T01:00:00Z") }]).await.unwrap();
+        repository.set_quiet_hours(1, "23:00-08:00".parse().ok()).await.unwrap();
+        assert_eq!(repository.get_user_settings(1).await.unwrap().quiet_hours.unwrap().to_string(), "23:00-08:00");
+
+        let fired = repository.get_events_to_fire(at("2030-01-07T01:01:00Z"), 100).await.unwrap();
+        repository.defer_delivery(fired[0].event_id, 1, at("2030-01-07T06:00:00Z")).await.unwrap();
+        repository.mark_fired(vec![fired[0].event_id], at("2030-01-07T01:01:00Z")).await.unwrap();
+
+        assert!(repository.get_due_deferred(at("2030-01-07T05:59:00Z")).await.unwrap().is_empty());
+        let due = repository.get_due_deferred(at("2030-01-07T06:00:00Z")).await.unwrap();
+        assert_eq!(due.iter().map(|event| event.text.as_str()).collect::<Vec<_>>(), vec!["water plants"]);
+        repository.remove_deferred(vec![due[0].event_id]).await.unwrap();
+        assert!(repository.get_deferred(1).await.unwrap().is_empty());
+        repository.set_quiet_hours(1, None).await.unwrap();
+        assert!(repository.get_user_settings(1).await.unwrap().quiet_hours.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_undo_latest_actions_in_reverse_order() {
+        let repository = create_repository().await;
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        let absolute = |time: &str| vec![StoredNotification::Absolute { time: at(time) }];
+        let dentist = repository.insert_event(1, "dentist".to_string(), Source::Telegram, absolute("2030-01-07T10:00:00Z")).await.unwrap();
+        repository.record_action(1, "adding \"dentist\"".to_string(), dentist.clone(), Transition::Created).await.unwrap();
+        let deleted = repository.delete_by_text(1, "dentist".to_string()).await.unwrap();
+        repository.record_action(1, "cancelling \"dentist\"".to_string(), deleted, Transition::Deleted).await.unwrap();
+
+        assert_eq!(repository.undo_last_action(2).await.unwrap(), None);
+        assert_eq!(repository.undo_last_action(1).await.unwrap(), Some(("cancelling \"dentist\"".to_string(), 1)));
+        assert!(!repository.get_event(1, dentist[0]).await.unwrap().unwrap().is_deleted);
+        assert_eq!(repository.undo_last_action(1).await.unwrap(), Some(("adding \"dentist\"".to_string(), 1)));
+        assert!(repository.get_event(1, dentist[0]).await.unwrap().unwrap().is_deleted);
+        assert_eq!(repository.undo_last_action(1).await.unwrap(), None);
+
+        let history = repository.get_user_history(1).await.unwrap().into_iter().map(|entry| entry.transition).collect::<Vec<_>>();
+        assert_eq!(history, vec![Transition::Created, Transition::Deleted, Transition::Restored, Transition::Undone]);
+
+        for index in 0..7 {
+            let ids = repository.insert_event(1, format!("task {}", index), Source::Telegram, absolute("2030-01-07T10:00:00Z")).await.unwrap();
+            repository.record_action(1, format!("adding task {}", index), ids, Transition::Created).await.unwrap();
+        }
+        let kept = repository.get_undo_actions(1).await.unwrap().into_iter().map(|action| action.label).collect::<Vec<_>>();
+        assert_eq!(kept, (2..7).map(|index| format!("adding task {}", index)).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn should_persist_approved_join_requests() {
+        let repository = create_repository().await;
+        assert!(repository.request_access(5, Some("alice".to_string())).await.unwrap());
+        assert!(!repository.request_access(5, None).await.unwrap());
+        assert!(repository.request_access(6, None).await.unwrap());
+        assert!(repository.get_users().await.unwrap().is_empty());
+
+        assert!(repository.decide_access(5, AccessStatus::Approved).await.unwrap());
+        assert!(!repository.decide_access(5, AccessStatus::Rejected).await.unwrap());
+        assert!(repository.decide_access(6, AccessStatus::Rejected).await.unwrap());
+        assert!(!repository.decide_access(7, AccessStatus::Approved).await.unwrap());
+
+        let users = repository.get_users().await.unwrap();
+        assert_eq!(users.iter().map(|user| (user.user_id, user.display_name.as_deref())).collect::<Vec<_>>(), vec![(5, Some("alice"))]);
+        let request = repository.get_access_request(5).await.unwrap().unwrap();
+        assert_eq!((request.username.as_deref(), request.status), (Some("alice"), AccessStatus::Approved));
+        assert!(request.decided_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn should_keep_removed_users_out_of_the_seed() {
+        let repository = create_repository().await;
+        assert_eq!(repository.seed_users(vec![1, 2]).await.unwrap(), 2);
+        assert!(repository.upsert_user(3, Some("Bob".to_string()), Some("Europe/Berlin".to_string())).await.unwrap());
+        assert!(!repository.upsert_user(3, None, Some("Asia/Jerusalem".to_string())).await.unwrap());
+        repository.set_user_role(2, Role::ReadOnly).await.unwrap();
+
+        assert!(repository.remove_user(2).await.unwrap());
+        assert!(!repository.remove_user(2).await.unwrap());
+        assert_eq!(repository.seed_users(vec![1, 2]).await.unwrap(), 0);
+        assert!(repository.get_user_role(2).await.unwrap().is_none());
+        assert!(repository.get_user(2).await.unwrap().unwrap().removed_at.is_some());
+
+        let users = repository.get_users().await.unwrap();
+        assert_eq!(users.iter().map(|user| user.user_id).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!((users[1].display_name.as_deref(), users[1].timezone.as_deref()), (Some("Bob"), Some("Asia/Jerusalem")));
+
+        // adding a removed user again gives access back
+        assert!(repository.upsert_user(2, None, None).await.unwrap());
+        assert_eq!(repository.get_users().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn should_override_default_roles_with_stored_ones() {
+        let repository = create_repository().await;
+        repository.set_user_role(2, Role::ReadOnly).await.unwrap();
+        repository.set_user_role(2, Role::Admin).await.unwrap();
+        repository.set_user_role(3, Role::ReadOnly).await.unwrap();
+
+        let defaults = [(1, Role::User), (2, Role::User), (3, Role::User)];
+        let users = UserRepository::new(defaults.into_iter().chain(repository.get_user_roles().await.unwrap()));
+        assert_eq!(users.role(1), Some(Role::User));
+        assert_eq!(users.role(2), Some(Role::Admin));
+        assert_eq!(users.role(3), Some(Role::ReadOnly));
+        assert!(!users.is_chat_id_valid(4));
+
+        users.add(3);
+        assert_eq!(users.role(3), Some(Role::ReadOnly));
+        users.add(4);
+        assert_eq!(users.role(4), Some(Role::User));
+        assert_eq!("read-only".parse::<Role>().unwrap(), Role::ReadOnly);
+        assert!("owner".parse::<Role>().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_forget_chats_of_disabled_business_connection() {
+        let repository = create_repository().await;
+        repository.upsert_business_connection("conn".to_string(), 1, 1, true).await.unwrap();
+        repository.route_business_chat(42, "conn".to_string()).await.unwrap();
+        assert_eq!(repository.get_business_chats().await.unwrap(), vec![(42, "conn".to_string())]);
+        assert_eq!(repository.get_business_connections(1).await.unwrap().len(), 1);
+
+        repository.upsert_business_connection("conn".to_string(), 1, 1, false).await.unwrap();
+        assert!(repository.get_business_chats().await.unwrap().is_empty());
+        assert!(!repository.get_business_connection("conn".to_string()).await.unwrap().unwrap().is_enabled);
+
+        repository.purge_user(1).await.unwrap();
+        assert!(repository.get_business_connection("conn".to_string()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn should_backfill_missing_uids_in_checkpointed_job() {
+        let path = database_path("backfill");
+        let repository = EventRepository::new(&path, Arc::new(UuidV7Generator)).await.unwrap();
+        let time = Utc::now() + Duration::hours(1);
+        repository.insert_event(1, "legacy".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        repository.pool.get().await.unwrap().interact(|connection| {
+            connection.execute_batch("update event set uid = null; delete from job;")
+        }).await.unwrap().unwrap();
+
+        let repository = EventRepository::new(&path, Arc::new(UuidV7Generator)).await.unwrap();
+
+        let events = repository.get_events(1, None, None).await.unwrap();
+        assert_eq!(events[0].uid.len(), 36);
+        let jobs = repository.get_jobs().await.unwrap();
+        assert_eq!(jobs[0].name, "uid-backfill");
+        assert_eq!(jobs[0].status, "done");
+        assert_eq!(jobs[0].processed, 1);
+    }
+
+    #[tokio::test]
+    async fn should_accumulate_monthly_usage() {
+        let repository = create_repository().await;
+        let usage = Usage { prompt_tokens: 100, completion_tokens: 20 };
+        repository.record_usage(1, "2023-01".to_string(), usage, 0.5, 0).await.unwrap();
+        repository.record_usage(1, "2023-01".to_string(), usage, 0.5, 0).await.unwrap();
+        repository.record_usage(1, "2023-02".to_string(), usage, 0.5, 0).await.unwrap();
+
+        let months = repository.get_user_usage(1).await.unwrap();
+        assert_eq!(months.len(), 2);
+        assert_eq!((months[0].month.as_str(), months[0].prompt_tokens, months[0].completion_tokens), ("2023-01", 200, 40));
+        assert_eq!(months[0].cost, 1.0);
+    }
+
+    #[tokio::test]
+    async fn should_count_reserved_tokens_against_budgets() {
+        let repository = create_repository().await;
+        let month = || "2023-01".to_string();
+        // two calls in flight reach the user's budget before either recorded its usage
+        assert!(repository.reserve_usage(1, month(), 60, Some(100), None).await.unwrap());
+        assert!(repository.reserve_usage(1, month(), 60, Some(100), None).await.unwrap());
+        assert!(!repository.reserve_usage(1, month(), 60, Some(100), None).await.unwrap());
+        assert!(!repository.reserve_usage(2, month(), 60, None, Some(100)).await.unwrap());
+        assert!(repository.reserve_usage(2, month(), 60, Some(100), None).await.unwrap());
+
+        let usage = Usage { prompt_tokens: 10, completion_tokens: 5 };
+        repository.record_usage(1, month(), usage, 0.5, 60).await.unwrap();
+        repository.record_usage(1, month(), Usage::default(), 0.0, 60).await.unwrap();
+        let spent = &repository.get_user_usage(1).await.unwrap()[0];
+        assert_eq!((spent.prompt_tokens, spent.completion_tokens), (10, 5));
+        assert!(repository.reserve_usage(1, month(), 60, Some(100), None).await.unwrap());
+
+        repository.clear_reservations().await.unwrap();
+        assert!(repository.reserve_usage(2, month(), 60, None, Some(100)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_wipe_demo_data() {
+        let repository = create_repository().await;
+        let usage = Usage { prompt_tokens: 100, completion_tokens: 20 };
+        repository.record_usage(1, "2023-01".to_string(), usage, 0.5, 0).await.unwrap();
+        repository.record_usage(2, "2023-01".to_string(), usage, 0.5, 0).await.unwrap();
+        let time = Utc::now() + Duration::hours(1);
+        repository.insert_event(1, "demo #tag".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        assert_eq!(repository.get_user_usage(2).await.unwrap().len(), 1);
+
+        assert_eq!(repository.wipe().await.unwrap(), 1);
+
+        assert!(repository.get_all_user_events(1).await.unwrap().is_empty());
+        assert!(repository.get_user_tags(1).await.unwrap().is_empty());
+        assert!(repository.search_text(1, "demo", 10).await.unwrap().is_empty());
+        assert!(repository.get_user_usage(1).await.unwrap().is_empty());
+        assert!(repository.get_user_usage(2).await.unwrap().is_empty());
+
+        let users = UserRepository::new([(7, Role::Admin)].into_iter()).with_default_role(Role::User);
+        assert_eq!((users.role(7), users.role(8)), (Some(Role::Admin), Some(Role::User)));
+        assert_eq!(UserRepository::new(std::iter::empty()).role(8), None);
+    }
+
+    #[tokio::test]
+    async fn should_persist_update_offset() {
+        let path = database_path("offset");
+        let repository = EventRepository::new(&path, Arc::new(UuidV7Generator)).await.unwrap();
+        assert_eq!(repository.get_update_offset().await.unwrap(), 0);
+        repository.set_update_offset(41).await.unwrap();
+        repository.set_update_offset(42).await.unwrap();
+
+        let reopened = EventRepository::new(&path, Arc::new(UuidV7Generator)).await.unwrap();
+        assert_eq!(reopened.get_update_offset().await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn should_read_replica_without_writing() {
+        let path = database_path("standby");
+        let primary = EventRepository::new(&path, Arc::new(UuidV7Generator)).await.unwrap();
+        let time = Utc::now() + Duration::hours(1);
+        primary.insert_event(1, "from primary".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+
+        let standby = EventRepository::open_read_only(&path, Arc::new(UuidV7Generator), ConnectionOptions::default()).await.unwrap();
+        assert_eq!(standby.get_all_user_events(1).await.unwrap().len(), 1);
+        assert!(standby.insert_event(1, "from standby".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_apply_pragmas_to_pooled_connections() {
+        use rusqlite::types::Value;
+        let options = ConnectionOptions {
+            journal_mode: "WAL".parse().unwrap(),
+            synchronous: "full".parse().unwrap(),
+            busy_timeout: std::time::Duration::from_millis(1234),
+            ..ConnectionOptions::default()
+        };
+        let repository = EventRepository::open(&database_path("pragmas"), Arc::new(UuidV7Generator), options).await.unwrap();
+        let pragmas = repository.pool.get().await.unwrap().interact(|connection| {
+            let pragma = |name: &str| connection.query_row(&format!("pragma {}", name), [], |row| row.get::<_, Value>(0));
+            Ok::<_, rusqlite::Error>((pragma("journal_mode")?, pragma("synchronous")?, pragma("busy_timeout")?))
+        }).await.unwrap().unwrap();
+        assert_eq!(pragmas, (Value::Text("wal".to_string()), Value::Integer(2), Value::Integer(1234)));
+        assert!("fast".parse::<super::Synchronous>().is_err());
+        assert!("wal2".parse::<super::JournalMode>().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_apply_database_key_to_connections() {
+        let path = database_path("encrypted");
+        let key = Some("correct horse".to_string());
+        if cfg!(feature = "sqlcipher") {
+            let repository = EventRepository::open(&path, Arc::new(UuidV7Generator), ConnectionOptions { key: key.clone(), ..ConnectionOptions::default() }).await.unwrap();
+            repository.set_update_offset(42).await.unwrap();
+            assert!(EventRepository::new(&path, Arc::new(UuidV7Generator)).await.is_err());
+            let reopened = EventRepository::open(&path, Arc::new(UuidV7Generator), ConnectionOptions { key, ..ConnectionOptions::default() }).await.unwrap();
+            assert_eq!(reopened.get_update_offset().await.unwrap(), 42);
+        } else {
+            // without sqlcipher the key would be ignored and the file left readable
+            assert!(EventRepository::open(&path, Arc::new(UuidV7Generator), ConnectionOptions { key, ..ConnectionOptions::default() }).await.is_err());
+        }
+    }
+
+    #[test]
+    fn should_replace_allowed_users() {
+        let users = UserRepository::new([(1, Role::User), (2, Role::Admin)].into_iter());
+        users.replace([(2, Role::Admin), (3, Role::User)].into_iter());
+        assert!(!users.is_chat_id_valid(1));
+        assert_eq!((users.role(2), users.role(3)), (Some(Role::Admin), Some(Role::User)));
+    }
+
+    #[tokio::test]
+    async fn should_attach_webhook_to_event_rows() {
+        let repository = create_repository().await;
+        let time = Utc::now() + Duration::hours(1);
+        let ids = repository.insert_event(1, "lights".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        let uid = repository.get_events(1, None, None).await.unwrap()[0].uid.clone();
+
+        assert!(!repository.attach_webhook(1, uid.clone(), "lights".to_string()).await.unwrap());
+        repository.upsert_webhook(1, "lights".to_string(), "http://localhost/lights".to_string()).await.unwrap();
+        assert!(!repository.attach_webhook(2, uid.clone(), "lights".to_string()).await.unwrap());
+        assert!(repository.attach_webhook(1, uid, "lights".to_string()).await.unwrap());
+
+        let webhooks = repository.get_event_webhooks(ids[0]).await.unwrap();
+        assert_eq!(webhooks.len(), 1);
+        assert_eq!(webhooks[0].url, "http://localhost/lights");
+
+        assert!(repository.remove_webhook(1, "lights".to_string()).await.unwrap());
+        assert!(repository.get_event_webhooks(ids[0]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_record_lifecycle_transitions() {
+        let repository = create_repository().await;
+        let time = Utc::now() + Duration::hours(1);
+        let ids = repository.insert_event(1, "fire".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        let uid = repository.get_events(1, None, None).await.unwrap()[0].uid.clone();
+        assert!(!repository.edit_event_text(2, ids[0], "stolen".to_string()).await.unwrap());
+        assert!(repository.edit_event_text(1, ids[0], "fire drill".to_string()).await.unwrap());
+        assert_eq!(repository.get_event(1, ids[0]).await.unwrap().unwrap().text, "fire drill");
+        repository.mark_fired(ids.clone(), Utc::now()).await.unwrap();
+        repository.mark_snoozed(1, ids[0]).await.unwrap();
+        // closing an already closed event must not add another transition
+        repository.delete_events(ids.clone()).await.unwrap();
+        assert!(!repository.edit_event_text(1, ids[0], "too late".to_string()).await.unwrap());
+
+        let history = repository.get_event_history(1, uid.clone()).await.unwrap();
+        let transitions = history.iter().map(|entry| entry.transition).collect::<Vec<_>>();
+        assert_eq!(transitions, vec![Transition::Created, Transition::Edited, Transition::Fired, Transition::Snoozed]);
+        assert!(repository.get_event_history(2, uid).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_write_readable_snapshot() {
+        let repository = create_repository().await;
+        let time = Utc::now() + Duration::hours(1);
+        repository.insert_event(1, "snapshotted".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+
+        let path = database_path("snapshot");
+        repository.snapshot(path.clone().into()).await.unwrap();
+        repository.snapshot(path.clone().into()).await.unwrap();
+
+        let snapshot = EventRepository::new(&path, Arc::new(UuidV7Generator)).await.unwrap();
+        assert_eq!(snapshot.get_events(1, None, None).await.unwrap()[0].text, "snapshotted");
+    }
+
+    #[tokio::test]
+    async fn should_fire_backlog_in_batches_oldest_first() {
+        let repository = create_repository().await;
+        let now = Utc::now();
+        for (text, hours) in [("first", 30), ("second", 20), ("third", 10)] {
+            let time = now - Duration::hours(hours);
+            repository.insert_event(1, text.to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        }
+
+        let batch = repository.get_events_to_fire(now, 2).await.unwrap();
+        assert_eq!(batch.iter().map(|event| event.text.as_str()).collect::<Vec<_>>(), ["first", "second"]);
+        assert!(batch[0].due_at.is_some_and(|due_at| now - due_at >= Duration::hours(30)));
+        repository.mark_fired(batch.iter().map(|event| event.event_id).collect(), now).await.unwrap();
+        let batch = repository.get_events_to_fire(now, 2).await.unwrap();
+        assert_eq!(batch.iter().map(|event| event.text.as_str()).collect::<Vec<_>>(), ["third"]);
+    }
+
+    #[tokio::test]
+    async fn should_expire_event_past_its_validity_window() {
+        let repository = create_repository().await;
+        let now = Utc::now();
+        let delivery = Delivery { expires_minutes: 30, ..Delivery::default() };
+        let time = now - Duration::hours(2);
+        repository.insert_event_with_delivery(1, "join the call".to_string(), Source::Telegram, delivery, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        let uid = repository.get_events(1, None, None).await.unwrap()[0].uid.clone();
+
+        let fired = repository.get_events_to_fire(now, 100).await.unwrap();
+        assert_eq!((fired[0].delivery.expires_minutes, fired[0].due_at), (30, Some(time)));
+        repository.expire(vec![fired[0].event_id], now).await.unwrap();
+        assert!(repository.get_events_to_fire(now, 100).await.unwrap().is_empty());
+        let transitions = repository.get_event_history(1, uid).await.unwrap().iter().map(|entry| entry.transition).collect::<Vec<_>>();
+        assert_eq!(transitions, vec![Transition::Created, Transition::Expired]);
+    }
+
+    #[tokio::test]
+    async fn should_retry_and_dead_letter_failed_deliveries() {
+        let repository = create_repository().await;
+        let time = Utc::now() - Duration::minutes(1);
+        let ids = repository.insert_event(1, "undeliverable".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        assert_eq!(repository.get_events_to_fire(Utc::now(), 100).await.unwrap().len(), 1);
+
+        assert!(!repository.record_delivery_failure(ids[0], "blocked".to_string(), 2).await.unwrap());
+        // the next attempt is postponed
+        assert!(repository.get_events_to_fire(Utc::now(), 100).await.unwrap().is_empty());
+        let later: DateTime<Utc> = Utc::now() + Duration::minutes(5);
+        assert_eq!(repository.get_events_to_fire(later, 100).await.unwrap().len(), 1);
+
+        assert!(repository.record_delivery_failure(ids[0], "blocked".to_string(), 2).await.unwrap());
+        assert!(repository.get_events_to_fire(later, 100).await.unwrap().is_empty());
+        let dead_letters = repository.get_dead_letters().await.unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].attempts, 2);
+        assert_eq!(dead_letters[0].last_error.as_deref(), Some("blocked"));
+    }
+
+    #[tokio::test]
+    async fn should_purge_only_events_closed_before_cutoff() {
+        let repository = create_repository().await;
+        let time = Utc::now() - Duration::minutes(1);
+        let fired = repository.insert_event(1, "fired".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        repository.insert_event(1, "active".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        repository.mark_fired(fired, Utc::now()).await.unwrap();
+
+        assert_eq!(repository.purge_closed_events(Utc::now() - Duration::days(1)).await.unwrap(), 0);
+        assert_eq!(repository.purge_closed_events(Utc::now() + Duration::seconds(1)).await.unwrap(), 1);
+        repository.vacuum().await.unwrap();
+
+        let remaining = repository.get_all_user_events(1).await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].text, "active");
+        let history = repository.get_user_history(1).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].transition, Transition::Created);
+    }
+
+    #[tokio::test]
+    async fn should_find_active_events_by_uid_and_count_stats() {
+        let repository = create_repository().await;
+        let time = Utc::now() + Duration::hours(1);
+        let ids = repository.insert_event(1, "first".to_string(), Source::Api, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        repository.insert_event(2, "second".to_string(), Source::Api, vec![StoredNotification::Absolute { time }]).await.unwrap();
+        let uid = repository.get_events(1, None, None).await.unwrap()[0].uid.clone();
+
+        assert_eq!(repository.find_event_id(2, uid.clone()).await.unwrap(), None);
+        assert_eq!(repository.find_event_id(1, uid.clone()).await.unwrap(), Some(ids[0]));
+        let stats = repository.get_stats().await.unwrap();
+        assert_eq!((stats.pending_events, stats.active_users), (2, 2));
+        assert!(repository.database_size().await.unwrap() > 0);
+
+        repository.delete_events(ids).await.unwrap();
+        assert_eq!(repository.find_event_id(1, uid).await.unwrap(), None);
+        assert_eq!(repository.get_stats().await.unwrap().active_users, 1);
+    }
+
+    #[tokio::test]
+    async fn should_fire_recurrent_reminder_parsed_from_completion_once_a_day() {
+        let repository = create_repository().await;
+        let completion = "{\"kind\": \"recurrent\", \"text\": \"take pills\", \"days\": null, \"times\": [\"09:00\"]}";
+        let notification = LlmParser::parse_completion(completion).unwrap();
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        let created = at("2030-01-06T12:00:00Z");
+        repository.insert_event(1, notification.get_text().to_string(), Source::Telegram, notification.create_stored_notifications(created, DEFAULT_TIMEZONE)).await.unwrap();
+        assert_eq!(repository.get_events(1, None, None).await.unwrap().len(), 7);
+
+        // 09:00 in Israel is 07:00 utc in winter
+        assert!(repository.get_events_to_fire(at("2030-01-07T06:59:00Z"), 100).await.unwrap().is_empty());
+        let monday = at("2030-01-07T07:00:00Z");
+        let events = repository.get_events_to_fire(monday, 100).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].text, "take pills");
+        repository.mark_fired(vec![events[0].event_id], monday).await.unwrap();
+
+        assert!(repository.get_events_to_fire(at("2030-01-07T07:30:00Z"), 100).await.unwrap().is_empty());
+        assert_eq!(repository.get_events_to_fire(at("2030-01-08T07:00:00Z"), 100).await.unwrap().len(), 1);
+        assert_eq!(repository.get_events_to_fire(at("2030-01-14T07:00:00Z"), 100).await.unwrap().len(), 1);
+        assert_eq!(repository.get_events(1, None, None).await.unwrap().len(), 7);
+    }
+
+    #[tokio::test]
+    async fn should_find_events_close_to_new_reminder() {
+        let repository = create_repository().await;
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        let window = Duration::minutes(30);
+        let standup = at("2030-01-07T10:00:00Z");
+        repository.insert_event(1, "standup".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time: standup }]).await.unwrap();
+        repository.insert_event(1, "gym".to_string(), Source::Telegram, vec![StoredNotification::Recurrent { hours: 18, minutes: 0, days: Some([1, 3].into_iter().collect()) }]).await.unwrap();
+        repository.insert_event(2, "other user".to_string(), Source::Telegram, vec![StoredNotification::Absolute { time: standup }]).await.unwrap();
+
+        let conflicts = |time: &str| {
+            let notifications = vec![StoredNotification::Absolute { time: at(time) }];
+            let repository = &repository;
+            async move {
+                repository.find_conflicts(1, notifications, window).await.unwrap().into_iter().map(|event| event.text).collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(conflicts("2030-01-07T10:20:00Z").await, vec!["standup"]);
+        assert!(conflicts("2030-01-07T11:00:00Z").await.is_empty());
+        // monday evening hits the weekly reminder
+        assert_eq!(conflicts("2030-01-07T17:45:00Z").await, vec!["gym"]);
+        assert!(conflicts("2030-01-08T17:45:00Z").await.is_empty());
+
+        let weekly = vec![StoredNotification::Recurrent { hours: 18, minutes: 15, days: Some([1, 2, 3].into_iter().collect()) }];
+        let found = repository.find_conflicts(1, weekly, window).await.unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|event| event.text == "gym"));
+    }
+
+    #[tokio::test]
+    async fn should_keep_calendar_connection_and_known_events() {
+        let repository = create_repository().await;
+        let connection = CalendarConnection { user_id: 1, refresh_token: "token".to_string(), push: true, pull: false };
+        repository.set_calendar_connection(connection.clone()).await.unwrap();
+        repository.set_calendar_connection(CalendarConnection { pull: true, ..connection.clone() }).await.unwrap();
+        assert_eq!(repository.get_calendar_connection(1).await.unwrap(), Some(CalendarConnection { pull: true, ..connection }));
+        assert_eq!(repository.get_calendar_connections().await.unwrap().len(), 1);
+        let exported = serde_json::to_value(repository.get_calendar_connection(1).await.unwrap()).unwrap();
+        assert_eq!(exported["refresh_token"], "<redacted>");
+
+        assert!(repository.track_calendar_event(1, "google".to_string()).await.unwrap());
+        assert!(!repository.track_calendar_event(1, "google".to_string()).await.unwrap());
+        assert!(repository.track_calendar_event(2, "google".to_string()).await.unwrap());
+
+        let time = Utc::now() + Duration::hours(1);
+        let id = repository.insert_pulled_event(1, "pulled".to_string(), "dentist".to_string(), time).await.unwrap().unwrap();
+        assert_eq!(repository.insert_pulled_event(1, "pulled".to_string(), "dentist".to_string(), time).await.unwrap(), None);
+        assert_eq!(repository.insert_pulled_event(1, "google".to_string(), "standup".to_string(), time).await.unwrap(), None);
+        let event = repository.get_event(1, id).await.unwrap().unwrap();
+        assert_eq!((event.text.as_str(), event.source, event.time), ("dentist", Source::CalendarSync, Some(time)));
+        assert_eq!(repository.get_all_user_events(1).await.unwrap().len(), 1);
+        let google_ids = repository.get_calendar_events(1).await.unwrap().into_iter().map(|event| event.google_id).collect::<Vec<_>>();
+        assert_eq!(google_ids, ["google", "pulled"]);
+
+        assert!(repository.remove_calendar_connection(1).await.unwrap());
+        assert!(!repository.remove_calendar_connection(1).await.unwrap());
+        assert_eq!(repository.get_calendar_connection(1).await.unwrap(), None);
+        assert!(repository.track_calendar_event(1, "google".to_string()).await.unwrap());
+    }
+}
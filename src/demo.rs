@@ -0,0 +1,48 @@
+use std::path::Path;
+use crate::db::IN_MEMORY;
+use crate::models::Env;
+
+// shown on /start, so visitors know what happens to their reminders
+pub const NOTICE: &str = "This is a demo: anyone can try the bot, your reminders are visible only to you and everything is wiped every hour.";
+
+// public showcase: a separate throwaway database next to the real one, which is never opened,
+// and none of the hooks that could copy demo data elsewhere
+pub fn apply(env: &mut Env) -> std::io::Result<()> {
+    if !env.demo_mode {
+        return Ok(());
+    }
+    env.connection_string = demo_database(&env.connection_string);
+    for suffix in ["", "-wal", "-shm"] {
+        let path = format!("{}{}", env.connection_string, suffix);
+        if Path::new(&path).exists() {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    env.snapshot_interval_secs = None;
+    env.snapshot_hook = None;
+    env.restore_hook = None;
+    if env.message_prefix.is_none() {
+        env.message_prefix = Some("[demo]".to_string());
+    }
+    Ok(())
+}
+
+// an in-memory database is throwaway already
+fn demo_database(connection_string: &str) -> String {
+    if connection_string == IN_MEMORY {
+        return connection_string.to_string();
+    }
+    format!("{}.demo", connection_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::IN_MEMORY;
+    use super::demo_database;
+
+    #[test]
+    fn should_keep_demo_database_apart() {
+        assert_eq!(demo_database("data/notify.db"), "data/notify.db.demo");
+        assert_eq!(demo_database(IN_MEMORY), IN_MEMORY);
+    }
+}
@@ -1,6 +1,4 @@
 use thiserror::Error;
-use tokio::sync::mpsc::error::SendError;
-use crate::bot::State;
 
 #[derive(Debug, Error)]
 pub enum BotError {
@@ -19,13 +17,59 @@ pub enum BotError {
     #[error("{0}")]
     Url(#[from] url::ParseError),
     #[error("{0}")]
-    Other(#[from] SendError<(u64, State)>),
-    #[error("{0}")]
     Parse(#[from] std::num::ParseIntError),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Hyper(#[from] hyper::Error),
     #[error("no env ids")]
     EnvIds,
     #[error("no completion given")]
     NoCompletionGiven,
     #[error("invalid callback query")]
     InvalidCallbackQuery,
+    #[error("unknown source {0}")]
+    UnknownSource(String),
+    #[error("unknown role {0}, expected admin, user or read-only")]
+    UnknownRole(String),
+    #[error("unknown priority {0}, expected low, normal or urgent")]
+    UnknownPriority(String),
+    #[error("unknown log format {0}, expected text or json")]
+    UnknownLogFormat(String),
+    #[error("unknown language {0}, expected en, ru or he")]
+    UnknownLocale(String),
+    #[error("unknown journal mode {0}, expected delete, truncate, persist, memory, wal or off")]
+    UnknownJournalMode(String),
+    #[error("unknown synchronous setting {0}, expected off, normal, full or extra")]
+    UnknownSynchronous(String),
+    #[error("a draft can only be edited into a reminder, not into a cancel request")]
+    InvalidEdit,
+    #[error("unknown missed policy {0}, expected deliver, prefix, summary or expire")]
+    UnknownMissedPolicy(String),
+    #[error("unknown llm provider {0}")]
+    UnknownProvider(String),
+    #[error("usage: {0}")]
+    Usage(&'static str),
+    #[error("profile file {0} not found")]
+    ProfileNotFound(String),
+    #[error("config file {0}")]
+    Config(String),
+    #[error("database schema is at version {0}, this build expects {1}")]
+    SchemaMismatch(usize, usize),
+    #[error("hook `{0}` exited with {1:?}")]
+    HookFailed(String, Option<i32>),
+    #[error("invalid quiet hours {0}, expected hh:mm-hh:mm")]
+    InvalidQuietHours(String),
+    #[error("invalid cron expression {0}, expected minute, hour, day of month, month and day of week")]
+    InvalidCron(String),
+    #[error("invalid settings file: {0}")]
+    InvalidBundle(String),
+    #[error("no recorded completion at {0}, record it with PARSER_FIXTURES=record:<dir>")]
+    MissingFixture(String),
+    #[error("webhook {0} is not allowed, only https urls of public addresses are")]
+    UnsafeWebhook(String),
+    #[error("google calendar answered {0}: {1}")]
+    Calendar(u16, String),
+    #[error("Monthly parsing budget is used up, please try again next month")]
+    BudgetExceeded,
 }
\ No newline at end of file
@@ -0,0 +1,62 @@
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Serialize, Serializer};
+use tokio::io::AsyncWriteExt;
+use crate::errors::BotError;
+use crate::models::{on_wall_clock, Notification};
+
+// a text the offline parser read differently from the model, the model's reading is missing when it failed;
+// times are on the wall clock of the timezone, as the model gives them
+#[derive(Debug, Serialize)]
+pub struct Disagreement<'a> {
+    pub text: &'a str,
+    pub model: Option<&'a Notification>,
+    pub offline: &'a Notification,
+    #[serde(serialize_with = "timezone_name")]
+    pub timezone: Tz,
+    pub parsed_at: DateTime<Utc>,
+}
+
+fn timezone_name<S: Serializer>(timezone: &Tz, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(timezone.name())
+}
+
+// appended as one json line, so the corpus can be collected while the bot runs and read line by line
+pub async fn append(path: &Path, disagreement: &Disagreement<'_>) -> Result<(), BotError> {
+    let mut line = on_wall_clock(disagreement.timezone, || serde_json::to_vec(disagreement))?;
+    line.push(b'\n');
+    let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+    file.write_all(&line).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+    use crate::ids::IdGenerator;
+    use crate::models::{FormattedTime, Notification, Priority};
+    use super::{append, Disagreement};
+
+    #[tokio::test]
+    async fn should_append_disagreements_as_json_lines() {
+        let path = std::env::temp_dir().join(format!("eval-{}-{}.jsonl", std::process::id(), crate::ids::UuidV7Generator.generate()));
+        let time = DateTime::parse_from_rfc3339("2023-07-21T12:00:00Z").unwrap().with_timezone(&Utc);
+        let offline = Notification::Absolute {
+            text: "call mom".to_string(), times: vec![FormattedTime { time }], leads: vec![], priority: Priority::Normal, nag: None, valid: None,
+        };
+        for text in ["21.07 15:00 call mom", "21.07 15:00 call dad"] {
+            let disagreement = Disagreement { text, model: None, offline: &offline, timezone: chrono_tz::Asia::Jerusalem, parsed_at: time };
+            append(&path, &disagreement).await.unwrap();
+        }
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines = content.lines().map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()).collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["text"], "21.07 15:00 call dad");
+        assert_eq!(lines[1]["model"], serde_json::Value::Null);
+        assert_eq!(lines[1]["timezone"], "Asia/Jerusalem");
+        assert_eq!(lines[1]["offline"]["times"][0], "21.07.2023 15:00:00");
+    }
+}
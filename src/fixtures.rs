@@ -0,0 +1,106 @@
+use std::hash::Hasher;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::str::FromStr;
+use fnv::FnvHasher;
+use serde::{Deserialize, Serialize};
+use crate::errors::BotError;
+use crate::parser::{Completion, Usage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixtureMode {
+    // completions still come from the provider and are written to the directory as well
+    Record,
+    // completions are read from the directory only, a request without a fixture fails
+    Replay,
+}
+
+// completions kept as files named after a hash of the whole request, so the parsing pipeline
+// can be tested offline; set as PARSER_FIXTURES=record:<dir> or replay:<dir>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParserFixtures {
+    pub mode: FixtureMode,
+    pub dir: PathBuf,
+}
+
+// the system prompt is only a part of the hash, the user message is kept to tell the files apart
+#[derive(Debug, Serialize, Deserialize)]
+struct Fixture {
+    model: String,
+    request: String,
+    content: String,
+    usage: Usage,
+}
+
+impl ParserFixtures {
+    // fnv, unlike the std hasher, gives the same names on every build
+    fn path(&self, model: &str, system_message: &str, user_message: &str) -> PathBuf {
+        let mut hasher = FnvHasher::default();
+        for part in [model, system_message, user_message] {
+            hasher.write(part.as_bytes());
+            hasher.write_u8(0);
+        }
+        self.dir.join(format!("{:016x}.json", hasher.finish()))
+    }
+
+    pub async fn load(&self, model: &str, system_message: &str, user_message: &str) -> Result<Completion, BotError> {
+        let path = self.path(model, system_message, user_message);
+        let content = match tokio::fs::read(&path).await {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Err(BotError::MissingFixture(path.display().to_string())),
+            Err(err) => return Err(err.into()),
+        };
+        let fixture: Fixture = serde_json::from_slice(&content)?;
+        Ok(Completion { content: fixture.content, usage: fixture.usage })
+    }
+
+    pub async fn save(&self, model: &str, system_message: &str, user_message: &str, completion: &Completion) -> Result<(), BotError> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let fixture = Fixture {
+            model: model.to_string(),
+            request: user_message.to_string(),
+            content: completion.content.clone(),
+            usage: completion.usage,
+        };
+        let mut content = serde_json::to_vec_pretty(&fixture)?;
+        content.push(b'\n');
+        tokio::fs::write(self.path(model, system_message, user_message), content).await?;
+        Ok(())
+    }
+}
+
+impl FromStr for ParserFixtures {
+    type Err = BotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mode, dir) = match s.split_once(':') {
+            Some(("record", dir)) if !dir.is_empty() => (FixtureMode::Record, dir),
+            Some(("replay", dir)) if !dir.is_empty() => (FixtureMode::Replay, dir),
+            _ => return Err(BotError::Usage("PARSER_FIXTURES=record:<dir> or replay:<dir>")),
+        };
+        Ok(ParserFixtures { mode, dir: PathBuf::from(dir) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::BotError;
+    use crate::parser::{Completion, Usage};
+    use super::{FixtureMode, ParserFixtures};
+
+    #[tokio::test]
+    async fn should_replay_recorded_completion() {
+        let dir = std::env::temp_dir().join(format!("notify-rs-fixtures-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let recorder = format!("record:{}", dir.display()).parse::<ParserFixtures>().unwrap();
+        assert_eq!(recorder.mode, FixtureMode::Record);
+        let usage = Usage { prompt_tokens: 700, completion_tokens: 30 };
+        recorder.save("gpt-4o-mini", "system", "call mom", &Completion { content: "{}".to_string(), usage }).await.unwrap();
+
+        let player = format!("replay:{}", dir.display()).parse::<ParserFixtures>().unwrap();
+        let completion = player.load("gpt-4o-mini", "system", "call mom").await.unwrap();
+        assert_eq!((completion.content.as_str(), completion.usage), ("{}", usage));
+        assert!(matches!(player.load("gpt-4o", "system", "call mom").await, Err(BotError::MissingFixture(_))));
+        assert!("replay:".parse::<ParserFixtures>().is_err());
+    }
+}
@@ -0,0 +1,311 @@
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use fnv::FnvHashMap;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use url::Url;
+use crate::db::{CalendarConnection, Event, Kind};
+use crate::errors::BotError;
+use crate::ics;
+use crate::ids::{IdGenerator, UuidV7Generator};
+use crate::models::Env;
+
+const AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const EVENTS_URL: &str = "https://www.googleapis.com/calendar/v3/calendars/primary/events";
+// events of the calendars only, the calendars themselves and their sharing stay out of reach
+const SCOPE: &str = "https://www.googleapis.com/auth/calendar.events";
+// the link sent by /connect_calendar is good for this long
+const AUTHORIZATION_TTL_MINUTES: i64 = 15;
+// events pulled at once, the ones past it come with the next pull as the earlier ones are already known
+const MAX_PULLED: usize = 50;
+// private property of the pushed events, pulling leaves them out so a reminder doesn't come back as a copy
+const UID_PROPERTY: &str = "notifyUid";
+
+// which way reminders go, both ways when /connect_calendar is sent without one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncDirections {
+    pub push: bool,
+    pub pull: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PendingAuthorization {
+    user_id: u64,
+    directions: SyncDirections,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Tokens {
+    access_token: String,
+    refresh_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GoogleEvent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(default)]
+    summary: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+    #[serde(default)]
+    start: EventTime,
+    #[serde(default)]
+    end: EventTime,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    recurrence: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    extended_properties: Option<ExtendedProperties>,
+}
+
+impl GoogleEvent {
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+}
+
+// a time of day with the zone it is on, or a whole day
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EventTime {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    date_time: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    date: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    time_zone: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ExtendedProperties {
+    #[serde(default)]
+    private: FnvHashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventList {
+    #[serde(default)]
+    items: Vec<GoogleEvent>,
+}
+
+// oauth web flow and the events api of google calendar, set up with GOOGLE_CLIENT_ID, GOOGLE_CLIENT_SECRET and
+// GOOGLE_REDIRECT_URL; the redirect url is the /calendar/callback route of the api, which is public for it
+#[derive(Clone)]
+pub struct GoogleCalendar {
+    client: Client,
+    client_id: String,
+    client_secret: String,
+    redirect_url: String,
+    // by the state parameter of the links sent to users, the callback finds out whose calendar it is
+    pending: Arc<Mutex<FnvHashMap<String, PendingAuthorization>>>,
+}
+
+impl GoogleCalendar {
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new(env: &Env) -> Result<Option<GoogleCalendar>, BotError> {
+        let (client_id, client_secret, redirect_url) = match (&env.google_client_id, &env.google_client_secret, &env.google_redirect_url) {
+            (None, None, None) => return Ok(None),
+            (Some(client_id), Some(client_secret), Some(redirect_url)) => (client_id, client_secret, redirect_url),
+            _ => return Err(BotError::Usage("GOOGLE_CLIENT_ID, GOOGLE_CLIENT_SECRET and GOOGLE_REDIRECT_URL are set together")),
+        };
+        if env.api_bind.is_none() {
+            return Err(BotError::Usage("GOOGLE_REDIRECT_URL points at the api, set API_BIND as well"));
+        }
+        Url::parse(redirect_url)?;
+        Ok(Some(GoogleCalendar {
+            client: Client::builder().timeout(Self::TIMEOUT).build()?,
+            client_id: client_id.clone(),
+            client_secret: client_secret.clone(),
+            redirect_url: redirect_url.clone(),
+            pending: Arc::default(),
+        }))
+    }
+
+    // link to the consent screen of google; offline access gives the refresh token the sync runs on
+    pub fn authorization_url(&self, user_id: u64, directions: SyncDirections, now: DateTime<Utc>) -> Result<String, BotError> {
+        let state = UuidV7Generator.generate();
+        let url = Url::parse_with_params(AUTH_URL, [
+            ("client_id", self.client_id.as_str()), ("redirect_uri", self.redirect_url.as_str()), ("response_type", "code"),
+            ("scope", SCOPE), ("access_type", "offline"), ("prompt", "consent"), ("state", state.as_str()),
+        ])?;
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        pending.retain(|_, authorization| authorization.expires_at > now);
+        let expires_at = now + chrono::Duration::minutes(AUTHORIZATION_TTL_MINUTES);
+        pending.insert(state, PendingAuthorization { user_id, directions, expires_at });
+        Ok(url.to_string())
+    }
+
+    // exchanges the code google sent to the callback for the refresh token, none for an unknown or expired state
+    pub async fn authorize(&self, state: &str, code: &str, now: DateTime<Utc>) -> Result<Option<CalendarConnection>, BotError> {
+        let pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner).remove(state);
+        let Some(pending) = pending.filter(|pending| pending.expires_at > now) else {
+            return Ok(None);
+        };
+        let tokens = self.token(&[("grant_type", "authorization_code"), ("code", code), ("redirect_uri", &self.redirect_url)]).await?;
+        let refresh_token = tokens.refresh_token.ok_or_else(|| BotError::Calendar(200, "no refresh token given".to_string()))?;
+        Ok(Some(CalendarConnection { user_id: pending.user_id, refresh_token, push: pending.directions.push, pull: pending.directions.pull }))
+    }
+
+    // access tokens live for an hour, every sync gets a fresh one
+    pub async fn access_token(&self, refresh_token: &str) -> Result<String, BotError> {
+        Ok(self.token(&[("grant_type", "refresh_token"), ("refresh_token", refresh_token)]).await?.access_token)
+    }
+
+    async fn token(&self, params: &[(&str, &str)]) -> Result<Tokens, BotError> {
+        let params = [("client_id", self.client_id.as_str()), ("client_secret", self.client_secret.as_str())].iter()
+            .chain(params)
+            .copied()
+            .collect::<Vec<_>>();
+        read(self.client.post(TOKEN_URL).form(&params).send().await?).await
+    }
+
+    // returns the id google gave the event
+    pub async fn insert_event(&self, access_token: &str, event: &GoogleEvent) -> Result<String, BotError> {
+        let response = self.client.post(EVENTS_URL).bearer_auth(access_token).json(event).send().await?;
+        let inserted: GoogleEvent = read(response).await?;
+        inserted.id.ok_or_else(|| BotError::Calendar(200, "no event id given".to_string()))
+    }
+
+    // events starting between the times, a recurring one comes once per occurrence with an id of its own
+    pub async fn list_events(&self, access_token: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<GoogleEvent>, BotError> {
+        let response = self.client.get(EVENTS_URL).bearer_auth(access_token)
+            .query(&[("timeMin", from.to_rfc3339()), ("timeMax", to.to_rfc3339()), ("singleEvents", "true".to_string()),
+                     ("orderBy", "startTime".to_string()), ("maxResults", MAX_PULLED.to_string())])
+            .send().await?;
+        let list: EventList = read(response).await?;
+        Ok(list.items)
+    }
+}
+
+async fn read<T: DeserializeOwned>(response: reqwest::Response) -> Result<T, BotError> {
+    let status = response.status();
+    if !status.is_success() {
+        return Err(BotError::Calendar(status.as_u16(), response.text().await.unwrap_or_default()));
+    }
+    Ok(response.json().await?)
+}
+
+// a reminder as an event on the wall clock of the timezone, weekly ones repeat; it takes no time, so it ends as it starts.
+// heads-ups belong to the reminder they come before, and cron rules have no recurrence google knows, so neither is pushed
+pub fn to_google_event(event: &Event, now: DateTime<Utc>, timezone: Tz) -> Option<GoogleEvent> {
+    if event.lead_minutes > 0 || matches!(event.kind, Kind::Cron) {
+        return None;
+    }
+    let (start, rule) = ics::first_occurrence(event, now, timezone)?;
+    let time = EventTime { date_time: Some(start.format("%Y-%m-%dT%H:%M:%S").to_string()), date: None, time_zone: Some(timezone.name().to_string()) };
+    Some(GoogleEvent {
+        summary: event.text.clone(),
+        start: time.clone(),
+        end: time,
+        recurrence: rule.into_iter().collect(),
+        extended_properties: Some(ExtendedProperties { private: [(UID_PROPERTY.to_string(), event.uid.clone())].into_iter().collect() }),
+        ..GoogleEvent::default()
+    })
+}
+
+// text and time of the reminder for an event of the calendar; pushed, cancelled and untitled events are left out,
+// all-day ones are reminded of at 9 am like the ones of imported calendar files
+pub fn to_reminder(event: &GoogleEvent, timezone: Tz) -> Option<(String, DateTime<Utc>)> {
+    let pushed = event.extended_properties.as_ref().is_some_and(|properties| properties.private.contains_key(UID_PROPERTY));
+    if pushed || event.status.as_deref() == Some("cancelled") || event.summary.trim().is_empty() {
+        return None;
+    }
+    let time = match (&event.start.date_time, event.start.date) {
+        (Some(date_time), _) => DateTime::parse_from_rfc3339(date_time).ok()?.with_timezone(&Utc),
+        (None, Some(date)) => timezone.from_local_datetime(&date.and_hms_opt(9, 0, 0)?).earliest()?.with_timezone(&Utc),
+        (None, None) => return None,
+    };
+    Some((event.summary.trim().to_string(), time))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use chrono::{DateTime, Duration, Utc};
+    use envconfig::Envconfig;
+    use crate::db::{Event, Kind, Source};
+    use crate::models::{Env, Priority};
+    use super::{to_google_event, to_reminder, GoogleCalendar, GoogleEvent, SyncDirections};
+
+    fn time(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn event(kind: Kind, time: Option<DateTime<Utc>>, day: Option<u8>) -> Event {
+        Event {
+            uid: "uid".to_string(), kind, source: Source::Telegram, text: "standup".to_string(), time, day, hour: Some(7), minute: Some(0),
+            is_deleted: false, lead_minutes: 0, priority: Priority::Normal, nag_minutes: 0, expires_minutes: 0, silent: false,
+            cron: None, quote: None, timezone: None, holiday_country: None,
+        }
+    }
+
+    fn calendar(settings: &[(&str, &str)]) -> Result<Option<GoogleCalendar>, crate::errors::BotError> {
+        let env = Env::init_from_hashmap(&[("TG_KEY", "key"), ("OAI_TOKEN", "key"), ("TG_USERS", "1"), ("CONN_STRING", ":memory:")].iter().chain(settings)
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>()).unwrap();
+        GoogleCalendar::new(&env)
+    }
+
+    #[tokio::test]
+    async fn should_accept_only_states_of_unexpired_links() {
+        assert!(calendar(&[]).unwrap().is_none());
+        assert!(calendar(&[("GOOGLE_CLIENT_ID", "id")]).is_err());
+        let google = [("GOOGLE_CLIENT_ID", "id"), ("GOOGLE_CLIENT_SECRET", "secret"), ("GOOGLE_REDIRECT_URL", "https://bot.example.com/calendar/callback")];
+        assert!(calendar(&google).is_err());
+        let calendar = calendar(&[&google[..], &[("API_BIND", "127.0.0.1:8080")]].concat()).unwrap().unwrap();
+
+        let now = time("2023-01-26T12:00:00Z");
+        let directions = SyncDirections { push: true, pull: true };
+        let url = url::Url::parse(&calendar.authorization_url(1, directions, now).unwrap()).unwrap();
+        let state = url.query_pairs().find(|(key, _)| key == "state").unwrap().1.into_owned();
+        assert!(url.query_pairs().any(|(key, value)| key == "redirect_uri" && value == "https://bot.example.com/calendar/callback"));
+
+        assert!(calendar.authorize("unknown", "code", now).await.unwrap().is_none());
+        assert!(calendar.authorize(&state, "code", now + Duration::minutes(16)).await.unwrap().is_none());
+        // the state is used up by the first callback, expired or not
+        assert!(calendar.authorize(&state, "code", now).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn should_push_reminders_as_events_on_the_wall_clock() {
+        let now = time("2023-01-26T12:00:00Z");
+        let absolute = to_google_event(&event(Kind::Absolute, Some(time("2023-01-27T12:00:00Z")), None), now, chrono_tz::Asia::Jerusalem).unwrap();
+        assert_eq!(serde_json::to_value(&absolute).unwrap(), serde_json::json!({
+            "summary": "standup",
+            "start": { "dateTime": "2023-01-27T14:00:00", "timeZone": "Asia/Jerusalem" },
+            "end": { "dateTime": "2023-01-27T14:00:00", "timeZone": "Asia/Jerusalem" },
+            "extendedProperties": { "private": { "notifyUid": "uid" } },
+        }));
+        // monday 7:00 utc
+        let weekly = to_google_event(&event(Kind::Recurrent, None, Some(1)), now, chrono_tz::Asia::Jerusalem).unwrap();
+        assert_eq!(weekly.start.date_time.as_deref(), Some("2023-01-30T09:00:00"));
+        assert_eq!(weekly.recurrence, ["RRULE:FREQ=WEEKLY;BYDAY=MO"]);
+        assert!(to_google_event(&Event { lead_minutes: 10, ..event(Kind::Absolute, Some(now), None) }, now, chrono_tz::UTC).is_none());
+        assert!(to_google_event(&event(Kind::Cron, Some(now), None), now, chrono_tz::UTC).is_none());
+    }
+
+    #[test]
+    fn should_pull_events_not_pushed_from_here() {
+        let events: Vec<GoogleEvent> = serde_json::from_value(serde_json::json!([
+            { "id": "a", "summary": " dentist ", "start": { "dateTime": "2023-01-27T10:00:00+01:00" } },
+            { "id": "b", "summary": "holiday", "start": { "date": "2023-01-28" } },
+            { "id": "c", "summary": "standup", "start": { "dateTime": "2023-01-27T09:00:00Z" },
+              "extendedProperties": { "private": { "notifyUid": "uid" } } },
+            { "id": "d", "summary": "moved", "status": "cancelled", "start": { "dateTime": "2023-01-27T09:00:00Z" } },
+            { "id": "e", "start": { "dateTime": "2023-01-27T09:00:00Z" } },
+        ])).unwrap();
+
+        let pulled = events.iter().filter_map(|event| to_reminder(event, chrono_tz::America::New_York)).collect::<Vec<_>>();
+        assert_eq!(pulled, vec![
+            ("dentist".to_string(), time("2023-01-27T09:00:00Z")),
+            ("holiday".to_string(), time("2023-01-28T14:00:00Z")),
+        ]);
+    }
+}
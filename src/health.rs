@@ -0,0 +1,266 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use chrono::Utc;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use tracing::{error, info};
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use crate::api::json_response;
+use crate::bot::BotDeps;
+use crate::errors::BotError;
+use crate::models::Env;
+
+// a loop that hasn't made progress for this long is considered stuck
+const STALE_AFTER_SECS: i64 = 60;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Task {
+    Polling,
+    Background,
+}
+
+impl Task {
+    const ALL: [Task; 2] = [Task::Polling, Task::Background];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Task::Polling => "polling",
+            Task::Background => "background",
+        }
+    }
+}
+
+// last time each long-running loop made progress, checked by the liveness probe
+#[derive(Debug)]
+pub struct Heartbeats {
+    beats: [AtomicI64; 2],
+}
+
+impl Heartbeats {
+    pub fn new() -> Heartbeats {
+        let now = Utc::now().timestamp();
+        Heartbeats { beats: [AtomicI64::new(now), AtomicI64::new(now)] }
+    }
+
+    pub fn beat(&self, task: Task) {
+        self.beats[task as usize].store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    fn stale_tasks(&self, now: i64) -> Vec<&'static str> {
+        Task::ALL.iter()
+            .filter(|task| now - self.beats[**task as usize].load(Ordering::Relaxed) > STALE_AFTER_SECS)
+            .map(Task::name)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Subsystem {
+    Parser,
+    Database,
+    Telegram,
+}
+
+impl Subsystem {
+    const ALL: [Subsystem; 3] = [Subsystem::Parser, Subsystem::Database, Subsystem::Telegram];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Subsystem::Parser => "parser",
+            Subsystem::Database => "database",
+            Subsystem::Telegram => "telegram",
+        }
+    }
+}
+
+// outcome of the latest call into each subsystem, a failed call marks it degraded until one succeeds
+#[derive(Debug)]
+pub struct SubsystemHealth {
+    // unix time of the first failure in the current streak, zero while healthy
+    failing_since: [AtomicI64; 3],
+}
+
+impl SubsystemHealth {
+    pub fn new() -> SubsystemHealth {
+        SubsystemHealth { failing_since: [AtomicI64::new(0), AtomicI64::new(0), AtomicI64::new(0)] }
+    }
+
+    pub fn record<T>(&self, subsystem: Subsystem, result: &Result<T, BotError>) {
+        let failing_since = &self.failing_since[subsystem as usize];
+        match result {
+            // running out of budget is the user's limit, not an outage
+            Ok(_) | Err(BotError::BudgetExceeded) => failing_since.store(0, Ordering::Relaxed),
+            Err(_) => {
+                let _ = failing_since.compare_exchange(0, Utc::now().timestamp(), Ordering::Relaxed, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // degraded subsystems with the time their failures started
+    pub fn degraded(&self) -> Vec<(&'static str, i64)> {
+        Subsystem::ALL.iter()
+            .map(|subsystem| (subsystem.name(), self.failing_since[*subsystem as usize].load(Ordering::Relaxed)))
+            .filter(|(_, since)| *since != 0)
+            .collect()
+    }
+
+    // short line prepended to replies while something is down, like "⚠️ parser degraded"
+    pub fn banner(&self) -> Option<String> {
+        let degraded = self.degraded();
+        if degraded.is_empty() {
+            return None;
+        }
+        let names = degraded.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ");
+        Some(format!("⚠️ {} degraded", names))
+    }
+
+    // one "name: ok" or "name: degraded for N min" line per subsystem
+    pub fn describe(&self, now: i64) -> String {
+        Subsystem::ALL.iter()
+            .map(|subsystem| match self.failing_since[*subsystem as usize].load(Ordering::Relaxed) {
+                0 => format!("{}: ok", subsystem.name()),
+                since => format!("{}: degraded for {} min", subsystem.name(), (now - since).max(0) / 60),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Liveness {
+    status: &'static str,
+    stale_tasks: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+struct SubsystemStatus {
+    name: &'static str,
+    failing_since: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+    status: &'static str,
+    degraded: Vec<SubsystemStatus>,
+}
+
+#[derive(Debug, Serialize)]
+struct Readiness {
+    status: &'static str,
+    database: String,
+    telegram: String,
+}
+
+// probe endpoints for docker and kubernetes, served when HEALTH_BIND is set
+pub struct HealthServer {
+    deps: Arc<BotDeps>,
+    address: SocketAddr,
+}
+
+impl HealthServer {
+    pub fn new(env: &Env, deps: Arc<BotDeps>) -> Result<Option<HealthServer>, BotError> {
+        let address = match &env.health_bind {
+            Some(bind) => bind.parse().map_err(|_| BotError::Usage("HEALTH_BIND should be an address like 0.0.0.0:8081"))?,
+            None => return Ok(None),
+        };
+        Ok(Some(HealthServer { deps, address }))
+    }
+
+    pub fn run_task(self) -> Result<JoinHandle<()>, BotError> {
+        let deps = self.deps;
+        let server = hyper::Server::try_bind(&self.address)?
+            .serve(make_service_fn(move |_| {
+                let deps = deps.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request| {
+                        let deps = deps.clone();
+                        async move { Ok::<_, Infallible>(handle(&deps, request).await) }
+                    }))
+                }
+            }));
+        info!("Health probes are listening on {}", self.address);
+        Ok(tokio::spawn(async move {
+            if let Err(err) = server.await {
+                error!("Health server stopped: {}", err);
+            }
+        }))
+    }
+}
+
+async fn handle(deps: &BotDeps, request: Request<Body>) -> Response<Body> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/livez") => {
+            // a standby runs none of the loops
+            let stale_tasks = if deps.is_standby() { vec![] } else { deps.heartbeats().stale_tasks(Utc::now().timestamp()) };
+            let (status, code) = if stale_tasks.is_empty() { ("ok", StatusCode::OK) } else { ("stale", StatusCode::SERVICE_UNAVAILABLE) };
+            json_response(code, &Liveness { status, stale_tasks })
+        }
+        (&Method::GET, "/healthz") => {
+            let degraded = deps.subsystems().degraded().into_iter()
+                .map(|(name, failing_since)| SubsystemStatus { name, failing_since })
+                .collect::<Vec<_>>();
+            let (status, code) = if degraded.is_empty() { ("ok", StatusCode::OK) } else { ("degraded", StatusCode::SERVICE_UNAVAILABLE) };
+            json_response(code, &Status { status, degraded })
+        }
+        (&Method::GET, "/readyz") => {
+            let (database, telegram) = tokio::join!(deps.event_repository().ping(), async {
+                // a standby never talks to telegram, so it stays ready while telegram is down
+                if deps.is_standby() { Ok(None) } else { deps.tg().get_me().await.map(Some) }
+            });
+            let (status, code) = if database.is_ok() && telegram.is_ok() { ("ok", StatusCode::OK) } else { ("unavailable", StatusCode::SERVICE_UNAVAILABLE) };
+            // request urls carry the bot key, so they are stripped from reported errors
+            let describe = |result: Result<(), BotError>| match result {
+                Ok(_) => "ok".to_string(),
+                Err(BotError::Reqwest(err)) => err.without_url().to_string(),
+                Err(err) => err.to_string(),
+            };
+            json_response(code, &Readiness { status, database: describe(database), telegram: match telegram {
+                Ok(None) => "not used on standby".to_string(),
+                telegram => describe(telegram.map(|_| ())),
+            } })
+        }
+        _ => json_response(StatusCode::NOT_FOUND, &serde_json::json!({ "error": "not found" })),
+    };
+    response.unwrap_or_else(|err| {
+        error!("Error in health handler: {}", err);
+        Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body(Body::empty()).unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use chrono::Utc;
+    use crate::errors::BotError;
+    use super::{Heartbeats, Subsystem, SubsystemHealth, Task};
+
+    #[test]
+    fn should_report_loops_without_recent_heartbeat() {
+        let heartbeats = Heartbeats::new();
+        let now = Utc::now().timestamp();
+        assert!(heartbeats.stale_tasks(now).is_empty());
+
+        heartbeats.beats[Task::Background as usize].store(now - 120, Ordering::Relaxed);
+        assert_eq!(heartbeats.stale_tasks(now), vec!["background"]);
+        heartbeats.beat(Task::Background);
+        assert!(heartbeats.stale_tasks(now).is_empty());
+    }
+
+    #[test]
+    fn should_degrade_subsystem_until_it_recovers() {
+        let health = SubsystemHealth::new();
+        assert_eq!(health.banner(), None);
+
+        health.record::<()>(Subsystem::Parser, &Err(BotError::InvalidCallbackQuery));
+        health.record::<()>(Subsystem::Telegram, &Err(BotError::BudgetExceeded));
+        assert_eq!(health.banner().as_deref(), Some("⚠️ parser degraded"));
+        let since = health.degraded()[0].1;
+        assert!(health.describe(since + 300).starts_with("parser: degraded for 5 min\ndatabase: ok"));
+
+        health.record(Subsystem::Parser, &Ok(()));
+        assert_eq!(health.banner(), None);
+    }
+}
@@ -0,0 +1,82 @@
+use chrono::NaiveDate;
+use crate::db::Holiday;
+use crate::errors::BotError;
+
+// days off most users of the bot have, the jewish ones move every year so they are listed by date;
+// countries missing here or years past these come from HOLIDAYS_FILE
+const EMBEDDED: [(&str, &str, &str); 27] = [
+    ("IL", "2025-04-13", "Pesach"),
+    ("IL", "2025-04-19", "Pesach VII"),
+    ("IL", "2025-05-01", "Yom HaAtzmaut"),
+    ("IL", "2025-06-02", "Shavuot"),
+    ("IL", "2025-09-23", "Rosh Hashana"),
+    ("IL", "2025-09-24", "Rosh Hashana II"),
+    ("IL", "2025-10-02", "Yom Kippur"),
+    ("IL", "2025-10-07", "Sukkot"),
+    ("IL", "2025-10-14", "Simchat Torah"),
+    ("IL", "2026-04-02", "Pesach"),
+    ("IL", "2026-04-08", "Pesach VII"),
+    ("IL", "2026-04-22", "Yom HaAtzmaut"),
+    ("IL", "2026-05-22", "Shavuot"),
+    ("IL", "2026-09-12", "Rosh Hashana"),
+    ("IL", "2026-09-13", "Rosh Hashana II"),
+    ("IL", "2026-09-21", "Yom Kippur"),
+    ("IL", "2026-09-26", "Sukkot"),
+    ("IL", "2026-10-03", "Simchat Torah"),
+    ("IL", "2027-04-22", "Pesach"),
+    ("IL", "2027-04-28", "Pesach VII"),
+    ("IL", "2027-05-12", "Yom HaAtzmaut"),
+    ("IL", "2027-06-11", "Shavuot"),
+    ("IL", "2027-10-02", "Rosh Hashana"),
+    ("IL", "2027-10-03", "Rosh Hashana II"),
+    ("IL", "2027-10-11", "Yom Kippur"),
+    ("IL", "2027-10-16", "Sukkot"),
+    ("IL", "2027-10-23", "Simchat Torah"),
+];
+
+// "YYYY-MM-DD name" per line, blank lines and lines starting with # are skipped
+pub fn parse(country: &str, content: &str) -> Result<Vec<Holiday>, BotError> {
+    content.lines()
+        .enumerate()
+        .map(|(number, line)| (number + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            let (day, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match NaiveDate::parse_from_str(day, "%Y-%m-%d") {
+                Ok(day) => Ok(Holiday { country: country.to_string(), day, name: name.trim().to_string() }),
+                Err(_) => Err(BotError::Config(format!("holidays line {}: expected YYYY-MM-DD name", number))),
+            }
+        })
+        .collect()
+}
+
+// the embedded days of the country with the ones of the file on top, the file wins on the same day
+pub fn calendar(country: &str, file: Option<&str>) -> Result<Vec<Holiday>, BotError> {
+    let country = country.to_uppercase();
+    let mut holidays = EMBEDDED.iter()
+        .filter(|(embedded, _, _)| *embedded == country)
+        .filter_map(|(_, day, name)| Some(Holiday { country: country.clone(), day: day.parse().ok()?, name: name.to_string() }))
+        .collect::<Vec<_>>();
+    if let Some(path) = file {
+        holidays.extend(parse(&country, &std::fs::read_to_string(path)?)?);
+    }
+    Ok(holidays)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use super::{calendar, parse, EMBEDDED};
+
+    #[test]
+    fn should_read_embedded_and_file_holidays() {
+        assert!(EMBEDDED.iter().all(|(_, day, _)| day.parse::<NaiveDate>().is_ok()));
+        assert_eq!(calendar("il", None).unwrap().len(), EMBEDDED.len());
+        assert!(calendar("DE", None).unwrap().is_empty());
+
+        let holidays = parse("DE", "# germany\n2026-12-25 Weihnachten\n\n2026-12-26  2. Weihnachtstag\n").unwrap();
+        let days = holidays.iter().map(|holiday| (holiday.day.to_string(), holiday.name.as_str())).collect::<Vec<_>>();
+        assert_eq!(days, [("2026-12-25".to_string(), "Weihnachten"), ("2026-12-26".to_string(), "2. Weihnachtstag")]);
+        assert!(parse("DE", "25.12.2026 Weihnachten").is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use std::str::FromStr;
+use chrono::{DateTime, Datelike, NaiveDate, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use crate::errors::BotError;
+use crate::models::shift_weekly;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Ru,
+    He,
+}
+
+impl Locale {
+    // telegram sends IETF language tags like "en", "ru" or "pt-br", older clients send "iw" for hebrew
+    pub fn from_language_code(code: Option<&str>) -> Locale {
+        match code.and_then(|code| code.split('-').next()) {
+            Some("ru") => Locale::Ru,
+            Some("he" | "iw") => Locale::He,
+            _ => Locale::En,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Ru => "ru",
+            Locale::He => "he",
+        }
+    }
+}
+
+impl FromStr for Locale {
+    type Err = BotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "en" => Ok(Locale::En),
+            "ru" => Ok(Locale::Ru),
+            "he" => Ok(Locale::He),
+            _ => Err(BotError::UnknownLocale(s.to_string())),
+        }
+    }
+}
+
+const EN_WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const RU_WEEKDAYS: [&str; 7] = ["Пн", "Вт", "Ср", "Чт", "Пт", "Сб", "Вс"];
+const EN_MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+const RU_MONTHS: [&str; 12] = ["янв", "фев", "мар", "апр", "мая", "июн", "июл", "авг", "сен", "окт", "ноя", "дек"];
+// plural weekday names, as in "every Monday" and "по понедельникам"
+const EN_EVERY_WEEKDAY: [&str; 7] = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+const RU_EVERY_WEEKDAY: [&str; 7] = ["понедельникам", "вторникам", "средам", "четвергам", "пятницам", "субботам", "воскресеньям"];
+const HE_WEEKDAYS: [&str; 7] = ["ב׳", "ג׳", "ד׳", "ה׳", "ו׳", "ש׳", "א׳"];
+const HE_MONTHS: [&str; 12] = ["ינו׳", "פבר׳", "מרץ", "אפר׳", "מאי", "יוני", "יולי", "אוג׳", "ספט׳", "אוק׳", "נוב׳", "דצמ׳"];
+// as in "בימי שני, רביעי"
+const HE_EVERY_WEEKDAY: [&str; 7] = ["שני", "שלישי", "רביעי", "חמישי", "שישי", "שבת", "ראשון"];
+
+// renders time in the timezone, like "tomorrow at 15:00" or "Fri, 26 Jul 15:00 (in 3 days)"
+pub fn format_time(time: DateTime<Utc>, now: DateTime<Utc>, timezone: Tz, locale: Locale) -> String {
+    let local = timezone.from_utc_datetime(&time.naive_utc());
+    let today = timezone.from_utc_datetime(&now.naive_utc()).date_naive();
+    let days = (local.date_naive() - today).num_days();
+    let clock = local.format("%H:%M").to_string();
+    match (days, locale) {
+        (-1, Locale::En) => return format!("yesterday at {}", clock),
+        (-1, Locale::Ru) => return format!("вчера в {}", clock),
+        (0, Locale::En) => return format!("today at {}", clock),
+        (0, Locale::Ru) => return format!("сегодня в {}", clock),
+        (1, Locale::En) => return format!("tomorrow at {}", clock),
+        (1, Locale::Ru) => return format!("завтра в {}", clock),
+        (-1, Locale::He) => return format!("אתמול ב-{}", clock),
+        (0, Locale::He) => return format!("היום ב-{}", clock),
+        (1, Locale::He) => return format!("מחר ב-{}", clock),
+        _ => {}
+    }
+
+    let (weekdays, months) = match locale {
+        Locale::En => (EN_WEEKDAYS, EN_MONTHS),
+        Locale::Ru => (RU_WEEKDAYS, RU_MONTHS),
+        Locale::He => (HE_WEEKDAYS, HE_MONTHS),
+    };
+    let weekday = weekdays[local.weekday().num_days_from_monday() as usize];
+    let month = months[local.month0() as usize];
+    let mut text = format!("{}, {} {}", weekday, local.day(), month);
+    if local.year() != today.year() {
+        text = format!("{} {}", text, local.year());
+    }
+    text = format!("{} {}", text, clock);
+    if (2..7).contains(&days) {
+        text = match locale {
+            Locale::En => format!("{} (in {} days)", text, days),
+            Locale::Ru => format!("{} (через {} {})", text, days, ru_days(days)),
+            Locale::He => format!("{} (בעוד {} ימים)", text, days),
+        };
+    }
+    text
+}
+
+// bars longer than this are cut, busy days are still told apart by the count next to them
+const MAX_LOAD_BAR: u32 = 10;
+
+// minutes the timezone is ahead of utc at the given moment
+pub fn offset_minutes(timezone: Tz, now: DateTime<Utc>) -> i32 {
+    timezone.offset_from_utc_datetime(&now.naive_utc()).fix().local_minus_utc() / 60
+}
+
+// renders a weekly utc schedule in the timezone, days are numbered from 1 (Monday) to 7
+pub fn format_weekly(days: &[u8], hours: u8, minutes: u8, now: DateTime<Utc>, timezone: Tz, locale: Locale) -> String {
+    let (mut days, hours, minutes) = shift_weekly(days, hours, minutes, offset_minutes(timezone, now));
+    days.sort_unstable();
+    let names = match locale {
+        Locale::En => EN_EVERY_WEEKDAY,
+        Locale::Ru => RU_EVERY_WEEKDAY,
+        Locale::He => HE_EVERY_WEEKDAY,
+    };
+    let every_day = days.is_empty() || (1..=7).all(|day| days.contains(&day));
+    let days = days.iter()
+        .filter_map(|day| names.get((*day as usize).wrapping_sub(1)))
+        .copied()
+        .collect::<Vec<_>>()
+        .join(", ");
+    match (locale, every_day) {
+        (Locale::En, true) => format!("every day at {:02}:{:02}", hours, minutes),
+        (Locale::Ru, true) => format!("каждый день в {:02}:{:02}", hours, minutes),
+        (Locale::En, false) => format!("every {} at {:02}:{:02}", days, hours, minutes),
+        (Locale::Ru, false) => format!("по {} в {:02}:{:02}", days, hours, minutes),
+        (Locale::He, true) => format!("כל יום ב-{:02}:{:02}", hours, minutes),
+        (Locale::He, false) => format!("בימי {} ב-{:02}:{:02}", days, hours, minutes),
+    }
+}
+
+// a line of blocks per day, like "Fri, 26 Jul ███ 3", so overloaded days stand out
+pub fn format_load(days: &[(NaiveDate, u32)], locale: Locale) -> String {
+    let (mut text, weekdays, months) = match locale {
+        Locale::En => (format!("Reminders in the next {} days:", days.len()), EN_WEEKDAYS, EN_MONTHS),
+        Locale::Ru => (format!("Напоминания на ближайшие {} {}:", days.len(), ru_days(days.len() as i64)), RU_WEEKDAYS, RU_MONTHS),
+        Locale::He => (format!("תזכורות ב-{} הימים הקרובים:", days.len()), HE_WEEKDAYS, HE_MONTHS),
+    };
+    for (date, count) in days {
+        let bar = match *count {
+            0 => "·".to_string(),
+            count if count > MAX_LOAD_BAR => format!("{}+", "█".repeat(MAX_LOAD_BAR as usize)),
+            count => "█".repeat(count as usize),
+        };
+        let weekday = weekdays[date.weekday().num_days_from_monday() as usize];
+        let month = months[date.month0() as usize];
+        text.push_str(&format!("\n{}, {} {} {} {}", weekday, date.day(), month, bar, count));
+    }
+    text
+}
+
+// reminders grouped by day in the timezone, one "HH:MM text" line per occurrence
+pub fn format_agenda(heading: &str, entries: &[(DateTime<Utc>, &str)], timezone: Tz, locale: Locale) -> String {
+    if entries.is_empty() {
+        return match locale {
+            Locale::En => format!("{}\nNothing planned", heading),
+            Locale::Ru => format!("{}\nНичего не запланировано", heading),
+            Locale::He => format!("{}\nאין שום דבר מתוכנן", heading),
+        };
+    }
+    let (weekdays, months) = match locale {
+        Locale::En => (EN_WEEKDAYS, EN_MONTHS),
+        Locale::Ru => (RU_WEEKDAYS, RU_MONTHS),
+        Locale::He => (HE_WEEKDAYS, HE_MONTHS),
+    };
+    let mut text = heading.to_string();
+    let mut current_date = None;
+    for (time, entry) in entries {
+        let local = timezone.from_utc_datetime(&time.naive_utc());
+        if current_date != Some(local.date_naive()) {
+            current_date = Some(local.date_naive());
+            let weekday = weekdays[local.weekday().num_days_from_monday() as usize];
+            text.push_str(&format!("\n\n{}, {} {}", weekday, local.day(), months[local.month0() as usize]));
+        }
+        text.push_str(&format!("\n{} {}", local.format("%H:%M"), entry));
+    }
+    text
+}
+
+// a lead time in the largest whole unit, like "30 minutes", "2 hours" or "1 day"
+pub fn format_duration(minutes: u32, locale: Locale) -> String {
+    let minutes = minutes as i64;
+    let (count, unit) = if minutes > 0 && minutes % (24 * 60) == 0 {
+        (minutes / (24 * 60), 2)
+    } else if minutes > 0 && minutes % 60 == 0 {
+        (minutes / 60, 1)
+    } else {
+        (minutes, 0)
+    };
+    let name = match (locale, unit) {
+        (Locale::En, 0) => if count == 1 { "minute" } else { "minutes" },
+        (Locale::En, 1) => if count == 1 { "hour" } else { "hours" },
+        (Locale::En, _) => if count == 1 { "day" } else { "days" },
+        (Locale::Ru, 0) => ru_plural(count, ["минуту", "минуты", "минут"]),
+        (Locale::Ru, 1) => ru_plural(count, ["час", "часа", "часов"]),
+        (Locale::Ru, _) => ru_days(count),
+        (Locale::He, 0) => if count == 1 { "דקה" } else { "דקות" },
+        (Locale::He, 1) => if count == 1 { "שעה" } else { "שעות" },
+        (Locale::He, _) => if count == 1 { "יום" } else { "ימים" },
+    };
+    format!("{} {}", count, name)
+}
+
+pub fn ru_days(days: i64) -> &'static str {
+    ru_plural(days, ["день", "дня", "дней"])
+}
+
+fn ru_plural(count: i64, [one, few, many]: [&'static str; 3]) -> &'static str {
+    match (count % 10, count % 100) {
+        (1, n) if n != 11 => one,
+        (2..=4, n) if !(12..=14).contains(&n) => few,
+        _ => many,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, NaiveDate, Utc};
+    use super::{format_agenda, format_duration, format_load, format_time, format_weekly, Locale};
+
+    fn time(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn should_use_relative_phrasing_for_near_days() {
+        let now = time("2024-07-23T09:00:00Z");
+
+        assert_eq!(format_time(time("2024-07-23T12:00:00Z"), now, chrono_tz::Israel, Locale::En), "today at 15:00");
+        assert_eq!(format_time(time("2024-07-24T12:00:00Z"), now, chrono_tz::Israel, Locale::Ru), "завтра в 15:00");
+        assert_eq!(format_time(time("2024-07-26T12:00:00Z"), now, chrono_tz::Israel, Locale::En), "Fri, 26 Jul 15:00 (in 3 days)");
+        assert_eq!(format_time(time("2024-07-26T12:00:00Z"), now, chrono_tz::Israel, Locale::Ru), "Пт, 26 июл 15:00 (через 3 дня)");
+        assert_eq!(format_time(time("2024-07-28T12:00:00Z"), now, chrono_tz::Israel, Locale::Ru), "Вс, 28 июл 15:00 (через 5 дней)");
+        assert_eq!(format_time(time("2024-08-30T12:00:00Z"), now, chrono_tz::Israel, Locale::En), "Fri, 30 Aug 15:00");
+        assert_eq!(format_time(time("2025-01-03T12:00:00Z"), now, chrono_tz::Israel, Locale::En), "Fri, 3 Jan 2025 14:00");
+        assert_eq!(format_time(time("2024-07-24T12:00:00Z"), now, chrono_tz::Israel, Locale::He), "מחר ב-15:00");
+        // 02:00 utc is still yesterday evening in New York
+        assert_eq!(format_time(time("2024-07-23T02:00:00Z"), now, chrono_tz::America::New_York, Locale::En), "yesterday at 22:00");
+    }
+
+    #[test]
+    fn should_format_weekly_schedules_in_timezone() {
+        let winter = time("2024-01-10T09:00:00Z");
+        let summer = time("2024-07-10T09:00:00Z");
+
+        assert_eq!(format_weekly(&[1, 3], 7, 5, winter, chrono_tz::Israel, Locale::En), "every Monday, Wednesday at 09:05");
+        assert_eq!(format_weekly(&[5], 15, 0, summer, chrono_tz::Israel, Locale::Ru), "по пятницам в 18:00");
+        assert_eq!(format_weekly(&[1, 2, 3, 4, 5, 6, 7], 6, 0, winter, chrono_tz::Israel, Locale::En), "every day at 08:00");
+        assert_eq!(format_weekly(&[7], 23, 30, winter, chrono_tz::Israel, Locale::En), "every Monday at 01:30");
+        assert_eq!(format_weekly(&[1], 3, 0, winter, chrono_tz::America::New_York, Locale::En), "every Sunday at 22:00");
+    }
+
+    #[test]
+    fn should_format_lead_times_in_largest_unit() {
+        assert_eq!(format_duration(30, Locale::En), "30 minutes");
+        assert_eq!(format_duration(60, Locale::En), "1 hour");
+        assert_eq!(format_duration(90, Locale::En), "90 minutes");
+        assert_eq!(format_duration(2 * 24 * 60, Locale::En), "2 days");
+        assert_eq!(format_duration(21, Locale::Ru), "21 минуту");
+        assert_eq!(format_duration(180, Locale::Ru), "3 часа");
+    }
+
+    #[test]
+    fn should_draw_a_bar_per_day() {
+        let date = |value: &str| NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap();
+        let days = [(date("2024-07-26"), 3), (date("2024-07-27"), 0), (date("2024-07-28"), 12)];
+        assert_eq!(format_load(&days, Locale::En), "Reminders in the next 3 days:\n\
+            Fri, 26 Jul ███ 3\n\
+            Sat, 27 Jul · 0\n\
+            Sun, 28 Jul ██████████+ 12");
+        assert!(format_load(&days[..1], Locale::Ru).starts_with("Напоминания на ближайшие 1 день:\nПт, 26 июл"));
+    }
+
+    #[test]
+    fn should_detect_locale_from_language_code() {
+        assert_eq!(Locale::from_language_code(Some("ru")), Locale::Ru);
+        assert_eq!(Locale::from_language_code(Some("en-US")), Locale::En);
+        assert_eq!(Locale::from_language_code(None), Locale::En);
+        assert_eq!(Locale::from_language_code(Some("iw")), Locale::He);
+        assert_eq!("he".parse::<Locale>().unwrap(), Locale::He);
+        assert!("de".parse::<Locale>().is_err());
+    }
+
+    #[test]
+    fn should_group_agenda_by_local_day() {
+        let entries = [
+            (time("2024-07-23T06:00:00Z"), "pills"),
+            (time("2024-07-23T21:30:00Z"), "call mom"),
+        ];
+
+        assert_eq!(format_agenda("Next 7 days:", &entries, chrono_tz::Israel, Locale::En),
+                   "Next 7 days:\n\nTue, 23 Jul\n09:00 pills\n\nWed, 24 Jul\n00:30 call mom");
+        assert_eq!(format_agenda("Next 7 days:", &entries, chrono_tz::Europe::London, Locale::En),
+                   "Next 7 days:\n\nTue, 23 Jul\n07:00 pills\n22:30 call mom");
+        assert_eq!(format_agenda("Today:", &[], chrono_tz::Israel, Locale::En), "Today:\nNothing planned");
+    }
+}
@@ -0,0 +1,337 @@
+use crate::humanize::Locale;
+
+// fixed texts of buttons and replies; texts with values in them have their own functions below
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Phrase {
+    Accept,
+    Repeat,
+    Cancel,
+    KeepBoth,
+    RemindAgain,
+    Done,
+    SkipNext,
+    InOneDay,
+    InOneWeek,
+    Snooze,
+    NotificationAccepted,
+    NotificationDeleted,
+    RequestRepeated,
+    ParseFailed,
+    AcceptWithErrors,
+    AlreadyAccepted,
+    DraftNotPending,
+    ScheduleConflict,
+    CalendarImported,
+    MarkedAsDone,
+    AlreadyDone,
+    AlreadyCancelled,
+    NotRepeating,
+    ReadOnly,
+    QuietHoursDigest,
+    LowPriority,
+    Urgent,
+    RepeatedUntilDone,
+    LocationHeld,
+    ForwardHeld,
+    CreateReminder,
+    SeveralMeanings,
+    Edit,
+    EditPrompt,
+    EditFailed,
+    DraftUpdated,
+    PickDate,
+    PickHour,
+    PickMinute,
+    TimePassed,
+    Silently,
+    WithSound,
+    Silent,
+    SendLocationForTimezone,
+    NoClosedReminders,
+    OutcomeCompleted,
+    OutcomeMissed,
+    OutcomeCancelled,
+    SkipHolidays,
+    OnHolidaysToo,
+}
+
+#[cfg(test)]
+const PHRASES: [Phrase; 50] = [
+    Phrase::Accept, Phrase::Repeat, Phrase::Cancel, Phrase::KeepBoth, Phrase::RemindAgain, Phrase::Done, Phrase::SkipNext,
+    Phrase::InOneDay, Phrase::InOneWeek, Phrase::Snooze, Phrase::NotificationAccepted, Phrase::NotificationDeleted,
+    Phrase::RequestRepeated, Phrase::ParseFailed, Phrase::AcceptWithErrors, Phrase::AlreadyAccepted, Phrase::DraftNotPending,
+    Phrase::ScheduleConflict, Phrase::CalendarImported, Phrase::MarkedAsDone, Phrase::AlreadyDone, Phrase::AlreadyCancelled,
+    Phrase::NotRepeating, Phrase::ReadOnly, Phrase::QuietHoursDigest, Phrase::LowPriority, Phrase::Urgent, Phrase::RepeatedUntilDone,
+    Phrase::LocationHeld, Phrase::ForwardHeld, Phrase::CreateReminder, Phrase::SeveralMeanings,
+    Phrase::Edit, Phrase::EditPrompt, Phrase::EditFailed, Phrase::DraftUpdated, Phrase::PickDate, Phrase::PickHour, Phrase::PickMinute,
+    Phrase::TimePassed, Phrase::Silently, Phrase::WithSound, Phrase::Silent, Phrase::SendLocationForTimezone,
+    Phrase::NoClosedReminders, Phrase::OutcomeCompleted, Phrase::OutcomeMissed, Phrase::OutcomeCancelled,
+    Phrase::SkipHolidays, Phrase::OnHolidaysToo,
+];
+
+pub fn tr(phrase: Phrase, locale: Locale) -> &'static str {
+    let [en, ru, he] = match phrase {
+        Phrase::Accept => ["Accept", "Принять", "אישור"],
+        Phrase::Repeat => ["Repeat", "Повторить", "שוב"],
+        Phrase::Cancel => ["Cancel", "Отменить", "ביטול"],
+        Phrase::KeepBoth => ["Keep both", "Оставить оба", "להשאיר את שתיהן"],
+        Phrase::RemindAgain => ["Remind again…", "Напомнить ещё…", "להזכיר שוב…"],
+        Phrase::Done => ["Done", "Готово", "בוצע"],
+        Phrase::SkipNext => ["Skip next", "Пропустить следующее", "לדלג על הבאה"],
+        Phrase::InOneDay => ["In 1 day", "Через день", "בעוד יום"],
+        Phrase::InOneWeek => ["In 1 week", "Через неделю", "בעוד שבוע"],
+        Phrase::Snooze => ["Snooze…", "Отложить…", "לדחות…"],
+        Phrase::NotificationAccepted => ["Notification accepted", "Напоминание сохранено", "התזכורת נשמרה"],
+        Phrase::NotificationDeleted => ["Notification deleted", "Напоминание удалено", "התזכורת נמחקה"],
+        Phrase::RequestRepeated => ["Request was repeated", "Запрос повторён", "הבקשה נשלחה שוב"],
+        Phrase::ParseFailed => ["Error while parsing command", "Не удалось разобрать запрос", "לא הצלחתי להבין את הבקשה"],
+        Phrase::AcceptWithErrors => ["Impossible to accept notification with errors", "Нельзя принять напоминание с ошибками", "אי אפשר לאשר תזכורת עם שגיאות"],
+        Phrase::AlreadyAccepted => ["Already accepted", "Уже принято", "כבר אושר"],
+        Phrase::DraftNotPending => ["This draft is no longer pending", "Этот черновик больше не ждёт ответа", "הטיוטה הזאת כבר לא ממתינה"],
+        Phrase::ScheduleConflict => ["Schedule conflict", "Пересечение в расписании", "התנגשות בלוח הזמנים"],
+        Phrase::CalendarImported => ["Calendar imported", "Календарь импортирован", "היומן יובא"],
+        Phrase::MarkedAsDone => ["Marked as done", "Отмечено как выполненное", "סומן כבוצע"],
+        Phrase::AlreadyDone => ["Already done", "Уже выполнено", "כבר בוצע"],
+        Phrase::AlreadyCancelled => ["This reminder was already cancelled", "Это напоминание уже отменено", "התזכורת הזאת כבר בוטלה"],
+        Phrase::NotRepeating => ["This reminder doesn't repeat anymore", "Это напоминание больше не повторяется", "התזכורת הזאת כבר לא חוזרת"],
+        Phrase::ReadOnly => ["You have read-only access, ask an admin to let you create reminders",
+                             "У вас доступ только на чтение, попросите администратора разрешить создавать напоминания",
+                             "יש לך גישת קריאה בלבד, אפשר לבקש ממנהל הרשאה ליצור תזכורות"],
+        Phrase::QuietHoursDigest => ["While quiet hours were on:", "Пока действовали тихие часы:", "בזמן השעות השקטות:"],
+        Phrase::LowPriority => ["low priority, silent", "низкий приоритет, без звука", "עדיפות נמוכה, בשקט"],
+        Phrase::Urgent => ["urgent", "срочно", "דחוף"],
+        Phrase::RepeatedUntilDone => ["repeated until done", "повторять до выполнения", "חוזר עד לביצוע"],
+        Phrase::LocationHeld => ["Got the location, now send what to remind you about there and when",
+                                 "Место получено, теперь напишите, о чём и когда там напомнить",
+                                 "קיבלתי את המיקום, עכשיו כתבו על מה ומתי להזכיר שם"],
+        Phrase::CreateReminder => ["Create reminder", "Создать напоминание", "ליצור תזכורת"],
+        Phrase::Edit => ["Edit…", "Изменить…", "לערוך…"],
+        Phrase::EditPrompt => ["What should be changed? Describe it or send the corrected json",
+                               "Что изменить? Опишите словами или пришлите исправленный json",
+                               "מה לשנות? תארו במילים או שלחו json מתוקן"],
+        Phrase::EditFailed => ["Couldn't apply the change", "Не удалось применить изменение", "לא הצלחתי להחיל את השינוי"],
+        Phrase::DraftUpdated => ["Draft updated, accept it above", "Черновик обновлён, примите его выше", "הטיוטה עודכנה, אפשר לאשר אותה למעלה"],
+        Phrase::PickDate => ["I still couldn't understand it, pick the date:", "Так и не удалось разобрать, выберите дату:", "עדיין לא הצלחתי להבין, בחרו תאריך:"],
+        Phrase::PickHour => ["Pick the hour:", "Выберите час:", "בחרו שעה:"],
+        Phrase::PickMinute => ["Pick the minutes:", "Выберите минуты:", "בחרו דקות:"],
+        Phrase::SkipHolidays => ["🏖 Skip holidays", "🏖 Кроме праздников", "🏖 לדלג על חגים"],
+        Phrase::OnHolidaysToo => ["📅 On holidays too", "📅 И в праздники", "📅 גם בחגים"],
+        Phrase::NoClosedReminders => ["No closed reminders yet", "Завершённых напоминаний пока нет", "אין עדיין תזכורות שהסתיימו"],
+        Phrase::OutcomeCompleted => ["✅ completed", "✅ выполнено", "✅ בוצע"],
+        Phrase::OutcomeMissed => ["⚠️ missed", "⚠️ пропущено", "⚠️ הוחמץ"],
+        Phrase::OutcomeCancelled => ["✖️ cancelled", "✖️ отменено", "✖️ בוטל"],
+        Phrase::SendLocationForTimezone => ["Send a location pin and I'll set the timezone from it, or /timezone <name> like /timezone Europe/Berlin",
+            "Отправьте геопозицию, и я определю по ней часовой пояс, или /timezone <название>, например /timezone Europe/Berlin",
+            "שלחו מיקום ואקבע לפיו את אזור הזמן, או /timezone <שם>, למשל /timezone Europe/Berlin"],
+        Phrase::Silently => ["🔕 Send silently", "🔕 Без звука", "🔕 לשלוח בשקט"],
+        Phrase::WithSound => ["🔔 Send with sound", "🔔 Со звуком", "🔔 לשלוח עם צליל"],
+        Phrase::Silent => ["silent", "без звука", "בשקט"],
+        Phrase::TimePassed => ["This time has already passed", "Это время уже прошло", "השעה הזאת כבר עברה"],
+        Phrase::SeveralMeanings => ["This can be read in several ways, pick one:", "Это можно понять по-разному, выберите вариант:", "אפשר להבין את זה בכמה דרכים, בחרו אחת:"],
+        Phrase::ForwardHeld => ["Got the forwarded message, now tell me when to remind you about it",
+                                "Пересланное сообщение получено, теперь напишите, когда о нём напомнить",
+                                "קיבלתי את ההודעה המועברת, עכשיו כתבו מתי להזכיר עליה"],
+    };
+    match locale {
+        Locale::En => en,
+        Locale::Ru => ru,
+        Locale::He => he,
+    }
+}
+
+// calendar columns, the week starts on monday
+pub fn weekdays(locale: Locale) -> [&'static str; 7] {
+    match locale {
+        Locale::En => ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"],
+        Locale::Ru => ["Пн", "Вт", "Ср", "Чт", "Пт", "Сб", "Вс"],
+        Locale::He => ["ב׳", "ג׳", "ד׳", "ה׳", "ו׳", "ש׳", "א׳"],
+    }
+}
+
+pub fn accept_option(option: usize, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("Accept {}", option),
+        Locale::Ru => format!("Принять {}", option),
+        Locale::He => format!("אישור {}", option),
+    }
+}
+
+pub fn due_digest(count: usize, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("{} reminders are due:", count),
+        Locale::Ru => format!("Сработали напоминания ({}):", count),
+        Locale::He => format!("{} תזכורות הגיעו:", count),
+    }
+}
+
+// the duration is already formatted for the locale
+pub fn lead_reminder(duration: &str, text: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("In {}: {}", duration, text),
+        Locale::Ru => format!("Через {}: {}", duration, text),
+        Locale::He => format!("בעוד {}: {}", duration, text),
+    }
+}
+
+pub fn overdue_by(duration: &str, text: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("Overdue by {}: {}", duration, text),
+        Locale::Ru => format!("Просрочено на {}: {}", duration, text),
+        Locale::He => format!("באיחור של {}: {}", duration, text),
+    }
+}
+
+pub fn missed_notice(text: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("You missed: {}", text),
+        Locale::Ru => format!("Вы пропустили: {}", text),
+        Locale::He => format!("פספסת: {}", text),
+    }
+}
+
+pub fn upcoming_heading(count: usize, timezone: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("Next {} reminders, {}:", count, timezone),
+        Locale::Ru => format!("Ближайшие напоминания ({}), {}:", count, timezone),
+        Locale::He => format!("{} התזכורות הקרובות, {}:", count, timezone),
+    }
+}
+
+pub fn timezone_is(name: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("Your timezone is {}", name),
+        Locale::Ru => format!("Ваш часовой пояс: {}", name),
+        Locale::He => format!("אזור הזמן שלך: {}", name),
+    }
+}
+
+pub fn timezone_set(name: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("Timezone set to {}", name),
+        Locale::Ru => format!("Часовой пояс изменён на {}", name),
+        Locale::He => format!("אזור הזמן שונה ל-{}", name),
+    }
+}
+
+pub fn timezone_found(name: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("This location is probably in {}. Accept it, or send /timezone <name> if it's another one", name),
+        Locale::Ru => format!("Похоже, это часовой пояс {}. Примите его или отправьте /timezone <название>, если он другой", name),
+        Locale::He => format!("כנראה שזה אזור הזמן {}. אשרו אותו, או שלחו /timezone <שם> אם הוא אחר", name),
+    }
+}
+
+pub fn expires_after(duration: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("expires {} after", duration),
+        Locale::Ru => format!("истекает через {}", duration),
+        Locale::He => format!("פג תוקף אחרי {}", duration),
+    }
+}
+
+pub fn missed_summary(count: usize, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("You missed {} reminders while I was offline:", count),
+        Locale::Ru => format!("Пока я был недоступен, вы пропустили напоминания ({}):", count),
+        Locale::He => format!("פספסת {} תזכורות בזמן שלא הייתי זמין:", count),
+    }
+}
+
+pub fn heads_up(duration: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("heads-up {} before", duration),
+        Locale::Ru => format!("предупредить за {}", duration),
+        Locale::He => format!("התראה {} לפני", duration),
+    }
+}
+
+pub fn every_until_done(duration: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("every {} until done", duration),
+        Locale::Ru => format!("каждые {} до выполнения", duration),
+        Locale::He => format!("כל {} עד לביצוע", duration),
+    }
+}
+
+pub fn shift_by(minutes: i64, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("Shift by {} min", minutes),
+        Locale::Ru => format!("Сдвинуть на {} мин", minutes),
+        Locale::He => format!("להזיז ב-{} דק׳", minutes),
+    }
+}
+
+// the time is already formatted for the locale
+pub fn remind_again_at(time: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("I will remind you again {}", time),
+        Locale::Ru => format!("Напомню ещё раз {}", time),
+        Locale::He => format!("אזכיר לך שוב {}", time),
+    }
+}
+
+pub fn skipping(time: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("Skipping {}", time),
+        Locale::Ru => format!("Пропускаю {}", time),
+        Locale::He => format!("מדלג על {}", time),
+    }
+}
+
+pub fn snooze_prompt(text: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("When should I remind you about \"{}\" again?", text),
+        Locale::Ru => format!("Когда напомнить про «{}» ещё раз?", text),
+        Locale::He => format!("מתי להזכיר לך שוב על \"{}\"?", text),
+    }
+}
+
+pub fn cancelled(text: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("Cancelled \"{}\"", text),
+        Locale::Ru => format!("Отменено «{}»", text),
+        Locale::He => format!("\"{}\" בוטלה", text),
+    }
+}
+
+pub fn template_value(placeholder: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("What should go in place of {{{}}}?", placeholder),
+        Locale::Ru => format!("Что подставить вместо {{{}}}?", placeholder),
+        Locale::He => format!("מה לשים במקום {{{}}}?", placeholder),
+    }
+}
+
+// the next time is already formatted for the locale
+pub fn cron_schedule(expression: &str, next: &str, locale: Locale) -> String {
+    match locale {
+        Locale::En => format!("cron \"{}\", next {}", expression, next),
+        Locale::Ru => format!("cron «{}», следующее {}", expression, next),
+        Locale::He => format!("cron \"{}\", הבא {}", expression, next),
+    }
+}
+
+pub fn language_set(locale: Option<Locale>) -> &'static str {
+    match locale {
+        Some(Locale::En) => "Replies are in English now",
+        Some(Locale::Ru) => "Теперь я отвечаю по-русски",
+        Some(Locale::He) => "מעכשיו אענה בעברית",
+        None => "The language follows your Telegram settings now",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tr, PHRASES};
+    use crate::humanize::Locale;
+
+    #[test]
+    fn should_translate_every_phrase() {
+        for phrase in PHRASES {
+            let texts = [Locale::En, Locale::Ru, Locale::He].map(|locale| tr(phrase, locale));
+            assert!(texts.iter().all(|text| !text.is_empty()), "{:?}", phrase);
+            assert_ne!(texts[0], texts[1], "{:?}", phrase);
+            assert_ne!(texts[0], texts[2], "{:?}", phrase);
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use std::str::FromStr;
+use arrayvec::ArrayVec;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone, Timelike, Utc};
+use chrono_tz::{OffsetComponents, Tz};
+use crate::db::{Event, Kind};
+use crate::models::StoredNotification;
+
+const WEEKDAYS: [&str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+
+// renders pending events as an RFC 5545 calendar; start times are local to `timezone`,
+// which is described by a VTIMEZONE block, so recurrences keep their wall-clock time across DST
+pub fn render_calendar(events: &[Event], now: DateTime<Utc>, timezone: Tz) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//notify-rs//reminders//EN".to_string(),
+    ];
+    let mut vevents = vec![];
+    let mut last_year = now.year() + 1;
+    // heads-ups belong to the main reminder and aren't events of their own
+    for event in events.iter().filter(|event| event.lead_minutes == 0) {
+        let Some((start, rule)) = first_occurrence(event, now, timezone) else { continue };
+        last_year = last_year.max(start.year());
+        vevents.push("BEGIN:VEVENT".to_string());
+        vevents.push(format!("UID:{}@notify-rs", event.uid));
+        vevents.push(format!("DTSTAMP:{}", format_time(now)));
+        vevents.push(format!("DTSTART;TZID={}:{}", timezone.name(), start.format("%Y%m%dT%H%M%S")));
+        vevents.extend(rule);
+        vevents.push(format!("SUMMARY:{}", escape_text(&event.text)));
+        vevents.push("END:VEVENT".to_string());
+    }
+    lines.extend(render_timezone(timezone, now.year(), last_year));
+    lines.extend(vevents);
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold_line(line)).collect::<Vec<_>>().join("")
+}
+
+// start of the event on the wall clock of the timezone, with the RRULE line of the weekly ones
+pub fn first_occurrence(event: &Event, now: DateTime<Utc>, timezone: Tz) -> Option<(DateTime<Tz>, Option<String>)> {
+    match event.kind {
+        // cron rules don't map onto RRULE, only the next occurrence is exported
+        Kind::Absolute | Kind::Cron => event.time.map(|time| (time.with_timezone(&timezone), None)),
+        Kind::Recurrent => match (event.day, event.hour, event.minute) {
+            (Some(day @ 1..=7), Some(hour), Some(minute)) => {
+                // the weekday is stored in UTC and may differ from the local one
+                let start = next_weekly_occurrence(now, day, hour, minute).with_timezone(&timezone);
+                let local_day = WEEKDAYS[start.weekday().num_days_from_monday() as usize];
+                Some((start, Some(format!("RRULE:FREQ=WEEKLY;BYDAY={}", local_day))))
+            }
+            _ => None,
+        },
+    }
+}
+
+// VTIMEZONE with one observance per offset change within the years, chrono-tz doesn't expose
+// the zone rules, so the changes are found by comparing offsets day by day
+fn render_timezone(timezone: Tz, from_year: i32, to_year: i32) -> Vec<String> {
+    let start = year_start(from_year);
+    let end = year_start(to_year + 1);
+    // the first observance covers everything before the changes
+    let mut observances = vec![(year_start(1970), utc_offset(timezone, start), utc_offset(timezone, start))];
+    let mut day = start;
+    while day < end {
+        let next = day + Duration::days(1);
+        if utc_offset(timezone, day) != utc_offset(timezone, next) {
+            let change = find_offset_change(timezone, day, next);
+            observances.push((change, utc_offset(timezone, day), utc_offset(timezone, change)));
+        }
+        day = next;
+    }
+
+    let mut lines = vec!["BEGIN:VTIMEZONE".to_string(), format!("TZID:{}", timezone.name())];
+    for (change, from, to) in observances {
+        let offset = timezone.offset_from_utc_datetime(&change.max(start).naive_utc());
+        let component = if offset.dst_offset().is_zero() { "STANDARD" } else { "DAYLIGHT" };
+        lines.push(format!("BEGIN:{}", component));
+        // the local time of the change is written in the offset in effect before it
+        let local = if change < start { change } else { change + Duration::seconds(from as i64) };
+        lines.push(format!("DTSTART:{}", local.format("%Y%m%dT%H%M%S")));
+        lines.push(format!("TZOFFSETFROM:{}", format_offset(from)));
+        lines.push(format!("TZOFFSETTO:{}", format_offset(to)));
+        lines.push(format!("TZNAME:{}", offset));
+        lines.push(format!("END:{}", component));
+    }
+    lines.push("END:VTIMEZONE".to_string());
+    lines
+}
+
+fn year_start(year: i32) -> DateTime<Utc> {
+    let date = NaiveDate::from_ymd_opt(year, 1, 1).unwrap_or_default();
+    Utc.from_utc_datetime(&date.and_time(NaiveTime::default()))
+}
+
+fn utc_offset(timezone: Tz, time: DateTime<Utc>) -> i32 {
+    timezone.offset_from_utc_datetime(&time.naive_utc()).fix().local_minus_utc()
+}
+
+// first minute in (before, after] with the offset of `after`
+fn find_offset_change(timezone: Tz, mut before: DateTime<Utc>, mut after: DateTime<Utc>) -> DateTime<Utc> {
+    let offset = utc_offset(timezone, after);
+    while after - before > Duration::minutes(1) {
+        let middle = before + (after - before) / 2;
+        let middle = middle - Duration::seconds(middle.second() as i64);
+        if utc_offset(timezone, middle) == offset { after = middle } else { before = middle }
+    }
+    after
+}
+
+fn format_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.abs() / 60;
+    format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+}
+
+// reminder read from a calendar file, ready to be stored
+#[derive(Debug, Clone)]
+pub struct ImportedEvent {
+    pub text: String,
+    pub notification: StoredNotification,
+}
+
+// reads VEVENTs with a DTSTART, skipping past one-off events and recurrences other than daily or weekly
+// floating and date-only times are on the wall clock of the timezone
+pub fn parse_calendar(content: &str, now: DateTime<Utc>, timezone: Tz) -> Vec<ImportedEvent> {
+    let unfolded = content.replace("\r\n ", "").replace("\r\n\t", "").replace("\n ", "").replace("\n\t", "");
+    let mut events = vec![];
+    let mut current: Option<Vec<(String, String)>> = None;
+    for line in unfolded.lines().map(|line| line.trim_end_matches('\r')) {
+        match line {
+            "BEGIN:VEVENT" => current = Some(vec![]),
+            "END:VEVENT" => {
+                if let Some(event) = current.take().and_then(|properties| read_event(&properties, now, timezone)) {
+                    events.push(event);
+                }
+            }
+            _ => {
+                if let (Some(properties), Some((name, value))) = (current.as_mut(), line.split_once(':')) {
+                    properties.push((name.to_string(), value.to_string()));
+                }
+            }
+        }
+    }
+    events
+}
+
+fn read_event(properties: &[(String, String)], now: DateTime<Utc>, timezone: Tz) -> Option<ImportedEvent> {
+    let property = |name: &str| properties.iter()
+        .find(|(key, _)| key == name || key.starts_with(&format!("{};", name)));
+    let text = property("SUMMARY").map(|(_, value)| unescape_text(value))?;
+    let start = property("DTSTART").and_then(|(key, value)| parse_time(key, value, timezone))?;
+
+    let notification = match property("RRULE") {
+        None if start > now => StoredNotification::Absolute { time: start },
+        None => return None,
+        Some((_, rule)) => {
+            let days = recurrence_days(rule, start)?;
+            // BYDAY names local weekdays, the stored ones are in UTC
+            let (_, value) = property("DTSTART")?;
+            let shift = NaiveDate::parse_from_str(value.get(..8)?, "%Y%m%d").ok()
+                .map_or(0, |local| (start.date_naive() - local).num_days());
+            let days = days.iter().map(|day| ((*day as i64 - 1 + shift).rem_euclid(7) + 1) as u8).collect();
+            StoredNotification::Recurrent { hours: start.hour() as u8, minutes: start.minute() as u8, days: Some(days) }
+        }
+    };
+    Some(ImportedEvent { text, notification })
+}
+
+// DTSTART in UTC, with a TZID parameter, floating (the given timezone) or date-only (9 am in the given timezone)
+fn parse_time(key: &str, value: &str, timezone: Tz) -> Option<DateTime<Utc>> {
+    if let Some(utc) = value.strip_suffix('Z') {
+        return NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok().map(|time| Utc.from_utc_datetime(&time));
+    }
+    let timezone = key.split(';')
+        .find_map(|param| param.strip_prefix("TZID="))
+        .and_then(|name| Tz::from_str(name).ok())
+        .unwrap_or(timezone);
+    let local = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()
+        .or_else(|| NaiveDate::parse_from_str(value, "%Y%m%d").ok()
+            .map(|date| date.and_time(NaiveTime::from_hms_opt(9, 0, 0).unwrap_or_default())))?;
+    timezone.from_local_datetime(&local).earliest().map(|time| time.with_timezone(&Utc))
+}
+
+fn recurrence_days(rule: &str, start: DateTime<Utc>) -> Option<ArrayVec<u8, 7>> {
+    let parts = rule.split(';').filter_map(|part| part.split_once('=')).collect::<Vec<_>>();
+    let value = |name: &str| parts.iter().find(|(key, _)| *key == name).map(|(_, value)| *value);
+    match value("FREQ")? {
+        "DAILY" => Some((1..=7).collect()),
+        "WEEKLY" => match value("BYDAY") {
+            Some(days) => Some(days.split(',')
+                .filter_map(|day| WEEKDAYS.iter().position(|name| day.ends_with(name)))
+                .map(|index| index as u8 + 1)
+                .take(7)
+                .collect()),
+            None => Some(std::iter::once(start.weekday().num_days_from_monday() as u8 + 1).collect()),
+        },
+        _ => None,
+    }
+}
+
+fn unescape_text(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            unescaped.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(next) => unescaped.push(next),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+// first moment at or after `now` that falls on the weekday (1 = monday) and time
+pub fn next_weekly_occurrence(now: DateTime<Utc>, day: u8, hour: u8, minute: u8) -> DateTime<Utc> {
+    let current_day = now.weekday().num_days_from_monday() as i64 + 1;
+    let candidate = (now + Duration::days((day as i64 - current_day).rem_euclid(7)))
+        .with_hour(hour as u32).and_then(|time| time.with_minute(minute as u32))
+        .and_then(|time| time.with_second(0))
+        .and_then(|time| time.with_nanosecond(0))
+        .unwrap_or(now);
+    if candidate < now { candidate + Duration::weeks(1) } else { candidate }
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+// content lines longer than 75 octets are continued on lines starting with a space
+fn fold_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 8);
+    let mut width = 0;
+    for ch in line.chars() {
+        if width + ch.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(ch);
+        width += ch.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+    use chrono_tz::America::New_York;
+    use chrono_tz::Asia::{Jerusalem, Tokyo};
+    use crate::db::{Event, Kind, Source};
+    use crate::models::{Priority, StoredNotification};
+    use super::{fold_line, parse_calendar, render_calendar};
+
+    fn event(kind: Kind, time: Option<DateTime<Utc>>, day: Option<u8>) -> Event {
+        Event {
+            uid: "0189".to_string(),
+            kind,
+            source: Source::Telegram,
+            text: "call Alex, then; rest".to_string(),
+            time,
+            day,
+            hour: day.map(|_| 9),
+            minute: day.map(|_| 30),
+            is_deleted: false,
+            lead_minutes: 0,
+            priority: Priority::Normal,
+            nag_minutes: 0,
+            expires_minutes: 0,
+            silent: false,
+            cron: None,
+            quote: None,
+            timezone: None,
+            holiday_country: None,
+        }
+    }
+
+    #[test]
+    fn should_render_absolute_and_recurrent_events() {
+        // thursday
+        let now = DateTime::parse_from_rfc3339("2023-01-26T14:40:00Z").unwrap().with_timezone(&Utc);
+        let time = DateTime::parse_from_rfc3339("2023-01-27T12:00:00Z").unwrap().with_timezone(&Utc);
+        let calendar = render_calendar(&[event(Kind::Absolute, Some(time), None), event(Kind::Recurrent, None, Some(1))], now, Jerusalem);
+
+        assert!(calendar.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(calendar.contains("DTSTART;TZID=Asia/Jerusalem:20230127T140000\r\n"));
+        assert!(calendar.contains("DTSTART;TZID=Asia/Jerusalem:20230130T113000\r\nRRULE:FREQ=WEEKLY;BYDAY=MO\r\n"));
+        assert!(calendar.contains("SUMMARY:call Alex\\, then\\; rest\r\n"));
+        assert!(calendar.ends_with("END:VCALENDAR\r\n"));
+    }
+
+    // golden files pin the whole output, VTIMEZONE observances included, for zones with and without DST
+    #[test]
+    fn should_match_golden_calendars() {
+        let now = DateTime::parse_from_rfc3339("2023-01-26T14:40:00Z").unwrap().with_timezone(&Utc);
+        let time = DateTime::parse_from_rfc3339("2023-07-27T12:00:00Z").unwrap().with_timezone(&Utc);
+        let mut late = event(Kind::Recurrent, None, Some(7));
+        late.hour = Some(22);
+        let events = [event(Kind::Absolute, Some(time), None), late];
+
+        for (timezone, golden) in [(Jerusalem, "assets/golden/jerusalem.ics"), (New_York, "assets/golden/new_york.ics"), (Tokyo, "assets/golden/tokyo.ics")] {
+            let expected = std::fs::read_to_string(golden).unwrap();
+            assert_eq!(render_calendar(&events, now, timezone).replace('\r', ""), expected.replace('\r', ""), "{}", golden);
+        }
+    }
+
+    #[test]
+    fn should_fold_long_lines() {
+        let folded = fold_line(&"x".repeat(100));
+        assert_eq!(folded, format!("{}\r\n {}\r\n", "x".repeat(75), "x".repeat(25)));
+    }
+
+    #[test]
+    fn should_import_rendered_calendar() {
+        let now = DateTime::parse_from_rfc3339("2023-01-26T14:40:00Z").unwrap().with_timezone(&Utc);
+        let time = DateTime::parse_from_rfc3339("2023-01-27T12:00:00Z").unwrap().with_timezone(&Utc);
+        let mut late = event(Kind::Recurrent, None, Some(7));
+        late.hour = Some(22);
+        let calendar = render_calendar(&[event(Kind::Absolute, Some(time), None), event(Kind::Recurrent, None, Some(1)), late], now, Jerusalem);
+
+        let imported = parse_calendar(&calendar, now, chrono_tz::Israel);
+
+        assert_eq!(imported.len(), 3);
+        assert_eq!(imported[0].text, "call Alex, then; rest");
+        assert!(matches!(imported[0].notification, StoredNotification::Absolute { time: t } if t == time));
+        match &imported[1].notification {
+            StoredNotification::Recurrent { hours, minutes, days } => {
+                assert_eq!((*hours, *minutes), (9, 30));
+                assert_eq!(days.as_ref().unwrap().as_slice(), &[1]);
+            }
+            _ => panic!("event should be recurrent"),
+        }
+        // sunday 22:00 UTC is monday in Jerusalem, the weekday is shifted back on import
+        match &imported[2].notification {
+            StoredNotification::Recurrent { hours, days, .. } => {
+                assert_eq!(*hours, 22);
+                assert_eq!(days.as_ref().unwrap().as_slice(), &[7]);
+            }
+            _ => panic!("event should be recurrent"),
+        }
+    }
+
+    #[test]
+    fn should_convert_zoned_times_and_skip_past_events() {
+        let now = DateTime::parse_from_rfc3339("2023-01-26T14:40:00Z").unwrap().with_timezone(&Utc);
+        let calendar = "BEGIN:VCALENDAR\nBEGIN:VEVENT\nSUMMARY:dentist\nDTSTART;TZID=Europe/Berlin:20230201T100000\nEND:VEVENT\n\
+            BEGIN:VEVENT\nSUMMARY:old\nDTSTART:20230101T100000Z\nEND:VEVENT\n\
+            BEGIN:VEVENT\nSUMMARY:yearly\nDTSTART:20230101T100000Z\nRRULE:FREQ=YEARLY\nEND:VEVENT\nEND:VCALENDAR\n";
+
+        let imported = parse_calendar(calendar, now, chrono_tz::Israel);
+
+        assert_eq!(imported.len(), 1);
+        let expected = DateTime::parse_from_rfc3339("2023-02-01T09:00:00Z").unwrap().with_timezone(&Utc);
+        assert!(matches!(imported[0].notification, StoredNotification::Absolute { time } if time == expected));
+
+        // a floating time is on the wall clock of the importing user
+        let floating = "BEGIN:VCALENDAR\nBEGIN:VEVENT\nSUMMARY:market\nDTSTART:20230201T100000\nEND:VEVENT\nEND:VCALENDAR\n";
+        let imported = parse_calendar(floating, now, chrono_tz::America::Guatemala);
+        let expected = DateTime::parse_from_rfc3339("2023-02-01T16:00:00Z").unwrap().with_timezone(&Utc);
+        assert!(matches!(imported[0].notification, StoredNotification::Absolute { time } if time == expected));
+    }
+}
\ No newline at end of file
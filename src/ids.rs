@@ -0,0 +1,45 @@
+use std::fmt::Debug;
+use chrono::Utc;
+
+pub trait IdGenerator: Debug + Send + Sync {
+    fn generate(&self) -> String;
+}
+
+// time-ordered identifiers (RFC 9562), safe to expose without leaking row counts
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidV7Generator;
+
+impl IdGenerator for UuidV7Generator {
+    fn generate(&self) -> String {
+        let mut bytes = [0u8; 16];
+        getrandom::getrandom(&mut bytes).expect("system random source is unavailable");
+        let millis = Utc::now().timestamp_millis() as u64;
+        bytes[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+        bytes[6] = (bytes[6] & 0x0f) | 0x70;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdGenerator, UuidV7Generator};
+
+    #[test]
+    fn should_generate_version_7_uuid() {
+        let id = UuidV7Generator.generate();
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "7");
+        assert!(matches!(&id[19..20], "8" | "9" | "a" | "b"));
+    }
+
+    #[test]
+    fn should_generate_time_ordered_ids() {
+        let first = UuidV7Generator.generate();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = UuidV7Generator.generate();
+        assert!(first < second);
+    }
+}
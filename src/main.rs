@@ -1,8 +1,16 @@
 use std::error::Error;
+use std::io::IsTerminal;
 use std::sync::Arc;
 use envconfig::Envconfig;
+use crate::api::Api;
 use crate::bot::Bot;
+use crate::cli::Command;
+use crate::config::{ConfigFile, SettingsFiles};
+use crate::errors::BotError;
+use crate::health::HealthServer;
 use crate::models::Env;
+use crate::profile::Profile;
+use crate::replication::Replication;
 
 mod models;
 mod tg;
@@ -10,21 +18,120 @@ mod db;
 mod parser;
 mod bot;
 mod errors;
+mod cli;
+mod ids;
+mod migrations;
+mod webhooks;
+mod replication;
+mod state;
+mod ics;
+mod humanize;
+mod api;
+mod commands;
+mod health;
+mod render;
+mod profile;
+mod agenda;
+mod setup;
+mod demo;
+mod queue;
+mod bundle;
+mod config;
+mod logging;
+mod cron;
+mod templates;
+mod i18n;
+mod fixtures;
+mod eval;
+mod gcal;
+mod tzlookup;
+mod holidays;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    dotenv::dotenv().ok();
-    env_logger::builder().filter(None, log::LevelFilter::Info).init();
-    let env = Env::init_from_env()?;
-    let bot = bot::BotDeps::new(&env).await?;
+    let (config, args) = ConfigFile::from_args(std::env::args().skip(1))?;
+    let (profile, args) = Profile::from_args(args.into_iter())?;
+    profile.load()?;
+    config.load()?;
+    let settings_files = SettingsFiles { profile: profile.clone(), config };
+    let mut env = match Env::init_from_env() {
+        Ok(env) => env,
+        // only a bot started by hand in a terminal asks for the missing settings, services keep failing fast
+        Err(err) if args.is_empty() && std::io::stdin().is_terminal() => {
+            println!("{}", err);
+            setup::run_wizard().await?;
+            Env::init_from_env()?
+        }
+        Err(err) => return Err(err.into()),
+    };
+    profile.apply_defaults(&mut env);
+    demo::apply(&mut env)?;
+    logging::init(env.log_level, env.log_format);
+    if let Some(name) = &profile.name {
+        tracing::info!("Using profile {}", name);
+    }
+    if env.demo_mode {
+        tracing::info!("Running in demo mode with database {}", env.connection_string);
+    }
+    if let Some(command) = Command::from_args(args.into_iter())? {
+        command.run(&env).await?;
+        return Ok(());
+    }
+    let replication = Replication::new(&env);
+    replication.restore_if_missing().await?;
+    let bot = bot::BotDeps::new(&env).await?.with_settings_files(settings_files);
+    // the primary owns the database, so the standby neither snapshots it nor runs anything that writes
+    let snapshot_handle = if env.standby_mode { None } else { replication.run_snapshot_task(bot.event_repository().clone()) };
     let arced = Arc::new(bot);
+    let api_handle = match Api::new(&env, arced.clone())? {
+        Some((api, address)) => Some(api.run_task(address)?),
+        None => None,
+    };
+    let health_handle = match HealthServer::new(&env, arced.clone())? {
+        Some(health) => Some(health.run_task()?),
+        None => None,
+    };
+    if env.standby_mode {
+        let handles = api_handle.into_iter().chain(health_handle).collect::<Vec<_>>();
+        if handles.is_empty() {
+            return Err(BotError::Usage("STANDBY_MODE serves only the api and probes, set API_BIND or HEALTH_BIND").into());
+        }
+        tracing::info!("Running as standby, telegram is not polled and events are not fired");
+        for handle in handles {
+            handle.await?;
+        }
+        return Ok(());
+    }
     let bot = Bot { dependency: arced.clone() };
-    let task_bot = Bot { dependency: arced };
-    log::info!("Starting background task");
+    let task_bot = Bot { dependency: arced.clone() };
+    let cleanup_bot = Bot { dependency: arced.clone() };
+    let maintenance_bot = Bot { dependency: arced.clone() };
+    let calendar_handle = arced.calendar().is_some().then(|| Bot { dependency: arced.clone() }.run_calendar_task());
+    let demo_handle = env.demo_mode.then(|| Bot { dependency: arced }.run_demo_wipe_task());
+    tracing::info!("Starting background task");
     let handle = task_bot.run_background_task();
+    let cleanup_handle = cleanup_bot.run_cleanup_task();
+    let maintenance_handle = maintenance_bot.run_maintenance_task();
 
-    log::info!("Starting bot");
+    tracing::info!("Starting bot");
     bot.run().await?;
     handle.await?;
+    cleanup_handle.await?;
+    maintenance_handle.await?;
+    if let Some(calendar_handle) = calendar_handle {
+        calendar_handle.await?;
+    }
+    if let Some(demo_handle) = demo_handle {
+        demo_handle.await?;
+    }
+    if let Some(snapshot_handle) = snapshot_handle {
+        snapshot_handle.await?;
+    }
+    if let Some(api_handle) = api_handle {
+        api_handle.await?;
+    }
+    if let Some(health_handle) = health_handle {
+        health_handle.await?;
+    }
     Ok(())
 }
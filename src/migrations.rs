@@ -0,0 +1,518 @@
+use rusqlite::{Connection, Transaction};
+
+type Migration = fn(&Transaction<'_>) -> rusqlite::Result<()>;
+
+// schema changes in the order they were introduced; never edit or reorder shipped entries,
+// add a new one at the end instead
+const MIGRATIONS: &[(&str, Migration)] = &[
+    ("create event table", create_event_table),
+    ("add event source", add_event_source),
+    ("add event uid", add_event_uid),
+    ("create job table", create_job_table),
+    ("create usage table", create_usage_table),
+    ("create webhook tables", create_webhook_tables),
+    ("create event history table", create_event_history_table),
+    ("add delivery tracking", add_delivery_tracking),
+    ("add recurrent last fired time", add_last_fired_at),
+    ("create user settings table", create_user_settings_table),
+    ("create access request table", create_access_request_table),
+    ("create business connection tables", create_business_connection_tables),
+    ("create user role table", create_user_role_table),
+    ("add lead minutes", add_lead_minutes),
+    ("create event search index", create_event_search_index),
+    ("create event tag table", create_event_tag_table),
+    ("add event priority", add_event_priority),
+    ("move acknowledgements to event rows", add_nag_mode),
+    ("create event exclusion table", create_event_exclusion_table),
+    ("add pause to user settings", add_paused_until),
+    ("add quiet hours", add_quiet_hours),
+    ("create undo action table", create_undo_action_table),
+    ("create allowed user table", create_allowed_user_table),
+    ("create bot state table", create_bot_state_table),
+    ("add user language", add_user_language),
+    ("add recurrent event timezone", add_event_timezone),
+    ("add cron expression", add_event_cron),
+    ("add templates", add_templates),
+    ("add event locations", add_event_locations),
+    ("add event quote", add_event_quote),
+    ("add event expiry window", add_event_expiry),
+    ("create parse correction table", create_parse_correction_table),
+    ("add event silent flag", add_event_silent),
+    ("add event source message", add_event_source_message),
+    ("add event pinned message", add_event_pinned_message),
+    ("add event completion", add_event_completion),
+    ("add holidays", add_holidays),
+    ("add event group uid", add_event_group_uid),
+    ("create calendar connection table", create_calendar_connection_table),
+    ("add usage reservations", add_usage_reservations),
+];
+
+// applies every migration newer than the recorded schema version, each in its own transaction
+pub fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    connection.execute("create table if not exists schema_version (
+        version integer primary key,
+        name text not null,
+        applied_at datetime not null default current_timestamp
+    )", ())?;
+
+    let current: usize = connection.query_row("select coalesce(max(version), 0) from schema_version", [], |row| row.get(0))?;
+    for (index, (name, migration)) in MIGRATIONS.iter().enumerate().skip(current) {
+        let version = index + 1;
+        tracing::info!("Applying migration {}: {}", version, name);
+        let tx = connection.transaction()?;
+        migration(&tx)?;
+        tx.execute("insert into schema_version (version, name) values (?1, ?2)", (version, name))?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+// a read-only standby can't migrate, it only checks that the primary left the schema where this build expects it
+pub fn check_current(connection: &Connection) -> rusqlite::Result<Option<(usize, usize)>> {
+    let applied: usize = connection.query_row("select coalesce(max(version), 0) from schema_version", [], |row| row.get(0))?;
+    Ok((applied != MIGRATIONS.len()).then_some((applied, MIGRATIONS.len())))
+}
+
+fn has_column(tx: &Transaction<'_>, table: &str, column: &str) -> rusqlite::Result<bool> {
+    tx.prepare("select 1 from pragma_table_info(?1) where name = ?2")?
+        .exists([table, column])
+}
+
+// first migrations also run against databases created before versioning existed,
+// so they have to tolerate objects that are already in place
+fn create_event_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("create table if not exists event (
+        id integer primary key autoincrement,
+        kind text not null,
+        user_id integer not null,
+        event_text text not null,
+        event_time datetime,
+        day integer,
+        hour integer,
+        minute integer,
+        is_deleted integer
+    );
+
+    create index if not exists event_user_id_is_deleted on event (user_id, is_deleted);
+    create index if not exists event_is_deleted on event (is_deleted);")
+}
+
+fn add_event_source(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    if !has_column(tx, "event", "source")? {
+        tx.execute("alter table event add column source text not null default 'telegram'", ())?;
+    }
+    Ok(())
+}
+
+fn add_event_uid(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    if !has_column(tx, "event", "uid")? {
+        tx.execute("alter table event add column uid text", ())?;
+    }
+    tx.execute("create unique index if not exists event_uid on event (uid)", ())?;
+    Ok(())
+}
+
+fn create_job_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("create table if not exists job (
+        name text primary key,
+        status text not null,
+        checkpoint integer not null default 0,
+        processed integer not null default 0,
+        updated_at datetime not null
+    )", ())?;
+    Ok(())
+}
+
+fn create_usage_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("create table if not exists usage (
+        user_id integer not null,
+        month text not null,
+        prompt_tokens integer not null default 0,
+        completion_tokens integer not null default 0,
+        cost real not null default 0,
+        primary key (user_id, month)
+    )", ())?;
+    Ok(())
+}
+
+fn create_webhook_tables(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("create table webhook (
+        user_id integer not null,
+        name text not null,
+        url text not null,
+        primary key (user_id, name)
+    );
+
+    create table event_webhook (
+        event_id integer not null,
+        user_id integer not null,
+        name text not null,
+        primary key (event_id, name)
+    );
+
+    create table webhook_call (
+        id integer primary key autoincrement,
+        user_id integer not null,
+        name text not null,
+        event_id integer,
+        status integer,
+        error text,
+        called_at datetime not null
+    );
+
+    create index webhook_call_user_id on webhook_call (user_id);")
+}
+
+fn create_event_history_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("create table event_history (
+        id integer primary key autoincrement,
+        event_id integer not null,
+        user_id integer not null,
+        transition text not null,
+        at datetime not null
+    );
+
+    create index event_history_event_id on event_history (event_id);
+    create index event_history_user_id on event_history (user_id);")
+}
+
+fn add_delivery_tracking(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("alter table event add column delivery_attempts integer not null default 0;
+    alter table event add column next_attempt_at datetime;
+    alter table event add column last_error text;
+    alter table event add column is_dead_lettered integer not null default 0;")
+}
+
+// recurrent events stay active after firing, this keeps them from firing twice on the same day
+fn add_last_fired_at(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("alter table event add column last_fired_at datetime", [])?;
+    Ok(())
+}
+
+fn create_user_settings_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("create table if not exists user_settings (
+        user_id integer primary key,
+        plain_mode integer not null default 0
+    )", [])?;
+    Ok(())
+}
+
+fn create_access_request_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("create table if not exists access_request (
+        user_id integer primary key,
+        username text,
+        status text not null,
+        requested_at datetime not null,
+        decided_at datetime
+    )", [])?;
+    Ok(())
+}
+
+fn create_business_connection_tables(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("create table if not exists business_connection (
+        id text primary key,
+        user_id integer not null,
+        user_chat_id integer not null,
+        is_enabled integer not null,
+        updated_at datetime not null
+    )", [])?;
+    tx.execute("create table if not exists business_chat (
+        chat_id integer primary key,
+        connection_id text not null
+    )", [])?;
+    Ok(())
+}
+
+fn create_user_role_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("create table if not exists user_role (
+        user_id integer primary key,
+        role text not null
+    )", [])?;
+    Ok(())
+}
+
+fn add_lead_minutes(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("alter table event add column lead_minutes integer not null default 0", [])?;
+    Ok(())
+}
+
+// full-text index over reminder texts keyed by event id, heads-ups aren't indexed;
+// the repository keeps it in sync when events are inserted or purged
+fn create_event_search_index(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("create virtual table if not exists event_search using fts5(text, tokenize = 'unicode61')", [])?;
+    tx.execute("insert into event_search (rowid, text) select id, event_text from event where lead_minutes = 0", [])?;
+    Ok(())
+}
+
+fn create_event_tag_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("create table if not exists event_tag (
+        event_id integer not null,
+        user_id integer not null,
+        tag text not null,
+        primary key (event_id, tag)
+    )", [])?;
+    tx.execute("create index if not exists event_tag_user_tag on event_tag (user_id, tag)", [])?;
+    Ok(())
+}
+
+// urgent deliveries wait here for an acknowledgement and are sent again when none comes
+fn add_event_priority(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("alter table event add column priority text not null default 'normal'", [])?;
+    tx.execute("create table if not exists urgent_delivery (
+        event_id integer primary key,
+        user_id integer not null,
+        sent_at datetime not null,
+        resends integer not null default 0
+    )", [])?;
+    Ok(())
+}
+
+// a fired reminder waits for the done button while ack_sent_at is set,
+// pending urgent deliveries move over from their own table
+fn add_nag_mode(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("alter table event add column nag_minutes integer not null default 0;
+    alter table event add column ack_sent_at datetime;
+    alter table event add column ack_resends integer not null default 0;
+    update event set ack_sent_at = (select sent_at from urgent_delivery where event_id = event.id),
+        ack_resends = coalesce((select resends from urgent_delivery where event_id = event.id), 0);
+    drop table urgent_delivery;")
+}
+
+// skipped occurrences of recurrent rows, by the utc date they would have fired on
+fn create_event_exclusion_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("create table if not exists event_exclusion (
+        event_id integer not null,
+        user_id integer not null,
+        occurs_on date not null,
+        primary key (event_id, occurs_on)
+    )", [])?;
+    Ok(())
+}
+
+// nothing fires for the user until this time, missed absolute events are delivered after it
+fn add_paused_until(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("alter table user_settings add column paused_until datetime", [])?;
+    Ok(())
+}
+
+// reminders fired within the quiet hours of their user wait in deferred_delivery until the window is over
+fn add_quiet_hours(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("alter table user_settings add column quiet_start integer;
+    alter table user_settings add column quiet_end integer;
+
+    create table deferred_delivery (
+        event_id integer primary key,
+        user_id integer not null,
+        release_at datetime not null
+    );
+
+    create index deferred_delivery_release_at on deferred_delivery (release_at);")
+}
+
+// history rows written by one user action share its id, so the action can be compensated with /undo
+fn create_undo_action_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("create table undo_action (
+        id integer primary key autoincrement,
+        user_id integer not null,
+        label text not null,
+        at datetime not null
+    );
+
+    alter table event_history add column action_id integer;
+    create index event_history_action_id on event_history (action_id);")
+}
+
+// users with access, approved join requests and users with a stored role move over;
+// removed users keep their row, so seeding from TG_USERS doesn't bring them back
+fn create_allowed_user_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("create table allowed_user (
+        user_id integer primary key,
+        display_name text,
+        timezone text,
+        created_at datetime not null,
+        removed_at datetime
+    );
+
+    insert or ignore into allowed_user (user_id, display_name, created_at)
+        select user_id, username, coalesce(decided_at, requested_at) from access_request where status = 'approved';
+    insert or ignore into allowed_user (user_id, created_at) select user_id, current_timestamp from user_role;")
+}
+
+// counters of the bot itself that have to survive a restart, like the telegram update offset
+fn create_bot_state_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("create table if not exists bot_state (
+        name text primary key,
+        value integer not null,
+        updated_at datetime not null
+    )", [])?;
+    Ok(())
+}
+
+// language chosen with /language, without it replies follow the telegram client language
+fn add_user_language(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("alter table user_settings add column language text", [])?;
+    Ok(())
+}
+
+// weekly rows with a timezone keep day, hour and minute as wall time there, so they follow dst;
+// rows without one keep the fixed utc time they always had
+fn add_event_timezone(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("alter table event add column timezone text", [])?;
+    Ok(())
+}
+
+// cron events keep their next occurrence in event_time and the zone they are evaluated in in timezone
+fn add_event_cron(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("alter table event add column cron text", [])?;
+    Ok(())
+}
+
+fn add_templates(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("create table template (
+        id integer primary key,
+        user_id integer not null,
+        name text not null,
+        body text not null,
+        unique (user_id, name)
+    )", [])?;
+    Ok(())
+}
+
+fn add_event_locations(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("create table event_location (
+        event_id integer primary key,
+        user_id integer not null,
+        latitude real not null,
+        longitude real not null
+    )", [])?;
+    Ok(())
+}
+
+fn add_event_quote(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("alter table event add column quote text", [])?;
+    Ok(())
+}
+
+fn add_event_expiry(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("alter table event add column expires_minutes integer not null default 0", [])?;
+    Ok(())
+}
+
+// accepted is the notification json in the format the model answers with
+fn create_parse_correction_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("create table parse_correction (
+        id integer primary key,
+        user_id integer not null,
+        input text not null,
+        accepted text not null,
+        parsed_at datetime not null
+    );
+    create index parse_correction_user on parse_correction (user_id, id);")?;
+    Ok(())
+}
+
+fn add_event_silent(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("alter table event add column silent integer not null default 0", [])?;
+    Ok(())
+}
+
+// id of the message in the user's chat the reminder was parsed from
+fn add_event_source_message(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("alter table event add column source_message_id integer", [])?;
+    Ok(())
+}
+
+// the last sent message of an urgent reminder that is pinned until it's marked done
+fn add_event_pinned_message(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("alter table event add column pinned_message_id integer", [])?;
+    Ok(())
+}
+
+// when done was last pressed, for a recurrent event it's about the occurrence that fired before
+fn add_event_completion(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("alter table event add column completed_at datetime", [])?;
+    Ok(())
+}
+
+// days off by country; a recurrent event with a holiday country doesn't fire on the days off there
+fn add_holidays(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("create table holiday (
+        country text not null,
+        day date not null,
+        name text not null,
+        primary key (country, day)
+    );
+    alter table event add column holiday_country text;")?;
+    Ok(())
+}
+
+// public id shared by the rows created together, e.g. the weekdays of one weekly reminder;
+// existing rows are a group of their own, the ones still without a uid get it with the uid backfill
+fn add_event_group_uid(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("alter table event add column group_uid text;
+    update event set group_uid = uid;
+    create index event_group_uid on event (group_uid);")?;
+    Ok(())
+}
+
+// google calendars linked with /connect_calendar; calendar_event keeps the ids of the events already pushed
+// or pulled, so a pulled event isn't added twice and a pushed one doesn't come back as a reminder
+fn create_calendar_connection_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("create table calendar_connection (
+        user_id integer primary key,
+        refresh_token text not null,
+        push integer not null,
+        pull integer not null,
+        connected_at datetime not null
+    );
+    create table calendar_event (
+        user_id integer not null,
+        google_id text not null,
+        primary key (user_id, google_id)
+    );")?;
+    Ok(())
+}
+
+// tokens of parser calls in flight, counted against the budgets until the call's usage is recorded
+fn add_usage_reservations(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute("alter table usage add column reserved_tokens integer not null default 0", ())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+    use super::{migrate, MIGRATIONS};
+
+    #[test]
+    fn should_apply_migrations_once() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection).unwrap();
+        migrate(&mut connection).unwrap();
+
+        let applied: usize = connection.query_row("select count(*) from schema_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(applied, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn should_upgrade_unversioned_database() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        connection.execute("create table event (
+            id integer primary key autoincrement,
+            kind text not null,
+            user_id integer not null,
+            event_text text not null,
+            event_time datetime,
+            day integer,
+            hour integer,
+            minute integer,
+            is_deleted integer
+        )", ()).unwrap();
+        connection.execute("insert into event (kind, user_id, event_text, is_deleted) values ('absolute', 1, 'legacy', 0)", ()).unwrap();
+
+        migrate(&mut connection).unwrap();
+
+        let source: String = connection.query_row("select source from event", [], |row| row.get(0)).unwrap();
+        assert_eq!(source, "telegram");
+    }
+}
@@ -1,10 +1,17 @@
+use std::cell::Cell;
+use std::path::PathBuf;
 use std::str::FromStr;
 use arrayvec::ArrayVec;
-use chrono::{Datelike, DateTime, Duration, Timelike, TimeZone, Utc};
+use chrono::{Datelike, DateTime, Duration, Offset, Timelike, TimeZone, Utc};
+use chrono_tz::Tz;
 use envconfig::Envconfig;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde::de::Error;
+use crate::db::{JournalMode, Synchronous};
 use crate::errors::BotError;
+use crate::fixtures::ParserFixtures;
+use crate::parser::Provider;
+use crate::logging::LogFormat;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Chat { pub id: u64, }
@@ -14,7 +21,41 @@ pub struct Message {
     pub message_id: u64,
     pub date: u64,
     pub chat: Chat,
+    pub from: Option<User>,
     pub text: Option<String>,
+    pub document: Option<Document>,
+    pub business_connection_id: Option<String>,
+    pub reply_markup: Option<InlineKeyboardMarkup>,
+    pub location: Option<Location>,
+    pub venue: Option<Venue>,
+    pub caption: Option<String>,
+    pub forward_origin: Option<MessageOrigin>,
+}
+
+// sender of a forwarded message, only the fact of forwarding matters here
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageOrigin {
+    pub date: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document {
+    pub file_id: String,
+    pub file_name: Option<String>,
+    pub mime_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Venue {
+    pub location: Location,
+    pub title: String,
+    pub address: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,7 +63,59 @@ pub struct Update {
     pub update_id: u64,
     pub message: Option<Message>,
     pub edited_message: Option<Message>,
-    pub callback_query: Option<CallbackQuery>
+    pub callback_query: Option<CallbackQuery>,
+    pub business_connection: Option<BusinessConnection>,
+    pub business_message: Option<Message>,
+    pub inline_query: Option<InlineQuery>,
+    pub chosen_inline_result: Option<ChosenInlineResult>,
+}
+
+// text typed after the bot name in any chat, as in "@notifybot call mom tomorrow 9am"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InlineQuery {
+    pub id: String,
+    pub from: User,
+    pub query: String,
+}
+
+// the user picked one of the results of an inline query, telegram only reports it with inline feedback turned on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChosenInlineResult {
+    pub result_id: String,
+    pub from: User,
+    pub query: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnswerInlineQuery {
+    pub inline_query_id: String,
+    pub results: Vec<InlineQueryResultArticle>,
+    pub cache_time: u32,
+    pub is_personal: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InlineQueryResultArticle {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub input_message_content: InputTextMessageContent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputTextMessageContent {
+    pub message_text: String,
+}
+
+// a business account owner connected the bot to the chats of their account, or changed that connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BusinessConnection {
+    pub id: String,
+    pub user: User,
+    pub user_chat_id: u64,
+    pub is_enabled: bool,
 }
 
 impl Update {
@@ -30,6 +123,79 @@ impl Update {
         self.message.as_ref().map(|m| m.chat.id)
             .or(self.edited_message.as_ref().map(|m| m.chat.id))
             .or(self.callback_query.as_ref().map(|m| m.from.id))
+            .or(self.inline_query.as_ref().map(|query| query.from.id))
+            .or(self.chosen_inline_result.as_ref().map(|result| result.from.id))
+    }
+
+    pub fn get_language_code(&self) -> Option<&str> {
+        self.message.as_ref().and_then(|m| m.from.as_ref())
+            .or(self.edited_message.as_ref().and_then(|m| m.from.as_ref()))
+            .or(self.callback_query.as_ref().map(|m| &m.from))
+            .or(self.inline_query.as_ref().map(|query| &query.from))
+            .or(self.chosen_inline_result.as_ref().map(|result| &result.from))
+            .and_then(|user| user.language_code.as_deref())
+    }
+
+    // copy for the logs: texts, file and user names become their length, ids stay so lines can still be correlated
+    pub fn redacted(&self) -> Update {
+        let mut update = self.clone();
+        for message in [&mut update.message, &mut update.edited_message, &mut update.business_message].into_iter().flatten() {
+            message.redact();
+        }
+        if let Some(callback_query) = update.callback_query.as_mut() {
+            callback_query.from.redact();
+            callback_query.message.iter_mut().for_each(Message::redact);
+        }
+        if let Some(inline_query) = update.inline_query.as_mut() {
+            inline_query.from.redact();
+            inline_query.query = format!("<{} chars>", inline_query.query.chars().count());
+        }
+        if let Some(chosen) = update.chosen_inline_result.as_mut() {
+            chosen.from.redact();
+            chosen.query = format!("<{} chars>", chosen.query.chars().count());
+        }
+        if let Some(connection) = update.business_connection.as_mut() {
+            connection.user.redact();
+        }
+        update
+    }
+}
+
+impl Message {
+    fn redact(&mut self) {
+        redact(&mut self.text);
+        redact(&mut self.caption);
+        self.from.iter_mut().for_each(User::redact);
+        if let Some(document) = self.document.as_mut() {
+            redact(&mut document.file_name);
+        }
+        // where someone is going says as much as what they write
+        self.location = None;
+        self.venue = None;
+    }
+
+    // text or caption of a forwarded message
+    pub fn forwarded_text(&self) -> Option<&str> {
+        self.forward_origin.as_ref()?;
+        self.text.as_deref().or(self.caption.as_deref()).filter(|text| !text.trim().is_empty())
+    }
+
+    // a venue is a location with a name and an address
+    pub fn shared_location(&self) -> Option<Location> {
+        self.venue.as_ref().map(|venue| venue.location).or(self.location)
+    }
+}
+
+impl User {
+    fn redact(&mut self) {
+        redact(&mut self.username);
+        redact(&mut self.first_name);
+    }
+}
+
+fn redact(value: &mut Option<String>) {
+    if let Some(value) = value.as_mut() {
+        *value = format!("<{} chars>", value.chars().count());
     }
 }
 
@@ -46,22 +212,87 @@ pub struct InlineKeyboardButton {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SendMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub business_connection_id: Option<String>,
     pub chat_id: u64,
     pub text: String,
     pub reply_markup: Option<InlineKeyboardMarkup>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_notification: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to_message_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_sending_without_reply: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendLocation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub business_connection_id: Option<String>,
+    pub chat_id: u64,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+// asks the client to open a reply to the message, so a free-text answer is tied to its question
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForceReply {
+    pub force_reply: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_field_placeholder: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendForceReply {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub business_connection_id: Option<String>,
+    pub chat_id: u64,
+    pub text: String,
+    pub reply_markup: ForceReply,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EditMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub business_connection_id: Option<String>,
     pub chat_id: u64,
     pub message_id: u64,
     pub text: String,
     pub reply_markup: Option<InlineKeyboardMarkup>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditMessageReplyMarkup {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub business_connection_id: Option<String>,
+    pub chat_id: u64,
+    pub message_id: u64,
+    pub reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteBusinessMessages {
+    pub business_connection_id: String,
+    pub message_ids: Vec<u64>,
+}
+
+// body of both pinChatMessage and unpinChatMessage, the latter ignores disable_notification
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinChatMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub business_connection_id: Option<String>,
+    pub chat_id: u64,
+    pub message_id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_notification: Option<bool>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
-    pub id: u64
+    pub id: u64,
+    pub language_code: Option<String>,
+    pub username: Option<String>,
+    pub first_name: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,6 +304,11 @@ pub struct CallbackQuery {
     pub data: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetMeResponse {
+    pub result: User,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GetUpdatesResponse {
     pub result: Vec<Update>,
@@ -83,6 +319,16 @@ pub struct SendMessageResponse {
     pub result: Message,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct File {
+    pub file_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetFileResponse {
+    pub result: File,
+}
+
 #[derive(Debug, Clone)]
 pub struct CommaSeparatedIds(Vec<u64>);
 
@@ -113,12 +359,141 @@ impl FromStr for CommaSeparatedIds {
 pub struct Env {
     #[envconfig(from = "TG_KEY")]
     pub bot_token: String,
+    #[envconfig(from = "LLM_PROVIDER", default = "openai")]
+    pub llm_provider: Provider,
     #[envconfig(from = "OAI_TOKEN")]
-    pub openai_token: String,
+    pub openai_token: Option<String>,
+    #[envconfig(from = "OAI_MODEL", default = "gpt-3.5-turbo")]
+    pub openai_model: String,
+    #[envconfig(from = "OAI_TEMPERATURE")]
+    pub openai_temperature: Option<f32>,
+    #[envconfig(from = "OAI_MAX_TOKENS")]
+    pub openai_max_tokens: Option<u32>,
+    #[envconfig(from = "OAI_BASE_URL")]
+    pub openai_base_url: Option<String>,
+    #[envconfig(from = "OAI_ORG")]
+    pub openai_organization: Option<String>,
+    #[envconfig(from = "OAI_PROJECT")]
+    pub openai_project: Option<String>,
+    #[envconfig(from = "AZURE_API_VERSION", default = "2024-02-01")]
+    pub azure_api_version: String,
+    #[envconfig(from = "OAI_MAX_RETRIES", default = "3")]
+    pub openai_max_retries: u32,
+    #[envconfig(from = "OAI_RETRY_BASE_MS", default = "500")]
+    pub openai_retry_base_ms: u64,
+    #[envconfig(from = "OAI_TIMEOUT_SECS", default = "60")]
+    pub openai_timeout_secs: u64,
+    #[envconfig(from = "OAI_PROMPT_PRICE", default = "0")]
+    pub openai_prompt_price: f64,
+    #[envconfig(from = "OAI_COMPLETION_PRICE", default = "0")]
+    pub openai_completion_price: f64,
+    #[envconfig(from = "PARSER_CACHE_TTL_SECS", default = "60")]
+    pub parser_cache_ttl_secs: u64,
+    #[envconfig(from = "PARSER_FIXTURES")]
+    pub parser_fixtures: Option<ParserFixtures>,
+    #[envconfig(from = "SYSTEM_PROMPT_FILE")]
+    pub system_prompt_file: Option<PathBuf>,
+    // texts the offline parser reads differently from the model while /debug is on, one json line each
+    #[envconfig(from = "PARSER_EVAL_CORPUS")]
+    pub parser_eval_corpus: Option<PathBuf>,
+    #[envconfig(from = "SUMMARY_MODEL")]
+    pub summary_model: Option<String>,
+    // parser requests running at once, and how many of them a single user may hold
+    #[envconfig(from = "PARSER_CONCURRENCY", default = "4")]
+    pub parser_concurrency: usize,
+    #[envconfig(from = "PARSER_USER_CONCURRENCY", default = "1")]
+    pub parser_user_concurrency: usize,
+    #[envconfig(from = "SUMMARIZE_THRESHOLD", default = "1000")]
+    pub summarize_threshold: usize,
+    #[envconfig(from = "DELIVERY_MAX_ATTEMPTS", default = "5")]
+    pub delivery_max_attempts: u32,
+    #[envconfig(from = "FIRE_BATCH_SIZE", default = "100")]
+    pub fire_batch_size: usize,
+    #[envconfig(from = "MISSED_POLICY", default = "prefix")]
+    pub missed_policy: MissedPolicy,
+    #[envconfig(from = "MISSED_THRESHOLD_MINUTES", default = "30")]
+    pub missed_threshold_minutes: u32,
+    #[envconfig(from = "EXPIRY_NOTICE", default = "true")]
+    pub expiry_notice: bool,
+    #[envconfig(from = "URGENT_RESEND_MINUTES", default = "10")]
+    pub urgent_resend_minutes: i64,
+    #[envconfig(from = "URGENT_MAX_RESENDS", default = "3")]
+    pub urgent_max_resends: u32,
+    #[envconfig(from = "CLEANUP_RETENTION_DAYS", default = "30")]
+    pub cleanup_retention_days: i64,
+    #[envconfig(from = "CLEANUP_INTERVAL_SECS", default = "86400")]
+    pub cleanup_interval_secs: u64,
+    #[envconfig(from = "MAINTENANCE_HOUR", default = "4")]
+    pub maintenance_hour: u32,
+    #[envconfig(from = "MONTHLY_TOKEN_BUDGET")]
+    pub monthly_token_budget: Option<u64>,
     #[envconfig(from = "TG_USERS")]
     pub user_ids: CommaSeparatedIds,
+    #[envconfig(from = "ADMIN_ID")]
+    pub admin_id: Option<u64>,
     #[envconfig(from = "CONN_STRING")]
-    pub connection_string: String
+    pub connection_string: String,
+    #[envconfig(from = "SNAPSHOT_INTERVAL_SECS")]
+    pub snapshot_interval_secs: Option<u64>,
+    #[envconfig(from = "SNAPSHOT_PATH")]
+    pub snapshot_path: Option<String>,
+    #[envconfig(from = "SNAPSHOT_HOOK")]
+    pub snapshot_hook: Option<String>,
+    #[envconfig(from = "RESTORE_HOOK")]
+    pub restore_hook: Option<String>,
+    #[envconfig(from = "API_BIND")]
+    pub api_bind: Option<String>,
+    #[envconfig(from = "API_TOKEN")]
+    pub api_token: Option<String>,
+    #[envconfig(from = "HEALTH_BIND")]
+    pub health_bind: Option<String>,
+    #[envconfig(from = "CONFLICT_WINDOW_MINUTES", default = "30")]
+    pub conflict_window_minutes: i64,
+    // seconds telegram holds getUpdates open while there are no updates, kept below the liveness threshold
+    #[envconfig(from = "TG_POLL_TIMEOUT_SECS", default = "30")]
+    pub poll_timeout_secs: u64,
+    #[envconfig(from = "LOG_LEVEL", default = "info")]
+    pub log_level: tracing_subscriber::filter::LevelFilter,
+    #[envconfig(from = "LOG_FORMAT", default = "text")]
+    pub log_format: LogFormat,
+    // message texts and user names are left out of the logs unless this is turned off
+    #[envconfig(from = "LOG_REDACT", default = "true")]
+    pub log_redact: bool,
+    #[envconfig(from = "MESSAGE_PREFIX")]
+    pub message_prefix: Option<String>,
+    #[envconfig(from = "DEMO_MODE", default = "false")]
+    pub demo_mode: bool,
+    #[envconfig(from = "DEMO_TOKEN_BUDGET", default = "50000")]
+    pub demo_token_budget: u64,
+    #[envconfig(from = "DEMO_WIPE_INTERVAL_SECS", default = "3600")]
+    pub demo_wipe_interval_secs: u64,
+    // second instance on a replica of the database: read-only api and probes, no polling and no firing
+    #[envconfig(from = "STANDBY_MODE", default = "false")]
+    pub standby_mode: bool,
+    // encrypts the database with sqlcipher, needs a build with the sqlcipher feature; without it secrets kept in
+    // the database, like the google calendar refresh tokens, are readable by anyone who can read the file
+    #[envconfig(from = "DATABASE_KEY")]
+    pub database_key: Option<String>,
+    #[envconfig(from = "SQLITE_JOURNAL_MODE", default = "wal")]
+    pub sqlite_journal_mode: JournalMode,
+    #[envconfig(from = "SQLITE_SYNCHRONOUS", default = "normal")]
+    pub sqlite_synchronous: Synchronous,
+    #[envconfig(from = "SQLITE_BUSY_TIMEOUT_MS", default = "5000")]
+    pub sqlite_busy_timeout_ms: u64,
+    // calendar weekly reminders toggled to skip holidays follow, the file adds "YYYY-MM-DD name" lines to it
+    #[envconfig(from = "HOLIDAY_COUNTRY", default = "IL")]
+    pub holiday_country: String,
+    #[envconfig(from = "HOLIDAYS_FILE")]
+    pub holidays_file: Option<String>,
+    // oauth client of google calendar sync, the redirect url is <api>/calendar/callback as google reaches it
+    #[envconfig(from = "GOOGLE_CLIENT_ID")]
+    pub google_client_id: Option<String>,
+    #[envconfig(from = "GOOGLE_CLIENT_SECRET")]
+    pub google_client_secret: Option<String>,
+    #[envconfig(from = "GOOGLE_REDIRECT_URL")]
+    pub google_redirect_url: Option<String>,
+    #[envconfig(from = "CALENDAR_PULL_INTERVAL_SECS", default = "900")]
+    pub calendar_pull_interval_secs: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -138,22 +513,38 @@ impl Serialize for Time {
 
 impl<'de> Deserialize<'de> for Time {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
-        let s = <&str>::deserialize(deserializer)?;
+        let s = String::deserialize(deserializer)?;
         let mut parts = s.split(':');
         let hours = parts
             .next()
             .ok_or(D::Error::custom("no hours found"))?
             .parse::<u8>()
-            .map_err(|x| D::Error::custom(x))?;
+            .map_err(D::Error::custom)?;
         let minutes = parts
             .next()
             .ok_or(D::Error::custom("no minutes found"))?
             .parse::<u8>()
-            .map_err(|x| D::Error::custom(x))?;
+            .map_err(D::Error::custom)?;
         Ok(Time { hours, minutes })
     }
 }
 
+// timezone of users who haven't set one with /timezone, the canonical name of israel time that calendar clients know
+pub const DEFAULT_TIMEZONE: Tz = chrono_tz::Asia::Jerusalem;
+
+thread_local! {
+    // wall clock formatted times are read and written in, serde gives no way to pass it along
+    static WALL_CLOCK: Cell<Tz> = const { Cell::new(DEFAULT_TIMEZONE) };
+}
+
+// runs the (de)serialization of a user's notifications with their times on the user's wall clock
+pub fn on_wall_clock<T>(timezone: Tz, f: impl FnOnce() -> T) -> T {
+    let previous = WALL_CLOCK.replace(timezone);
+    let result = f();
+    WALL_CLOCK.set(previous);
+    result
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FormattedTime {
     pub time: DateTime<Utc>
@@ -162,19 +553,19 @@ pub struct FormattedTime {
 // should be formatted like 21.07.2022 15:00
 impl Serialize for FormattedTime {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
-        let naive = self.time.naive_utc();
-        let israel_time = chrono_tz::Israel.from_utc_datetime(&naive);
-        serializer.serialize_str(&format!("{}", israel_time.format("%d.%m.%Y %H:%M:%S")))
+        let local = self.time.with_timezone(&WALL_CLOCK.get());
+        serializer.serialize_str(&format!("{}", local.format("%d.%m.%Y %H:%M:%S")))
     }
 }
 
 impl <'de> Deserialize<'de> for FormattedTime {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
-        let s = <&str>::deserialize(deserializer)?;
+        let s = String::deserialize(deserializer)?;
+        let timezone = WALL_CLOCK.get();
         // deserialize in "%d.%m.%Y %H:%M" or "%d.%m.%Y %H:%M" format
-        let time = chrono_tz::Israel.datetime_from_str(s, "%d.%m.%Y %H:%M")
-            .or_else(|_| chrono_tz::Israel.datetime_from_str(s, "%d.%m.%Y %H:%M:%S"))
-            .map_err(|x| D::Error::custom(x))?;
+        let time = timezone.datetime_from_str(&s, "%d.%m.%Y %H:%M")
+            .or_else(|_| timezone.datetime_from_str(&s, "%d.%m.%Y %H:%M:%S"))
+            .map_err(D::Error::custom)?;
         let time = time.naive_utc();
         let time = Utc.from_utc_datetime(&time);
 
@@ -182,30 +573,209 @@ impl <'de> Deserialize<'de> for FormattedTime {
     }
 }
 
+// how insistently a reminder is delivered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    // arrives silently
+    Low,
+    #[default]
+    Normal,
+    // rings and is sent again until marked done
+    Urgent,
+}
+
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::Urgent => "urgent",
+        }
+    }
+
+    pub fn is_normal(&self) -> bool {
+        *self == Priority::Normal
+    }
+
+    // none leaves the sound to the chat settings
+    pub fn disable_notification(&self) -> Option<bool> {
+        match self {
+            Priority::Low => Some(true),
+            Priority::Normal => None,
+            Priority::Urgent => Some(false),
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = BotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Priority::Low),
+            "normal" => Ok(Priority::Normal),
+            "urgent" => Ok(Priority::Urgent),
+            _ => Err(BotError::UnknownPriority(s.to_string())),
+        }
+    }
+}
+
+// daily window of the user's timezone in which non-urgent reminders are held back, may wrap over midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct QuietHours {
+    // minutes since local midnight
+    pub start: u16,
+    pub end: u16,
+}
+
+impl QuietHours {
+    fn local_minute(now: DateTime<Utc>, timezone: Tz) -> u16 {
+        let local = now.with_timezone(&timezone);
+        (local.hour() * 60 + local.minute()) as u16
+    }
+
+    pub fn contains(&self, now: DateTime<Utc>, timezone: Tz) -> bool {
+        let minute = QuietHours::local_minute(now, timezone);
+        if self.start < self.end {
+            minute >= self.start && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+
+    // next time the window is over, falls back to utc on nonexistent local times
+    pub fn end_after(&self, now: DateTime<Utc>, timezone: Tz) -> DateTime<Utc> {
+        let today = now.with_timezone(&timezone).date_naive();
+        today.iter_days()
+            .filter_map(|date| date.and_hms_opt((self.end / 60) as u32, (self.end % 60) as u32, 0))
+            .map(|local| timezone.from_local_datetime(&local).earliest()
+                .map_or_else(|| Utc.from_utc_datetime(&local), |time| time.with_timezone(&Utc)))
+            .find(|time| *time > now)
+            .unwrap_or(now)
+    }
+}
+
+impl std::fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}:{:02}-{:02}:{:02}", self.start / 60, self.start % 60, self.end / 60, self.end % 60)
+    }
+}
+
+// as in 23:00-08:00
+impl FromStr for QuietHours {
+    type Err = BotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || BotError::InvalidQuietHours(s.to_string());
+        let minute = |time: &str| chrono::NaiveTime::parse_from_str(time.trim(), "%H:%M")
+            .map(|time| (time.hour() * 60 + time.minute()) as u16)
+            .map_err(|_| invalid());
+        let (start, end) = s.split_once(['-', '–']).ok_or_else(invalid)?;
+        let quiet_hours = QuietHours { start: minute(start)?, end: minute(end)? };
+        if quiet_hours.start == quiet_hours.end {
+            return Err(invalid());
+        }
+        Ok(quiet_hours)
+    }
+}
+
+impl From<QuietHours> for String {
+    fn from(quiet_hours: QuietHours) -> String {
+        quiet_hours.to_string()
+    }
+}
+
+impl TryFrom<String> for QuietHours {
+    type Error = BotError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+// how a reminder is delivered once it fires
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Delivery {
+    pub priority: Priority,
+    // minutes between repeats until the reminder is marked done, 0 sends it once
+    pub nag_minutes: u32,
+    // minutes after the due time the reminder is still worth sending, later it expires; 0 never expires
+    pub expires_minutes: u32,
+    // chosen on the draft, never rings whatever the priority
+    pub silent: bool,
+}
+
+impl Delivery {
+    pub fn disable_notification(&self) -> Option<bool> {
+        if self.silent { Some(true) } else { self.priority.disable_notification() }
+    }
+
+    // urgent and nagging reminders are sent again until the done button is pressed
+    pub fn awaits_done(&self) -> bool {
+        self.priority == Priority::Urgent || self.nag_minutes > 0
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "kind")]
 pub enum Notification {
     #[serde(rename = "absolute")]
     Absolute {
         text: String,
-        times: Vec<FormattedTime>
+        times: Vec<FormattedTime>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        leads: Vec<u32>,
+        #[serde(default, skip_serializing_if = "Priority::is_normal")]
+        priority: Priority,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nag: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        valid: Option<u32>,
     },
     #[serde(rename = "relative")]
     Relative {
         text: String,
         week: u8,
         days: ArrayVec<u8, 7>,
-        times: Vec<Time>
+        times: Vec<Time>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        leads: Vec<u32>,
+        #[serde(default, skip_serializing_if = "Priority::is_normal")]
+        priority: Priority,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nag: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        valid: Option<u32>,
     },
-    #[serde(rename = "reccurrent")]
+    #[serde(rename = "recurrent")]
     Recurrent {
         text: String,
         days: Option<ArrayVec<u8, 7>>,
-        times: Vec<Time>
+        times: Vec<Time>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        leads: Vec<u32>,
+        #[serde(default, skip_serializing_if = "Priority::is_normal")]
+        priority: Priority,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nag: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        valid: Option<u32>,
+        // the working days of the bot timezone instead of `days`, optionally without the holidays of HOLIDAY_COUNTRY
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        workdays: bool,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        skip_holidays: bool,
+    },
+    // asks to cancel a stored reminder, the text is matched against the user's reminders
+    #[serde(rename = "cancel")]
+    Cancel {
+        text: String,
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum StoredNotification {
     Absolute {
         time: DateTime<Utc>,
@@ -214,6 +784,26 @@ pub enum StoredNotification {
         hours: u8,
         minutes: u8,
         days: Option<ArrayVec<u8, 7>>,
+    },
+    // heads-up sent the given number of minutes before the main reminder, the inner notification is when it fires
+    Lead {
+        minutes: u32,
+        notification: Box<StoredNotification>,
+    },
+}
+
+impl StoredNotification {
+    // the same reminder moved by the given number of minutes, weekly ones can move to another day
+    pub fn shifted(&self, offset_minutes: i32) -> StoredNotification {
+        match self {
+            StoredNotification::Absolute { time } => StoredNotification::Absolute { time: *time + Duration::minutes(offset_minutes as i64) },
+            StoredNotification::Recurrent { hours, minutes, days } => {
+                let (shifted_days, hours, minutes) = shift_weekly(days.as_ref().map_or(&[][..], |days| days.as_slice()), *hours, *minutes, offset_minutes);
+                StoredNotification::Recurrent { hours, minutes, days: days.as_ref().map(|_| shifted_days) }
+            }
+            StoredNotification::Lead { minutes, notification } =>
+                StoredNotification::Lead { minutes: *minutes, notification: Box::new(notification.shifted(offset_minutes)) },
+        }
     }
 }
 
@@ -223,53 +813,173 @@ impl Notification {
             Notification::Absolute { text, .. } => text.as_str(),
             Notification::Relative { text, .. } => text.as_str(),
             Notification::Recurrent { text, .. } => text.as_str(),
+            Notification::Cancel { text } => text.as_str(),
+        }
+    }
+
+    pub fn get_leads(&self) -> &[u32] {
+        match self {
+            Notification::Absolute { leads, .. } => leads,
+            Notification::Relative { leads, .. } => leads,
+            Notification::Recurrent { leads, .. } => leads,
+            Notification::Cancel { .. } => &[],
+        }
+    }
+
+    pub fn skips_holidays(&self) -> bool {
+        matches!(self, Notification::Recurrent { skip_holidays: true, .. })
+    }
+
+    pub fn get_delivery(&self) -> Delivery {
+        match self {
+            Notification::Absolute { priority, nag, valid, .. }
+            | Notification::Relative { priority, nag, valid, .. }
+            | Notification::Recurrent { priority, nag, valid, .. } =>
+                Delivery { priority: *priority, nag_minutes: nag.unwrap_or(0), expires_minutes: valid.unwrap_or(0), silent: false },
+            Notification::Cancel { .. } => Delivery::default(),
         }
     }
 
-    pub fn create_stored_notifications(&self, current_time: DateTime<Utc>) -> Vec<StoredNotification> {
+    // main notifications followed by a heads-up for every lead time, weekly times are read on the wall clock of the timezone
+    pub fn create_stored_notifications(&self, current_time: DateTime<Utc>, timezone: Tz) -> Vec<StoredNotification> {
+        let notifications = self.create_main_notifications(current_time, timezone);
+        let leads = self.get_leads().iter()
+            .filter(|lead| **lead > 0)
+            .flat_map(|lead| notifications.iter().map(move |notification| StoredNotification::Lead {
+                minutes: *lead,
+                notification: Box::new(notification.shifted(-(*lead as i32))),
+            }))
+            .collect::<Vec<_>>();
+        notifications.into_iter().chain(leads).collect()
+    }
+
+    fn create_main_notifications(&self, current_time: DateTime<Utc>, timezone: Tz) -> Vec<StoredNotification> {
         match self {
             Notification::Absolute { times, .. } =>
                 times.iter()
                     .map(|time| StoredNotification::Absolute { time: time.time })
                     .collect(),
             Notification::Relative {  week, days, times, .. } => {
-                let current_day_of_week = (current_time.weekday().num_days_from_monday() + 1) as u8;
+                let local_time = current_time.with_timezone(&timezone);
+                let current_day_of_week = (local_time.weekday().num_days_from_monday() + 1) as u8;
                 let has_any_day_in_past = days.iter().any(|day| *day <= current_day_of_week);
                 let week = if *week == 0 && has_any_day_in_past { 1 } else { *week };
-                let monday = current_time
+                let monday = local_time
                     - Duration::days((current_day_of_week - 1) as i64)
                     + Duration::weeks(week as i64);
                 days.iter()
-                    .map(|x| (monday + Duration::days((*x - 1) as i64)))
+                    .map(|x| monday + Duration::days((*x - 1) as i64))
                     .flat_map(|x| times.iter().map(move |time| (x, time)))
                     .filter_map(|(x, time)| Some(StoredNotification::Absolute {
-                        time: x.with_hour(time.hours as u32)?.with_minute(time.minutes as u32)?
+                        time: x.with_hour(time.hours as u32)?.with_minute(time.minutes as u32)?.with_timezone(&Utc)
                     }))
                     .collect()
             }
-            Notification::Recurrent { days, times, .. } => {
+            Notification::Recurrent { days, times, workdays, .. } => {
+                // the model answers in the user's timezone while recurrent events are stored and fired in utc
+                let days = match days {
+                    _ if *workdays => WORKDAYS.into_iter().collect(),
+                    Some(days) => days.clone(),
+                    None => (1..=7).collect(),
+                };
+                let offset = timezone.offset_from_utc_datetime(&current_time.naive_utc()).fix().local_minus_utc() / 60;
                 times
                     .iter()
-                    .map(|x| StoredNotification::Recurrent {
-                        hours: x.hours,
-                        minutes: x.minutes,
-                        days: days.clone()
+                    .map(|x| {
+                        let (days, hours, minutes) = shift_weekly(&days, x.hours, x.minutes, -offset);
+                        StoredNotification::Recurrent { hours, minutes, days: Some(days) }
                     })
                     .collect()
             }
+            Notification::Cancel { .. } => vec![],
         }
     }
 }
 
+// sunday to thursday, the working week of the bot timezone
+pub const WORKDAYS: [u8; 5] = [7, 1, 2, 3, 4];
+
+// moves a weekly schedule by the offset in minutes, wrapping days around the week
+pub fn shift_weekly(days: &[u8], hours: u8, minutes: u8, offset_minutes: i32) -> (ArrayVec<u8, 7>, u8, u8) {
+    let total = hours as i32 * 60 + minutes as i32 + offset_minutes;
+    let day_shift = total.div_euclid(24 * 60);
+    let total = total.rem_euclid(24 * 60);
+    let days = days.iter()
+        .map(|day| ((*day as i32 - 1 + day_shift).rem_euclid(7) + 1) as u8)
+        .collect();
+    (days, (total / 60) as u8, (total % 60) as u8)
+}
+
 #[derive(Debug)]
 pub struct EventToFire {
     pub event_id: u64,
+    pub event_uid: String,
     pub user_id: u64,
     pub text: String,
+    pub lead_minutes: u32,
+    pub delivery: Delivery,
+    pub is_recurrent: bool,
+    pub quote: Option<String>,
+    // message the reminder was created from, the notification replies to it
+    pub source_message_id: Option<u64>,
+    // when the occurrence was scheduled, none for held back and resent reminders
+    pub due_at: Option<DateTime<Utc>>,
+    // set by the background loop for reminders sent long after due_at, shown before the text
+    pub overdue_minutes: u32,
+}
+
+// what happens to reminders that are more than MISSED_THRESHOLD_MINUTES late, e.g. after downtime
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MissedPolicy {
+    // sent like the ones on time
+    Deliver,
+    // sent with how late they are
+    Prefix,
+    // rolled up into one message per user
+    Summary,
+    // closed without a message
+    Expire,
+}
+
+impl FromStr for MissedPolicy {
+    type Err = BotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deliver" => Ok(MissedPolicy::Deliver),
+            "prefix" => Ok(MissedPolicy::Prefix),
+            "summary" => Ok(MissedPolicy::Summary),
+            "expire" => Ok(MissedPolicy::Expire),
+            _ => Err(BotError::UnknownMissedPolicy(s.to_string()))
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use chrono::{DateTime, Utc};
+    use super::QuietHours;
+
+    #[test]
+    fn should_hold_within_quiet_hours_over_midnight() {
+        let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        let quiet_hours = "23:00-08:00".parse::<QuietHours>().unwrap();
+        assert_eq!(quiet_hours.to_string(), "23:00-08:00");
+        // israel is utc+2 in winter
+        let israel = chrono_tz::Israel;
+        assert!(quiet_hours.contains(at("2023-01-26T01:00:00Z"), israel));
+        assert!(quiet_hours.contains(at("2023-01-26T21:00:00Z"), israel));
+        assert!(!quiet_hours.contains(at("2023-01-26T06:00:00Z"), israel));
+        assert_eq!(quiet_hours.end_after(at("2023-01-26T01:00:00Z"), israel), at("2023-01-26T06:00:00Z"));
+        assert_eq!(quiet_hours.end_after(at("2023-01-26T21:00:00Z"), israel), at("2023-01-27T06:00:00Z"));
+        // and new york is utc-5
+        let new_york = chrono_tz::America::New_York;
+        assert!(!quiet_hours.contains(at("2023-01-26T01:00:00Z"), new_york));
+        assert!(quiet_hours.contains(at("2023-01-26T06:00:00Z"), new_york));
+        assert_eq!(quiet_hours.end_after(at("2023-01-26T06:00:00Z"), new_york), at("2023-01-26T13:00:00Z"));
+        assert!("08:00-08:00".parse::<QuietHours>().is_err());
+        assert!("late".parse::<QuietHours>().is_err());
+    }
 
     #[test]
     fn should_parse_notification_from_json() {
@@ -279,4 +989,100 @@ mod tests {
         let notification: super::Notification = serde_json::from_str(json).unwrap();
         assert_eq!(notification.get_text(), "testing the bot");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn should_parse_business_updates() {
+        let json = r#"{"update_id": 1, "business_message": {"message_id": 5, "date": 0, "chat": {"id": 42},
+            "from": {"id": 42, "first_name": "Dana"}, "text": "call me tomorrow", "business_connection_id": "conn"}}"#;
+        let update: super::Update = serde_json::from_str(json).unwrap();
+        let message = update.business_message.unwrap();
+        assert_eq!(message.business_connection_id.as_deref(), Some("conn"));
+        assert_eq!(message.from.unwrap().first_name.as_deref(), Some("Dana"));
+
+        let json = r#"{"update_id": 2, "business_connection": {"id": "conn", "user": {"id": 7}, "user_chat_id": 7, "is_enabled": false, "date": 0}}"#;
+        let update: super::Update = serde_json::from_str(json).unwrap();
+        assert!(!update.business_connection.unwrap().is_enabled);
+    }
+
+    #[test]
+    fn should_redact_texts_and_names_of_update() {
+        let json = r#"{"update_id": 3, "message": {"message_id": 5, "date": 0, "chat": {"id": 42},
+            "from": {"id": 42, "first_name": "Dana", "username": "dana"}, "text": "call mom"}}"#;
+        let update: super::Update = serde_json::from_str(json).unwrap();
+        let logged = format!("{:?}", update.redacted());
+        assert!(!logged.contains("call mom") && !logged.contains("Dana"));
+        assert!(logged.contains("<8 chars>") && logged.contains("id: 42"));
+        assert_eq!(update.message.unwrap().text.as_deref(), Some("call mom"));
+    }
+
+    #[test]
+    fn should_route_inline_query_to_its_sender() {
+        let json = r#"{"update_id": 5, "inline_query": {"id": "q1", "from": {"id": 42, "language_code": "ru"}, "query": "call mom tomorrow 9am", "offset": ""}}"#;
+        let update: super::Update = serde_json::from_str(json).unwrap();
+        assert_eq!(update.get_chat_id(), Some(42));
+        assert_eq!(update.get_language_code(), Some("ru"));
+        assert!(!format!("{:?}", update.redacted()).contains("call mom"));
+
+        let answer = super::AnswerInlineQuery {
+            inline_query_id: "q1".to_string(),
+            results: vec![super::InlineQueryResultArticle {
+                kind: "article".to_string(),
+                id: "create".to_string(),
+                title: "Create reminder".to_string(),
+                description: None,
+                input_message_content: super::InputTextMessageContent { message_text: "call mom".to_string() },
+            }],
+            cache_time: 0,
+            is_personal: true,
+        };
+        let json = serde_json::to_value(&answer).unwrap();
+        assert_eq!(json["results"][0]["type"], "article");
+        assert_eq!(json["results"][0]["input_message_content"]["message_text"], "call mom");
+    }
+
+    #[test]
+    fn should_take_location_of_shared_venue() {
+        let json = r#"{"update_id": 4, "message": {"message_id": 6, "date": 0, "chat": {"id": 42},
+            "location": {"latitude": 32.08, "longitude": 34.78},
+            "venue": {"location": {"latitude": 32.08, "longitude": 34.78}, "title": "School", "address": "Dizengoff 1"}}}"#;
+        let update: super::Update = serde_json::from_str(json).unwrap();
+        let message = update.message.as_ref().unwrap();
+        assert_eq!(message.shared_location(), Some(super::Location { latitude: 32.08, longitude: 34.78 }));
+        let logged = format!("{:?}", update.redacted());
+        assert!(!logged.contains("Dizengoff") && !logged.contains("32.08"));
+    }
+
+    #[test]
+    fn should_serialize_force_reply_prompt() {
+        let prompt = super::SendForceReply {
+            business_connection_id: None,
+            chat_id: 42,
+            text: "When?".to_string(),
+            reply_markup: super::ForceReply { force_reply: true, input_field_placeholder: None },
+        };
+        let json = serde_json::to_value(&prompt).unwrap();
+        assert_eq!(json, serde_json::json!({"chat_id": 42, "text": "When?", "reply_markup": {"force_reply": true}}));
+    }
+
+    #[test]
+    fn should_shift_weekly_schedule_across_days() {
+        let (days, hours, minutes) = super::shift_weekly(&[1, 7], 1, 30, -120);
+        assert_eq!((days.as_slice(), hours, minutes), (&[7, 6][..], 23, 30));
+        let (days, hours, minutes) = super::shift_weekly(&[7], 23, 0, 180);
+        assert_eq!((days.as_slice(), hours, minutes), (&[1][..], 2, 0));
+
+        let shifted = super::StoredNotification::Recurrent { hours: 23, minutes: 45, days: Some([5].into_iter().collect()) }.shifted(30);
+        assert!(matches!(shifted, super::StoredNotification::Recurrent { hours: 0, minutes: 15, days: Some(days) } if days.as_slice() == [6]));
+    }
+
+    #[test]
+    fn should_store_workdays_recurrence_on_working_week() {
+        let now = DateTime::parse_from_rfc3339("2023-01-26T14:40:00+02:00").unwrap().with_timezone(&Utc);
+        let notification: super::Notification = serde_json::from_str("{\"kind\": \"recurrent\", \"text\": \"standup\", \"days\": null, \"times\": [\"09:30\"], \"workdays\": true, \"skip_holidays\": true}").unwrap();
+        assert!(notification.skips_holidays());
+        let stored = notification.create_stored_notifications(now, chrono_tz::Israel);
+        // 09:30 in israel is 07:30 utc in winter, the same days
+        assert!(matches!(stored.as_slice(), [super::StoredNotification::Recurrent { hours: 7, minutes: 30, days: Some(days) }] if days.as_slice() == super::WORKDAYS));
+        assert_eq!(serde_json::to_value(&notification).unwrap()["workdays"], true);
+    }
+}
@@ -1,19 +1,232 @@
-use chrono::{DateTime, TimeZone, Utc};
-use log::info;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use arrayvec::ArrayVec;
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use std::fmt::{Display, Formatter, Write};
+use std::hash::{Hash, Hasher};
+use fnv::{FnvHashMap, FnvHashSet, FnvHasher};
+use tracing::{debug, info, warn};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use crate::db::ParseCorrection;
 use crate::errors::BotError;
-use crate::models::Notification;
+use crate::fixtures::{FixtureMode, ParserFixtures};
+use crate::models::{FormattedTime, Notification, Priority, Time};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    OpenAI,
+    AzureOpenAI,
+    Anthropic,
+    Ollama,
+}
+
+impl Provider {
+    fn default_base_url(&self) -> &'static str {
+        match self {
+            Provider::OpenAI => "https://api.openai.com/v1",
+            // azure endpoints are per-resource, so base url has to be configured explicitly
+            Provider::AzureOpenAI => "",
+            Provider::Anthropic => "https://api.anthropic.com/v1",
+            Provider::Ollama => "http://localhost:11434",
+        }
+    }
+}
+
+impl Display for Provider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Provider::OpenAI => "openai",
+            Provider::AzureOpenAI => "azure",
+            Provider::Anthropic => "anthropic",
+            Provider::Ollama => "ollama",
+        })
+    }
+}
+
+impl FromStr for Provider {
+    type Err = BotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "openai" => Ok(Provider::OpenAI),
+            "azure" => Ok(Provider::AzureOpenAI),
+            "anthropic" => Ok(Provider::Anthropic),
+            "ollama" => Ok(Provider::Ollama),
+            _ => Err(BotError::UnknownProvider(s.to_string()))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ModelOptions {
+    pub provider: Provider,
+    pub model: String,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub base_url: Option<String>,
+    // sent as OpenAI-Organization and OpenAI-Project, gateways use them to attribute usage
+    pub organization: Option<String>,
+    pub project: Option<String>,
+    pub api_version: String,
+    pub max_retries: u32,
+    pub retry_base_delay: Duration,
+    pub timeout: Duration,
+    // prices per 1000 tokens, used only for usage accounting
+    pub prompt_price: f64,
+    pub completion_price: f64,
+    // zero disables caching of completions
+    pub cache_ttl: Duration,
+    // cheaper model for summarizing long messages, the main one is used when not set
+    pub summary_model: Option<String>,
+    pub fixtures: Option<ParserFixtures>,
+    // system prompt with its few-shot examples, the built-in one is used when not set
+    pub prompt_file: Option<PathBuf>,
+}
+
+impl ModelOptions {
+    fn base_url(&self) -> &str {
+        self.base_url.as_deref()
+            .unwrap_or_else(|| self.provider.default_base_url())
+            .trim_end_matches('/')
+    }
+
+    // settings the provider can't work without are reported at startup instead of on the first message;
+    // replayed fixtures never reach the provider, so they need neither a key nor an endpoint
+    fn validate(&self, api_key: Option<&str>) -> Result<(), BotError> {
+        if matches!(&self.fixtures, Some(fixtures) if fixtures.mode == FixtureMode::Replay) {
+            return Ok(());
+        }
+        let base_url = self.base_url();
+        if base_url.is_empty() {
+            return Err(BotError::Config(format!("OAI_BASE_URL is required for the {} provider", self.provider)));
+        }
+        match reqwest::Url::parse(base_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            _ => return Err(BotError::Config(format!("OAI_BASE_URL {} is not an http(s) url", base_url))),
+        }
+        if self.provider != Provider::Ollama && api_key.is_none_or(str::is_empty) {
+            return Err(BotError::Config(format!("OAI_TOKEN is required for the {} provider", self.provider)));
+        }
+        if self.provider == Provider::AzureOpenAI && self.api_version.is_empty() {
+            return Err(BotError::Config("AZURE_API_VERSION is required for the azure provider".to_string()));
+        }
+        Ok(())
+    }
+}
+
+// completions for identical requests made within the same time bucket
+#[derive(Debug)]
+struct CompletionCache {
+    ttl: Duration,
+    entries: FnvHashMap<CacheKey, (Instant, Completion)>,
+}
+
+// the prompt differs per user, so one user's completion is never handed to another;
+// the text is kept as written, case and spacing can change the reading, and a new
+// correction added to the prompt or another timezone makes it a different request
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    user_id: u64,
+    timezone: Tz,
+    text: String,
+    corrections: u64,
+    bucket: i64,
+}
+
+impl CompletionCache {
+    const MAX_ENTRIES: usize = 256;
+
+    fn new(ttl: Duration) -> CompletionCache {
+        CompletionCache { ttl, entries: FnvHashMap::default() }
+    }
+
+    // requests in the same bucket share "now", so relative phrases resolve to the same times
+    fn key(&self, user_id: u64, current_date: DateTime<Utc>, timezone: Tz, text: &str, corrections: &[&ParseCorrection]) -> CacheKey {
+        let bucket = current_date.timestamp() / self.ttl.as_secs().max(1) as i64;
+        let mut hasher = FnvHasher::default();
+        for correction in corrections {
+            (&correction.input, &correction.accepted, correction.parsed_at).hash(&mut hasher);
+        }
+        CacheKey { user_id, timezone, text: text.to_string(), corrections: hasher.finish(), bucket }
+    }
+
+    fn get(&self, key: &CacheKey) -> Option<Completion> {
+        self.entries.get(key)
+            .filter(|(inserted, _)| inserted.elapsed() < self.ttl)
+            .map(|(_, completion)| Completion { content: completion.content.clone(), usage: Usage::default() })
+    }
+
+    fn insert(&mut self, key: CacheKey, completion: Completion) {
+        let ttl = self.ttl;
+        self.entries.retain(|_, (inserted, _)| inserted.elapsed() < ttl);
+        if self.entries.len() >= Self::MAX_ENTRIES {
+            return;
+        }
+        self.entries.insert(key, (Instant::now(), completion));
+    }
+}
+
+// the file is read again whenever its modification time changes, so the prompt can be tuned without a restart;
+// the built-in prompt is used until the file is read once, a broken edit keeps the last good prompt
+#[derive(Debug)]
+struct PromptFile {
+    path: PathBuf,
+    loaded: Mutex<(Option<SystemTime>, Option<Arc<str>>)>,
+}
+
+impl PromptFile {
+    fn new(path: PathBuf) -> PromptFile {
+        PromptFile { path, loaded: Mutex::new((None, None)) }
+    }
+
+    fn current(&self) -> Option<Arc<str>> {
+        let mut loaded = self.loaded.lock().unwrap();
+        let modified = match std::fs::metadata(&self.path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(err) => {
+                if loaded.0.take().is_some() {
+                    warn!("System prompt file {} is gone, keeping the last prompt: {}", self.path.display(), err);
+                }
+                return loaded.1.clone();
+            }
+        };
+        if loaded.0 != Some(modified) {
+            loaded.0 = Some(modified);
+            match std::fs::read_to_string(&self.path) {
+                Ok(prompt) if !prompt.trim().is_empty() => {
+                    info!("Loaded system prompt from {}", self.path.display());
+                    loaded.1 = Some(Arc::from(prompt.replace('\r', "")));
+                }
+                Ok(_) => warn!("System prompt file {} is empty, keeping the last prompt", self.path.display()),
+                Err(err) => warn!("Couldn't read system prompt file {}, keeping the last prompt: {}", self.path.display(), err),
+            }
+        }
+        loaded.1.clone()
+    }
+}
 
 #[derive(Clone)]
-pub struct OpenAIParser {
-    pub api_key: String,
+pub struct LlmParser {
+    pub api_key: Option<String>,
+    pub options: ModelOptions,
     pub client: reqwest::Client,
+    cache: Option<Arc<Mutex<CompletionCache>>>,
+    prompt_file: Option<Arc<PromptFile>>,
 }
 
 #[derive(Debug, Serialize)]
 struct OpenAIChatRequest {
     model: String,
     messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,6 +238,14 @@ struct Message {
 #[derive(Debug, Deserialize)]
 struct OpenAIChatResponse {
     choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<OpenAIUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
 }
 
 #[derive(Debug, Deserialize)]
@@ -32,17 +253,118 @@ struct Choice {
     message: Message
 }
 
-impl OpenAIParser {
-    pub fn new(api_key: String) -> OpenAIParser {
-        let client = reqwest::Client::new();
-        OpenAIParser { api_key, client }
+#[derive(Debug, Serialize)]
+struct AnthropicRequest {
+    model: String,
+    system: String,
+    messages: Vec<Message>,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicResponse {
+    content: Vec<AnthropicContent>,
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicContent {
+    text: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaChatRequest {
+    model: String,
+    messages: Vec<Message>,
+    stream: bool,
+    options: OllamaOptions,
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaChatResponse {
+    message: Message,
+    #[serde(default)]
+    prompt_eval_count: u64,
+    #[serde(default)]
+    eval_count: u64,
+}
+
+// tokens billed for a single completion
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub content: String,
+    pub usage: Usage,
+}
+
+impl LlmParser {
+    // anthropic requires max_tokens to be set explicitly
+    const DEFAULT_MAX_TOKENS: u32 = 1024;
+
+    // upper bound for a single backoff sleep regardless of attempt number
+    const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+    pub fn new(api_key: Option<String>, options: ModelOptions) -> Result<LlmParser, BotError> {
+        options.validate(api_key.as_deref())?;
+        let client = reqwest::Client::builder()
+            .timeout(options.timeout)
+            .build()?;
+        let cache = if options.cache_ttl.is_zero() {
+            None
+        } else {
+            Some(Arc::new(Mutex::new(CompletionCache::new(options.cache_ttl))))
+        };
+        let prompt_file = options.prompt_file.clone().map(|path| Arc::new(PromptFile::new(path)));
+        if let Some(prompt_file) = &prompt_file {
+            if prompt_file.current().is_none() {
+                warn!("System prompt file {} can't be read, using the built-in prompt", prompt_file.path.display());
+            }
+        }
+        Ok(LlmParser { api_key, options, client, cache, prompt_file })
+    }
+
+    fn system_prompt(&self) -> Arc<str> {
+        self.prompt_file.as_ref()
+            .and_then(|prompt_file| prompt_file.current())
+            .unwrap_or_else(|| Arc::from(Self::SYSTEM_PROMPT))
     }
 
     const SYSTEM_PROMPT: &'static str = "You are an assistant tasked with converting user queries into json formatted notifications. You shouldn't comment on the query, just output the json. 
 
-Examples of how notifications should be parsed into two possible types:
+Examples of how notifications should be parsed into three possible types:
 Type 1: absolute date and time of format {\"kind\": \"absolute\", \"text\": \"string\", \"times\": [\"22.07.2022 03:37:01\"]}
-Type 2: relative to current date and time of format {\"kind\": \"relative\", \"text\": \"string\", \"week\": 0, \"days\": [5], \"time\": \"12:00\"}
+Type 2: relative to current date and time of format {\"kind\": \"relative\", \"text\": \"string\", \"week\": 0, \"days\": [5], \"times\": [\"12:00\"]}
+Type 3: recurrent every week on given days (1 is Monday, null means every day) of format {\"kind\": \"recurrent\", \"text\": \"string\", \"days\": [1, 3], \"times\": [\"09:00\"]}
+A recurrent reminder on working days has \"workdays\": true and \"days\": null, with \"skip_holidays\": true when public holidays are to be left out too.
+Any type may also have \"leads\": minutes before every time to send an early heads-up, for example \"leads\": [30]. Leave it out when no heads-up is asked for.
+Any type may also have \"priority\": \"urgent\" when the reminder is important or must not be missed, \"low\" when it is minor and may arrive silently. Leave it out otherwise.
+Any type may also have \"nag\": minutes between repeats when the user wants to be reminded again and again until they confirm it is done. Leave it out otherwise.
+Any type may also have \"valid\": minutes after every time during which the reminder is still useful, when the user says it is pointless later. Leave it out otherwise.
+Keep hashtags like #work in the \"text\" field exactly as they are written.
+When the user asks to cancel or delete an existing reminder, answer {\"kind\": \"cancel\", \"text\": \"string\"} with the words describing that reminder.
+When the query can reasonably be understood in more than one way, the answer may also have \"alternatives\": up to two other readings in the same format, the most likely reading goes first. Leave it out when the query is clear.
 
 Examples of queries:
 
@@ -64,78 +386,652 @@ Answer: {\"kind\": \"relative\", \"text\": \"позвонить Алексу\",
 Current time is \"25.02.2023 18:00:00, Tuesday\"
 'Через два и три часа напомни мне проверить плиту'
 
-Answer: {\"kind\": \"absolute\", \"text\": \"проверить плиту\", \"times\": [\"25.02.2023 20:00:00\", \"25.02.2023 21:00:00\"]}";
+Answer: {\"kind\": \"absolute\", \"text\": \"проверить плиту\", \"times\": [\"25.02.2023 20:00:00\", \"25.02.2023 21:00:00\"]}
+
+Current time is \"26.01.2023 14:40:00, Thursday\"
+Напоминай мне каждый день в 9 утра пить таблетки
+
+Answer: {\"kind\": \"recurrent\", \"text\": \"пить таблетки\", \"days\": null, \"times\": [\"09:00\"]}
 
-    fn create_prompt(current_date: DateTime<Utc>, text: &str) -> (String, String) {
-        let current_date_as_naive = current_date.naive_utc();
-        let current_date = chrono_tz::Israel.from_utc_datetime(&current_date_as_naive);
+Current time is \"26.01.2023 14:40:00, Thursday\"
+Remind me to \"water the plants\" every Monday and Thursday at 19:00
+
+Answer: {\"kind\": \"recurrent\", \"text\": \"water the plants\", \"days\": [1, 4], \"times\": [\"19:00\"]}
+
+Current time is \"26.01.2023 14:40:00, Thursday\"
+Every working day at 9:30 remind me about the standup, except on holidays
+
+Answer: {\"kind\": \"recurrent\", \"text\": \"the standup\", \"days\": null, \"times\": [\"09:30\"], \"workdays\": true, \"skip_holidays\": true}
+
+Current time is \"26.01.2023 14:40:00, Thursday\"
+Remind me about the dentist tomorrow at 10:00, warn me 30 minutes and an hour before
+
+Answer: {\"kind\": \"absolute\", \"text\": \"the dentist\", \"times\": [\"27.01.2023 10:00:00\"], \"leads\": [30, 60]}
+
+Current time is \"26.01.2023 14:40:00, Thursday\"
+Urgent: remind me to take the cake out of the oven in 40 minutes
+
+Answer: {\"kind\": \"absolute\", \"text\": \"take the cake out of the oven\", \"times\": [\"26.01.2023 15:20:00\"], \"priority\": \"urgent\"}
+
+Current time is \"26.01.2023 14:40:00, Thursday\"
+Напоминай мне каждые 15 минут принять лекарство в 21:00, пока я не отвечу
+
+Answer: {\"kind\": \"absolute\", \"text\": \"принять лекарство\", \"times\": [\"26.01.2023 21:00:00\"], \"nag\": 15}
+
+Current time is \"26.01.2023 14:40:00, Thursday\"
+Remind me to join the call at 15:00, pointless after 15:30
+
+Answer: {\"kind\": \"absolute\", \"text\": \"join the call\", \"times\": [\"26.01.2023 15:00:00\"], \"valid\": 30}
+
+Current time is \"26.01.2023 14:40:00, Thursday\"
+Remind me to call Dana at 8
+
+Answer: {\"kind\": \"absolute\", \"text\": \"call Dana\", \"times\": [\"26.01.2023 20:00:00\"], \"alternatives\": [{\"kind\": \"absolute\", \"text\": \"call Dana\", \"times\": [\"27.01.2023 08:00:00\"]}]}
+
+Current time is \"26.01.2023 14:40:00, Thursday\"
+Cancel my dentist reminder
+
+Answer: {\"kind\": \"cancel\", \"text\": \"dentist\"}";
+
+    // the current time is given on the user's wall clock, so the times in the answer are on it too
+    fn create_prompt(system_prompt: &str, current_date: DateTime<Utc>, timezone: Tz, text: &str) -> (String, String) {
+        let current_date = current_date.with_timezone(&timezone);
         // format should be like 21.07.2022 22:37:01, thursday
         let formatted_date = current_date.format("%d.%m.%Y %H:%M:%S, %A");
 
-        (Self::SYSTEM_PROMPT.to_owned(), format!("Current time is \"{}\"\n{}\n", formatted_date, text))
+        (system_prompt.to_owned(), format!("Current time is \"{}\"\n{}\n", formatted_date, text))
+    }
+
+    // corrections the user made before are added to the prompt as examples when they look like the text
+    pub async fn complete(&self, user_id: u64, current_date: DateTime<Utc>, timezone: Tz, text: &str, corrections: &[ParseCorrection]) -> Result<Completion, BotError> {
+        let corrections = relevant_corrections(text, corrections);
+        let key = match &self.cache {
+            Some(cache) => {
+                let cache = cache.lock().unwrap();
+                let key = cache.key(user_id, current_date, timezone, text, &corrections);
+                if let Some(completion) = cache.get(&key) {
+                    info!("Using cached completion");
+                    return Ok(completion);
+                }
+                Some(key)
+            }
+            None => None,
+        };
+
+        let (system_message, user_message) = Self::create_prompt(&self.system_prompt(), current_date, timezone, text);
+        let system_message = with_corrections(system_message, &corrections, timezone);
+
+        let completion = self.complete_with(&self.options.model, system_message, user_message).await?;
+
+        if let (Some(cache), Some(key)) = (&self.cache, key) {
+            cache.lock().unwrap().insert(key, completion.clone());
+        }
+        Ok(completion)
+    }
+
+    const SUMMARY_PROMPT: &'static str = "You are given a long message, for example a forwarded email. \
+Reply with a single sentence in the language of the message that says what the user should be reminded about and when, \
+keeping all dates and times exactly as written. Don't add anything else.";
+
+    const REVISE_PROMPT: &'static str = "You are given a reminder parsed into json and a correction of it written by the user. \
+Answer with the corrected json in the same format, changing only what the correction asks for. Don't add anything else.";
+
+    // applies a tweak like "make it 18:00 not 8:00" to an already parsed reminder instead of parsing the text from scratch
+    pub async fn revise(&self, current_date: DateTime<Utc>, timezone: Tz, notification: &str, correction: &str) -> Result<Completion, BotError> {
+        let (_, user_message) = Self::create_prompt("", current_date, timezone, &format!("{}\nCorrection: {}", notification, correction));
+        self.complete_with(&self.options.model, Self::REVISE_PROMPT.to_owned(), user_message).await
     }
 
-    pub async fn parse(&self, current_date: DateTime<Utc>, text: &str) -> Result<Notification, BotError> {
-        let (system_message, user_message) = Self::create_prompt(current_date, text);
+    // cuts a long message down to the reminder-relevant sentence, so the parse prompt stays small
+    pub async fn summarize(&self, text: &str) -> Result<Completion, BotError> {
+        let model = self.options.summary_model.as_deref().unwrap_or(&self.options.model);
+        let completion = self.complete_with(model, Self::SUMMARY_PROMPT.to_owned(), text.to_owned()).await?;
+        Ok(Completion { content: Self::clean_summary(&completion.content), usage: completion.usage })
+    }
+
+    fn clean_summary(content: &str) -> String {
+        content.trim().trim_matches(|ch| ch == '"' || ch == '«' || ch == '»').trim().to_string()
+    }
+
+    async fn complete_with(&self, model: &str, system_message: String, user_message: String) -> Result<Completion, BotError> {
+        match &self.options.fixtures {
+            Some(fixtures) if fixtures.mode == FixtureMode::Replay => fixtures.load(model, &system_message, &user_message).await,
+            Some(fixtures) => {
+                let completion = self.request_completion(model, system_message.clone(), user_message.clone()).await?;
+                fixtures.save(model, &system_message, &user_message, &completion).await?;
+                Ok(completion)
+            }
+            None => self.request_completion(model, system_message, user_message).await,
+        }
+    }
+
+    async fn request_completion(&self, model: &str, system_message: String, user_message: String) -> Result<Completion, BotError> {
+        match self.options.provider {
+            Provider::OpenAI | Provider::AzureOpenAI => self.complete_openai(model, system_message, user_message).await,
+            Provider::Anthropic => self.complete_anthropic(model, system_message, user_message).await,
+            Provider::Ollama => self.complete_ollama(model, system_message, user_message).await,
+        }
+    }
 
+    // lists models instead of completing anything, so checking credentials costs no tokens;
+    // azure deployments have no such listing and are only checked by the first completion
+    pub async fn ping(provider: Provider, base_url: Option<&str>, api_key: Option<&str>) -> Result<(), BotError> {
+        let base_url = base_url.unwrap_or_else(|| provider.default_base_url()).trim_end_matches('/');
+        let client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?;
+        let request = match provider {
+            Provider::OpenAI => client.get(format!("{}/models", base_url)).bearer_auth(api_key.unwrap_or_default()),
+            Provider::AzureOpenAI => return Ok(()),
+            Provider::Anthropic => client.get(format!("{}/models", base_url))
+                .header("x-api-key", api_key.unwrap_or_default())
+                .header("anthropic-version", "2023-06-01"),
+            Provider::Ollama => client.get(format!("{}/api/tags", base_url)),
+        };
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    // the most a completion can spend, reserved against the budgets while the call is in flight
+    pub fn max_completion_tokens(&self) -> u64 {
+        self.options.max_tokens.unwrap_or(Self::DEFAULT_MAX_TOKENS) as u64
+    }
+
+    pub fn cost(&self, usage: Usage) -> f64 {
+        usage.prompt_tokens as f64 / 1000.0 * self.options.prompt_price
+            + usage.completion_tokens as f64 / 1000.0 * self.options.completion_price
+    }
+
+    fn chat_messages(system_message: String, user_message: String) -> Vec<Message> {
+        vec![
+            Message {
+                role: "system".to_owned(),
+                content: system_message,
+            },
+            Message {
+                role: "user".to_owned(),
+                content: user_message,
+            },
+        ]
+    }
+
+    async fn complete_openai(&self, model: &str, system_message: String, user_message: String) -> Result<Completion, BotError> {
         let request = OpenAIChatRequest {
-            model: "gpt-3.5-turbo".to_owned(),
-            messages: vec![
-                Message {
-                    role: "system".to_owned(),
-                    content: system_message,
-                },
-                Message {
-                    role: "user".to_owned(),
-                    content: user_message,
-                },
-            ],
+            model: model.to_owned(),
+            messages: Self::chat_messages(system_message, user_message),
+            temperature: self.options.temperature,
+            max_tokens: self.options.max_tokens,
+        };
+
+        let model: OpenAIChatResponse = self.send_with_retry(|| self.openai_builder(model).json(&request)).await?;
+
+        Self::extract_openai_content(model)
+    }
+
+    fn openai_builder(&self, model: &str) -> RequestBuilder {
+        if self.options.provider == Provider::AzureOpenAI {
+            let url = format!("{}/openai/deployments/{}/chat/completions?api-version={}",
+                              self.options.base_url(), model, self.options.api_version);
+            return self.client.post(url).header("api-key", self.api_key.as_deref().unwrap_or_default());
+        }
+        let mut builder = self.client.post(format!("{}/chat/completions", self.options.base_url()))
+            .bearer_auth(self.api_key.as_deref().unwrap_or_default());
+        if let Some(organization) = &self.options.organization {
+            builder = builder.header("OpenAI-Organization", organization);
+        }
+        if let Some(project) = &self.options.project {
+            builder = builder.header("OpenAI-Project", project);
+        }
+        builder
+    }
+
+    async fn complete_anthropic(&self, model: &str, system_message: String, user_message: String) -> Result<Completion, BotError> {
+        let request = AnthropicRequest {
+            model: model.to_owned(),
+            system: system_message,
+            messages: vec![Message { role: "user".to_owned(), content: user_message }],
+            max_tokens: self.options.max_tokens.unwrap_or(Self::DEFAULT_MAX_TOKENS),
+            temperature: self.options.temperature,
         };
 
-        // like curl above
-        let model = self.client.post("https://api.openai.com/v1/chat/completions")
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request)
-            .send().await?
-            .json::<OpenAIChatResponse>().await?;
+        let response: AnthropicResponse = self.send_with_retry(|| {
+            self.client.post(format!("{}/messages", self.options.base_url()))
+                .header("x-api-key", self.api_key.as_deref().unwrap_or_default())
+                .header("anthropic-version", "2023-06-01")
+                .json(&request)
+        }).await?;
 
-        Self::parse_response(model)
+        Self::extract_anthropic_content(response)
     }
-    
+
+    async fn complete_ollama(&self, model: &str, system_message: String, user_message: String) -> Result<Completion, BotError> {
+        let request = OllamaChatRequest {
+            model: model.to_owned(),
+            messages: Self::chat_messages(system_message, user_message),
+            stream: false,
+            options: OllamaOptions {
+                temperature: self.options.temperature,
+                num_predict: self.options.max_tokens,
+            },
+        };
+
+        let response: OllamaChatResponse = self.send_with_retry(|| {
+            self.client.post(format!("{}/api/chat", self.options.base_url()))
+                .json(&request)
+        }).await?;
+
+        Ok(Completion {
+            content: response.message.content,
+            usage: Usage {
+                prompt_tokens: response.prompt_eval_count,
+                completion_tokens: response.eval_count,
+            },
+        })
+    }
+
+    async fn send_with_retry<T: DeserializeOwned>(&self, request: impl Fn() -> RequestBuilder) -> Result<T, BotError> {
+        let mut attempt = 0;
+        loop {
+            let result = request().send().await.and_then(|response| response.error_for_status());
+            match result {
+                Ok(response) => return Ok(response.json::<T>().await?),
+                Err(err) if attempt < self.options.max_retries && Self::is_retryable(&err) => {
+                    let delay = Self::backoff_delay(self.options.retry_base_delay, attempt, Self::jitter());
+                    warn!("Completion request failed ({}), retrying in {:?}", err, delay);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    fn is_retryable(err: &reqwest::Error) -> bool {
+        if err.is_timeout() || err.is_connect() {
+            return true;
+        }
+        matches!(err.status(), Some(StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT))
+    }
+
+    // random value in [0, 1) used to spread retries of concurrent requests
+    fn jitter() -> f64 {
+        let mut bytes = [0u8; 4];
+        match getrandom::getrandom(&mut bytes) {
+            Ok(_) => u32::from_le_bytes(bytes) as f64 / (u32::MAX as f64 + 1.0),
+            Err(_) => 0.5,
+        }
+    }
+
+    // exponential backoff with equal jitter: half of the delay is fixed, the other half is random
+    fn backoff_delay(base: Duration, attempt: u32, jitter: f64) -> Duration {
+        let exponential = base.saturating_mul(2_u32.saturating_pow(attempt)).min(Self::MAX_RETRY_DELAY);
+        exponential / 2 + exponential.mul_f64(jitter / 2.0)
+    }
+
+    fn extract_openai_content(model_response: OpenAIChatResponse) -> Result<Completion, BotError> {
+        let usage = model_response.usage
+            .map(|usage| Usage { prompt_tokens: usage.prompt_tokens, completion_tokens: usage.completion_tokens })
+            .unwrap_or_default();
+        model_response.choices.into_iter()
+            .next()
+            .map(|choice| Completion { content: choice.message.content, usage })
+            .ok_or(BotError::NoCompletionGiven)
+    }
+
+    fn extract_anthropic_content(response: AnthropicResponse) -> Result<Completion, BotError> {
+        let usage = response.usage
+            .map(|usage| Usage { prompt_tokens: usage.input_tokens, completion_tokens: usage.output_tokens })
+            .unwrap_or_default();
+        response.content.into_iter()
+            .find_map(|content| content.text)
+            .map(|content| Completion { content, usage })
+            .ok_or(BotError::NoCompletionGiven)
+    }
+
+    #[cfg(test)]
     fn parse_response(model_response: OpenAIChatResponse) -> Result<Notification, BotError> {
-        let choice = model_response.choices.first().ok_or(BotError::NoCompletionGiven)?;
+        Self::parse_completion(&Self::extract_openai_content(model_response)?.content)
+    }
+
+    pub fn parse_completion(content: &str) -> Result<Notification, BotError> {
+        Ok(Self::parse_candidates(content)?.swap_remove(0))
+    }
+
+    // the most likely reading followed by the distinct alternatives the model offered for an ambiguous query;
+    // alternatives that don't parse are dropped, a cancel request never has any
+    pub fn parse_candidates(content: &str) -> Result<Vec<Notification>, BotError> {
+        // completions echo what the user wrote, so they only show up with LOG_LEVEL=debug
+        debug!("\"{}\"", content);
+
+        let mut value: serde_json::Value = serde_json::from_str(content)?;
+        let alternatives = match value.as_object_mut().and_then(|object| object.remove("alternatives")) {
+            Some(serde_json::Value::Array(alternatives)) => alternatives,
+            _ => vec![],
+        };
+        let mut candidates = vec![Self::parse_value(value)?];
+        if matches!(candidates[0], Notification::Cancel { .. }) {
+            return Ok(candidates);
+        }
+        for alternative in alternatives {
+            if candidates.len() == MAX_CANDIDATES {
+                break;
+            }
+            match Self::parse_value(alternative) {
+                Ok(Notification::Cancel { .. }) => {}
+                Ok(notification) if !candidates.iter().any(|known| same_notification(known, &notification)) => candidates.push(notification),
+                Ok(_) => {}
+                Err(err) => debug!("Dropping alternative: {}", err),
+            }
+        }
+        Ok(candidates)
+    }
 
-        info!("\"{}\"", choice.message.content);
+    fn parse_value(mut value: serde_json::Value) -> Result<Notification, BotError> {
+        if let Some(kind) = value.get_mut("kind") {
+            if let Some(canonical) = kind.as_str().and_then(canonical_kind) {
+                *kind = serde_json::Value::from(canonical);
+            }
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+// corrections added to a single prompt
+const MAX_CORRECTION_EXAMPLES: usize = 3;
+
+fn words(text: &str) -> FnvHashSet<String> {
+    text.split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| word.chars().count() > 2)
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+// the corrections sharing most words with the text, newer ones first among equals
+fn relevant_corrections<'a>(text: &str, corrections: &'a [ParseCorrection]) -> Vec<&'a ParseCorrection> {
+    let text = words(text);
+    let mut scored = corrections.iter()
+        .map(|correction| (words(&correction.input).intersection(&text).count(), correction))
+        .filter(|(shared, _)| *shared > 0)
+        .collect::<Vec<_>>();
+    // the sort is stable, so the order of the newest first list is kept for equal scores
+    scored.sort_by_key(|(shared, _)| std::cmp::Reverse(*shared));
+    scored.into_iter().take(MAX_CORRECTION_EXAMPLES).map(|(_, correction)| correction).collect()
+}
+
+fn with_corrections(mut system_message: String, corrections: &[&ParseCorrection], timezone: Tz) -> String {
+    if corrections.is_empty() {
+        return system_message;
+    }
+    system_message.push_str("\n\nThis user corrected answers to these queries before, answer similar queries the same way:");
+    for correction in corrections {
+        let (_, user_message) = LlmParser::create_prompt("", correction.parsed_at, timezone, &correction.input);
+        let _ = write!(system_message, "\n\n{}\nAnswer: {}", user_message, correction.accepted);
+    }
+    system_message
+}
+
+// a draft offers at most this many readings of the same query
+pub const MAX_CANDIDATES: usize = 3;
+
+fn same_notification(a: &Notification, b: &Notification) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+// models answer with both long and short kind names, and older prompts spelled recurrent as "reccurrent"
+fn canonical_kind(kind: &str) -> Option<&'static str> {
+    match kind.to_lowercase().as_str() {
+        "absolute" | "abs" => Some("absolute"),
+        "relative" | "rel" => Some("relative"),
+        "recurrent" | "reccurrent" | "recurring" | "rec" => Some("recurrent"),
+        "cancel" | "delete" => Some("cancel"),
+        _ => None,
+    }
+}
+
+const REMIND_WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
 
-        let notification: Notification = serde_json::from_str(&choice.message.content)?;
+// the /remind syntax is read without a model: "21.07 15:00 call mom", a year as in "21.07.2030" is optional,
+// or "every mon,wed 09:00 standup" with "every day" for all days
+pub fn parse_remind(arg: &str, now: DateTime<Utc>, timezone: Tz) -> Option<Notification> {
+    let words = arg.split_whitespace().collect::<Vec<_>>();
+    match words.as_slice() {
+        [every, days, rest @ ..] if every.eq_ignore_ascii_case("every") => {
+            let workdays = days.eq_ignore_ascii_case("workday") || days.eq_ignore_ascii_case("workdays");
+            // working days can leave out the holidays too, as in "every workday except holidays 09:30 standup"
+            let (skip_holidays, rest) = match rest {
+                [except, holidays, rest @ ..] if workdays && except.eq_ignore_ascii_case("except") && holidays.eq_ignore_ascii_case("holidays") => (true, rest),
+                rest => (false, rest),
+            };
+            let [time, text @ ..] = rest else { return None };
+            if text.is_empty() {
+                return None;
+            }
+            let days = if workdays || days.eq_ignore_ascii_case("day") { None } else { Some(parse_weekdays(days)?) };
+            Some(Notification::Recurrent {
+                text: text.join(" "), days, times: vec![parse_clock(time)?], leads: vec![], priority: Priority::Normal, nag: None, valid: None,
+                workdays, skip_holidays,
+            })
+        }
+        [date, time, text @ ..] if !text.is_empty() => {
+            let clock = parse_clock(time)?;
+            let at = |date: NaiveDate| date.and_hms_opt(clock.hours as u32, clock.minutes as u32, 0)
+                .and_then(|local| timezone.from_local_datetime(&local).earliest())
+                .map(|time| time.with_timezone(&Utc));
+            let time = match NaiveDate::parse_from_str(date, "%d.%m.%Y") {
+                Ok(date) => at(date)?,
+                // without a year the date is the next one to come
+                Err(_) => {
+                    let year = now.with_timezone(&timezone).year();
+                    let this_year = NaiveDate::parse_from_str(&format!("{}.{}", date, year), "%d.%m.%Y").ok()?;
+                    match at(this_year) {
+                        Some(time) if time > now => time,
+                        _ => at(this_year.with_year(year + 1)?)?,
+                    }
+                }
+            };
+            Some(Notification::Absolute {
+                text: text.join(" "), times: vec![FormattedTime { time }], leads: vec![], priority: Priority::Normal, nag: None, valid: None,
+            })
+        }
+        _ => None,
+    }
+}
+
+fn parse_clock(time: &str) -> Option<Time> {
+    let (hours, minutes) = time.split_once(':')?;
+    let (hours, minutes) = (hours.parse::<u8>().ok()?, minutes.parse::<u8>().ok()?);
+    (hours < 24 && minutes < 60).then_some(Time { hours, minutes })
+}
+
+// comma separated english day names, 1 is monday as in the recurrent answers of the model
+fn parse_weekdays(days: &str) -> Option<ArrayVec<u8, 7>> {
+    let mut numbers = ArrayVec::new();
+    for day in days.split(',') {
+        let day = day.to_lowercase();
+        let number = REMIND_WEEKDAYS.iter().position(|name| *name == day)? as u8 + 1;
+        if !numbers.contains(&number) {
+            numbers.push(number);
+        }
+    }
+    numbers.sort();
+    Some(numbers)
+}
+
+const OFFSET_WORDS: [&str; 3] = ["in", "через", "בעוד"];
+const FILLER_WORDS: [&str; 13] = ["remind", "me", "to", "about", "напомни", "напомнить", "мне", "что", "про", "о", "об", "תזכיר", "לי"];
+// offsets further than this are left to the model, they tend to come with a time of day
+const MAX_OFFSET_MINUTES: u32 = 7 * 24 * 60;
+
+fn unit_minutes(word: &str) -> Option<u32> {
+    match word {
+        "min" | "mins" | "minute" | "minutes" | "мин" | "минуту" | "минуты" | "минут" | "דקה" | "דקות" => Some(1),
+        "полчаса" => Some(30),
+        "h" | "hour" | "hours" | "час" | "часа" | "часов" | "שעה" | "שעות" => Some(60),
+        "day" | "days" | "день" | "дня" | "дней" | "יום" | "ימים" => Some(24 * 60),
+        _ => None,
+    }
+}
+
+// "in 25 minutes", "через час" or "בעוד 2 שעות" starting at the word, with the number of words it takes
+fn offset_at(words: &[String], start: usize) -> Option<(u32, usize)> {
+    if !OFFSET_WORDS.contains(&words[start].as_str()) {
+        return None;
+    }
+    let (amount, unit) = match words.get(start + 1)?.as_str() {
+        "a" | "an" => (1, start + 2),
+        amount => match amount.parse::<u32>() {
+            Ok(amount) => (amount, start + 2),
+            Err(_) => (1, start + 1),
+        },
+    };
+    let minutes = amount.checked_mul(unit_minutes(words.get(unit)?)?)?;
+    (minutes > 0 && minutes <= MAX_OFFSET_MINUTES).then_some((minutes, unit + 1 - start))
+}
 
-        Ok(notification)
+// plain offsets from now are read without the model; anything else in the text that looks like a time,
+// such as another number, leaves the whole text to the model
+pub fn parse_offset(text: &str, now: DateTime<Utc>) -> Option<Notification> {
+    let words = text.split_whitespace().collect::<Vec<_>>();
+    let normalized = words.iter()
+        .map(|word| word.trim_matches(|ch: char| !ch.is_alphanumeric()).to_lowercase())
+        .collect::<Vec<_>>();
+    let (start, (minutes, length)) = (0..normalized.len()).find_map(|start| Some((start, offset_at(&normalized, start)?)))?;
+    let rest = words[..start].iter().chain(&words[start + length..]).copied().collect::<Vec<_>>();
+    let fillers = normalized[..start].iter().chain(&normalized[start + length..])
+        .take_while(|word| FILLER_WORDS.contains(&word.as_str()))
+        .count();
+    let text = rest[fillers..].join(" ");
+    let text = text.trim_matches(|ch: char| ch.is_whitespace() || ch.is_ascii_punctuation());
+    if text.is_empty() || text.chars().any(|ch| ch.is_ascii_digit()) {
+        return None;
     }
+    let time = now.with_second(0)?.with_nanosecond(0)? + chrono::Duration::minutes(minutes as i64);
+    Some(Notification::Absolute {
+        text: text.to_string(), times: vec![FormattedTime { time }], leads: vec![], priority: Priority::Normal, nag: None, valid: None,
+    })
+}
+
+// what the text would give without the model, offsets from now or the /remind syntax
+pub fn parse_offline(text: &str, now: DateTime<Utc>, timezone: Tz) -> Option<Notification> {
+    parse_offset(text, now).or_else(|| parse_remind(text, now, timezone))
 }
 
 #[cfg(test)]
 mod tests {
     use arrayvec::ArrayVec;
+    use std::time::Duration;
     use chrono::{Utc, DateTime};
 
-    use crate::models::{Notification, FormattedTime};
+    use crate::models::{on_wall_clock, Notification, FormattedTime};
 
-    use super::{OpenAIParser, OpenAIChatResponse};
+    use crate::db::ParseCorrection;
+    use crate::fixtures::ParserFixtures;
+    use crate::models::StoredNotification;
+    use super::{parse_offset, parse_remind, AnthropicContent, AnthropicResponse, AnthropicUsage, Completion, CompletionCache, LlmParser, ModelOptions, OpenAIChatResponse, Provider, Usage};
 
     #[test]
     fn should_create_prompt_as_expected() {
         let current_date = DateTime::parse_from_rfc3339("2023-01-26T14:40:00+02:00").unwrap();
         let current_date_in_utc = current_date.with_timezone(&Utc);
         let text = "Завтра в 12 и 15 часов напомни проверить почту";
-        let (system_prompt, user_prompt) = OpenAIParser::create_prompt(current_date_in_utc, text);
+        let (system_prompt, user_prompt) = LlmParser::create_prompt(LlmParser::SYSTEM_PROMPT, current_date_in_utc, chrono_tz::Israel, text);
 
         // read prompt from assets/example_prompt.txt
         let expected_prompt = std::fs::read_to_string("assets/example_prompt.txt").unwrap().replace("\r", "");
 
         assert_eq!(system_prompt, expected_prompt);
 
-        assert_eq!("Current time is \"26.01.2023 14:40:00, Thursday\"\nЗавтра в 12 и 15 часов напомни проверить почту\n", user_prompt)
+        assert_eq!("Current time is \"26.01.2023 14:40:00, Thursday\"\nЗавтра в 12 и 15 часов напомни проверить почту\n", user_prompt);
+
+        let (_, user_prompt) = LlmParser::create_prompt("", current_date_in_utc, chrono_tz::America::Mexico_City, "call mom");
+        assert_eq!("Current time is \"26.01.2023 06:40:00, Thursday\"\ncall mom\n", user_prompt);
+    }
+
+    #[test]
+    fn should_reload_prompt_file_when_it_changes() {
+        let path = std::env::temp_dir().join(format!("notify-rs-prompt-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let parser = LlmParser::new(Some("key".to_owned()), ModelOptions { prompt_file: Some(path.clone()), ..options(None) }).unwrap();
+        assert_eq!(&*parser.system_prompt(), LlmParser::SYSTEM_PROMPT);
+
+        std::fs::write(&path, "first prompt\r\n").unwrap();
+        assert_eq!(&*parser.system_prompt(), "first prompt\n");
+        // a broken edit keeps the last good prompt
+        let file = std::fs::File::create(&path).unwrap();
+        file.set_modified(std::time::SystemTime::now() + Duration::from_secs(1)).unwrap();
+        assert_eq!(&*parser.system_prompt(), "first prompt\n");
+        std::fs::write(&path, "second prompt").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(std::time::SystemTime::now() + Duration::from_secs(2)).unwrap();
+        assert_eq!(&*parser.system_prompt(), "second prompt");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_parse_remind_command_without_model() {
+        // 2023-07-25 12:00 in israel, so 21.07 is already gone this year
+        let now = DateTime::parse_from_rfc3339("2023-07-25T09:00:00Z").unwrap().with_timezone(&Utc);
+        match parse_remind("21.07 15:00 call  mom", now, chrono_tz::Israel) {
+            Some(Notification::Absolute { text, times, .. }) => {
+                assert_eq!(text, "call mom");
+                assert_eq!(times, vec![FormattedTime { time: DateTime::parse_from_rfc3339("2024-07-21T12:00:00Z").unwrap().with_timezone(&Utc) }]);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_remind("every wed,MON,mon 09:00 standup", now, chrono_tz::Israel) {
+            Some(Notification::Recurrent { text, days, times, .. }) => {
+                assert_eq!((text.as_str(), days, times[0].hours, times[0].minutes), ("standup", Some(ArrayVec::from([1, 3]).into_iter().collect()), 9, 0));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(parse_remind("every day 9:30 pills", now, chrono_tz::Israel), Some(Notification::Recurrent { days: None, workdays: false, .. })));
+        assert!(matches!(parse_remind("every workday 9:30 standup", now, chrono_tz::Israel), Some(Notification::Recurrent { workdays: true, skip_holidays: false, .. })));
+        let standup = parse_remind("every workday except holidays 9:30 standup", now, chrono_tz::Israel).unwrap();
+        assert!(matches!(&standup, Notification::Recurrent { text, workdays: true, skip_holidays: true, .. } if text == "standup"));
+        for invalid in ["21.07 15:00", "32.07 15:00 call", "21.07 24:00 call", "every someday 09:00 standup", "every workday except holidays 09:30", "call mom at 5"] {
+            assert!(parse_remind(invalid, now, chrono_tz::Israel).is_none(), "{}", invalid);
+        }
+        // 15:00 in Lima is 20:00 utc
+        let lima = parse_remind("26.07 15:00 call mom", now, chrono_tz::America::Lima).unwrap();
+        assert!(matches!(lima, Notification::Absolute { times, .. } if times[0].time == DateTime::parse_from_rfc3339("2023-07-26T20:00:00Z").unwrap()));
+    }
+
+    #[test]
+    fn should_parse_plain_offsets_without_model() {
+        let now = DateTime::parse_from_rfc3339("2023-07-25T09:00:30Z").unwrap().with_timezone(&Utc);
+        let at = |text: &str| match parse_offset(text, now) {
+            Some(Notification::Absolute { text, times, .. }) => Some((text, (times[0].time - now).num_seconds())),
+            _ => None,
+        };
+        assert_eq!(at("через 25 минут напомни позвонить маме"), Some(("позвонить маме".to_string(), 25 * 60 - 30)));
+        assert_eq!(at("Remind me to check the oven in 2 hours."), Some(("check the oven".to_string(), 2 * 3600 - 30)));
+        assert_eq!(at("напомни через полчаса: выключить плиту"), Some(("выключить плиту".to_string(), 30 * 60 - 30)));
+        assert_eq!(at("put it in the fridge in an hour"), Some(("put it in the fridge".to_string(), 3600 - 30)));
+        for model in ["in 2 hours", "call mom at 5", "in 2 hours call room 12", "через 2 недели отпуск", "in 300 days renew passport"] {
+            assert_eq!(at(model), None, "{}", model);
+        }
+    }
+
+    #[test]
+    fn should_add_most_relevant_corrections_to_prompt() {
+        let parsed_at = DateTime::parse_from_rfc3339("2023-01-26T14:40:00+02:00").unwrap().with_timezone(&Utc);
+        let correction = |input: &str| ParseCorrection { input: input.to_owned(), accepted: format!("{{\"text\": \"{}\"}}", input), parsed_at };
+        let corrections = [correction("pay rent on the first"), correction("call Dana at 8"), correction("call mom at 8 tonight"), correction("water plants")];
+
+        let relevant = super::relevant_corrections("Call Dana at 9", &corrections);
+        assert_eq!(relevant.iter().map(|correction| correction.input.as_str()).collect::<Vec<_>>(), ["call Dana at 8", "call mom at 8 tonight"]);
+        assert_eq!(super::with_corrections("prompt".to_owned(), &[], chrono_tz::Israel), "prompt");
+        assert_eq!(super::with_corrections("prompt".to_owned(), &relevant[..1], chrono_tz::Israel),
+                   "prompt\n\nThis user corrected answers to these queries before, answer similar queries the same way:\n\n\
+                   Current time is \"26.01.2023 14:40:00, Thursday\"\ncall Dana at 8\n\nAnswer: {\"text\": \"call Dana at 8\"}");
+    }
+
+    #[test]
+    fn should_clean_summary() {
+        assert_eq!(LlmParser::clean_summary("  \"Call the bank on Friday at 10:00\"\n"), "Call the bank on Friday at 10:00");
+        assert_eq!(LlmParser::clean_summary("«Позвонить в банк в пятницу»"), "Позвонить в банк в пятницу");
     }
 
     #[test]
@@ -148,13 +1044,14 @@ mod tests {
                         role: "assistant".to_owned(), 
                     },
                 }
-            ]
+            ],
+            usage: None,
         };
 
-        let notification = OpenAIParser::parse_response(completion).unwrap();
+        let notification = LlmParser::parse_response(completion).unwrap();
 
         match notification {
-            Notification::Absolute { text, times } => {
+            Notification::Absolute { text, times, .. } => {
                 assert_eq!(text, "проверить почту");
                 let expected_time_one = DateTime::parse_from_rfc3339("2023-01-27T12:00:00+02:00").unwrap();
                 let expected_time_two = DateTime::parse_from_rfc3339("2023-01-27T15:00:00+02:00").unwrap();
@@ -175,13 +1072,14 @@ mod tests {
                         content: "{\"kind\": \"relative\", \"text\": \"проверить почту\", \"week\": 0, \"days\": [5], \"times\": [\"12:00\", \"15:00\"]}".to_owned(), 
                     },
                 }
-            ]
+            ],
+            usage: None,
         };
 
-        let notification = OpenAIParser::parse_response(completion).unwrap();
+        let notification = LlmParser::parse_response(completion).unwrap();
 
         match notification {
-            Notification::Relative { text, week, days, times } => {
+            Notification::Relative { text, week, days, times, .. } => {
                 assert_eq!(text, "проверить почту");
                 assert_eq!(week, 0);
                 assert_eq!(days, ArrayVec::from_iter(std::iter::once(5)));
@@ -193,4 +1091,165 @@ mod tests {
             _ => panic!("Notification should be relative"),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn should_offer_distinct_alternatives_of_ambiguous_query() {
+        let reading = |time: &str| format!("{{\"kind\": \"abs\", \"text\": \"call Dana\", \"times\": [\"{}\"]}}", time);
+        let content = format!("{{\"kind\": \"absolute\", \"text\": \"call Dana\", \"times\": [\"26.01.2023 20:00:00\"], \"alternatives\": [{}, {}, {{\"kind\": \"nonsense\"}}, {}, {}]}}",
+                              reading("26.01.2023 20:00:00"), reading("27.01.2023 08:00:00"), reading("27.01.2023 20:00:00"), reading("28.01.2023 08:00:00"));
+        let candidates = LlmParser::parse_candidates(&content).unwrap();
+        let times = candidates.iter().map(|candidate| match candidate {
+            Notification::Absolute { times, .. } => times[0].time.with_timezone(&chrono_tz::Israel).format("%d %H").to_string(),
+            _ => panic!("Notification should be absolute"),
+        }).collect::<Vec<_>>();
+        // the repeated first reading and the broken one are dropped, the rest is cut at three
+        assert_eq!(times, ["26 20", "27 08", "27 20"]);
+        assert_eq!(LlmParser::parse_candidates(&reading("26.01.2023 20:00:00")).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn should_accept_short_and_legacy_kind_names() {
+        for kind in ["abs", "Absolute"] {
+            let content = format!("{{\"kind\": \"{}\", \"text\": \"t\", \"times\": [\"27.01.2023 12:00:00\"]}}", kind);
+            assert!(matches!(LlmParser::parse_completion(&content).unwrap(), Notification::Absolute { .. }));
+        }
+        for kind in ["rec", "reccurrent", "recurrent"] {
+            let content = format!("{{\"kind\": \"{}\", \"text\": \"t\", \"days\": null, \"times\": [\"09:00\"]}}", kind);
+            assert!(matches!(LlmParser::parse_completion(&content).unwrap(), Notification::Recurrent { days: None, .. }));
+        }
+        let content = "{\"kind\": \"rel\", \"text\": \"t\", \"week\": 1, \"days\": [2], \"times\": [\"09:00\"]}";
+        assert!(matches!(LlmParser::parse_completion(content).unwrap(), Notification::Relative { week: 1, .. }));
+        assert!(LlmParser::parse_completion("{\"kind\": \"sometimes\", \"text\": \"t\"}").is_err());
+    }
+
+    #[test]
+    fn should_round_trip_every_kind() {
+        let examples = [
+            "{\"kind\":\"absolute\",\"text\":\"t\",\"times\":[\"27.01.2023 12:00:00\"]}",
+            "{\"kind\":\"relative\",\"text\":\"t\",\"week\":0,\"days\":[5],\"times\":[\"12:00\"]}",
+            "{\"kind\":\"recurrent\",\"text\":\"t\",\"days\":[1,3],\"times\":[\"09:00\"]}",
+        ];
+        for example in examples {
+            let notification = LlmParser::parse_completion(example).unwrap();
+            assert_eq!(serde_json::to_string(&notification).unwrap(), example);
+        }
+
+        // the answer is on the user's wall clock, 12:00 in Anchorage is 21:00 utc in winter
+        let notification = on_wall_clock(chrono_tz::America::Anchorage, || LlmParser::parse_completion(examples[0])).unwrap();
+        assert!(matches!(&notification, Notification::Absolute { times, .. } if times[0].time == DateTime::parse_from_rfc3339("2023-01-27T21:00:00Z").unwrap()));
+        assert_eq!(on_wall_clock(chrono_tz::America::Anchorage, || serde_json::to_string(&notification)).unwrap(), examples[0]);
+    }
+
+    #[test]
+    fn should_extract_anthropic_text_content() {
+        let response = AnthropicResponse {
+            content: vec![
+                AnthropicContent { text: None },
+                AnthropicContent { text: Some("{\"kind\": \"absolute\", \"text\": \"проверить почту\", \"times\": [\"27.01.2023 12:00:00\"]}".to_owned()) },
+            ],
+            usage: Some(AnthropicUsage { input_tokens: 700, output_tokens: 30 }),
+        };
+
+        let completion = LlmParser::extract_anthropic_content(response).unwrap();
+        let notification = LlmParser::parse_completion(&completion.content).unwrap();
+
+        assert_eq!(notification.get_text(), "проверить почту");
+        assert_eq!(completion.usage, Usage { prompt_tokens: 700, completion_tokens: 30 });
+    }
+
+    #[test]
+    fn should_grow_backoff_delay_exponentially() {
+        let base = Duration::from_millis(500);
+
+        assert_eq!(LlmParser::backoff_delay(base, 0, 0.0), Duration::from_millis(250));
+        assert_eq!(LlmParser::backoff_delay(base, 0, 1.0), Duration::from_millis(500));
+        assert_eq!(LlmParser::backoff_delay(base, 3, 0.0), Duration::from_millis(2000));
+        assert_eq!(LlmParser::backoff_delay(base, 20, 1.0), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn should_reuse_cached_completion_within_bucket() {
+        let mut cache = CompletionCache::new(Duration::from_secs(60));
+        let now = DateTime::parse_from_rfc3339("2023-01-26T14:40:10+02:00").unwrap().with_timezone(&Utc);
+        let later = DateTime::parse_from_rfc3339("2023-01-26T14:40:50+02:00").unwrap().with_timezone(&Utc);
+        let next_bucket = DateTime::parse_from_rfc3339("2023-01-26T14:41:00+02:00").unwrap().with_timezone(&Utc);
+
+        let usage = Usage { prompt_tokens: 10, completion_tokens: 5 };
+        cache.insert(cache.key(1, now, chrono_tz::Israel, "Remind me to call", &[]), Completion { content: "{}".to_owned(), usage });
+
+        let cached = cache.get(&cache.key(1, later, chrono_tz::Israel, "Remind me to call", &[])).unwrap();
+        assert_eq!(cached.content, "{}");
+        assert_eq!(cached.usage, Usage::default());
+        assert!(cache.get(&cache.key(1, next_bucket, chrono_tz::Israel, "Remind me to call", &[])).is_none());
+        // another user or another spelling is a different request
+        assert!(cache.get(&cache.key(2, later, chrono_tz::Israel, "Remind me to call", &[])).is_none());
+        assert!(cache.get(&cache.key(1, later, chrono_tz::Israel, "remind me  to call", &[])).is_none());
+        // so is the same text once a correction changes the prompt
+        let correction = ParseCorrection { input: "call at 8".to_owned(), accepted: "{}".to_owned(), parsed_at: now };
+        assert!(cache.get(&cache.key(1, later, chrono_tz::Israel, "Remind me to call", &[&correction])).is_none());
+        assert!(cache.get(&cache.key(1, later, chrono_tz::Pacific::Honolulu, "Remind me to call", &[])).is_none());
+    }
+
+    fn options(fixtures: Option<ParserFixtures>) -> ModelOptions {
+        ModelOptions {
+            provider: Provider::OpenAI,
+            model: "gpt-4o-mini".to_owned(),
+            temperature: None,
+            max_tokens: None,
+            base_url: Some("https://gateway.local/v1/".to_owned()),
+            organization: Some("org-1".to_owned()),
+            project: Some("proj-1".to_owned()),
+            api_version: String::new(),
+            max_retries: 0,
+            retry_base_delay: Duration::from_millis(1),
+            timeout: Duration::from_secs(1),
+            prompt_price: 0.0,
+            completion_price: 0.0,
+            cache_ttl: Duration::ZERO,
+            summary_model: None,
+            fixtures,
+            prompt_file: None,
+        }
+    }
+
+    #[test]
+    fn should_send_organization_and_project_headers() {
+        let parser = LlmParser::new(Some("key".to_owned()), options(None)).unwrap();
+
+        let request = parser.openai_builder("gpt-4o-mini").build().unwrap();
+
+        assert_eq!(request.url().as_str(), "https://gateway.local/v1/chat/completions");
+        assert_eq!(request.headers()["OpenAI-Organization"], "org-1");
+        assert_eq!(request.headers()["OpenAI-Project"], "proj-1");
+    }
+
+    #[test]
+    fn should_reject_incomplete_provider_settings() {
+        let key = || Some("key".to_owned());
+        let azure = |base_url: Option<&str>, api_version: &str| ModelOptions {
+            provider: Provider::AzureOpenAI, base_url: base_url.map(str::to_owned), api_version: api_version.to_owned(), ..options(None)
+        };
+        assert!(LlmParser::new(key(), azure(None, "2024-02-01")).is_err());
+        assert!(LlmParser::new(key(), azure(Some("https://res.openai.azure.com/openai"), "")).is_err());
+        assert!(LlmParser::new(None, azure(Some("https://res.openai.azure.com/openai"), "2024-02-01")).is_err());
+        assert!(LlmParser::new(key(), azure(Some("https://res.openai.azure.com/openai"), "2024-02-01")).is_ok());
+
+        assert!(LlmParser::new(None, options(None)).is_err());
+        assert!(LlmParser::new(key(), ModelOptions { base_url: Some("gateway.local/v1".to_owned()), ..options(None) }).is_err());
+        assert!(LlmParser::new(None, ModelOptions { provider: Provider::Ollama, base_url: None, ..options(None) }).is_ok());
+    }
+
+    // prompt, completion, notification and stored times, with the completion from assets/fixtures
+    #[tokio::test]
+    async fn should_parse_replayed_completion_into_stored_notifications() {
+        let parser = LlmParser::new(None, options(Some("replay:assets/fixtures/parser".parse().unwrap()))).unwrap();
+        let now = DateTime::parse_from_rfc3339("2023-01-26T14:40:00+02:00").unwrap().with_timezone(&Utc);
+
+        let completion = parser.complete(1, now, chrono_tz::Israel, "Remind me about the dentist tomorrow at 10:00", &[]).await.unwrap();
+        let notification = LlmParser::parse_completion(&completion.content).unwrap();
+        assert_eq!(notification.get_text(), "the dentist");
+        let stored = notification.create_stored_notifications(now, chrono_tz::Israel);
+        let expected = DateTime::parse_from_rfc3339("2023-01-27T10:00:00+02:00").unwrap().with_timezone(&Utc);
+        assert!(matches!(stored.as_slice(), [StoredNotification::Absolute { time }] if *time == expected), "{:?}", stored);
+    }
+}
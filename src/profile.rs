@@ -0,0 +1,79 @@
+use std::path::Path;
+use crate::errors::BotError;
+use crate::models::Env;
+
+// named config profile selected with `--profile <name>`, e.g. a staging bot next to the production one
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Profile {
+    pub name: Option<String>,
+}
+
+impl Profile {
+    // takes `--profile <name>` out of the arguments, the rest is left for the cli commands
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<(Profile, Vec<String>), BotError> {
+        let mut name = None;
+        let mut rest = vec![];
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--profile") {
+                Some("") => name = Some(args.next().ok_or(BotError::Usage("notify-rs --profile <name> [command]"))?),
+                Some(value) if value.starts_with('=') => name = Some(value[1..].to_string()),
+                _ => rest.push(arg),
+            }
+        }
+        Ok((Profile { name }, rest))
+    }
+
+    // variables from `.env.<name>` win over `.env`, real environment variables win over both;
+    // a missing profile file is an error so a typo can't silently start the bot with production settings
+    pub fn load(&self) -> Result<(), BotError> {
+        if let Some(name) = &self.name {
+            let path = format!(".env.{}", name);
+            if !Path::new(&path).exists() {
+                return Err(BotError::ProfileNotFound(path));
+            }
+            dotenv::from_filename(&path).ok();
+        }
+        dotenv::dotenv().ok();
+        Ok(())
+    }
+
+    // in the order they are looked up, the first one setting a variable wins
+    pub fn env_files(&self) -> Vec<String> {
+        self.name.iter().map(|name| format!(".env.{}", name))
+            .chain(std::iter::once(".env".to_string()))
+            .collect()
+    }
+
+    // messages of every profile except production are prefixed, so testers can't mistake them for real ones
+    pub fn apply_defaults(&self, env: &mut Env) {
+        if env.message_prefix.is_none() {
+            env.message_prefix = self.name.as_ref()
+                .filter(|name| name.as_str() != "production")
+                .map(|name| format!("[{}]", name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profile;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn should_take_profile_out_of_arguments() {
+        let (profile, rest) = Profile::from_args(args(&["--profile", "staging", "export", "1"])).unwrap();
+        assert_eq!(profile.name.as_deref(), Some("staging"));
+        assert_eq!(rest, vec!["export", "1"]);
+
+        let (profile, rest) = Profile::from_args(args(&["jobs", "--profile=staging"])).unwrap();
+        assert_eq!(profile.name.as_deref(), Some("staging"));
+        assert_eq!(rest, vec!["jobs"]);
+
+        assert_eq!(Profile::from_args(args(&[])).unwrap().0, Profile::default());
+        assert!(Profile::from_args(args(&["--profile"])).is_err());
+    }
+}
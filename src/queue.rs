@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use fnv::FnvHashMap;
+use tokio::sync::oneshot;
+
+// requests waiting for the parser, granted round-robin between users so one busy chat can't starve the rest;
+// every user may hold at most user_limit of the limit slots at once
+pub struct ParserQueue {
+    limit: usize,
+    user_limit: usize,
+    state: Mutex<QueueState>,
+    stats: WaitStats,
+}
+
+#[derive(Default)]
+struct QueueState {
+    running: usize,
+    running_per_user: FnvHashMap<u64, usize>,
+    // users with waiting requests in the order they are served
+    turns: VecDeque<u64>,
+    waiting: FnvHashMap<u64, VecDeque<oneshot::Sender<()>>>,
+}
+
+#[derive(Default)]
+struct WaitStats {
+    queued: AtomicU64,
+    total_millis: AtomicU64,
+    max_millis: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    pub waiting: usize,
+    pub queued: u64,
+    pub average_wait: Duration,
+    pub max_wait: Duration,
+}
+
+// a parser slot, handed over to the next waiting request when dropped
+pub struct ParserPermit {
+    queue: Arc<ParserQueue>,
+    user_id: u64,
+}
+
+pub enum Admission {
+    Granted(ParserPermit),
+    // position counts the requests served before this one, the first in line is 1
+    Queued { position: usize, ticket: Ticket },
+}
+
+// a place in the queue, leaving it gives the slot back if one was already granted
+pub struct Ticket {
+    queue: Arc<ParserQueue>,
+    user_id: u64,
+    receiver: Option<oneshot::Receiver<()>>,
+    queued_at: Instant,
+}
+
+impl ParserQueue {
+    pub fn new(limit: usize, user_limit: usize) -> Arc<ParserQueue> {
+        Arc::new(ParserQueue {
+            limit: limit.max(1),
+            user_limit: user_limit.max(1),
+            state: Mutex::default(),
+            stats: WaitStats::default(),
+        })
+    }
+
+    pub fn enter(self: &Arc<Self>, user_id: u64) -> Admission {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let running = state.running_per_user.get(&user_id).copied().unwrap_or_default();
+        let waiting = state.waiting.get(&user_id).map_or(0, VecDeque::len);
+        if waiting == 0 && running < self.user_limit && state.running < self.limit {
+            state.take(user_id);
+            return Admission::Granted(ParserPermit { queue: self.clone(), user_id });
+        }
+
+        // other users get a turn for every request of this user that is already waiting, and one more
+        let position = waiting + 1 + state.waiting.iter()
+            .filter(|(other, _)| **other != user_id)
+            .map(|(_, senders)| senders.len().min(waiting + 1))
+            .sum::<usize>();
+        let (sender, receiver) = oneshot::channel();
+        if waiting == 0 {
+            state.turns.push_back(user_id);
+        }
+        state.waiting.entry(user_id).or_default().push_back(sender);
+        self.stats.queued.fetch_add(1, Ordering::Relaxed);
+        Admission::Queued {
+            position,
+            ticket: Ticket { queue: self.clone(), user_id, receiver: Some(receiver), queued_at: Instant::now() },
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let waiting = self.state.lock().unwrap_or_else(PoisonError::into_inner).waiting.values().map(VecDeque::len).sum();
+        let queued = self.stats.queued.load(Ordering::Relaxed);
+        let total_millis = self.stats.total_millis.load(Ordering::Relaxed);
+        QueueStats {
+            waiting,
+            queued,
+            average_wait: Duration::from_millis(total_millis.checked_div(queued).unwrap_or_default()),
+            max_wait: Duration::from_millis(self.stats.max_millis.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn release(&self, user_id: u64) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.give_back(user_id);
+        self.dispatch(&mut state);
+    }
+
+    // grants free slots to the first users in turn that haven't used up their share
+    fn dispatch(&self, state: &mut QueueState) {
+        while state.running < self.limit {
+            let next = state.turns.iter()
+                .position(|user_id| state.running_per_user.get(user_id).copied().unwrap_or_default() < self.user_limit);
+            let user_id = match next.and_then(|index| state.turns.remove(index)) {
+                Some(user_id) => user_id,
+                None => return,
+            };
+            let senders = state.waiting.entry(user_id).or_default();
+            let sender = senders.pop_front();
+            if senders.is_empty() {
+                state.waiting.remove(&user_id);
+            } else {
+                state.turns.push_back(user_id);
+            }
+            // a request that stopped waiting is skipped
+            if let Some(sender) = sender {
+                if sender.send(()).is_ok() {
+                    state.take(user_id);
+                }
+            }
+        }
+    }
+}
+
+impl QueueState {
+    fn take(&mut self, user_id: u64) {
+        self.running += 1;
+        *self.running_per_user.entry(user_id).or_default() += 1;
+    }
+
+    fn give_back(&mut self, user_id: u64) {
+        self.running = self.running.saturating_sub(1);
+        if let Some(running) = self.running_per_user.get_mut(&user_id) {
+            *running -= 1;
+            if *running == 0 {
+                self.running_per_user.remove(&user_id);
+            }
+        }
+    }
+}
+
+impl Ticket {
+    pub async fn wait(mut self) -> ParserPermit {
+        if let Some(receiver) = self.receiver.as_mut() {
+            // the sender is only dropped together with the queue, which this ticket keeps alive
+            let _ = receiver.await;
+        }
+        self.receiver = None;
+        let millis = self.queued_at.elapsed().as_millis() as u64;
+        self.queue.stats.total_millis.fetch_add(millis, Ordering::Relaxed);
+        self.queue.stats.max_millis.fetch_max(millis, Ordering::Relaxed);
+        ParserPermit { queue: self.queue.clone(), user_id: self.user_id }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        if let Some(mut receiver) = self.receiver.take() {
+            receiver.close();
+            if receiver.try_recv().is_ok() {
+                self.queue.release(self.user_id);
+            }
+        }
+    }
+}
+
+impl Drop for ParserPermit {
+    fn drop(&mut self) {
+        self.queue.release(self.user_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Admission, ParserPermit, ParserQueue, Ticket};
+
+    fn granted(admission: Admission) -> ParserPermit {
+        match admission {
+            Admission::Granted(permit) => permit,
+            Admission::Queued { .. } => panic!("expected a free slot"),
+        }
+    }
+
+    fn queued(admission: Admission) -> (usize, Ticket) {
+        match admission {
+            Admission::Queued { position, ticket } => (position, ticket),
+            Admission::Granted(_) => panic!("expected to wait"),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_serve_users_in_turns() {
+        let queue = ParserQueue::new(2, 1);
+        let spammer = granted(queue.enter(1));
+        let (first, spam) = queued(queue.enter(1));
+        let (second, more_spam) = queued(queue.enter(1));
+        let quiet = granted(queue.enter(2));
+        let (third, other) = queued(queue.enter(3));
+        assert_eq!((first, second, third), (1, 2, 2));
+        assert_eq!(queue.stats().waiting, 3);
+
+        // user 1 already holds its share, so the freed slot goes to user 3 despite the earlier spam
+        drop(quiet);
+        let other = other.wait().await;
+        drop(spammer);
+        let spam = spam.wait().await;
+        drop(other);
+        assert_eq!(queue.stats().waiting, 1);
+        drop(spam);
+        drop(more_spam.wait().await);
+
+        let stats = queue.stats();
+        assert_eq!((stats.waiting, stats.queued), (0, 3));
+        granted(queue.enter(1));
+    }
+
+    #[tokio::test]
+    async fn should_free_slot_of_abandoned_ticket() {
+        let queue = ParserQueue::new(1, 1);
+        let permit = granted(queue.enter(1));
+        let (_, abandoned) = queued(queue.enter(2));
+        let (_, ticket) = queued(queue.enter(3));
+        // the slot is granted to user 2 on release, but nobody waits for it anymore
+        drop(permit);
+        drop(abandoned);
+        drop(ticket.wait().await);
+        granted(queue.enter(2));
+    }
+}
@@ -0,0 +1,53 @@
+use crate::models::InlineKeyboardMarkup;
+
+// keyboard buttons offered as a numbered list to users in plain mode, answered by sending the number
+#[derive(Debug, Clone, Default)]
+pub struct PlainChoices {
+    // message the buttons belong to, callbacks built from a choice act on it
+    pub message_id: u64,
+    pub options: Vec<String>,
+}
+
+// flattens an inline keyboard into text for screen readers and clients without keyboard support,
+// returns the callback data of every option in the order they are numbered
+pub fn render_plain(text: &str, markup: &InlineKeyboardMarkup) -> (String, Vec<String>) {
+    let buttons = markup.inline_keyboard.iter().flatten().collect::<Vec<_>>();
+    let mut rendered = text.to_string();
+    if !rendered.is_empty() {
+        rendered.push_str("\n\n");
+    }
+    rendered.push_str("Reply with a number:");
+    for (index, button) in buttons.iter().enumerate() {
+        rendered.push_str(&format!("\n{}. {}", index + 1, button.text.trim_end_matches('…')));
+    }
+    (rendered, buttons.into_iter().map(|button| button.callback_data.clone()).collect())
+}
+
+pub fn pick(reply: &str, choices: &PlainChoices) -> Option<String> {
+    let number = reply.trim().trim_end_matches('.').parse::<usize>().ok()?;
+    choices.options.get(number.checked_sub(1)?).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{InlineKeyboardButton, InlineKeyboardMarkup};
+    use super::{pick, render_plain, PlainChoices};
+
+    #[test]
+    fn should_number_buttons_and_pick_by_reply() {
+        let button = |text: &str, data: &str| InlineKeyboardButton { text: text.to_string(), callback_data: data.to_string() };
+        let markup = InlineKeyboardMarkup {
+            inline_keyboard: vec![vec![button("Accept", "accept")], vec![button("Remind again…", "again:1")]],
+        };
+
+        let (text, options) = render_plain("Call Alex", &markup);
+        assert_eq!(text, "Call Alex\n\nReply with a number:\n1. Accept\n2. Remind again");
+
+        let choices = PlainChoices { message_id: 7, options };
+        assert_eq!(pick(" 2 ", &choices).as_deref(), Some("again:1"));
+        assert_eq!(pick("1.", &choices).as_deref(), Some("accept"));
+        assert_eq!(pick("0", &choices), None);
+        assert_eq!(pick("3", &choices), None);
+        assert_eq!(pick("tomorrow at 3", &choices), None);
+    }
+}
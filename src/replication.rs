@@ -0,0 +1,90 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{error, info};
+use tokio::task::JoinHandle;
+use crate::db::{EventRepository, IN_MEMORY};
+use crate::errors::BotError;
+use crate::models::Env;
+
+// shell hooks that let an external tool (litestream, rclone, aws cli...) replicate the database file
+#[derive(Debug, Clone)]
+pub struct Replication {
+    database_path: PathBuf,
+    snapshot_path: PathBuf,
+    snapshot_hook: Option<String>,
+    restore_hook: Option<String>,
+    interval: Option<Duration>,
+}
+
+impl Replication {
+    pub fn new(env: &Env) -> Replication {
+        let snapshot_path = env.snapshot_path.clone()
+            .unwrap_or_else(|| format!("{}.snapshot", env.connection_string));
+        Replication {
+            database_path: PathBuf::from(&env.connection_string),
+            snapshot_path: PathBuf::from(snapshot_path),
+            snapshot_hook: env.snapshot_hook.clone(),
+            // there is no file to restore an in-memory database from
+            restore_hook: env.restore_hook.clone().filter(|_| env.connection_string != IN_MEMORY),
+            interval: env.snapshot_interval_secs.map(Duration::from_secs),
+        }
+    }
+
+    // runs the restore hook when the database file is missing, e.g. on a freshly provisioned host
+    pub async fn restore_if_missing(&self) -> Result<(), BotError> {
+        match &self.restore_hook {
+            Some(hook) if !self.database_path.exists() => {
+                info!("Database {} is missing, running restore hook", self.database_path.display());
+                run_hook(hook, "DATABASE_FILE", &self.database_path).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn run_snapshot_task(self, event_repository: EventRepository) -> Option<JoinHandle<()>> {
+        let interval = self.interval?;
+        Some(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                if let Err(err) = self.snapshot(&event_repository).await {
+                    error!("Error while taking database snapshot: {}", err);
+                }
+            }
+        }))
+    }
+
+    async fn snapshot(&self, event_repository: &EventRepository) -> Result<(), BotError> {
+        event_repository.snapshot(self.snapshot_path.clone()).await?;
+        info!("Database snapshot written to {}", self.snapshot_path.display());
+        match &self.snapshot_hook {
+            Some(hook) => run_hook(hook, "SNAPSHOT_FILE", &self.snapshot_path).await,
+            None => Ok(()),
+        }
+    }
+}
+
+async fn run_hook(hook: &str, variable: &str, path: &Path) -> Result<(), BotError> {
+    let status = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .env(variable, path)
+        .status()
+        .await?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(BotError::HookFailed(hook.to_string(), status.code()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::run_hook;
+
+    #[tokio::test]
+    async fn should_pass_file_to_hook_and_report_failures() {
+        run_hook("test \"$SNAPSHOT_FILE\" = /tmp/db.snapshot", "SNAPSHOT_FILE", Path::new("/tmp/db.snapshot")).await.unwrap();
+        assert!(run_hook("exit 3", "SNAPSHOT_FILE", Path::new("/tmp/db.snapshot")).await.is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::db::EventRepository;
+use crate::errors::BotError;
+use crate::ids::UuidV7Generator;
+use crate::parser::{LlmParser, Provider};
+use crate::tg::{TelegramApi, Tg};
+
+// written only when there is no such file yet, an existing config is never overwritten
+const CONFIG_PATH: &str = ".env";
+
+// interactive first run: asks for the required settings, checks them against the live services,
+// creates the database and makes whoever opens the deep link the admin;
+// the settings are exported into the process environment so the bot can start right after
+pub async fn run_wizard() -> Result<(), BotError> {
+    println!("Some required settings are missing, let's set the bot up.");
+    let mut settings = vec![];
+
+    let (tg, bot_name) = loop {
+        let key = ask("Telegram bot token (from @BotFather)", None)?;
+        let tg = Tg::new(key.clone(), None);
+        match tg.get_me().await {
+            Ok(me) => {
+                let bot_name = me.username.unwrap_or_default();
+                println!("Connected as @{}", bot_name);
+                settings.push(("TG_KEY", key));
+                break (tg, bot_name);
+            }
+            Err(err) => println!("Telegram rejected the token: {}", without_url(err)),
+        }
+    };
+
+    let provider = loop {
+        let name = ask("LLM provider (openai, azure, anthropic, ollama)", Some("openai"))?;
+        match Provider::from_str(&name) {
+            Ok(provider) => {
+                settings.push(("LLM_PROVIDER", name));
+                break provider;
+            }
+            Err(err) => println!("{}", err),
+        }
+    };
+    loop {
+        let base_url = match provider {
+            Provider::AzureOpenAI => Some(ask("Azure resource url", None)?),
+            _ => None,
+        };
+        let token = match provider {
+            Provider::Ollama => None,
+            _ => Some(ask("API token", None)?),
+        };
+        match LlmParser::ping(provider, base_url.as_deref(), token.as_deref()).await {
+            Ok(_) => {
+                settings.extend(base_url.map(|url| ("OAI_BASE_URL", url)));
+                settings.extend(token.map(|token| ("OAI_TOKEN", token)));
+                break;
+            }
+            Err(err) => println!("The provider rejected the settings: {}", without_url(err)),
+        }
+    }
+
+    let connection_string = ask("Database file", Some("notify.db"))?;
+    EventRepository::new(&connection_string, Arc::new(UuidV7Generator)).await?;
+    println!("Database is ready at {}", connection_string);
+    settings.push(("CONN_STRING", connection_string));
+
+    let admin_id = wait_for_admin(&tg, &bot_name).await?;
+    settings.push(("ADMIN_ID", admin_id.to_string()));
+    settings.push(("TG_USERS", admin_id.to_string()));
+
+    for (name, value) in settings.iter() {
+        std::env::set_var(name, value);
+    }
+    let config = render_config(&settings);
+    if Path::new(CONFIG_PATH).exists() {
+        println!("{} already exists, add these lines to it to keep the settings:\n{}", CONFIG_PATH, config);
+    } else if ask("Write the settings to .env? (y/n)", Some("y"))?.eq_ignore_ascii_case("y") {
+        std::fs::write(CONFIG_PATH, config)?;
+        println!("Settings are written to {}", CONFIG_PATH);
+    }
+    Ok(())
+}
+
+const HANDSHAKE_POLL_TIMEOUT: Duration = Duration::from_secs(30);
+
+// the first one to send /start with the random token becomes the admin
+async fn wait_for_admin(tg: &Tg, bot_name: &str) -> Result<u64, BotError> {
+    let token = handshake_token();
+    println!("Open {} and press Start to become the admin", deep_link(bot_name, &token));
+    let expected = format!("/start {}", token);
+    let mut offset = 0;
+    loop {
+        for update in tg.get_updates(offset, HANDSHAKE_POLL_TIMEOUT).await? {
+            offset = update.update_id + 1;
+            let admin = update.message
+                .filter(|message| message.text.as_deref().map(str::trim) == Some(expected.as_str()))
+                .and_then(|message| message.from);
+            if let Some(admin) = admin {
+                // confirms the handshake update, so the bot doesn't handle it again once started
+                tg.get_updates(offset, Duration::ZERO).await?;
+                tg.send_message(admin.id, "You are the admin of this bot now".to_string(), None).await?;
+                println!("Admin is {}", admin.username.map_or_else(|| admin.id.to_string(), |name| format!("@{}", name)));
+                return Ok(admin.id);
+            }
+        }
+    }
+}
+
+fn ask(question: &str, default: Option<&str>) -> Result<String, BotError> {
+    loop {
+        match default {
+            Some(default) => print!("{} [{}]: ", question, default),
+            None => print!("{}: ", question),
+        }
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        if std::io::stdin().lock().read_line(&mut answer)? == 0 {
+            return Err(BotError::Usage("setup was interrupted"));
+        }
+        match (answer.trim(), default) {
+            ("", Some(default)) => return Ok(default.to_string()),
+            ("", None) => continue,
+            (answer, _) => return Ok(answer.to_string()),
+        }
+    }
+}
+
+// request urls carry the bot key, so they are stripped from printed errors
+fn without_url(err: BotError) -> String {
+    match err {
+        BotError::Reqwest(err) => err.without_url().to_string(),
+        err => err.to_string(),
+    }
+}
+
+fn handshake_token() -> String {
+    let mut bytes = [0u8; 8];
+    getrandom::getrandom(&mut bytes).expect("system random source is unavailable");
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn deep_link(bot_name: &str, token: &str) -> String {
+    format!("https://t.me/{}?start={}", bot_name, token)
+}
+
+fn render_config(settings: &[(&str, String)]) -> String {
+    settings.iter()
+        .map(|(name, value)| format!("{}=\"{}\"\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deep_link, handshake_token, render_config};
+
+    #[test]
+    fn should_render_deep_link_and_config() {
+        let token = handshake_token();
+        assert_eq!(token.len(), 16);
+        assert_eq!(deep_link("notify_bot", "00ff"), "https://t.me/notify_bot?start=00ff");
+
+        let config = render_config(&[("TG_KEY", "1:abc".to_string()), ("CONN_STRING", "my \"db\".sqlite".to_string())]);
+        assert_eq!(config, "TG_KEY=\"1:abc\"\nCONN_STRING=\"my \\\"db\\\".sqlite\"\n");
+    }
+}
@@ -0,0 +1,88 @@
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use fnv::FnvHashMap;
+
+// per-key state tagged with a version that is bumped on every successful write, so a handler
+// that started from an outdated snapshot can't overwrite a transition made by a concurrent one
+#[derive(Debug)]
+pub struct StateStore<K, T> {
+    states: Arc<Mutex<FnvHashMap<K, (u64, T)>>>,
+}
+
+impl<K, T> Clone for StateStore<K, T> {
+    fn clone(&self) -> Self {
+        StateStore { states: self.states.clone() }
+    }
+}
+
+impl<K: Copy + Eq + Hash, T: Clone + Default> StateStore<K, T> {
+    pub fn new() -> StateStore<K, T> {
+        StateStore { states: Arc::new(Mutex::new(FnvHashMap::default())) }
+    }
+
+    pub fn get(&self, key: K) -> (u64, T) {
+        self.states.lock().unwrap()
+            .get(&key)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn set(&self, key: K, value: T) {
+        let mut states = self.states.lock().unwrap();
+        let entry = states.entry(key).or_insert_with(|| (0, T::default()));
+        *entry = (entry.0 + 1, value);
+    }
+
+    // stores the value only if nobody wrote since `expected_version` was read
+    pub fn compare_and_set(&self, key: K, expected_version: u64, value: T) -> bool {
+        let mut states = self.states.lock().unwrap();
+        let entry = states.entry(key).or_insert_with(|| (0, T::default()));
+        if entry.0 != expected_version {
+            return false;
+        }
+        *entry = (expected_version + 1, value);
+        true
+    }
+
+    // keeps the `limit` greatest keys of a group and forgets the rest, e.g. all but the latest drafts of a chat
+    pub fn retain_latest(&self, in_group: impl Fn(&K) -> bool, limit: usize) where K: Ord {
+        let mut states = self.states.lock().unwrap();
+        let mut keys = states.keys().filter(|key| in_group(key)).copied().collect::<Vec<_>>();
+        keys.sort_unstable_by(|a, b| b.cmp(a));
+        for key in keys.into_iter().skip(limit) {
+            states.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StateStore;
+
+    #[test]
+    fn should_reject_write_from_stale_snapshot() {
+        let store = StateStore::<u64, String>::new();
+        let (first_version, _) = store.get(1);
+        let (second_version, _) = store.get(1);
+
+        assert!(store.compare_and_set(1, second_version, "second".to_string()));
+        assert!(!store.compare_and_set(1, first_version, "first".to_string()));
+        assert_eq!(store.get(1), (1, "second".to_string()));
+        assert_eq!(store.get(2), (0, String::new()));
+    }
+
+    #[test]
+    fn should_keep_latest_keys_of_group() {
+        let store = StateStore::<(u64, u64), String>::new();
+        for message_id in 1..=4 {
+            store.set((1, message_id), format!("draft {}", message_id));
+        }
+        store.set((2, 1), "other chat".to_string());
+
+        store.retain_latest(|(chat_id, _)| *chat_id == 1, 2);
+        assert_eq!(store.get((1, 2)), (0, String::new()));
+        assert_eq!(store.get((1, 3)).1, "draft 3");
+        assert_eq!(store.get((1, 4)).1, "draft 4");
+        assert_eq!(store.get((2, 1)).1, "other chat");
+    }
+}
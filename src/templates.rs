@@ -0,0 +1,38 @@
+// names of {placeholders} in a template body without repeats, in the order they appear
+pub fn placeholders(body: &str) -> Vec<&str> {
+    let mut names = vec![];
+    let mut rest = body;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('}') else { break };
+        let name = &rest[..end];
+        if !name.is_empty() && name.chars().all(|ch| ch.is_alphanumeric() || ch == '_') {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+            rest = &rest[end + 1..];
+        }
+    }
+    names
+}
+
+// every occurrence of the placeholder gets the same value
+pub fn fill(body: &str, name: &str, value: &str) -> String {
+    body.replace(&format!("{{{}}}", name), value.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fill, placeholders};
+
+    #[test]
+    fn should_fill_placeholders_in_order() {
+        let body = "take {med} at {time}, {med} is in the {place}";
+        assert_eq!(placeholders(body), vec!["med", "time", "place"]);
+        assert_eq!(placeholders("json {\"a\": 1} and {not closed"), Vec::<&str>::new());
+
+        let body = fill(body, "med", " aspirin ");
+        assert_eq!(body, "take aspirin at {time}, aspirin is in the {place}");
+        assert_eq!(placeholders(&body), vec!["time", "place"]);
+    }
+}
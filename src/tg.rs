@@ -1,22 +1,124 @@
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+use async_trait::async_trait;
+use fnv::FnvHashMap;
 use reqwest::Url;
 use crate::errors::BotError;
-use crate::models::{EditMessage, GetUpdatesResponse, InlineKeyboardMarkup, SendMessage, Update};
+use crate::models::{AnswerInlineQuery, DeleteBusinessMessages, EditMessage, EditMessageReplyMarkup, ForceReply, GetFileResponse, GetMeResponse, GetUpdatesResponse, InlineKeyboardMarkup, InlineQueryResultArticle, Location, PinChatMessage, SendForceReply, SendLocation, SendMessage, SendMessageResponse, Update, User};
+
+// every kind of update the bot handles, the rest isn't even sent by telegram
+const ALLOWED_UPDATES: &str = r#"["message","edited_message","callback_query","business_connection","business_message","inline_query","chosen_inline_result"]"#;
+
+// slack on top of the long poll, so the request isn't cut off while telegram is about to answer
+const POLL_TIMEOUT_SLACK: Duration = Duration::from_secs(10);
+
+// everything the bot asks of telegram, so handlers can run against a recording client in tests
+#[async_trait]
+pub trait TelegramApi: Send + Sync {
+    fn route_business_chat(&self, chat_id: u64, connection_id: String);
+
+    fn unroute_business_connection(&self, connection_id: &str);
+
+    fn is_business_chat(&self, chat_id: u64) -> bool;
+
+    async fn get_me(&self) -> Result<User, BotError>;
+
+    async fn get_updates(&self, offset: u64, timeout: Duration) -> Result<Vec<Update>, BotError>;
+
+    async fn answer_callback_query(&self, callback_query_id: String, text: Option<String>) -> Result<(), BotError>;
+
+    async fn answer_inline_query(&self, inline_query_id: String, results: Vec<InlineQueryResultArticle>) -> Result<(), BotError>;
+
+    async fn send_message(&self, chat_id: u64, text: String, reply_markup: Option<InlineKeyboardMarkup>) -> Result<(), BotError> {
+        self.send_message_with_id(chat_id, text, reply_markup).await?;
+        Ok(())
+    }
+
+    // same as send_message, but returns the id of the sent message
+    async fn send_message_with_id(&self, chat_id: u64, text: String, reply_markup: Option<InlineKeyboardMarkup>) -> Result<u64, BotError> {
+        self.send_notification(chat_id, text, reply_markup, None, None).await
+    }
+
+    async fn send_notification(&self, chat_id: u64, text: String, reply_markup: Option<InlineKeyboardMarkup>, disable_notification: Option<bool>, reply_to: Option<u64>) -> Result<u64, BotError>;
+
+    async fn send_force_reply(&self, chat_id: u64, text: String, placeholder: Option<String>) -> Result<u64, BotError>;
+
+    async fn send_location(&self, chat_id: u64, location: Location) -> Result<(), BotError>;
+
+    async fn send_document(&self, chat_id: u64, file_name: &str, content_type: &str, content: Vec<u8>) -> Result<(), BotError>;
+
+    async fn edit_message_text(&self, chat_id: u64, message_id: u64, text: String, reply_markup: Option<InlineKeyboardMarkup>) -> Result<(), BotError>;
+
+    async fn edit_message_reply_markup(&self, chat_id: u64, message_id: u64, reply_markup: Option<InlineKeyboardMarkup>) -> Result<(), BotError>;
+
+    async fn download_file(&self, file_id: &str) -> Result<Vec<u8>, BotError>;
+
+    async fn delete_message(&self, chat_id: u64, message_id: u64) -> Result<(), BotError>;
+
+    async fn pin_chat_message(&self, chat_id: u64, message_id: u64) -> Result<(), BotError>;
+
+    async fn unpin_chat_message(&self, chat_id: u64, message_id: u64) -> Result<(), BotError>;
+}
 
 #[derive(Clone)]
 pub struct Tg {
     client: reqwest::Client,
-    key: String
+    key: String,
+    // prepended to every text sent by the bot, marks messages from non-production profiles
+    prefix: Option<String>,
+    // chats reached through a business account, messages to them are sent on behalf of that account
+    business_chats: Arc<RwLock<FnvHashMap<u64, String>>>,
 }
 
 impl Tg {
-    pub fn new(key: String) -> Tg {
+    pub fn new(key: String, prefix: Option<String>) -> Tg {
         let client = reqwest::Client::new();
-        Tg { client, key }
+        Tg { client, key, prefix, business_chats: Arc::default() }
+    }
+
+    fn business_connection_id(&self, chat_id: u64) -> Option<String> {
+        self.business_chats.read().unwrap_or_else(PoisonError::into_inner).get(&chat_id).cloned()
+    }
+
+    fn with_prefix(&self, text: String) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{} {}", prefix, text),
+            None => text,
+        }
+    }
+}
+
+#[async_trait]
+impl TelegramApi for Tg {
+    fn route_business_chat(&self, chat_id: u64, connection_id: String) {
+        self.business_chats.write().unwrap_or_else(PoisonError::into_inner).insert(chat_id, connection_id);
+    }
+
+    fn unroute_business_connection(&self, connection_id: &str) {
+        self.business_chats.write().unwrap_or_else(PoisonError::into_inner).retain(|_, id| id != connection_id);
     }
 
-    pub async fn get_updates(&self, offset: u64) -> Result<Vec<Update>, BotError> {
-        let url = format!("https://api.telegram.org/bot{}/getUpdates?offset={}", self.key, offset);
-        let updates: GetUpdatesResponse = self.client.get(&url)
+    fn is_business_chat(&self, chat_id: u64) -> bool {
+        self.business_connection_id(chat_id).is_some()
+    }
+
+    // cheap authenticated call used to check that telegram is reachable and the key is valid
+    async fn get_me(&self) -> Result<User, BotError> {
+        let url = format!("https://api.telegram.org/bot{}/getMe", self.key);
+        let response: GetMeResponse = self.client.get(&url).send().await?.error_for_status()?.json().await?;
+        Ok(response.result)
+    }
+
+    // long poll: telegram answers as soon as an update arrives or once `timeout` passes with none
+    async fn get_updates(&self, offset: u64, timeout: Duration) -> Result<Vec<Update>, BotError> {
+        let base = format!("https://api.telegram.org/bot{}/getUpdates", self.key);
+        let mut url: Url = Url::parse(&base)?;
+        url.query_pairs_mut()
+            .append_pair("offset", &offset.to_string())
+            .append_pair("timeout", &timeout.as_secs().to_string())
+            .append_pair("allowed_updates", ALLOWED_UPDATES);
+        let updates: GetUpdatesResponse = self.client.get(url)
+            .timeout(timeout + POLL_TIMEOUT_SLACK)
             .send()
             .await?
             .json()
@@ -24,7 +126,7 @@ impl Tg {
         Ok(updates.result)
     }
 
-    pub async fn answer_callback_query(&self, callback_query_id: String, text: Option<String>) -> Result<(), BotError> {
+    async fn answer_callback_query(&self, callback_query_id: String, text: Option<String>) -> Result<(), BotError> {
         let base = format!("https://api.telegram.org/bot{}/answerCallbackQuery", self.key);
         let mut url: Url = Url::parse(&base)?;
         {
@@ -38,34 +140,144 @@ impl Tg {
         Ok(())
     }
 
-    pub async fn send_message(&self, chat_id: u64, text: String, reply_markup: Option<InlineKeyboardMarkup>) -> Result<(), BotError> {
+    // results are personal and not cached, since they depend on who asks and the role they have
+    async fn answer_inline_query(&self, inline_query_id: String, results: Vec<InlineQueryResultArticle>) -> Result<(), BotError> {
+        let base = format!("https://api.telegram.org/bot{}/answerInlineQuery", self.key);
+        let url: Url = Url::parse(&base)?;
+        let answer = AnswerInlineQuery { inline_query_id, results, cache_time: 0, is_personal: true };
+        self.client.post(url).json(&answer).send().await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    // message that rings or stays silent regardless of the chat settings when disable_notification is given;
+    // a reply to a message deleted since then is sent as a plain message
+    async fn send_notification(&self, chat_id: u64, text: String, reply_markup: Option<InlineKeyboardMarkup>, disable_notification: Option<bool>, reply_to: Option<u64>) -> Result<u64, BotError> {
         // send post request with SendMessage in json in body
         let base = format!("https://api.telegram.org/bot{}/sendMessage", self.key);
         let url: Url = Url::parse(&base)?;
         let send_message = SendMessage {
+            business_connection_id: self.business_connection_id(chat_id),
             chat_id,
-            text,
-            reply_markup
+            text: self.with_prefix(text),
+            reply_markup,
+            disable_notification,
+            reply_to_message_id: reply_to,
+            allow_sending_without_reply: reply_to.map(|_| true),
         };
-        self.client.post(url).json(&send_message).send().await?;
+        let response: SendMessageResponse = self.client.post(url).json(&send_message).send().await?
+            .error_for_status()?
+            .json().await?;
+        Ok(response.result.message_id)
+    }
+
+    // prompt that opens a reply field in the client, the answer comes back as a regular message
+    async fn send_force_reply(&self, chat_id: u64, text: String, placeholder: Option<String>) -> Result<u64, BotError> {
+        let base = format!("https://api.telegram.org/bot{}/sendMessage", self.key);
+        let url: Url = Url::parse(&base)?;
+        let send_message = SendForceReply {
+            business_connection_id: self.business_connection_id(chat_id),
+            chat_id,
+            text: self.with_prefix(text),
+            reply_markup: ForceReply { force_reply: true, input_field_placeholder: placeholder },
+        };
+        let response: SendMessageResponse = self.client.post(url).json(&send_message).send().await?
+            .error_for_status()?
+            .json().await?;
+        Ok(response.result.message_id)
+    }
+
+    // map pin sent after a reminder that was created together with a location
+    async fn send_location(&self, chat_id: u64, location: Location) -> Result<(), BotError> {
+        let base = format!("https://api.telegram.org/bot{}/sendLocation", self.key);
+        let url: Url = Url::parse(&base)?;
+        let send_location = SendLocation {
+            business_connection_id: self.business_connection_id(chat_id),
+            chat_id,
+            latitude: location.latitude,
+            longitude: location.longitude,
+        };
+        self.client.post(url).json(&send_location).send().await?
+            .error_for_status()?;
         Ok(())
     }
 
-    pub async fn edit_message_text(&self, chat_id: u64, message_id: u64, text: String, reply_markup: Option<InlineKeyboardMarkup>) -> Result<(), BotError> {
+    async fn send_document(&self, chat_id: u64, file_name: &str, content_type: &str, content: Vec<u8>) -> Result<(), BotError> {
+        let base = format!("https://api.telegram.org/bot{}/sendDocument", self.key);
+        let url: Url = Url::parse(&base)?;
+        // multipart/form-data assembled by hand, the reqwest multipart feature isn't enabled
+        let boundary = format!("notify-rs-{:x}", chrono::Utc::now().timestamp_nanos());
+        let mut body = self.business_connection_id(chat_id)
+            .map(|id| format!("--{boundary}\r\nContent-Disposition: form-data; name=\"business_connection_id\"\r\n\r\n{id}\r\n"))
+            .unwrap_or_default()
+            .into_bytes();
+        body.extend(format!("--{boundary}\r\nContent-Disposition: form-data; name=\"chat_id\"\r\n\r\n{chat_id}\r\n\
+            --{boundary}\r\nContent-Disposition: form-data; name=\"document\"; filename=\"{file_name}\"\r\n\
+            Content-Type: {content_type}\r\n\r\n").into_bytes());
+        body.extend(content);
+        body.extend(format!("\r\n--{boundary}--\r\n").into_bytes());
+        self.client.post(url)
+            .header("Content-Type", format!("multipart/form-data; boundary={}", boundary))
+            .body(body)
+            .send().await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn edit_message_text(&self, chat_id: u64, message_id: u64, text: String, reply_markup: Option<InlineKeyboardMarkup>) -> Result<(), BotError> {
         // send post request with SendMessage in json in body
         let base = format!("https://api.telegram.org/bot{}/editMessageText", self.key);
         let url: Url = Url::parse(&base)?;
         let send_message = EditMessage {
+            business_connection_id: self.business_connection_id(chat_id),
             chat_id,
             message_id,
-            text,
+            text: self.with_prefix(text),
             reply_markup
         };
         self.client.post(url).json(&send_message).send().await?;
         Ok(())
     }
 
-    pub async fn delete_message(&self, chat_id: u64, message_id: u64) -> Result<(), BotError> {
+    async fn edit_message_reply_markup(&self, chat_id: u64, message_id: u64, reply_markup: Option<InlineKeyboardMarkup>) -> Result<(), BotError> {
+        let base = format!("https://api.telegram.org/bot{}/editMessageReplyMarkup", self.key);
+        let url: Url = Url::parse(&base)?;
+        let edit_markup = EditMessageReplyMarkup {
+            business_connection_id: self.business_connection_id(chat_id),
+            chat_id,
+            message_id,
+            reply_markup
+        };
+        self.client.post(url).json(&edit_markup).send().await?;
+        Ok(())
+    }
+
+    async fn download_file(&self, file_id: &str) -> Result<Vec<u8>, BotError> {
+        let base = format!("https://api.telegram.org/bot{}/getFile", self.key);
+        let mut url: Url = Url::parse(&base)?;
+        url.query_pairs_mut().append_pair("file_id", file_id);
+        let file: GetFileResponse = self.client.get(url)
+            .send().await?
+            .error_for_status()?
+            .json().await?;
+        let file_path = file.result.file_path.ok_or(BotError::Usage("File is too big to download"))?;
+        let url = format!("https://api.telegram.org/file/bot{}/{}", self.key, file_path);
+        let content = self.client.get(url)
+            .send().await?
+            .error_for_status()?
+            .bytes().await?;
+        Ok(content.to_vec())
+    }
+
+    async fn delete_message(&self, chat_id: u64, message_id: u64) -> Result<(), BotError> {
+        // deleteMessage can't touch messages of a business account, they have a separate method
+        if let Some(business_connection_id) = self.business_connection_id(chat_id) {
+            let base = format!("https://api.telegram.org/bot{}/deleteBusinessMessages", self.key);
+            let url: Url = Url::parse(&base)?;
+            let delete = DeleteBusinessMessages { business_connection_id, message_ids: vec![message_id] };
+            self.client.post(url).json(&delete).send().await?;
+            return Ok(());
+        }
         let base = format!("https://api.telegram.org/bot{}/deleteMessage", self.key);
         let mut url: Url = Url::parse(&base)?;
         {
@@ -76,4 +288,162 @@ impl Tg {
         self.client.get(url).send().await?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    // pinning is silent, the pinned message itself has already rung
+    async fn pin_chat_message(&self, chat_id: u64, message_id: u64) -> Result<(), BotError> {
+        let base = format!("https://api.telegram.org/bot{}/pinChatMessage", self.key);
+        let url: Url = Url::parse(&base)?;
+        let pin = PinChatMessage { business_connection_id: self.business_connection_id(chat_id), chat_id, message_id, disable_notification: Some(true) };
+        self.client.post(url).json(&pin).send().await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn unpin_chat_message(&self, chat_id: u64, message_id: u64) -> Result<(), BotError> {
+        let base = format!("https://api.telegram.org/bot{}/unpinChatMessage", self.key);
+        let url: Url = Url::parse(&base)?;
+        let unpin = PinChatMessage { business_connection_id: self.business_connection_id(chat_id), chat_id, message_id, disable_notification: None };
+        self.client.post(url).json(&unpin).send().await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+// a call made through the recording client, with buttons reduced to their texts
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TgCall {
+    AnswerCallbackQuery { text: Option<String> },
+    AnswerInlineQuery { results: usize },
+    SendMessage { chat_id: u64, text: String, buttons: Vec<String> },
+    SendForceReply { chat_id: u64, text: String },
+    SendLocation { chat_id: u64 },
+    SendDocument { chat_id: u64, file_name: String },
+    EditMessageText { chat_id: u64, message_id: u64, text: String, buttons: Vec<String> },
+    EditMessageReplyMarkup { chat_id: u64, message_id: u64, buttons: Vec<String> },
+    DeleteMessage { chat_id: u64, message_id: u64 },
+    PinChatMessage { chat_id: u64, message_id: u64 },
+    UnpinChatMessage { chat_id: u64, message_id: u64 },
+}
+
+// stands in for telegram in tests: remembers what the bot sent and numbers sent messages from 1
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct RecordingTg {
+    calls: std::sync::Mutex<Vec<TgCall>>,
+    last_message_id: std::sync::atomic::AtomicU64,
+    business_chats: RwLock<FnvHashMap<u64, String>>,
+    // message edits fail while set, as they do when telegram is unreachable
+    pub fail_edits: std::sync::atomic::AtomicBool,
+}
+
+#[cfg(test)]
+impl RecordingTg {
+    // calls made since the previous take
+    pub fn take_calls(&self) -> Vec<TgCall> {
+        std::mem::take(&mut *self.calls.lock().unwrap())
+    }
+
+    fn record(&self, call: TgCall) {
+        self.calls.lock().unwrap().push(call);
+    }
+
+    fn next_message_id(&self) -> u64 {
+        self.last_message_id.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1
+    }
+}
+
+#[cfg(test)]
+fn button_texts(reply_markup: Option<InlineKeyboardMarkup>) -> Vec<String> {
+    reply_markup.into_iter()
+        .flat_map(|markup| markup.inline_keyboard)
+        .flatten()
+        .map(|button| button.text)
+        .collect()
+}
+
+#[cfg(test)]
+#[async_trait]
+impl TelegramApi for RecordingTg {
+    fn route_business_chat(&self, chat_id: u64, connection_id: String) {
+        self.business_chats.write().unwrap().insert(chat_id, connection_id);
+    }
+
+    fn unroute_business_connection(&self, connection_id: &str) {
+        self.business_chats.write().unwrap().retain(|_, id| id != connection_id);
+    }
+
+    fn is_business_chat(&self, chat_id: u64) -> bool {
+        self.business_chats.read().unwrap().contains_key(&chat_id)
+    }
+
+    async fn get_me(&self) -> Result<User, BotError> {
+        Ok(User { id: 0, language_code: None, username: Some("notify_bot".to_string()), first_name: None })
+    }
+
+    async fn get_updates(&self, _offset: u64, _timeout: Duration) -> Result<Vec<Update>, BotError> {
+        Ok(vec![])
+    }
+
+    async fn answer_callback_query(&self, _callback_query_id: String, text: Option<String>) -> Result<(), BotError> {
+        self.record(TgCall::AnswerCallbackQuery { text });
+        Ok(())
+    }
+
+    async fn answer_inline_query(&self, _inline_query_id: String, results: Vec<InlineQueryResultArticle>) -> Result<(), BotError> {
+        self.record(TgCall::AnswerInlineQuery { results: results.len() });
+        Ok(())
+    }
+
+    async fn send_notification(&self, chat_id: u64, text: String, reply_markup: Option<InlineKeyboardMarkup>, _disable_notification: Option<bool>, _reply_to: Option<u64>) -> Result<u64, BotError> {
+        self.record(TgCall::SendMessage { chat_id, text, buttons: button_texts(reply_markup) });
+        Ok(self.next_message_id())
+    }
+
+    async fn send_force_reply(&self, chat_id: u64, text: String, _placeholder: Option<String>) -> Result<u64, BotError> {
+        self.record(TgCall::SendForceReply { chat_id, text });
+        Ok(self.next_message_id())
+    }
+
+    async fn send_location(&self, chat_id: u64, _location: Location) -> Result<(), BotError> {
+        self.record(TgCall::SendLocation { chat_id });
+        Ok(())
+    }
+
+    async fn send_document(&self, chat_id: u64, file_name: &str, _content_type: &str, _content: Vec<u8>) -> Result<(), BotError> {
+        self.record(TgCall::SendDocument { chat_id, file_name: file_name.to_string() });
+        Ok(())
+    }
+
+    async fn edit_message_text(&self, chat_id: u64, message_id: u64, text: String, reply_markup: Option<InlineKeyboardMarkup>) -> Result<(), BotError> {
+        if self.fail_edits.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(BotError::Usage("The recording client fails edits"));
+        }
+        self.record(TgCall::EditMessageText { chat_id, message_id, text, buttons: button_texts(reply_markup) });
+        Ok(())
+    }
+
+    async fn edit_message_reply_markup(&self, chat_id: u64, message_id: u64, reply_markup: Option<InlineKeyboardMarkup>) -> Result<(), BotError> {
+        self.record(TgCall::EditMessageReplyMarkup { chat_id, message_id, buttons: button_texts(reply_markup) });
+        Ok(())
+    }
+
+    async fn download_file(&self, _file_id: &str) -> Result<Vec<u8>, BotError> {
+        Err(BotError::Usage("The recording client has no files"))
+    }
+
+    async fn delete_message(&self, chat_id: u64, message_id: u64) -> Result<(), BotError> {
+        self.record(TgCall::DeleteMessage { chat_id, message_id });
+        Ok(())
+    }
+
+    async fn pin_chat_message(&self, chat_id: u64, message_id: u64) -> Result<(), BotError> {
+        self.record(TgCall::PinChatMessage { chat_id, message_id });
+        Ok(())
+    }
+
+    async fn unpin_chat_message(&self, chat_id: u64, message_id: u64) -> Result<(), BotError> {
+        self.record(TgCall::UnpinChatMessage { chat_id, message_id });
+        Ok(())
+    }
+}
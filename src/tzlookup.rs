@@ -0,0 +1,310 @@
+use chrono_tz::Tz;
+
+// a point in every populated part of the timezones users are likely to be in; the zone of a location is the one
+// of the closest point, which is coarse near borders but needs no shape files, so the bot asks to confirm it
+const ZONES: [(&str, f64, f64); 211] = [
+    ("Asia/Jerusalem", 31.77, 35.21),
+    ("Asia/Jerusalem", 32.08, 34.78),
+    ("Asia/Jerusalem", 32.79, 34.99),
+    ("Asia/Jerusalem", 29.56, 34.95),
+    ("Asia/Jerusalem", 31.25, 34.79),
+    ("Asia/Gaza", 31.50, 34.47),
+    ("Asia/Hebron", 31.53, 35.10),
+    ("Asia/Amman", 31.95, 35.93),
+    ("Asia/Beirut", 33.89, 35.50),
+    ("Asia/Damascus", 33.51, 36.29),
+    ("Africa/Cairo", 30.04, 31.24),
+    ("Asia/Nicosia", 35.17, 33.36),
+    ("Europe/Istanbul", 41.01, 28.98),
+    ("Europe/Istanbul", 39.93, 32.86),
+    ("Europe/Athens", 37.98, 23.73),
+    ("Europe/Sofia", 42.70, 23.32),
+    ("Europe/Bucharest", 44.43, 26.10),
+    ("Europe/Chisinau", 47.01, 28.86),
+    ("Europe/Kiev", 50.45, 30.52),
+    ("Europe/Kiev", 46.48, 30.73),
+    ("Europe/Kiev", 49.99, 36.23),
+    ("Europe/Minsk", 53.90, 27.56),
+    ("Europe/Vilnius", 54.69, 25.28),
+    ("Europe/Riga", 56.95, 24.11),
+    ("Europe/Tallinn", 59.44, 24.75),
+    ("Europe/Helsinki", 60.17, 24.94),
+    ("Europe/Moscow", 55.76, 37.62),
+    ("Europe/Moscow", 59.93, 30.34),
+    ("Europe/Moscow", 45.04, 38.98),
+    ("Europe/Moscow", 56.33, 44.00),
+    ("Europe/Moscow", 55.79, 49.12),
+    ("Europe/Kaliningrad", 54.71, 20.51),
+    ("Europe/Samara", 53.20, 50.15),
+    ("Europe/Volgograd", 48.71, 44.51),
+    ("Asia/Yekaterinburg", 56.84, 60.61),
+    ("Asia/Omsk", 54.99, 73.37),
+    ("Asia/Novosibirsk", 55.03, 82.92),
+    ("Asia/Krasnoyarsk", 56.01, 92.85),
+    ("Asia/Irkutsk", 52.29, 104.28),
+    ("Asia/Yakutsk", 62.03, 129.73),
+    ("Asia/Vladivostok", 43.12, 131.89),
+    ("Asia/Magadan", 59.56, 150.81),
+    ("Asia/Kamchatka", 53.02, 158.65),
+    ("Asia/Tbilisi", 41.72, 44.79),
+    ("Asia/Yerevan", 40.18, 44.51),
+    ("Asia/Baku", 40.41, 49.87),
+    ("Asia/Almaty", 43.24, 76.89),
+    ("Asia/Almaty", 51.17, 71.45),
+    ("Asia/Tashkent", 41.30, 69.24),
+    ("Asia/Bishkek", 42.87, 74.59),
+    ("Asia/Dushanbe", 38.56, 68.79),
+    ("Asia/Ashgabat", 37.96, 58.33),
+    ("Asia/Tehran", 35.69, 51.39),
+    ("Asia/Baghdad", 33.32, 44.37),
+    ("Asia/Riyadh", 24.71, 46.68),
+    ("Asia/Dubai", 25.20, 55.27),
+    ("Asia/Qatar", 25.29, 51.53),
+    ("Asia/Karachi", 24.86, 67.01),
+    ("Asia/Kabul", 34.56, 69.21),
+    ("Asia/Kolkata", 28.61, 77.21),
+    ("Asia/Kolkata", 19.08, 72.88),
+    ("Asia/Kolkata", 12.97, 77.59),
+    ("Asia/Kathmandu", 27.72, 85.32),
+    ("Asia/Dhaka", 23.81, 90.41),
+    ("Asia/Bangkok", 13.76, 100.50),
+    ("Asia/Ho_Chi_Minh", 10.82, 106.63),
+    ("Asia/Jakarta", -6.21, 106.85),
+    ("Asia/Singapore", 1.35, 103.82),
+    ("Asia/Manila", 14.60, 120.98),
+    ("Asia/Shanghai", 31.23, 121.47),
+    ("Asia/Shanghai", 39.90, 116.41),
+    ("Asia/Shanghai", 30.57, 104.07),
+    ("Asia/Shanghai", 25.04, 102.71),
+    ("Asia/Shanghai", 29.65, 91.14),
+    ("Asia/Shanghai", 36.06, 103.83),
+    ("Asia/Urumqi", 43.83, 87.62),
+    ("Asia/Urumqi", 39.47, 75.99),
+    ("Asia/Hong_Kong", 22.32, 114.17),
+    ("Asia/Taipei", 25.03, 121.57),
+    ("Asia/Seoul", 37.57, 126.98),
+    ("Asia/Tokyo", 35.68, 139.69),
+    ("Asia/Tokyo", 34.69, 135.50),
+    ("Australia/Perth", -31.95, 115.86),
+    ("Australia/Adelaide", -34.93, 138.60),
+    ("Australia/Brisbane", -27.47, 153.03),
+    ("Australia/Sydney", -33.87, 151.21),
+    ("Australia/Melbourne", -37.81, 144.96),
+    ("Pacific/Auckland", -36.85, 174.76),
+    ("Europe/London", 51.51, -0.13),
+    ("Europe/London", 53.48, -2.24),
+    ("Europe/London", 55.95, -3.19),
+    ("Europe/Dublin", 53.35, -6.26),
+    ("Europe/Lisbon", 38.72, -9.14),
+    ("Europe/Madrid", 40.42, -3.70),
+    ("Europe/Madrid", 41.39, 2.17),
+    ("Europe/Paris", 48.86, 2.35),
+    ("Europe/Paris", 43.30, 5.37),
+    ("Europe/Brussels", 50.85, 4.35),
+    ("Europe/Amsterdam", 52.37, 4.90),
+    ("Europe/Berlin", 52.52, 13.40),
+    ("Europe/Berlin", 48.14, 11.58),
+    ("Europe/Zurich", 47.38, 8.54),
+    ("Europe/Vienna", 48.21, 16.37),
+    ("Europe/Prague", 50.08, 14.44),
+    ("Europe/Warsaw", 52.23, 21.01),
+    ("Europe/Budapest", 47.50, 19.04),
+    ("Europe/Belgrade", 44.79, 20.45),
+    ("Europe/Rome", 41.90, 12.50),
+    ("Europe/Rome", 45.46, 9.19),
+    ("Europe/Copenhagen", 55.68, 12.57),
+    ("Europe/Oslo", 59.91, 10.75),
+    ("Europe/Stockholm", 59.33, 18.07),
+    ("Atlantic/Reykjavik", 64.15, -21.94),
+    ("Africa/Casablanca", 33.57, -7.59),
+    ("Africa/Lagos", 6.52, 3.38),
+    ("Africa/Nairobi", -1.29, 36.82),
+    ("Africa/Addis_Ababa", 9.03, 38.74),
+    ("Africa/Johannesburg", -26.20, 28.05),
+    ("America/New_York", 40.71, -74.01),
+    ("America/Chicago", 41.88, -87.63),
+    ("America/Denver", 39.74, -104.99),
+    ("America/Los_Angeles", 34.05, -118.24),
+    ("America/Toronto", 43.65, -79.38),
+    ("America/Vancouver", 49.28, -123.12),
+    ("America/Phoenix", 33.45, -112.07),
+    ("America/Anchorage", 61.22, -149.90),
+    ("America/Juneau", 58.30, -134.42),
+    // the us and canada have several zones each and their borders run between the big cities, so the interior and
+    // the cities along the borders get points of their own
+    ("America/New_York", 42.36, -71.06),
+    ("America/New_York", 38.91, -77.04),
+    ("America/New_York", 35.23, -80.84),
+    ("America/New_York", 33.75, -84.39),
+    ("America/New_York", 35.96, -83.92),
+    ("America/New_York", 30.44, -84.28),
+    ("America/New_York", 30.33, -81.66),
+    ("America/New_York", 25.76, -80.19),
+    ("America/New_York", 27.95, -82.46),
+    ("America/New_York", 39.96, -83.00),
+    ("America/New_York", 41.50, -81.69),
+    ("America/New_York", 39.10, -84.51),
+    ("America/New_York", 38.04, -84.50),
+    ("America/New_York", 40.44, -79.99),
+    ("America/Detroit", 42.33, -83.05),
+    ("America/Detroit", 42.96, -85.67),
+    ("America/Indiana/Indianapolis", 39.77, -86.16),
+    ("America/Indiana/Indianapolis", 41.08, -85.14),
+    ("America/Kentucky/Louisville", 38.25, -85.76),
+    ("America/Chicago", 36.16, -86.78),
+    ("America/Chicago", 35.15, -90.05),
+    ("America/Chicago", 33.52, -86.80),
+    ("America/Chicago", 30.42, -87.22),
+    ("America/Chicago", 29.95, -90.07),
+    ("America/Chicago", 29.76, -95.37),
+    ("America/Chicago", 32.78, -96.80),
+    ("America/Chicago", 29.42, -98.49),
+    ("America/Chicago", 35.47, -97.52),
+    ("America/Chicago", 39.10, -94.58),
+    ("America/Chicago", 38.63, -90.20),
+    ("America/Chicago", 43.04, -87.91),
+    ("America/Chicago", 44.98, -93.27),
+    ("America/Chicago", 41.26, -95.93),
+    ("America/Chicago", 37.69, -97.34),
+    ("America/Chicago", 33.58, -101.86),
+    ("America/Chicago", 35.22, -101.83),
+    ("America/Chicago", 46.88, -96.79),
+    ("America/Chicago", 46.81, -100.78),
+    ("America/Chicago", 30.16, -85.66),
+    ("America/Chicago", 37.97, -87.57),
+    ("America/Denver", 31.76, -106.49),
+    ("America/Denver", 35.08, -106.65),
+    ("America/Denver", 40.76, -111.89),
+    ("America/Denver", 41.14, -104.82),
+    ("America/Denver", 45.78, -108.50),
+    ("America/Boise", 43.62, -116.21),
+    ("America/Phoenix", 32.22, -110.97),
+    ("America/Los_Angeles", 37.77, -122.42),
+    ("America/Los_Angeles", 32.72, -117.16),
+    ("America/Los_Angeles", 36.17, -115.14),
+    ("America/Los_Angeles", 45.52, -122.68),
+    ("America/Los_Angeles", 47.61, -122.33),
+    ("America/Los_Angeles", 47.66, -117.43),
+    ("America/Toronto", 45.50, -73.57),
+    ("America/Toronto", 45.42, -75.70),
+    ("America/Toronto", 42.31, -83.04),
+    ("America/Halifax", 44.65, -63.57),
+    ("America/St_Johns", 47.56, -52.71),
+    ("America/Winnipeg", 49.90, -97.14),
+    ("America/Regina", 50.45, -104.62),
+    ("America/Edmonton", 51.05, -114.07),
+    ("America/Edmonton", 53.55, -113.49),
+    ("America/Hermosillo", 29.07, -110.96),
+    ("America/Chihuahua", 28.63, -106.09),
+    ("Pacific/Honolulu", 21.31, -157.86),
+    ("America/Mexico_City", 19.43, -99.13),
+    ("America/Monterrey", 25.69, -100.32),
+    ("America/Tijuana", 32.51, -117.04),
+    ("America/Cancun", 21.16, -86.85),
+    ("America/Havana", 23.11, -82.37),
+    ("America/Guatemala", 14.63, -90.51),
+    ("America/El_Salvador", 13.69, -89.22),
+    ("America/Tegucigalpa", 14.07, -87.19),
+    ("America/Managua", 12.11, -86.24),
+    ("America/Costa_Rica", 9.93, -84.08),
+    ("America/Panama", 8.98, -79.52),
+    ("America/Bogota", 4.71, -74.07),
+    ("America/Caracas", 10.48, -66.90),
+    ("America/Guayaquil", -2.19, -79.89),
+    ("America/Lima", -12.05, -77.04),
+    ("America/La_Paz", -16.49, -68.12),
+    ("America/Santiago", -33.45, -70.67),
+    ("America/Argentina/Buenos_Aires", -34.60, -58.38),
+    ("America/Sao_Paulo", -23.55, -46.63),
+];
+
+// a location farther than this from every point is in a zone missing here, better to ask for the name than to guess
+const MAX_DISTANCE_KM: f64 = 1000.0;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+// great circle distance on a unit sphere
+fn distance(latitude: f64, longitude: f64, other_latitude: f64, other_longitude: f64) -> f64 {
+    let (latitude, other_latitude) = (latitude.to_radians(), other_latitude.to_radians());
+    let half_latitude = (other_latitude - latitude) / 2.0;
+    let half_longitude = (other_longitude - longitude).to_radians() / 2.0;
+    let a = half_latitude.sin().powi(2) + latitude.cos() * other_latitude.cos() * half_longitude.sin().powi(2);
+    2.0 * a.sqrt().min(1.0).asin()
+}
+
+pub fn timezone_at(latitude: f64, longitude: f64) -> Option<Tz> {
+    ZONES.iter()
+        .map(|(name, zone_latitude, zone_longitude)| (name, distance(latitude, longitude, *zone_latitude, *zone_longitude)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .filter(|(_, distance)| distance * EARTH_RADIUS_KM <= MAX_DISTANCE_KM)
+        .and_then(|(name, _)| name.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Tz;
+    use super::{timezone_at, ZONES};
+
+    #[test]
+    fn should_find_timezone_of_closest_point() {
+        assert!(ZONES.iter().all(|(name, _, _)| name.parse::<Tz>().is_ok()));
+        assert_eq!(timezone_at(32.0853, 34.7818), Some(chrono_tz::Asia::Jerusalem));
+        // between Berlin and Warsaw, closer to Berlin
+        assert_eq!(timezone_at(52.4, 14.5), Some(chrono_tz::Europe::Berlin));
+        assert_eq!(timezone_at(40.75, -73.99), Some(chrono_tz::America::New_York));
+        assert_eq!(timezone_at(-33.9, 151.2), Some(chrono_tz::Australia::Sydney));
+    }
+
+    #[test]
+    fn should_find_timezones_of_the_americas_and_the_pacific() {
+        // Guadalajara, San Pedro Sula, Quito, Cochabamba, Fairbanks and Hilo
+        assert_eq!(timezone_at(20.67, -103.35), Some(chrono_tz::America::Mexico_City));
+        assert_eq!(timezone_at(15.50, -88.03), Some(chrono_tz::America::Tegucigalpa));
+        assert_eq!(timezone_at(-0.18, -78.47), Some(chrono_tz::America::Guayaquil));
+        assert_eq!(timezone_at(-17.39, -66.16), Some(chrono_tz::America::La_Paz));
+        assert_eq!(timezone_at(64.84, -147.72), Some(chrono_tz::America::Anchorage));
+        assert_eq!(timezone_at(19.72, -155.08), Some(chrono_tz::Pacific::Honolulu));
+        // the south pacific is thousands of kilometers from any point
+        assert_eq!(timezone_at(-40.0, -130.0), None);
+    }
+
+    #[test]
+    fn should_find_timezones_of_us_interior_and_border_cities() {
+        // Atlanta, Knoxville and Tallahassee are eastern, Nashville, Huntsville and Panama City next to them central
+        assert_eq!(timezone_at(33.76, -84.42), Some(chrono_tz::America::New_York));
+        assert_eq!(timezone_at(35.97, -83.95), Some(chrono_tz::America::New_York));
+        assert_eq!(timezone_at(30.45, -84.27), Some(chrono_tz::America::New_York));
+        assert_eq!(timezone_at(36.17, -86.77), Some(chrono_tz::America::Chicago));
+        assert_eq!(timezone_at(34.73, -86.59), Some(chrono_tz::America::Chicago));
+        assert_eq!(timezone_at(30.18, -85.68), Some(chrono_tz::America::Chicago));
+        // Indianapolis, Lansing and Louisville have zones of their own, Evansville in the corner of Indiana is central
+        assert_eq!(timezone_at(39.79, -86.15), Some(chrono_tz::America::Indiana::Indianapolis));
+        assert_eq!(timezone_at(42.73, -84.56), Some(chrono_tz::America::Detroit));
+        assert_eq!(timezone_at(38.26, -85.74), Some(chrono_tz::America::Kentucky::Louisville));
+        assert_eq!(timezone_at(37.98, -87.56), Some(chrono_tz::America::Chicago));
+        // El Paso and Las Cruces are mountain, Tucson doesn't switch to summer time, Amarillo and Bismarck are central
+        assert_eq!(timezone_at(31.79, -106.42), Some(chrono_tz::America::Denver));
+        assert_eq!(timezone_at(32.32, -106.76), Some(chrono_tz::America::Denver));
+        assert_eq!(timezone_at(32.25, -110.91), Some(chrono_tz::America::Phoenix));
+        assert_eq!(timezone_at(35.19, -101.85), Some(chrono_tz::America::Chicago));
+        assert_eq!(timezone_at(46.82, -100.77), Some(chrono_tz::America::Chicago));
+        // Boise, Spokane and Reno, San Diego and Tijuana across the border, Detroit and Windsor across the river
+        assert_eq!(timezone_at(43.60, -116.20), Some(chrono_tz::America::Boise));
+        assert_eq!(timezone_at(47.65, -117.42), Some(chrono_tz::America::Los_Angeles));
+        assert_eq!(timezone_at(39.53, -119.81), Some(chrono_tz::America::Los_Angeles));
+        assert_eq!(timezone_at(32.73, -117.15), Some(chrono_tz::America::Los_Angeles));
+        assert_eq!(timezone_at(32.52, -117.03), Some(chrono_tz::America::Tijuana));
+        assert_eq!(timezone_at(42.34, -83.06), Some(chrono_tz::America::Detroit));
+        assert_eq!(timezone_at(42.30, -83.02), Some(chrono_tz::America::Toronto));
+    }
+
+    #[test]
+    fn should_find_timezone_of_xinjiang_apart_from_its_neighbours() {
+        // Urumqi and Kashgar keep their own time, Almaty, Bishkek and Chengdu around them don't
+        assert_eq!(timezone_at(43.80, 87.60), Some(chrono_tz::Asia::Urumqi));
+        assert_eq!(timezone_at(39.46, 75.98), Some(chrono_tz::Asia::Urumqi));
+        assert_eq!(timezone_at(43.25, 76.92), Some(chrono_tz::Asia::Almaty));
+        assert_eq!(timezone_at(42.87, 74.61), Some(chrono_tz::Asia::Bishkek));
+        assert_eq!(timezone_at(30.66, 104.06), Some(chrono_tz::Asia::Shanghai));
+    }
+}
@@ -0,0 +1,120 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use reqwest::redirect::Policy;
+use serde::Serialize;
+use url::{Host, Url};
+use crate::errors::BotError;
+
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload<'a> {
+    pub name: &'a str,
+    pub text: Option<&'a str>,
+    pub fired_at: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct WebhookClient {
+    timeout: Duration,
+}
+
+impl WebhookClient {
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new() -> Result<WebhookClient, BotError> {
+        Ok(WebhookClient { timeout: Self::TIMEOUT })
+    }
+
+    // returns the response status, non-2xx statuses are reported rather than treated as errors;
+    // the request goes only to the addresses checked here, so a name can't resolve to a private one
+    // between the check and the call, and redirects are not followed for the same reason
+    pub async fn call(&self, url: &str, payload: &WebhookPayload<'_>) -> Result<u16, BotError> {
+        let url = check_url(url)?;
+        let addrs = resolve_public(&url).await?;
+        let mut builder = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .redirect(Policy::none());
+        if let Some(Host::Domain(domain)) = url.host() {
+            builder = builder.resolve_to_addrs(domain, &addrs);
+        }
+        let response = builder.build()?.post(url).json(payload).send().await?;
+        Ok(response.status().as_u16())
+    }
+}
+
+// webhooks are called from the bot's own network, so only https endpoints on public addresses are allowed;
+// a host given by name is checked again with every call, once it's resolved
+pub fn check_url(url: &str) -> Result<Url, BotError> {
+    let parsed = Url::parse(url)?;
+    let allowed = parsed.scheme() == "https" && match parsed.host() {
+        Some(Host::Domain(_)) => true,
+        Some(Host::Ipv4(ip)) => is_public(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_public(IpAddr::V6(ip)),
+        None => false,
+    };
+    if !allowed {
+        return Err(BotError::UnsafeWebhook(url.to_string()));
+    }
+    Ok(parsed)
+}
+
+async fn resolve_public(url: &Url) -> Result<Vec<SocketAddr>, BotError> {
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addrs = match url.host() {
+        Some(Host::Domain(domain)) => tokio::net::lookup_host((domain, port)).await?.collect::<Vec<_>>(),
+        Some(Host::Ipv4(ip)) => vec![SocketAddr::new(IpAddr::V4(ip), port)],
+        Some(Host::Ipv6(ip)) => vec![SocketAddr::new(IpAddr::V6(ip), port)],
+        None => vec![],
+    };
+    // one private address is enough to refuse, the client could pick any of them
+    if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+        return Err(BotError::UnsafeWebhook(url.to_string()));
+    }
+    Ok(addrs)
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            // 0.0.0.0/8 and the shared address space of carrier-grade nat, 100.64.0.0/10
+            let reserved = first == 0 || first == 100 && second & 0xc0 == 64;
+            !(reserved || ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+                || ip.is_broadcast() || ip.is_multicast() || ip.is_documentation())
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                // unique local fc00::/7 and link-local fe80::/10
+                let local = first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80;
+                !(local || ip.is_loopback() || ip.is_unspecified() || ip.is_multicast())
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use super::{check_url, WebhookClient, WebhookPayload};
+
+    #[test]
+    fn should_allow_only_https_urls_of_public_hosts() {
+        assert!(check_url("https://example.com/hook").is_ok());
+        assert!(check_url("https://93.184.216.34/hook").is_ok());
+        for url in ["http://example.com/hook", "ftp://example.com/hook", "https://127.0.0.1/hook", "https://10.0.0.5/hook",
+                    "https://192.168.1.1/hook", "https://172.16.0.1/hook", "https://169.254.169.254/latest/meta-data",
+                    "https://100.64.0.1/hook", "https://0.0.0.0/hook", "https://[::1]/hook", "https://[fd00::1]/hook",
+                    "https://[fe80::1]/hook", "https://[::ffff:127.0.0.1]/hook"] {
+            assert!(check_url(url).is_err(), "{}", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_refuse_names_resolving_to_private_addresses() {
+        let payload = WebhookPayload { name: "lights", text: None, fired_at: Utc::now() };
+        let result = WebhookClient::new().unwrap().call("https://localhost/lights", &payload).await;
+        assert!(matches!(result, Err(crate::errors::BotError::UnsafeWebhook(_))), "{:?}", result.err());
+    }
+}